@@ -3,10 +3,88 @@
 // Copyright (c) 2018-2020 Andre Richter <andre.o.richter@gmail.com>
 
 //! Architectural processor code.
+//!
+//! # Image header
+//!
+//! The kernel image starts with the 64 byte header of the arm64 Linux boot protocol, see
+//! `Documentation/arm64/booting.rst` in the Linux sources. Loaders like U-Boot's `booti` check its
+//! magic, and then place the image at `text_offset` above a 2 MiB aligned base:
+//!
+//! | Offset | Size | Field         | Value                                                        |
+//! |--------|------|---------------|--------------------------------------------------------------|
+//! | `0x00` | 4    | `code0`       | `b _start`, for loaders that jump to the start of the image  |
+//! | `0x04` | 4    | `code1`       | `0`                                                          |
+//! | `0x08` | 8    | `text_offset` | `0x80000`                                                    |
+//! | `0x10` | 8    | `image_size`  | From the start of the image to the end of `.bss`             |
+//! | `0x18` | 8    | `flags`       | Little endian, 64 KiB pages, base as close to DRAM start     |
+//! | `0x20` | 24   | reserved      | `0`                                                          |
+//! | `0x38` | 4    | `magic`       | `ARM\x64`                                                    |
+//! | `0x3C` | 4    | reserved      | `0`, i.e. no PE header                                       |
+//!
+//! The kernel is linked to run at `0x80000`, so the base must be the start of DRAM, which the
+//! `flags` demand.
 
-use crate::{bsp, cpu};
+use crate::{bsp, cpu, cpu::SecurityState};
+use core::ops::Range;
 use cortex_a::{asm, regs::*};
 
+// The linker script places this first in the image and defines `__kernel_image_size`.
+global_asm!(
+    "
+.section .text._head, \"ax\"
+
+    b      _start
+    .long  0
+    .quad  0x80000
+    .quad  __kernel_image_size
+    .quad  0x6
+    .quad  0
+    .quad  0
+    .quad  0
+    .ascii \"ARM\\x64\"
+    .long  0
+"
+);
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const IMAGE_HEADER_SIZE: usize = 64;
+
+/// The values of `x0`-`x3` at kernel entry.
+///
+/// Initialized to a non-zero value, so that it lives in `.data` and zeroing `.bss` does not clear
+/// it.
+static mut BOOT_REGS: [u64; 4] = [u64::MAX; 4];
+
+/// Size of the dedicated exception stack.
+const EXCEPTION_STACK_SIZE: usize = 16 * 1024;
+
+/// The AAPCS64 demands a 16 byte aligned stack pointer.
+#[repr(align(16))]
+struct ExceptionStack([u8; EXCEPTION_STACK_SIZE]);
+
+/// The stack that exception handlers run on after `use_sp_el0(true)`.
+static mut EXCEPTION_STACK: ExceptionStack = ExceptionStack([0; EXCEPTION_STACK_SIZE]);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The fields of an arm64 Linux image header that loaders act on.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ImageHeader {
+    /// Where above a 2 MiB aligned base the image must be placed.
+    pub text_offset: u64,
+
+    /// How much memory the image needs, including `.bss`.
+    pub image_size: u64,
+
+    /// Endianness in bit 0, page size in bits 1 and 2, placement in bit 3.
+    pub flags: u64,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Boot Code
 //--------------------------------------------------------------------------------------------------
@@ -17,14 +95,28 @@ use cortex_a::{asm, regs::*};
 ///
 /// # Safety
 ///
-/// - Linker script must ensure to place this function at `0x80_000`.
+/// - Linker script must ensure to place this function right after the image header, where `code0`
+///   branches to.
 #[naked]
 #[no_mangle]
 pub unsafe extern "C" fn _start() -> ! {
+    // Save x0-x3 before any other code uses them. Naming the registers explicitly ensures that no
+    // register is overwritten before it was read.
+    let (x0, x1, x2, x3): (u64, u64, u64, u64);
+    asm!(
+        "",
+        out("x0") x0,
+        out("x1") x1,
+        out("x2") x2,
+        out("x3") x3,
+        options(nomem, nostack, preserves_flags)
+    );
+
     // Expect the boot core to start in EL2.
     if (bsp::cpu::BOOT_CORE_ID == cpu::smp::core_id())
         && (CurrentEL.get() == CurrentEL::EL::EL2.value)
     {
+        BOOT_REGS = [x0, x1, x2, x3];
         el2_to_el1_transition()
     } else {
         // If not core0, infinitely wait for events.
@@ -49,6 +141,11 @@ unsafe fn el2_to_el1_transition() -> ! {
     // No offset for reading the counters.
     CNTVOFF_EL2.set(0);
 
+    // Give EL1 all PMU event counters (HPMN = PMCR_EL0.N) and do not trap PMU accesses to EL2.
+    let pmcr: u64;
+    asm!("mrs {}, PMCR_EL0", out(reg) pmcr, options(nomem, nostack, preserves_flags));
+    asm!("msr MDCR_EL2, {}", in(reg) (pmcr >> 11) & 0x1F, options(nomem, nostack, preserves_flags));
+
     // Set EL1 execution state to AArch64.
     HCR_EL2.write(HCR_EL2::RW::EL1IsAarch64);
 
@@ -80,6 +177,128 @@ unsafe fn el2_to_el1_transition() -> ! {
 
 pub use asm::nop;
 
+impl ImageHeader {
+    /// `ARM\x64`, little endian.
+    pub const MAGIC: u32 = 0x644D_5241;
+
+    /// Parse the header from the first bytes of an image. `None` if the magic is missing.
+    pub fn parse(image: &[u8]) -> Option<Self> {
+        let header = image.get(..IMAGE_HEADER_SIZE)?;
+        let u64_at = |offset: usize| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&header[offset..(offset + 8)]);
+            u64::from_le_bytes(bytes)
+        };
+
+        let magic = u32::from_le_bytes([header[0x38], header[0x39], header[0x3A], header[0x3B]]);
+        if magic != Self::MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            text_offset: u64_at(0x08),
+            image_size: u64_at(0x10),
+            flags: u64_at(0x18),
+        })
+    }
+
+    /// Parse the header at `addr`.
+    ///
+    /// # Safety
+    ///
+    /// - `addr` must point to at least 64 readable bytes.
+    pub unsafe fn from_addr(addr: usize) -> Option<Self> {
+        Self::parse(core::slice::from_raw_parts(
+            addr as *const u8,
+            IMAGE_HEADER_SIZE,
+        ))
+    }
+}
+
+/// The values of `x0`-`x3` that the boot core was entered with.
+///
+/// All `u64::MAX` if the boot code did not record them.
+pub fn boot_regs() -> [u64; 4] {
+    unsafe { core::ptr::read_volatile(&BOOT_REGS) }
+}
+
+/// The security state that the kernel runs in.
+///
+/// `SCR_EL3.NS` tells it, but only EL3 can read it. The boot code only proceeds on a core that was
+/// entered in EL2, though. Before Secure EL2 was introduced in ARMv8.4, which
+/// `ID_AA64PFR0_EL1.SEL2` reports, EL2 existed in the non-secure world only. The RPis' cores are
+/// ARMv8.0, so the kernel always runs non-secure on them. On a core with Secure EL2, `Unknown` is
+/// returned.
+pub fn security_state() -> SecurityState {
+    let pfr0: u64;
+    unsafe {
+        asm!("mrs {}, ID_AA64PFR0_EL1", out(reg) pfr0, options(nomem, nostack, preserves_flags))
+    };
+
+    match (pfr0 >> 36) & 0xF {
+        0 => SecurityState::NonSecure,
+        _ => SecurityState::Unknown,
+    }
+}
+
+/// The address range of the dedicated exception stack.
+pub fn exception_stack_range() -> Range<usize> {
+    let start = unsafe { &EXCEPTION_STACK as *const _ as usize };
+
+    start..(start + EXCEPTION_STACK_SIZE)
+}
+
+/// Return whether the kernel currently runs on `SP_EL0`.
+pub fn is_using_sp_el0() -> bool {
+    let spsel: u64;
+    unsafe { asm!("mrs {}, SPSel", out(reg) spsel, options(nomem, nostack, preserves_flags)) };
+
+    spsel & 1 == 0
+}
+
+/// Select the stack pointer that the kernel runs on outside of exception handlers.
+///
+/// At EL1, `SPSel` selects whether `sp` refers to `SP_EL0` (0) or `SP_EL1` (1). Taking an
+/// exception to EL1 always sets it to 1, so handlers run on `SP_EL1`, and `eret` restores the
+/// interrupted code's selection from SPSR_EL1. `SP_EL1` is only writable through `sp` itself.
+///
+/// With `true`, execution continues on `SP_EL0` with the current stack, and `SP_EL1` is pointed at
+/// the top of a dedicated exception stack. A stack overflow in normal code then can not corrupt the
+/// exception path. Exceptions are taken through the "current EL with SP_EL0" vectors from now on.
+///
+/// With `false`, execution continues on `SP_EL1` with the current stack. The exception stack is
+/// given up.
+///
+/// # Safety
+///
+/// - Must not be called from exception context.
+/// - There is one exception stack only, so only a single core may use it.
+pub unsafe fn use_sp_el0(enable: bool) {
+    if enable == is_using_sp_el0() {
+        return;
+    }
+
+    if enable {
+        let exception_stack_top = exception_stack_range().end;
+
+        asm!(
+            "mov {tmp}, sp",
+            "msr SP_EL0, {tmp}",
+            "mov sp, {top}",
+            "msr SPSel, #0",
+            tmp = out(reg) _,
+            top = in(reg) exception_stack_top,
+        );
+    } else {
+        asm!(
+            "mov {tmp}, sp",
+            "msr SPSel, #1",
+            "mov sp, {tmp}",
+            tmp = out(reg) _,
+        );
+    }
+}
+
 /// Spin for `n` cycles.
 #[inline(always)]
 pub fn spin_for_cycles(n: usize) {
@@ -88,6 +307,22 @@ pub fn spin_for_cycles(n: usize) {
     }
 }
 
+/// Pause execution on the core until an interrupt arrives.
+#[inline(always)]
+pub fn wait_for_interrupt() {
+    asm::wfi()
+}
+
+/// Branch to `addr` without setting up a return address.
+///
+/// # Safety
+///
+/// - `addr` must point to valid code.
+#[inline(always)]
+pub unsafe fn branch_to(addr: usize) -> ! {
+    asm!("br {}", in(reg) addr, options(noreturn))
+}
+
 /// Pause execution on the core.
 #[inline(always)]
 pub fn wait_forever() -> ! {
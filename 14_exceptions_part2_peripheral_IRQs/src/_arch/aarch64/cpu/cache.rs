@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural cache maintenance.
+
+use crate::memory::PageRange;
+use core::ops::Range;
+use cortex_a::barrier;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Clean and invalidate the D-cache and unified caches by set and way, in a single `asm!` block.
+///
+/// The loop does not access memory, so `$before` can switch the data cache off. Nothing is then
+/// allocated into the cache, and no memory access sees stale data, until the loop completed and
+/// `$after` runs. Levels without a data cache are skipped, up to `CLIDR_EL1.LoC`. `$before` and
+/// `$after` can use `{tmp}` as scratch register.
+macro_rules! clean_invalidate_dcache_all_asm {
+    ($before:literal, $after:literal) => {
+        asm!(
+            $before,
+            "
+             mrs    {clidr}, CLIDR_EL1
+             ubfx   {loc}, {clidr}, #24, #3
+             lsl    {loc}, {loc}, #1
+             mov    {level}, #0
+         2:
+             cmp    {level}, {loc}
+             b.ge   5f
+             add    {tmp}, {level}, {level}, lsr #1
+             lsr    {tmp}, {clidr}, {tmp}
+             and    {tmp}, {tmp}, #7
+             cmp    {tmp}, #2
+             b.lt   4f
+             msr    CSSELR_EL1, {level}
+             isb
+             mrs    {tmp}, CCSIDR_EL1
+             and    {line_shift}, {tmp}, #7
+             add    {line_shift}, {line_shift}, #4
+             ubfx   {max_way}, {tmp}, #3, #10
+             clz    {way_shift:w}, {max_way:w}
+             ubfx   {max_set}, {tmp}, #13, #15
+             mov    {way}, {max_way}
+         3:
+             mov    {set}, {max_set}
+         6:
+             lsl    {tmp}, {way}, {way_shift}
+             orr    {tmp}, {tmp}, {level}
+             lsl    {set_bits}, {set}, {line_shift}
+             orr    {tmp}, {tmp}, {set_bits}
+             dc     cisw, {tmp}
+             subs   {set}, {set}, #1
+             b.ge   6b
+             subs   {way}, {way}, #1
+             b.ge   3b
+         4:
+             add    {level}, {level}, #2
+             b      2b
+         5:
+             dsb    sy
+            ",
+            $after,
+            tmp = out(reg) _,
+            clidr = out(reg) _,
+            loc = out(reg) _,
+            level = out(reg) _,
+            line_shift = out(reg) _,
+            max_way = out(reg) _,
+            way_shift = out(reg) _,
+            max_set = out(reg) _,
+            way = out(reg) _,
+            set = out(reg) _,
+            set_bits = out(reg) _,
+            options(nostack)
+        )
+    };
+}
+pub(crate) use clean_invalidate_dcache_all_asm;
+
+/// Return the smallest D-cache line size in bytes, as reported by CTR_EL0.DminLine.
+#[inline(always)]
+fn dcache_min_line_size() -> usize {
+    let ctr: u64;
+
+    unsafe { asm!("mrs {}, CTR_EL0", out(reg) ctr, options(nomem, nostack, preserves_flags)) };
+
+    // DminLine is the log2 of the number of words in the smallest D-cache line.
+    4 << ((ctr >> 16) & 0xF)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Clean the D-cache lines covering `range` to the Point of Unification.
+///
+/// Afterwards, data that was written through the D-cache is visible to instruction fetches.
+pub fn clean_dcache_range_to_pou(range: Range<usize>) {
+    let lines = PageRange::with_granule(range.start, range.end, dcache_min_line_size());
+
+    // `dc cvau` operates on the line containing the address, so the clipped first line is fine.
+    for (addr, _) in lines {
+        unsafe { asm!("dc cvau, {}", in(reg) addr, options(nostack, preserves_flags)) };
+    }
+
+    unsafe { barrier::dsb(barrier::ISH) };
+}
+
+/// Clean the D-cache lines covering `range` to the Point of Coherency.
+///
+/// Afterwards, data that was written through the D-cache is visible to bus masters like the DMA
+/// engine. Returns the cleaned range, which is `range` extended to whole cache lines.
+pub fn clean_dcache_range_to_poc(range: Range<usize>) -> Range<usize> {
+    let line_size = dcache_min_line_size();
+    let lines = PageRange::with_granule(range.start, range.end, line_size);
+
+    for (addr, _) in lines {
+        unsafe { asm!("dc cvac, {}", in(reg) addr, options(nostack, preserves_flags)) };
+    }
+
+    unsafe { barrier::dsb(barrier::SY) };
+
+    if range.start >= range.end {
+        return range.start..range.start;
+    }
+    (range.start & !(line_size - 1))..((range.end + line_size - 1) & !(line_size - 1))
+}
+
+/// Clean and invalidate the D-cache lines covering `range` to the Point of Coherency.
+///
+/// Afterwards, data that was written through the D-cache is visible to bus masters like the DMA
+/// engine, and the next CPU read of the range fetches from memory.
+pub fn clean_invalidate_dcache_range_to_poc(range: Range<usize>) {
+    let lines = PageRange::with_granule(range.start, range.end, dcache_min_line_size());
+
+    for (addr, _) in lines {
+        unsafe { asm!("dc civac, {}", in(reg) addr, options(nostack, preserves_flags)) };
+    }
+
+    unsafe { barrier::dsb(barrier::SY) };
+}
+
+/// Clean and invalidate the executing core's whole D-cache and unified caches to the Point of
+/// Coherency, by set and way.
+///
+/// Only the executing core's caches are affected, and lines that other cores share may come back
+/// right away. Meant for a core that is about to be powered down.
+pub fn clean_invalidate_dcache_all() {
+    unsafe { clean_invalidate_dcache_all_asm!("", "isb") };
+}
+
+/// Invalidate the whole I-cache to the Point of Unification.
+pub fn invalidate_icache() {
+    unsafe {
+        asm!("ic iallu", options(nostack, preserves_flags));
+        barrier::dsb(barrier::ISH);
+        barrier::isb(barrier::SY);
+    }
+}
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural ID registers.
+//!
+//! The summary is decoded from `ID_AA64MMFR0_EL1`, `ID_AA64PFR0_EL1` and `ID_AA64ISAR0_EL1`, see
+//! the ARMv8-A Architecture Reference Manual for their fields.
+
+use crate::cpu::id::{ElSupport, FeatureSummary};
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The 4 bit field of `reg` that starts at bit `lsb`.
+fn field(reg: u64, lsb: u32) -> u64 {
+    (reg >> lsb) & 0xF
+}
+
+fn pa_range_bits(encoding: u64) -> Option<u8> {
+    match encoding {
+        0b0000 => Some(32),
+        0b0001 => Some(36),
+        0b0010 => Some(40),
+        0b0011 => Some(42),
+        0b0100 => Some(44),
+        0b0101 => Some(48),
+        0b0110 => Some(52),
+        _ => None,
+    }
+}
+
+fn el_support(encoding: u64) -> ElSupport {
+    match encoding {
+        0b0000 => ElSupport::NotImplemented,
+        0b0001 => ElSupport::AArch64,
+        _ => ElSupport::AArch64AndAArch32,
+    }
+}
+
+fn decode(mmfr0: u64, pfr0: u64, isar0: u64) -> FeatureSummary {
+    FeatureSummary {
+        pa_range_bits: pa_range_bits(field(mmfr0, 0)),
+        granule_4k: field(mmfr0, 28) != 0b1111,
+        granule_16k: field(mmfr0, 20) != 0b0000,
+        granule_64k: field(mmfr0, 24) != 0b1111,
+        el: [
+            el_support(field(pfr0, 0)),
+            el_support(field(pfr0, 4)),
+            el_support(field(pfr0, 8)),
+            el_support(field(pfr0, 12)),
+        ],
+        fp: field(pfr0, 16) != 0b1111,
+        simd: field(pfr0, 20) != 0b1111,
+        aes: field(isar0, 4) != 0b0000,
+        pmull: field(isar0, 4) >= 0b0010,
+        crc32: field(isar0, 16) != 0b0000,
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Decode the features of the executing core.
+pub fn feature_summary() -> FeatureSummary {
+    let (mmfr0, pfr0, isar0): (u64, u64, u64);
+    unsafe {
+        asm!(
+            "mrs {}, ID_AA64MMFR0_EL1",
+            "mrs {}, ID_AA64PFR0_EL1",
+            "mrs {}, ID_AA64ISAR0_EL1",
+            out(reg) mmfr0,
+            out(reg) pfr0,
+            out(reg) isar0,
+            options(nomem, nostack, preserves_flags)
+        )
+    };
+
+    decode(mmfr0, pfr0, isar0)
+}
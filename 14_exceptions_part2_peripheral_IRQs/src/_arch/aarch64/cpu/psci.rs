@@ -0,0 +1,50 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural PSCI calls.
+
+use crate::cpu::psci::Conduit;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Call the PSCI function `function_id` with `args` in `x1` to `x3`, and return `x0`.
+///
+/// # Safety
+///
+/// - The function must not affect the executing core's state in a way the caller is not prepared
+///   for, e.g. power it down.
+pub unsafe fn call(conduit: Conduit, function_id: u32, args: [u64; 3]) -> i64 {
+    let result: u64;
+
+    match conduit {
+        Conduit::Smc => asm!(
+            "smc #0",
+            inout("x0") u64::from(function_id) => result,
+            inout("x1") args[0] => _,
+            inout("x2") args[1] => _,
+            inout("x3") args[2] => _,
+            out("x4") _, out("x5") _, out("x6") _, out("x7") _,
+            out("x8") _, out("x9") _, out("x10") _, out("x11") _,
+            out("x12") _, out("x13") _, out("x14") _, out("x15") _,
+            out("x16") _, out("x17") _,
+            options(nostack)
+        ),
+        Conduit::Hvc => asm!(
+            "hvc #0",
+            inout("x0") u64::from(function_id) => result,
+            inout("x1") args[0] => _,
+            inout("x2") args[1] => _,
+            inout("x3") args[2] => _,
+            out("x4") _, out("x5") _, out("x6") _, out("x7") _,
+            out("x8") _, out("x9") _, out("x10") _, out("x11") _,
+            out("x12") _, out("x13") _, out("x14") _, out("x15") _,
+            out("x16") _, out("x17") _,
+            options(nostack)
+        ),
+    }
+
+    result as i64
+}
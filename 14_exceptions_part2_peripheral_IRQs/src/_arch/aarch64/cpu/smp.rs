@@ -3,13 +3,288 @@
 // Copyright (c) 2018-2020 Andre Richter <andre.o.richter@gmail.com>
 
 //! Architectural symmetric multiprocessing.
+//!
+//! # Core count
+//!
+//! `MPIDR_EL1` only identifies the executing core, not how many there are. On the Cortex-A53 and
+//! Cortex-A72, the implementation defined `L2CTLR_EL1[25:24]` holds the number of cores in the
+//! cluster minus one, which [`num_cores()`] reads. On any other core, the BSP's `NUM_CORES` is
+//! assumed.
+//!
+//! # Starting secondary cores
+//!
+//! If `cpu::psci::init()` found PSCI in the device tree, [`start_secondary_cores()`] starts each
+//! core with `CPU_ON`. The target affinity is the core id, and the core id is the context ID, too,
+//! though `__secondary_entry` derives it from `MPIDR_EL1` itself.
+//!
+//! Otherwise, it falls back to the spin table: the firmware's armstub parks the secondary cores in
+//! a loop that waits for an entry address, one `u64` per core starting at
+//! `bsp::cpu::SPIN_TABLE_BASE`. QEMU's boot stub for the RPis behaves the same.
+//!
+//! Either way, only the cores that exist are started, so it never waits for a core that is not
+//! there.
+//!
+//! The secondary cores stay in the exception level they arrive in, EL2 from the spin table or the
+//! kernel's EL1 from `CPU_ON`, with the MMU and caches off. Each gets a small stack of its
+//! own, runs the work that [`start_secondary_cores_with()`] was given, and parks. Their data
+//! accesses are therefore neither cached nor coherent with a boot core that has its D-cache on.
+//!
+//! # Coherency
+//!
+//! Cores that run with their caches on see each other's writes only if all of them have the
+//! D-cache and the MMU on, map shared data as inner shareable, write-back cacheable normal memory,
+//! and take part in the cluster's coherency. [`CoherencySetup`] reads what decides this on the
+//! executing core, and `cpu::smp::verify_coherency()` panics if it falls short. The last part is
+//! `CPUECTLR_EL1.SMPEN` on the Cortex-A53 and Cortex-A72, which the firmware's armstub sets.
 
-use cortex_a::regs::*;
+use crate::{bsp, cpu, cpu::smp::CoherencyError, time};
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use cortex_a::{asm, regs::*};
+
+// The secondary cores arrive here with the MMU and caches off, and without a stack. Each sets up
+// its own stack and continues in `__secondary_main()` with its core id in x0. The shift must match
+// `SECONDARY_STACK_SIZE`.
+global_asm!(
+    "
+.section .text
+
+.global __secondary_entry
+__secondary_entry:
+    mrs    x0,  MPIDR_EL1
+    and    x0,  x0,  #0b11
+    ldr    x1,  =__secondary_stacks
+    add    x2,  x0,  #1
+    add    x1,  x1,  x2,  lsl #14
+    mov    sp,  x1
+    b      __secondary_main
+"
+);
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+extern "C" {
+    fn __secondary_entry();
+}
+
+/// The size of each secondary core's stack.
+const SECONDARY_STACK_SIZE: usize = 16 * 1024;
+
+/// How long each secondary core gets to arrive at `__secondary_main()`.
+const START_TIMEOUT: Duration = Duration::from_millis(100);
+
+const SCTLR_EL1_M: u64 = 1 << 0;
+const SCTLR_EL1_C: u64 = 1 << 2;
+const TCR_EL1_SH0_SHIFT: u64 = 12;
+const PAR_EL1_F: u64 = 1 << 0;
+const PAR_EL1_SH_SHIFT: u64 = 7;
+const CPUECTLR_EL1_SMPEN: u64 = 1 << 6;
+
+const SH_INNER_SHAREABLE: u64 = 0b11;
+
+/// The `MAIR_EL1` encoding for inner and outer write-back, read and write allocate normal memory.
+const ATTR_NORMAL_WRITE_BACK: u64 = 0xFF;
+
+/// One flag per core, set by the core once it runs kernel code.
+///
+/// The secondary cores write it with their caches off. It gets a cache line of its own, so that
+/// the eviction of a line that the boot core dirtied can not overwrite the flags.
+#[repr(C)]
+#[repr(align(64))]
+struct StartedFlags([u64; bsp::cpu::NUM_CORES]);
+
+/// The stacks of the secondary cores, indexed by core id. The boot core's one is unused.
+#[repr(C)]
+#[repr(align(16))]
+struct SecondaryStacks([[u8; SECONDARY_STACK_SIZE]; bsp::cpu::NUM_CORES]);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The registers of the executing core that decide whether it takes part in coherent SMP.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug)]
+pub struct CoherencySetup {
+    pub sctlr_el1: u64,
+    pub tcr_el1: u64,
+
+    /// `PAR_EL1` after translating the address of kernel data.
+    pub data_par_el1: u64,
+
+    /// `None` on cores whose `CPUECTLR_EL1` is not known, or not emulated, like on QEMU.
+    pub cpuectlr_el1: Option<u64>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+#[no_mangle]
+static mut __secondary_started: StartedFlags = StartedFlags([0; bsp::cpu::NUM_CORES]);
+
+#[no_mangle]
+static mut __secondary_stacks: SecondaryStacks =
+    SecondaryStacks([[0; SECONDARY_STACK_SIZE]; bsp::cpu::NUM_CORES]);
+
+/// The `fn(usize)` that the secondary cores run, as address.
+static SECONDARY_WORK: AtomicUsize = AtomicUsize::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn started_flags_range() -> core::ops::Range<usize> {
+    let start = unsafe { &__secondary_started as *const _ as usize };
+
+    start..(start + core::mem::size_of::<StartedFlags>())
+}
+
+/// The Rust entry of the secondary cores.
+///
+/// # Safety
+///
+/// - Only `__secondary_entry` may call this, once per core.
+#[no_mangle]
+unsafe extern "C" fn __secondary_main(core: usize) -> ! {
+    ptr::write_volatile(&mut __secondary_started.0[core], 1);
+    asm::sev();
+
+    let work = SECONDARY_WORK.load(Ordering::Acquire);
+    if work != 0 {
+        let work: fn(usize) = core::mem::transmute(work);
+        work(core);
+    }
+
+    loop {
+        asm::wfe()
+    }
+}
+
+/// Hand `__secondary_entry` to `core` through its spin table slot.
+unsafe fn release_from_spin_table(core: usize) {
+    let slot = bsp::cpu::SPIN_TABLE_BASE + 8 * core;
+
+    ptr::write_volatile(slot as *mut u64, __secondary_entry as usize as u64);
+    cpu::cache::clean_dcache_range_to_poc(slot..(slot + 8));
+}
+
+/// The secondary cores to start if `num_cores` cores exist: All but the boot core, and none beyond
+/// the BSP's `NUM_CORES`.
+fn secondaries_to_start(num_cores: usize) -> impl Iterator<Item = usize> + Clone {
+    (0..core::cmp::min(num_cores, bsp::cpu::NUM_CORES))
+        .filter(|core| *core != bsp::cpu::BOOT_CORE_ID)
+}
+
+/// Whether the executing core is a Cortex-A53 or Cortex-A72, whose implementation defined
+/// registers the kernel knows.
+fn is_cortex_a53_or_a72() -> bool {
+    const IMPLEMENTER_ARM: u64 = 0x41;
+    const PART_CORTEX_A53: u64 = 0xD03;
+    const PART_CORTEX_A72: u64 = 0xD08;
+
+    let midr = MIDR_EL1.get();
+    let implementer = (midr >> 24) & 0xFF;
+    let part = (midr >> 4) & 0xFFF;
+
+    implementer == IMPLEMENTER_ARM && (part == PART_CORTEX_A53 || part == PART_CORTEX_A72)
+}
+
+/// Read `CPUECTLR_EL1`, if the executing core is known to implement it.
+///
+/// QEMU does not emulate the register, which reads as zero there. On hardware, the firmware set
+/// `SMPEN` before the kernel runs, so zero is taken as unknown, too. That includes a core that does
+/// not implement the register at all, whose read `exception::emulate_sysreg_read_as_zero()` turns
+/// into zero once the kernel registered it.
+fn cpuectlr() -> Option<u64> {
+    if !is_cortex_a53_or_a72() {
+        return None;
+    }
+
+    let cpuectlr: u64;
+    unsafe {
+        asm!("mrs {}, S3_1_C15_C2_1", out(reg) cpuectlr, options(nomem, nostack, preserves_flags))
+    };
+
+    Some(cpuectlr).filter(|cpuectlr| *cpuectlr != 0)
+}
+
+/// Read `L2CTLR_EL1`, if the executing core is known to implement it.
+fn l2ctlr() -> Option<u64> {
+    if !is_cortex_a53_or_a72() {
+        return None;
+    }
+
+    let l2ctlr: u64;
+    unsafe {
+        asm!("mrs {}, S3_1_C11_C0_2", out(reg) l2ctlr, options(nomem, nostack, preserves_flags))
+    };
+
+    Some(l2ctlr)
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl CoherencySetup {
+    /// Read the executing core's setup.
+    pub fn capture() -> Self {
+        let data_addr = &SECONDARY_WORK as *const _ as usize;
+        let data_par_el1: u64;
+        unsafe {
+            asm!(
+                "at s1e1r, {addr}",
+                "isb",
+                "mrs {par}, PAR_EL1",
+                addr = in(reg) data_addr,
+                par = out(reg) data_par_el1,
+                options(nostack, preserves_flags)
+            )
+        };
+
+        Self {
+            sctlr_el1: SCTLR_EL1.get(),
+            tcr_el1: TCR_EL1.get(),
+            data_par_el1,
+            cpuectlr_el1: cpuectlr(),
+        }
+    }
+
+    /// Check the setup, and report the first thing that breaks coherency.
+    pub fn check(&self) -> Result<(), CoherencyError> {
+        if self.sctlr_el1 & SCTLR_EL1_C == 0 {
+            return Err(CoherencyError::DCacheOff);
+        }
+        if self.sctlr_el1 & SCTLR_EL1_M == 0 {
+            return Err(CoherencyError::MmuOff);
+        }
+        if (self.tcr_el1 >> TCR_EL1_SH0_SHIFT) & 0b11 != SH_INNER_SHAREABLE {
+            return Err(CoherencyError::TableWalksNotInnerShareable);
+        }
+
+        let par = self.data_par_el1;
+        if par & PAR_EL1_F != 0 || (par >> PAR_EL1_SH_SHIFT) & 0b11 != SH_INNER_SHAREABLE {
+            return Err(CoherencyError::DataNotInnerShareable);
+        }
+        if par >> 56 != ATTR_NORMAL_WRITE_BACK {
+            return Err(CoherencyError::DataNotCacheable);
+        }
+
+        match self.cpuectlr_el1 {
+            Some(cpuectlr) if cpuectlr & CPUECTLR_EL1_SMPEN == 0 => {
+                Err(CoherencyError::SmpCoherencyOff)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 /// Return the executing core's id.
 #[inline(always)]
 pub fn core_id<T>() -> T
@@ -20,3 +295,138 @@ where
 
     T::from((MPIDR_EL1.get() & CORE_MASK) as u8)
 }
+
+/// The number of cores that exist.
+///
+/// Never more than the BSP's `NUM_CORES`, which per-core data is sized for.
+pub fn num_cores() -> u8 {
+    let num = match l2ctlr() {
+        Some(l2ctlr) => ((l2ctlr >> 24) & 0b11) as usize + 1,
+        None => bsp::cpu::NUM_CORES,
+    };
+
+    core::cmp::min(num, bsp::cpu::NUM_CORES) as u8
+}
+
+/// Whether `core` runs kernel code, i.e. was started and did not go offline. Always `true` for the
+/// boot core.
+pub fn is_core_started(core: usize) -> bool {
+    if core == bsp::cpu::BOOT_CORE_ID {
+        return true;
+    }
+    if core >= bsp::cpu::NUM_CORES {
+        return false;
+    }
+
+    // Drop the boot core's copy of the line, so that the read fetches what the cores wrote.
+    cpu::cache::clean_invalidate_dcache_range_to_poc(started_flags_range());
+
+    unsafe { ptr::read_volatile(&__secondary_started.0[core]) != 0 }
+}
+
+/// The number of cores that run kernel code, see `is_core_started()`.
+pub fn num_online_cores() -> usize {
+    (0..bsp::cpu::NUM_CORES)
+        .filter(|core| is_core_started(*core))
+        .count()
+}
+
+/// Mark the executing core as no longer running kernel code, right before it goes offline.
+pub(crate) fn mark_core_offline() {
+    let core: usize = core_id();
+
+    unsafe { ptr::write_volatile(&mut __secondary_started.0[core], 0) };
+    cpu::cache::clean_invalidate_dcache_range_to_poc(started_flags_range());
+}
+
+/// Start the secondary cores that `num_cores()` reports, through PSCI if there is one, else from
+/// the spin table, and return how many of them arrived. They park right away.
+///
+/// # Safety
+///
+/// - See `start_secondary_cores_with()`.
+pub unsafe fn start_secondary_cores() -> usize {
+    start_secondary_cores_with(|_| {})
+}
+
+/// Like `start_secondary_cores()`, but each secondary core runs `work` with its core id before it
+/// parks.
+///
+/// `work` runs concurrently with the caller, in EL2 and with the MMU and caches off. It may share
+/// data with other cores only through atomics and `Spinlock`s, and only while the boot core has its
+/// D-cache off, too. On real hardware, exclusive accesses need the MMU on, so that sharing is sound
+/// under QEMU only.
+///
+/// If the boot core has its D-cache on, it must be set up for coherent SMP, or this panics. See
+/// `cpu::smp::verify_coherency()`.
+///
+/// # Safety
+///
+/// - Only the boot core may call this, and only once.
+/// - Without PSCI, the spin table must be where `bsp::cpu::SPIN_TABLE_BASE` says.
+pub unsafe fn start_secondary_cores_with(work: fn(usize)) -> usize {
+    let secondaries = secondaries_to_start(num_cores() as usize);
+
+    // With its D-cache on, the boot core shares data with the secondaries through its caches, which
+    // needs coherent SMP.
+    if SCTLR_EL1.get() & SCTLR_EL1_C != 0 {
+        cpu::smp::verify_coherency();
+    }
+
+    SECONDARY_WORK.store(work as usize, Ordering::Release);
+    let work_addr = &SECONDARY_WORK as *const _ as usize;
+    cpu::cache::clean_dcache_range_to_poc(work_addr..(work_addr + 8));
+
+    // Write back the zeroed flags now, before the secondaries write to memory directly.
+    cpu::cache::clean_invalidate_dcache_range_to_poc(started_flags_range());
+
+    let use_psci = cpu::psci::conduit().is_some();
+    for core in secondaries.clone() {
+        if use_psci {
+            // A core that the firmware refuses to start does not arrive, and is not counted below.
+            let _ = cpu::psci::cpu_on(core as u64, __secondary_entry as usize, core as u64);
+        } else {
+            release_from_spin_table(core);
+        }
+    }
+
+    // Wake the cores that wait in `wfe`.
+    if !use_psci {
+        asm::sev();
+    }
+
+    secondaries
+        .filter(|core| {
+            time::with_timeout(START_TIMEOUT, || {
+                Some(()).filter(|_| is_core_started(*core))
+            })
+            .is_ok()
+        })
+        .count()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Only cores that exist may be started, and never the boot core.
+    #[kernel_test]
+    fn only_existing_secondaries_are_started() {
+        let started = |num_cores, expected: &[usize]| {
+            secondaries_to_start(num_cores).eq(expected.iter().copied())
+        };
+
+        assert!(started(1, &[]));
+        assert!(started(2, &[1]));
+        assert!(started(3, &[1, 2]));
+        assert!(started(4, &[1, 2, 3]));
+
+        // A count beyond what per-core data is sized for is capped.
+        assert!(started(8, &[1, 2, 3]));
+    }
+}
@@ -0,0 +1,216 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural self-hosted debug.
+//!
+//! Breakpoint `n` is programmed through `DBGBVR<n>_EL1` (the address) and `DBGBCR<n>_EL1` (the
+//! control), watchpoint `n` through `DBGWVR<n>_EL1` and `DBGWCR<n>_EL1`. `ID_AA64DFR0_EL1.BRPs`
+//! and `.WRPs` tell how many exist, from 2 up to 16 each. The Cortex-A53 and Cortex-A72 have six
+//! breakpoints and four watchpoints.
+//!
+//! The registers only generate exceptions in monitor mode, i.e. with `MDSCR_EL1.MDE` and, for EL1,
+//! `MDSCR_EL1.KDE` set, the OS lock clear and `PSTATE.D` clear. A watchpoint exception is taken
+//! before the access happens, and a breakpoint exception before the instruction executes.
+//!
+//! # Software step
+//!
+//! Software step is a state machine, driven by `MDSCR_EL1.SS` and `PSTATE.SS`:
+//!
+//! - **Inactive**: `MDSCR_EL1.SS` is clear, or debug exceptions are masked, as they are in the
+//!   exception handlers. Code runs normally.
+//! - **Active-not-pending**: `MDSCR_EL1.SS` and `PSTATE.SS` are set. The next instruction executes,
+//!   and clears `PSTATE.SS`.
+//! - **Active-pending**: `MDSCR_EL1.SS` is set, `PSTATE.SS` clear. The software step exception is
+//!   taken before the next instruction, with ELR pointing to it.
+//!
+//! An exception return loads `PSTATE.SS` from `SPSR_EL1.SS`. [`single_step()`] sets both bits, so
+//! the return to the context executes exactly one instruction. The step exception then saves
+//! `SPSR_EL1.SS` clear, so returning to it with `MDSCR_EL1.SS` still set would trap right away,
+//! before executing anything. Either step again, or call [`end_single_step()`].
+//!
+//! Some instructions need care:
+//!
+//! - Branches need none. The step completes at the branch target, which ELR then points to.
+//! - An `SVC` or `BRK` completes into its exception, and saves `SPSR_EL1.SS` clear. The step
+//!   exception is therefore taken once its handler returned, at the instruction after an `SVC`, or
+//!   wherever the `BRK` handler continues.
+//! - An IRQ that is taken before the stepped instruction saves `SPSR_EL1.SS` set, so the step
+//!   resumes when the handler returns. Handlers run with debug exceptions masked, and are not
+//!   stepped into.
+//! - An `ERET` that is stepped restores `PSTATE.SS` from the `SPSR_EL1` it returns to, as any
+//!   exception return does. Stepping the exception handlers themselves is not supported.
+//! - A `WFI` or `WFE` completes only once the core wakes up.
+
+use crate::{debug::WatchAccess, exception::ExceptionContext};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const MDSCR_EL1_SS: u64 = 1 << 0;
+const MDSCR_EL1_KDE: u64 = 1 << 13;
+const MDSCR_EL1_MDE: u64 = 1 << 15;
+
+const SPSR_D: u32 = 1 << 9;
+const SPSR_SS: u32 = 1 << 21;
+
+/// Enabled, matching at EL1 and EL0 (`PMC` / `PAC` = `0b11`).
+const CTRL_ENABLED_EL1_EL0: u64 = (0b11 << 1) | 1;
+
+/// `DBGBCR.BAS` for an A64 instruction.
+const BCR_BAS_A64: u64 = 0b1111 << 5;
+
+const WCR_LSC_SHIFT: u64 = 3;
+const WCR_BAS_SHIFT: u64 = 5;
+
+/// Write `$value` to the system register `<$prefix><$n>_EL1`, with `$n` known at runtime only.
+macro_rules! write_indexed {
+    ($prefix:literal, $n:expr, $value:expr) => {
+        write_indexed!(@ $prefix, $n, $value, 0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15)
+    };
+    (@ $prefix:literal, $n:expr, $value:expr, $($i:literal)*) => {
+        match $n {
+            $($i => asm!(
+                concat!("msr ", $prefix, $i, "_EL1, {}"),
+                in(reg) $value,
+                options(nomem, nostack, preserves_flags)
+            ),)*
+            _ => unreachable!(),
+        }
+    };
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn dfr0() -> u64 {
+    let dfr0: u64;
+    unsafe {
+        asm!("mrs {}, ID_AA64DFR0_EL1", out(reg) dfr0, options(nomem, nostack, preserves_flags))
+    };
+
+    dfr0
+}
+
+fn isb() {
+    unsafe { asm!("isb", options(nomem, nostack, preserves_flags)) };
+}
+
+fn mdscr() -> u64 {
+    let mdscr: u64;
+    unsafe { asm!("mrs {}, MDSCR_EL1", out(reg) mdscr, options(nomem, nostack, preserves_flags)) };
+
+    mdscr
+}
+
+fn set_mdscr(mdscr: u64) {
+    unsafe {
+        asm!(
+            "msr MDSCR_EL1, {}",
+            "isb",
+            in(reg) mdscr,
+            options(nomem, nostack, preserves_flags)
+        )
+    };
+}
+
+/// Clear the OS lock and enable debug exceptions at EL1, but leave `PSTATE.D` as it is.
+fn unlock_kernel_debug() {
+    unsafe {
+        asm!(
+            "msr OSLAR_EL1, xzr",
+            options(nomem, nostack, preserves_flags)
+        )
+    };
+
+    set_mdscr(mdscr() | MDSCR_EL1_KDE | MDSCR_EL1_MDE);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// The number of hardware breakpoints, `ID_AA64DFR0_EL1.BRPs + 1`.
+pub fn num_hw_breakpoints() -> usize {
+    ((dfr0() >> 12) & 0xF) as usize + 1
+}
+
+/// The number of watchpoints, `ID_AA64DFR0_EL1.WRPs + 1`.
+pub fn num_watchpoints() -> usize {
+    ((dfr0() >> 20) & 0xF) as usize + 1
+}
+
+/// Enable monitor debug exceptions at EL1 on the executing core.
+///
+/// # Safety
+///
+/// - Changes the HW state of the executing core.
+pub unsafe fn enable_monitor_debug() {
+    unlock_kernel_debug();
+
+    asm!("msr DAIFClr, #0b1000", options(nomem, nostack));
+}
+
+/// Arrange for the return to `e` to execute exactly one instruction, and then take a software step
+/// exception. See the module docs.
+pub fn single_step(e: &mut ExceptionContext) {
+    unlock_kernel_debug();
+    set_mdscr(mdscr() | MDSCR_EL1_SS);
+
+    // Debug exceptions must be unmasked after the return for the step to be taken.
+    e.set_spsr((e.spsr() | SPSR_SS) & !SPSR_D);
+}
+
+/// Stop stepping, so that the return to `e` runs on normally.
+pub fn end_single_step(e: &mut ExceptionContext) {
+    set_mdscr(mdscr() & !MDSCR_EL1_SS);
+
+    e.set_spsr(e.spsr() & !SPSR_SS);
+}
+
+/// Program breakpoint `slot` to match an instruction fetch from `addr`, or disable it with `None`.
+///
+/// # Safety
+///
+/// - `slot` must be below `num_hw_breakpoints()`, and `addr` 4 byte aligned.
+pub unsafe fn program_hw_breakpoint(slot: usize, addr: Option<usize>) {
+    write_indexed!("DBGBCR", slot, 0u64);
+
+    if let Some(addr) = addr {
+        write_indexed!("DBGBVR", slot, addr as u64);
+        write_indexed!("DBGBCR", slot, BCR_BAS_A64 | CTRL_ENABLED_EL1_EL0);
+    }
+
+    isb();
+}
+
+/// Program watchpoint `slot` to match `access`es to the `size` bytes at `addr`, or disable it with
+/// `None`.
+///
+/// # Safety
+///
+/// - `slot` must be below `num_watchpoints()`.
+/// - The watched bytes must be within an 8 byte aligned doubleword.
+pub unsafe fn program_watchpoint(slot: usize, watch: Option<(usize, WatchAccess, usize)>) {
+    write_indexed!("DBGWCR", slot, 0u64);
+
+    if let Some((addr, access, size)) = watch {
+        let lsc: u64 = match access {
+            WatchAccess::Load => 0b01,
+            WatchAccess::Store => 0b10,
+            WatchAccess::LoadStore => 0b11,
+        };
+        let bas = ((1u64 << size) - 1) << (addr % 8);
+
+        write_indexed!("DBGWVR", slot, (addr & !0b111) as u64);
+        write_indexed!(
+            "DBGWCR",
+            slot,
+            (bas << WCR_BAS_SHIFT) | (lsc << WCR_LSC_SHIFT) | CTRL_ENABLED_EL1_EL0
+        );
+    }
+
+    isb();
+}
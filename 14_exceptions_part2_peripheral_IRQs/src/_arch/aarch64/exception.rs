@@ -4,8 +4,16 @@
 
 //! Architectural synchronous and asynchronous exception handling.
 
-use crate::{bsp, exception};
-use core::fmt;
+use crate::{
+    bsp, exception, percpu,
+    percpu::{CacheLinePadded, PerCpu},
+    sched, synchronization,
+    synchronization::InitStateLock,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
 use cortex_a::{barrier, regs::*};
 use register::InMemoryRegister;
 
@@ -16,13 +24,54 @@ global_asm!(include_str!("exception.S"));
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
+const NUM_SYNC_HANDLERS: usize = 8;
+
+// SCTLR_EL1 bits that control trapping of WFI and WFE at EL0. A "0" traps the instruction to EL1.
+const SCTLR_EL1_NTWI: u64 = 1 << 16;
+const SCTLR_EL1_NTWE: u64 = 1 << 18;
+
 /// Wrapper struct for memory copy of SPSR_EL1.
 #[repr(transparent)]
 struct SpsrEL1(InMemoryRegister<u32, SPSR_EL1::Register>);
 
+/// Wrapper struct for pretty printing ESR_EL1.
+struct EsrEL1;
+
+// Exception classes, as per ARMv8-A Architecture Reference Manual section D13.2.37.
+mod ec {
+    pub const UNKNOWN: u8 = 0x00;
+    pub const WFI_WFE: u8 = 0x01;
+    pub const SVC_AARCH64: u8 = 0x15;
+    pub const HVC_AARCH64: u8 = 0x16;
+    pub const SMC_AARCH64: u8 = 0x17;
+    pub const TRAPPED_MSR_MRS: u8 = 0x18;
+    pub const INSTRUCTION_ABORT_LOWER_EL: u8 = 0x20;
+    pub const INSTRUCTION_ABORT_CURRENT_EL: u8 = 0x21;
+    pub const PC_ALIGNMENT: u8 = 0x22;
+    pub const DATA_ABORT_LOWER_EL: u8 = 0x24;
+    pub const DATA_ABORT_CURRENT_EL: u8 = 0x25;
+    pub const SP_ALIGNMENT: u8 = 0x26;
+    pub const SERROR: u8 = 0x2F;
+    pub const BREAKPOINT_LOWER_EL: u8 = 0x30;
+    pub const BREAKPOINT_CURRENT_EL: u8 = 0x31;
+    pub const SOFTWARE_STEP_LOWER_EL: u8 = 0x32;
+    pub const SOFTWARE_STEP_CURRENT_EL: u8 = 0x33;
+    pub const WATCHPOINT_LOWER_EL: u8 = 0x34;
+    pub const WATCHPOINT_CURRENT_EL: u8 = 0x35;
+    pub const BRK_AARCH64: u8 = 0x3C;
+}
+
+const EC_SHIFT: u64 = 26;
+const EC_MASK: u64 = 0x3F;
+const ISS_MASK: u64 = 0x1FF_FFFF;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
 /// The exception context as it is stored on the stack on exception entry.
 #[repr(C)]
-struct ExceptionContext {
+pub struct ExceptionContext {
     /// General Purpose Registers.
     gpr: [u64; 30],
 
@@ -36,12 +85,154 @@ struct ExceptionContext {
     spsr_el1: SpsrEL1,
 }
 
-/// Wrapper struct for pretty printing ESR_EL1.
-struct EsrEL1;
+/// The fault status of an abort, decoded from the DFSC or IFSC field.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum FaultStatus {
+    AddressSize { level: u8 },
+    Translation { level: u8 },
+    AccessFlag { level: u8 },
+    Permission { level: u8 },
+    SynchronousExternal,
+    Alignment,
+    TlbConflict,
+    Other(u8),
+}
+
+/// The access that caused a data abort. Only reported by the HW for single register loads and
+/// stores.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct DataAccess {
+    /// The access size in bytes.
+    pub size: u8,
+
+    /// The general purpose register that was loaded or stored.
+    pub reg: u8,
+
+    /// Whether a load sign-extends the value.
+    pub sign_extend: bool,
+}
+
+/// The decoded exception syndrome of a synchronous exception or an SError, as reported in ESR_EL1.
+///
+/// Exception classes that the kernel does not decode yet are reported as `Other`, along with the
+/// raw instruction specific syndrome (ISS).
+#[allow(missing_docs)]
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Syndrome {
+    /// Undefined instructions and other reasons without a dedicated class.
+    Unknown,
+
+    /// A trapped `WFI` or `WFE`.
+    WfiWfe { is_wfe: bool },
+
+    /// `SVC` executed in AArch64 state.
+    SvcAArch64 { imm16: u16 },
+
+    /// `HVC` executed in AArch64 state.
+    HvcAArch64 { imm16: u16 },
+
+    /// `SMC` executed in AArch64 state.
+    SmcAArch64 { imm16: u16 },
+
+    /// A trapped `MSR` or `MRS` of a system register.
+    TrappedMsrMrs {
+        op0: u8,
+        op1: u8,
+        crn: u8,
+        crm: u8,
+        op2: u8,
+        reg: u8,
+        is_read: bool,
+    },
+
+    /// An instruction fetch that faulted.
+    InstructionAbort {
+        from_lower_el: bool,
+        status: FaultStatus,
+    },
+
+    /// A misaligned PC.
+    PcAlignment,
+
+    /// A load or store that faulted.
+    DataAbort {
+        from_lower_el: bool,
+        is_write: bool,
+        status: FaultStatus,
+
+        /// Whether FAR_EL1 holds the faulting address.
+        far_valid: bool,
+        access: Option<DataAccess>,
+    },
+
+    /// A misaligned SP.
+    SpAlignment,
+
+    /// An SError interrupt.
+    SError,
+
+    /// An instruction fetch matched a hardware breakpoint, see `debug::set_hw_breakpoint()`.
+    HwBreakpoint { from_lower_el: bool },
+
+    /// A data access matched a watchpoint, see `debug::set_watchpoint()`. FAR_EL1 holds the
+    /// accessed address.
+    Watchpoint { from_lower_el: bool, is_write: bool },
+
+    /// A software step completed, see `MDSCR_EL1.SS`.
+    SoftwareStep { from_lower_el: bool },
+
+    /// `BRK` executed in AArch64 state.
+    Brk { comment: u16 },
+
+    /// Any other exception class.
+    Other { ec: u8, iss: u32 },
+}
+
+/// A handler for synchronous exceptions taken from the current EL.
+///
+/// Returns `true` if the exception was handled and execution shall continue at the (possibly
+/// modified) ELR. Returns `false` to pass the exception on to the next handler.
+pub type SyncExceptionHandler = fn(&mut ExceptionContext) -> bool;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static SYNC_HANDLERS: InitStateLock<[Option<SyncExceptionHandler>; NUM_SYNC_HANDLERS]> =
+    InitStateLock::new([None; NUM_SYNC_HANDLERS]);
+
+/// The PC that the IRQ currently serviced on a core interrupted. Zero outside of IRQ context.
+static INTERRUPTED_PC: PerCpu<AtomicU64, { bsp::cpu::NUM_CORES }> = PerCpu::new([
+    CacheLinePadded::new(AtomicU64::new(0)),
+    CacheLinePadded::new(AtomicU64::new(0)),
+    CacheLinePadded::new(AtomicU64::new(0)),
+    CacheLinePadded::new(AtomicU64::new(0)),
+]);
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
+use synchronization::interface::ReadWriteEx;
+
+/// Offer the exception to the registered handlers, in registration order.
+fn call_sync_handlers(e: &mut ExceptionContext) -> bool {
+    let mut r = &SYNC_HANDLERS;
+    r.read(|handlers| handlers.iter().flatten().any(|handler| handler(e)))
+}
+
+/// Dispatch an `svc` to the registered system call handler and place the result in `x0`.
+///
+/// ELR already points past the `svc`, so the caller continues with the next instruction.
+fn handle_svc(e: &mut ExceptionContext, num: u16) {
+    let mut args = [0; 8];
+    for (i, arg) in args.iter_mut().enumerate() {
+        *arg = e.gpr(i);
+    }
+
+    let ret = exception::syscall::dispatch(num, &args);
+    e.set_gpr(0, ret);
+}
 
 /// Print verbose information about the exception and the panic.
 fn default_exception_handler(e: &ExceptionContext) {
@@ -60,14 +251,17 @@ fn default_exception_handler(e: &ExceptionContext) {
 // Current, EL0
 //------------------------------------------------------------------------------
 
+// Taken when the kernel runs on SP_EL0, see `cpu::use_sp_el0()`. The handlers already run on
+// SP_EL1, so these are the same as for SP_ELx.
+
 #[no_mangle]
 unsafe extern "C" fn current_el0_synchronous(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    current_elx_synchronous(e);
 }
 
 #[no_mangle]
 unsafe extern "C" fn current_el0_irq(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    current_elx_irq(e);
 }
 
 #[no_mangle]
@@ -81,15 +275,28 @@ unsafe extern "C" fn current_el0_serror(e: &mut ExceptionContext) {
 
 #[no_mangle]
 unsafe extern "C" fn current_elx_synchronous(e: &mut ExceptionContext) {
+    if call_sync_handlers(e) {
+        return;
+    }
+
     default_exception_handler(e);
 }
 
 #[no_mangle]
-unsafe extern "C" fn current_elx_irq(_e: &mut ExceptionContext) {
+unsafe extern "C" fn current_elx_irq(e: &mut ExceptionContext) {
     use exception::asynchronous::interface::IRQManager;
 
+    let interrupted_pc = INTERRUPTED_PC.current();
+    interrupted_pc.store(e.elr(), Ordering::Relaxed);
+    exception::irq_enter();
+
     let token = &exception::asynchronous::IRQContext::new();
     bsp::exception::asynchronous::irq_manager().handle_pending_irqs(token);
+
+    exception::irq_exit();
+    interrupted_pc.store(0, Ordering::Relaxed);
+
+    percpu::stats().inc_irqs_serviced();
 }
 
 #[no_mangle]
@@ -103,7 +310,17 @@ unsafe extern "C" fn current_elx_serror(e: &mut ExceptionContext) {
 
 #[no_mangle]
 unsafe extern "C" fn lower_aarch64_synchronous(e: &mut ExceptionContext) {
-    default_exception_handler(e);
+    match Syndrome::current() {
+        Syndrome::SvcAArch64 { imm16 } => handle_svc(e, imm16),
+
+        // Trapped WFI or WFE, see `trap_el0_wfx()`. Instead of sleeping the core, give it to the
+        // next kernel task. ELR points to the trapped instruction, so skip it.
+        Syndrome::WfiWfe { .. } => {
+            e.skip_instruction();
+            sched::yield_now();
+        }
+        _ => default_exception_handler(e),
+    }
 }
 
 #[no_mangle]
@@ -152,11 +369,7 @@ impl fmt::Display for EsrEL1 {
         write!(f, "      Exception Class         (EC) : {:#x}", esr_el1.read(ESR_EL1::EC))?;
 
         // Exception class, translation.
-        let ec_translation = match esr_el1.read_as_enum(ESR_EL1::EC) {
-            Some(ESR_EL1::EC::Value::DataAbortCurrentEL) => "Data Abort, current EL",
-            _ => "N/A",
-        };
-        writeln!(f, " - {}", ec_translation)?;
+        writeln!(f, " - {}", Syndrome::from_esr(esr_el1.get() as u64))?;
 
         // Raw print of instruction specific syndrome.
         write!(f, "      Instr Specific Syndrome (ISS): {:#x}", esr_el1.read(ESR_EL1::ISS))?;
@@ -165,6 +378,104 @@ impl fmt::Display for EsrEL1 {
     }
 }
 
+impl fmt::Display for FaultStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FaultStatus::AddressSize { level } => write!(f, "Address size fault, level {}", level),
+            FaultStatus::Translation { level } => write!(f, "Translation fault, level {}", level),
+            FaultStatus::AccessFlag { level } => write!(f, "Access flag fault, level {}", level),
+            FaultStatus::Permission { level } => write!(f, "Permission fault, level {}", level),
+            FaultStatus::SynchronousExternal => write!(f, "Synchronous external abort"),
+            FaultStatus::Alignment => write!(f, "Alignment fault"),
+            FaultStatus::TlbConflict => write!(f, "TLB conflict abort"),
+            FaultStatus::Other(fsc) => write!(f, "Fault status {:#x}", fsc),
+        }
+    }
+}
+
+/// Human readable exception syndrome.
+impl fmt::Display for Syndrome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let to_el_str = |from_lower_el| {
+            if from_lower_el {
+                "lower EL"
+            } else {
+                "current EL"
+            }
+        };
+
+        match *self {
+            Syndrome::Unknown => write!(f, "Unknown reason"),
+            Syndrome::WfiWfe { is_wfe } => {
+                write!(f, "Trapped {}", if is_wfe { "WFE" } else { "WFI" })
+            }
+            Syndrome::SvcAArch64 { imm16 } => write!(f, "SVC #{:#x}", imm16),
+            Syndrome::HvcAArch64 { imm16 } => write!(f, "HVC #{:#x}", imm16),
+            Syndrome::SmcAArch64 { imm16 } => write!(f, "SMC #{:#x}", imm16),
+            Syndrome::TrappedMsrMrs {
+                op0,
+                op1,
+                crn,
+                crm,
+                op2,
+                reg,
+                is_read,
+            } => write!(
+                f,
+                "Trapped {} S{}_{}_C{}_C{}_{}, x{}",
+                if is_read { "MRS" } else { "MSR" },
+                op0,
+                op1,
+                crn,
+                crm,
+                op2,
+                reg
+            ),
+            Syndrome::InstructionAbort {
+                from_lower_el,
+                status,
+            } => write!(
+                f,
+                "Instruction Abort, {}: {}",
+                to_el_str(from_lower_el),
+                status
+            ),
+            Syndrome::PcAlignment => write!(f, "PC alignment fault"),
+            Syndrome::DataAbort {
+                from_lower_el,
+                is_write,
+                status,
+                ..
+            } => write!(
+                f,
+                "Data Abort, {}: {} on {}",
+                to_el_str(from_lower_el),
+                status,
+                if is_write { "write" } else { "read" }
+            ),
+            Syndrome::SpAlignment => write!(f, "SP alignment fault"),
+            Syndrome::SError => write!(f, "SError interrupt"),
+            Syndrome::HwBreakpoint { from_lower_el } => {
+                write!(f, "Hardware breakpoint, {}", to_el_str(from_lower_el))
+            }
+            Syndrome::Watchpoint {
+                from_lower_el,
+                is_write,
+            } => write!(
+                f,
+                "Watchpoint, {}: {}",
+                to_el_str(from_lower_el),
+                if is_write { "write" } else { "read" }
+            ),
+            Syndrome::SoftwareStep { from_lower_el } => {
+                write!(f, "Software step, {}", to_el_str(from_lower_el))
+            }
+            Syndrome::Brk { comment } => write!(f, "BRK #{:#x}", comment),
+            Syndrome::Other { ec, .. } => write!(f, "Exception class {:#x}", ec),
+        }
+    }
+}
+
 /// Human readable SPSR_EL1.
 #[rustfmt::skip]
 impl fmt::Display for SpsrEL1 {
@@ -228,6 +539,228 @@ impl fmt::Display for ExceptionContext {
 //--------------------------------------------------------------------------------------------------
 use crate::exception::PrivilegeLevel;
 
+/// The PC that the IRQ being serviced on the executing core interrupted, as saved in ELR.
+///
+/// Returns `None` outside of IRQ context.
+pub fn interrupted_pc() -> Option<u64> {
+    match INTERRUPTED_PC.current().load(Ordering::Relaxed) {
+        0 => None,
+        pc => Some(pc),
+    }
+}
+
+impl ExceptionContext {
+    /// The width of an AArch64 instruction.
+    const INSTRUCTION_SIZE: u64 = 4;
+
+    /// The size of the context on the stack, see `CALL_WITH_CONTEXT` in `exception.S`.
+    const FRAME_SIZE: u64 = 16 * 17;
+
+    /// The address execution continues at when the handler returns.
+    pub fn elr(&self) -> u64 {
+        self.elr_el1
+    }
+
+    /// Set the address execution continues at when the handler returns.
+    pub fn set_elr(&mut self, elr: u64) {
+        self.elr_el1 = elr;
+    }
+
+    /// The saved program status, which is restored when the handler returns.
+    pub fn spsr(&self) -> u32 {
+        self.spsr_el1.0.get()
+    }
+
+    /// Set the program status that is restored when the handler returns.
+    pub fn set_spsr(&mut self, spsr: u32) {
+        self.spsr_el1.0.set(spsr)
+    }
+
+    /// The stack pointer of the interrupted code.
+    ///
+    /// If it ran on `SP_EL1`, the context was pushed right below its stack pointer. Otherwise, the
+    /// handler did not touch `SP_EL0`.
+    pub fn sp(&self) -> u64 {
+        if self.spsr_el1.0.matches_all(SPSR_EL1::M::EL1h) {
+            self as *const _ as u64 + Self::FRAME_SIZE
+        } else {
+            SP_EL0.get()
+        }
+    }
+
+    /// Read general purpose register `x<reg>`. `31` reads as zero (XZR).
+    pub fn gpr(&self, reg: usize) -> u64 {
+        match reg {
+            0..=29 => self.gpr[reg],
+            30 => self.lr,
+            _ => 0,
+        }
+    }
+
+    /// Write general purpose register `x<reg>`. Writes to `31` (XZR) are ignored.
+    pub fn set_gpr(&mut self, reg: usize, value: u64) {
+        match reg {
+            0..=29 => self.gpr[reg] = value,
+            30 => self.lr = value,
+            _ => (),
+        }
+    }
+
+    /// Fetch the instruction that ELR points to.
+    ///
+    /// # Safety
+    ///
+    /// - ELR must point to mapped, readable memory. This holds for exceptions taken from kernel
+    ///   code.
+    pub unsafe fn instruction(&self) -> u32 {
+        core::ptr::read_volatile(self.elr_el1 as *const u32)
+    }
+
+    /// Continue execution after the instruction that ELR points to.
+    ///
+    /// For most synchronous exceptions, e.g. aborts or undefined instructions, ELR points to the
+    /// instruction that caused it, so returning without skipping would execute it again. Exceptions
+    /// that are generated by `SVC`, `HVC` and `SMC` already point past the instruction and must not
+    /// be skipped.
+    ///
+    /// Only valid for the fixed 32-bit wide AArch64 instructions. Skipping a T32 instruction of an
+    /// AArch32 context would need the instruction length from ESR_EL1.IL.
+    pub fn skip_instruction(&mut self) {
+        self.elr_el1 += Self::INSTRUCTION_SIZE;
+    }
+}
+
+impl FaultStatus {
+    fn from_fsc(fsc: u8) -> Self {
+        let level = fsc & 0b11;
+
+        match fsc {
+            0b00_0000..=0b00_0011 => FaultStatus::AddressSize { level },
+            0b00_0100..=0b00_0111 => FaultStatus::Translation { level },
+            0b00_1001..=0b00_1011 => FaultStatus::AccessFlag { level },
+            0b00_1101..=0b00_1111 => FaultStatus::Permission { level },
+            0b01_0000 => FaultStatus::SynchronousExternal,
+            0b10_0001 => FaultStatus::Alignment,
+            0b11_0000 => FaultStatus::TlbConflict,
+            _ => FaultStatus::Other(fsc),
+        }
+    }
+}
+
+impl Syndrome {
+    /// Decode the syndrome of the exception that is currently being handled.
+    pub fn current() -> Self {
+        Self::from_esr(ESR_EL1.get() as u64)
+    }
+
+    /// Decode a raw ESR_EL1 value.
+    pub fn from_esr(esr: u64) -> Self {
+        let ec = ((esr >> EC_SHIFT) & EC_MASK) as u8;
+        let iss = (esr & ISS_MASK) as u32;
+        let field = |shift: u32, bits: u32| ((iss >> shift) & ((1 << bits) - 1)) as u8;
+        let imm16 = iss as u16;
+
+        match ec {
+            ec::UNKNOWN => Syndrome::Unknown,
+            ec::WFI_WFE => Syndrome::WfiWfe {
+                is_wfe: field(0, 1) == 1,
+            },
+            ec::SVC_AARCH64 => Syndrome::SvcAArch64 { imm16 },
+            ec::HVC_AARCH64 => Syndrome::HvcAArch64 { imm16 },
+            ec::SMC_AARCH64 => Syndrome::SmcAArch64 { imm16 },
+            ec::TRAPPED_MSR_MRS => Syndrome::TrappedMsrMrs {
+                op0: field(20, 2),
+                op1: field(14, 3),
+                crn: field(10, 4),
+                crm: field(1, 4),
+                op2: field(17, 3),
+                reg: field(5, 5),
+                is_read: field(0, 1) == 1,
+            },
+            ec::INSTRUCTION_ABORT_LOWER_EL | ec::INSTRUCTION_ABORT_CURRENT_EL => {
+                Syndrome::InstructionAbort {
+                    from_lower_el: ec == ec::INSTRUCTION_ABORT_LOWER_EL,
+                    status: FaultStatus::from_fsc(field(0, 6)),
+                }
+            }
+            ec::PC_ALIGNMENT => Syndrome::PcAlignment,
+            ec::DATA_ABORT_LOWER_EL | ec::DATA_ABORT_CURRENT_EL => {
+                // ISV: The access fields are valid.
+                let access = if field(24, 1) == 1 {
+                    Some(DataAccess {
+                        size: 1 << field(22, 2),
+                        reg: field(16, 5),
+                        sign_extend: field(21, 1) == 1,
+                    })
+                } else {
+                    None
+                };
+
+                Syndrome::DataAbort {
+                    from_lower_el: ec == ec::DATA_ABORT_LOWER_EL,
+                    is_write: field(6, 1) == 1,
+                    status: FaultStatus::from_fsc(field(0, 6)),
+                    far_valid: field(10, 1) == 0,
+                    access,
+                }
+            }
+            ec::SP_ALIGNMENT => Syndrome::SpAlignment,
+            ec::SERROR => Syndrome::SError,
+            ec::BREAKPOINT_LOWER_EL | ec::BREAKPOINT_CURRENT_EL => Syndrome::HwBreakpoint {
+                from_lower_el: ec == ec::BREAKPOINT_LOWER_EL,
+            },
+            ec::WATCHPOINT_LOWER_EL | ec::WATCHPOINT_CURRENT_EL => Syndrome::Watchpoint {
+                from_lower_el: ec == ec::WATCHPOINT_LOWER_EL,
+                is_write: field(6, 1) == 1,
+            },
+            ec::SOFTWARE_STEP_LOWER_EL | ec::SOFTWARE_STEP_CURRENT_EL => Syndrome::SoftwareStep {
+                from_lower_el: ec == ec::SOFTWARE_STEP_LOWER_EL,
+            },
+            ec::BRK_AARCH64 => Syndrome::Brk { comment: imm16 },
+            _ => Syndrome::Other { ec, iss },
+        }
+    }
+}
+
+/// Register a handler for synchronous exceptions taken from the current EL.
+///
+/// Handlers are tried in registration order before the default handler, which panics.
+pub fn register_sync_handler(handler: SyncExceptionHandler) -> Result<(), &'static str> {
+    let mut r = &SYNC_HANDLERS;
+    r.write(|handlers| match handlers.iter_mut().find(|h| h.is_none()) {
+        None => Err("No sync exception handler slot left"),
+        Some(slot) => {
+            *slot = Some(handler);
+            Ok(())
+        }
+    })
+}
+
+/// A `SyncExceptionHandler` that emulates reads of unimplemented system registers.
+///
+/// Reading a system register that the core does not implement is an undefined instruction. With
+/// this handler registered, such an `MRS` reads as zero instead, which is useful for optional
+/// features that are probed through ID or IMPLEMENTATION DEFINED registers.
+pub fn emulate_sysreg_read_as_zero(e: &mut ExceptionContext) -> bool {
+    const MRS_MASK: u32 = 0xFFF0_0000;
+    const MRS: u32 = 0xD530_0000;
+    const RT_MASK: u32 = 0x1F;
+
+    if Syndrome::current() != Syndrome::Unknown {
+        return false;
+    }
+
+    let instruction = unsafe { e.instruction() };
+    if (instruction & MRS_MASK) != MRS {
+        return false;
+    }
+
+    e.set_gpr((instruction & RT_MASK) as usize, 0);
+    e.skip_instruction();
+
+    true
+}
+
 /// The processing element's current privilege level.
 pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
     let el = CurrentEL.read_as_enum(CurrentEL::EL);
@@ -239,6 +772,51 @@ pub fn current_privilege_level() -> (PrivilegeLevel, &'static str) {
     }
 }
 
+/// Control whether `WFI` and `WFE` at EL0 trap to the kernel.
+///
+/// `SCTLR_EL1.nTWI` (bit 16) and `SCTLR_EL1.nTWE` (bit 18) must be `0` to trap the respective
+/// instruction. Both reset to an UNKNOWN value, so set them explicitly before running EL0 code.
+///
+/// With `true`, a trapped `WFI` or `WFE` does not sleep the core. Instead, the kernel yields to the
+/// next runnable task, see `sched::yield_now()`, and the EL0 program continues after the
+/// instruction once the scheduler gets back to the task that entered EL0. This lets a cooperative
+/// scheduler reclaim cores from idling EL0 programs. Be aware that the other tasks run with IRQs
+/// masked in the meantime, as IRQs are masked during exception handling.
+///
+/// With `false`, both instructions execute at EL0 as usual.
+pub fn trap_el0_wfx(enable: bool) {
+    let sctlr = SCTLR_EL1.get();
+    let bits = SCTLR_EL1_NTWI | SCTLR_EL1_NTWE;
+
+    SCTLR_EL1.set(if enable { sctlr & !bits } else { sctlr | bits });
+    barrier::isb(barrier::SY);
+}
+
+/// Drop to EL0 and continue execution at `entry`, with `stack` as the stack pointer.
+///
+/// The unprivileged code gets back into the kernel through system calls only, see
+/// [`crate::exception::syscall`]. IRQs stay masked at EL0, because IRQs taken from a lower EL are
+/// not handled yet.
+///
+/// # Safety
+///
+/// - `entry` and the stack must be mapped with EL0 access, e.g. in `bsp::memory::user_range()`.
+/// - The kernel must run on `SP_EL1`, because `SP_EL0` is not accessible otherwise. See
+///   `cpu::use_sp_el0()`.
+pub unsafe fn enter_el0(entry: usize, stack: usize) -> ! {
+    SPSR_EL1.write(
+        SPSR_EL1::D::Masked
+            + SPSR_EL1::A::Masked
+            + SPSR_EL1::I::Masked
+            + SPSR_EL1::F::Masked
+            + SPSR_EL1::M::EL0t,
+    );
+    ELR_EL1.set(entry as u64);
+    SP_EL0.set(stack as u64);
+
+    cortex_a::asm::eret()
+}
+
 /// Init exception handling by setting the exception vector base address register.
 ///
 /// # Safety
@@ -259,3 +837,85 @@ pub unsafe fn handling_init() {
     // Force VBAR update to complete before next instruction.
     barrier::isb(barrier::SY);
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Known ESR values must decode to their classes, with the ISS fields parsed.
+    #[kernel_test]
+    fn syndrome_decodes_known_esr_values() {
+        // `ldr x1, [x0]` to an unmapped address, level 3 translation fault.
+        assert_eq!(
+            Syndrome::from_esr(0x9600_0007),
+            Syndrome::DataAbort {
+                from_lower_el: false,
+                is_write: false,
+                status: FaultStatus::Translation { level: 3 },
+                far_valid: true,
+                access: None,
+            }
+        );
+
+        // `str w2, [x0]` with a valid access syndrome, permission fault.
+        assert_eq!(
+            Syndrome::from_esr(0x9782_004F),
+            Syndrome::DataAbort {
+                from_lower_el: false,
+                is_write: true,
+                status: FaultStatus::Permission { level: 3 },
+                far_valid: true,
+                access: Some(DataAccess {
+                    size: 4,
+                    reg: 2,
+                    sign_extend: false,
+                }),
+            }
+        );
+
+        assert_eq!(
+            Syndrome::from_esr(0x8600_0006),
+            Syndrome::InstructionAbort {
+                from_lower_el: false,
+                status: FaultStatus::Translation { level: 2 },
+            }
+        );
+
+        assert_eq!(
+            Syndrome::from_esr(0x5600_0042),
+            Syndrome::SvcAArch64 { imm16: 0x42 }
+        );
+        assert_eq!(
+            Syndrome::from_esr(0xF200_0043),
+            Syndrome::Brk { comment: 0x43 }
+        );
+        assert_eq!(Syndrome::from_esr(0x0200_0000), Syndrome::Unknown);
+
+        // `mrs x3, CNTFRQ_EL0`, i.e. S3_3_C14_C0_0.
+        assert_eq!(
+            Syndrome::from_esr(0x6230_F861),
+            Syndrome::TrappedMsrMrs {
+                op0: 3,
+                op1: 3,
+                crn: 14,
+                crm: 0,
+                op2: 0,
+                reg: 3,
+                is_read: true,
+            }
+        );
+
+        assert_eq!(
+            Syndrome::from_esr(0x1C00_1234),
+            Syndrome::Other {
+                ec: 0x07,
+                iss: 0x1234
+            }
+        );
+    }
+}
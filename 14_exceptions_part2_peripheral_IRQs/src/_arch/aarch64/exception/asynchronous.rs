@@ -4,12 +4,71 @@
 
 //! Architectural asynchronous exception handling.
 
+#[cfg(feature = "irq_off_trace")]
+use crate::exception::irq_off_trace;
 use cortex_a::regs::*;
 
+// A setjmp/longjmp pair. Like for a context switch, only the callee-saved registers x19-x30 and the
+// stack pointer need to be preserved, because `__call_recoverable` is always called as a function.
+global_asm!(
+    "
+.section .text
+
+// Save the context to the `RecoveryPoint` pointed to by x0, then call the function in x1 with the
+// argument in x2. Returns 0 once that function returns.
+.global __call_recoverable
+__call_recoverable:
+    mov    x9,  sp
+    stp    x19, x20, [x0, #16 * 0]
+    stp    x21, x22, [x0, #16 * 1]
+    stp    x23, x24, [x0, #16 * 2]
+    stp    x25, x26, [x0, #16 * 3]
+    stp    x27, x28, [x0, #16 * 4]
+    stp    x29, x30, [x0, #16 * 5]
+    str    x9,       [x0, #16 * 6]
+
+    // x19 survives the call, and its own value is saved above.
+    mov    x19, x0
+    mov    x0,  x2
+    blr    x1
+
+    mov    x9,  x19
+    ldr    x19,      [x9, #16 * 0]
+    ldr    x30,      [x9, #16 * 5 + 8]
+    mov    x0,  #0
+    ret
+
+// Restore the context from the `RecoveryPoint` pointed to by x0, so that the `__call_recoverable`
+// that saved it returns 1.
+.global __recover
+__recover:
+    ldp    x19, x20, [x0, #16 * 0]
+    ldp    x21, x22, [x0, #16 * 1]
+    ldp    x23, x24, [x0, #16 * 2]
+    ldp    x25, x26, [x0, #16 * 3]
+    ldp    x27, x28, [x0, #16 * 4]
+    ldp    x29, x30, [x0, #16 * 5]
+    ldr    x9,       [x0, #16 * 6]
+    mov    sp,  x9
+
+    mov    x0,  #1
+    ret
+"
+);
+
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
 //--------------------------------------------------------------------------------------------------
 
+extern "C" {
+    fn __call_recoverable(
+        point: *mut RecoveryPoint,
+        f: extern "C" fn(*mut u8),
+        arg: *mut u8,
+    ) -> u64;
+    fn __recover(point: *const RecoveryPoint) -> !;
+}
+
 mod daif_bits {
     pub const IRQ: u8 = 0b0010;
 }
@@ -23,6 +82,20 @@ struct SError;
 struct IRQ;
 struct FIQ;
 
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The register state to resume at with `recover()`.
+#[repr(C)]
+pub struct RecoveryPoint {
+    /// x19 - x30. x30 is the link register, i.e. where `call_recoverable()` returns to.
+    gpr: [u64; 12],
+
+    /// The stack pointer.
+    sp: u64,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Private Code
 //--------------------------------------------------------------------------------------------------
@@ -55,10 +128,48 @@ fn is_masked<T: DaifField>() -> bool {
     DAIF.is_set(T::daif_field())
 }
 
+/// Call the closure that `arg` points to.
+extern "C" fn call_closure<F: FnMut()>(arg: *mut u8) {
+    let f = unsafe { &mut *(arg as *mut F) };
+
+    f()
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl RecoveryPoint {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            gpr: [0; 12],
+            sp: 0,
+        }
+    }
+}
+
+/// Call `f`, and return `true` if it returned, or `false` if `recover()` was called with `point`
+/// meanwhile.
+///
+/// # Safety
+///
+/// - `point` must stay valid while `f` runs.
+/// - If `f` does not return, none of its state is dropped. Whatever it was in the middle of stays
+///   as it is.
+pub unsafe fn call_recoverable<F: FnMut()>(point: &mut RecoveryPoint, f: &mut F) -> bool {
+    __call_recoverable(point, call_closure::<F>, f as *mut F as *mut u8) == 0
+}
+
+/// Continue at the `call_recoverable()` that is running with `point`, making it return `false`.
+///
+/// # Safety
+///
+/// - Must be called from within the `f` of that `call_recoverable()`, on the same stack.
+pub unsafe fn recover(point: *const RecoveryPoint) -> ! {
+    __recover(point)
+}
+
 /// Returns whether IRQs are masked on the executing core.
 pub fn is_local_irq_masked() -> bool {
     !is_masked::<IRQ>()
@@ -77,6 +188,11 @@ pub fn is_local_irq_masked() -> bool {
 /// - Changes the HW state of the executing core.
 #[inline(always)]
 pub unsafe fn local_irq_unmask() {
+    #[cfg(feature = "irq_off_trace")]
+    if is_masked::<IRQ>() {
+        irq_off_trace::unmasked();
+    }
+
     #[rustfmt::skip]
     asm!(
         "msr DAIFClr, {arg}",
@@ -91,13 +207,22 @@ pub unsafe fn local_irq_unmask() {
 ///
 /// - Changes the HW state of the executing core.
 #[inline(always)]
+#[track_caller]
 pub unsafe fn local_irq_mask() {
+    #[cfg(feature = "irq_off_trace")]
+    let was_masked = is_masked::<IRQ>();
+
     #[rustfmt::skip]
     asm!(
         "msr DAIFSet, {arg}",
         arg = const daif_bits::IRQ,
         options(nomem, nostack, preserves_flags)
     );
+
+    #[cfg(feature = "irq_off_trace")]
+    if !was_masked {
+        irq_off_trace::masked(core::panic::Location::caller());
+    }
 }
 
 /// Mask IRQs on the executing core and return the previously saved interrupt mask bits (DAIF).
@@ -106,6 +231,7 @@ pub unsafe fn local_irq_mask() {
 ///
 /// - Changes the HW state of the executing core.
 #[inline(always)]
+#[track_caller]
 pub unsafe fn local_irq_mask_save() -> u32 {
     let saved = DAIF.get();
     local_irq_mask();
@@ -120,8 +246,22 @@ pub unsafe fn local_irq_mask_save() -> u32 {
 /// - Changes the HW state of the executing core.
 /// - No sanity checks on the input.
 #[inline(always)]
+#[track_caller]
 pub unsafe fn local_irq_restore(saved: u32) {
+    #[cfg(feature = "irq_off_trace")]
+    let (was_masked, masks) = (is_masked::<IRQ>(), IRQ::daif_field().read(saved) != 0);
+
+    #[cfg(feature = "irq_off_trace")]
+    if was_masked && !masks {
+        irq_off_trace::unmasked();
+    }
+
     DAIF.set(saved);
+
+    #[cfg(feature = "irq_off_trace")]
+    if !was_masked && masks {
+        irq_off_trace::masked(core::panic::Location::caller());
+    }
 }
 
 /// Print the AArch64 exceptions status.
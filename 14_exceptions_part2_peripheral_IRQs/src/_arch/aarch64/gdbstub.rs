@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural debug support for the GDB stub.
+//!
+//! # Registers
+//!
+//! GDB's AArch64 register numbers are `x0`-`x30` as `0`-`30`, `sp` as `31`, `pc` as `32` and the
+//! 32 bit `cpsr` as `33`. The FP/SIMD registers that follow are not saved on exception entry, and
+//! therefore not reported.
+
+use crate::{
+    exception::{ExceptionContext, Syndrome},
+    gdbstub::StopReason,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// `BRK #0`, which GDB expects as software breakpoint.
+pub const BREAKPOINT_INSTRUCTION: u32 = 0xD420_0000;
+
+/// The number of registers in GDB's `g` packet, see the module docs.
+pub const NUM_REGISTERS: usize = 34;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Why the exception that is currently being handled stops the debuggee, if it does.
+pub fn stop_reason() -> Option<StopReason> {
+    match Syndrome::current() {
+        Syndrome::Brk { comment: 0 } => Some(StopReason::Breakpoint),
+        Syndrome::SoftwareStep {
+            from_lower_el: false,
+        } => Some(StopReason::Step),
+        _ => None,
+    }
+}
+
+/// Read GDB register `num` and return it with its size in bytes.
+pub fn register(e: &ExceptionContext, num: usize) -> Option<(u64, usize)> {
+    match num {
+        0..=30 => Some((e.gpr(num), 8)),
+        31 => Some((e.sp(), 8)),
+        32 => Some((e.elr(), 8)),
+        33 => Some((e.spsr() as u64, 4)),
+        _ => None,
+    }
+}
+
+/// Write GDB register `num`. Fails for `sp`, which is not part of the saved context.
+pub fn set_register(e: &mut ExceptionContext, num: usize, value: u64) -> Result<(), &'static str> {
+    match num {
+        0..=30 => e.set_gpr(num, value),
+        32 => e.set_elr(value),
+        33 => e.set_spsr(value as u32),
+        _ => return Err("Register not writable"),
+    }
+
+    Ok(())
+}
+
+/// Stop in the debugger as if a breakpoint was hit.
+pub fn breakpoint() {
+    unsafe { asm!("brk #0", options(nomem, nostack)) };
+}
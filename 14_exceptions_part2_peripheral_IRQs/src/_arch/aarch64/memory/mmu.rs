@@ -5,10 +5,33 @@
 //! Memory Management Unit Driver.
 //!
 //! Static translation tables, compiled on boot; Everything 64 KiB granule.
+//!
+//! # ASIDs
+//!
+//! Each set of tables carries an 8 bit address space identifier (ASID), which is installed in
+//! `TTBR0_EL1[55:48]` along with the base address. `TCR_EL1.AS` selects 8 bit ASIDs, so the upper
+//! byte of the field stays zero.
+//!
+//! All page descriptors are non-global (`nG == 1`), so the TLB tags each entry with the ASID of the
+//! tables it was walked from. Entries of different tables can therefore coexist in the TLB, and
+//! switching tables does not invalidate anything. Instead, an ASID's entries are dropped when they
+//! can go stale: When the ASID is freed for reuse, when the allocator starts a new generation, and
+//! when its tables are repopulated. The kernel's own tables use ASID `0`. Use an [`AsidAllocator`]
+//! to hand out the others.
+//!
+//! # Table walk attributes
+//!
+//! `TCR_EL1.IRGN0`, `ORGN0` and `SH0` hold the inner cacheability, outer cacheability and
+//! shareability of the walks through the `TTBR0_EL1` tables. DRAM is mapped inner shareable, with
+//! the write-back or non-cacheable MAIR attributes, so walks of tables in DRAM must use the same.
+//! Tables in device memory are not supported.
 
-use super::{AccessPermissions, AttributeFields, MemAttributes};
-use crate::{bsp, memory};
-use core::convert;
+use super::{
+    AccessPermissions, AttributeFields, Inconsistencies, Inconsistency, MemAttributes,
+    TableWalkAttributes, WalkCacheability, WalkShareability,
+};
+use crate::{bsp, exception, memory};
+use core::{convert, ops::Range};
 use cortex_a::{barrier, regs::*};
 use register::register_bitfields;
 
@@ -37,6 +60,12 @@ register_bitfields! {u64,
 // A level 3 page descriptor, as per ARMv8-A Architecture Reference Manual Figure D5-17.
 register_bitfields! {u64,
     STAGE1_PAGE_DESCRIPTOR [
+        /// Unprivileged execute-never.
+        UXN      OFFSET(54) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
         /// Privileged execute-never.
         PXN      OFFSET(53) NUMBITS(1) [
             False = 0,
@@ -46,6 +75,12 @@ register_bitfields! {u64,
         /// Physical address of the next table descriptor (lvl2) or the page descriptor (lvl3).
         OUTPUT_ADDR_64KiB OFFSET(16) NUMBITS(32) [], // [47:16]
 
+        /// Not global. The TLB tags non-global entries with the current ASID.
+        NG       OFFSET(11) NUMBITS(1) [
+            False = 0,
+            True = 1
+        ],
+
         /// Access flag.
         AF       OFFSET(10) NUMBITS(1) [
             False = 0,
@@ -82,6 +117,7 @@ register_bitfields! {u64,
 }
 
 const SIXTYFOUR_KIB_SHIFT: usize = 16; //  log2(64 * 1024)
+const NUM_ASIDS: usize = 256;
 const FIVETWELVE_MIB_SHIFT: usize = 29; // log2(512 * 1024 * 1024)
 
 /// A table descriptor for 64 KiB aperture.
@@ -98,18 +134,6 @@ struct TableDescriptor(u64);
 #[repr(transparent)]
 struct PageDescriptor(u64);
 
-/// Big monolithic struct for storing the translation tables. Individual levels must be 64 KiB
-/// aligned, hence the "reverse" order of appearance.
-#[repr(C)]
-#[repr(align(65536))]
-struct TranslationTables<const N: usize> {
-    /// Page descriptors, covering 64 KiB windows per entry.
-    lvl3: [[PageDescriptor; 8192]; N],
-
-    /// Table descriptors, covering 512 MiB windows.
-    lvl2: [TableDescriptor; N],
-}
-
 /// Usually evaluates to 1 GiB for RPi3 and 4 GiB for RPi 4.
 const ENTRIES_512_MIB: usize = bsp::memory::mmu::addr_space_size() >> FIVETWELVE_MIB_SHIFT;
 
@@ -118,10 +142,7 @@ const ENTRIES_512_MIB: usize = bsp::memory::mmu::addr_space_size() >> FIVETWELVE
 /// # Safety
 ///
 /// - Supposed to land in `.bss`. Therefore, ensure that they boil down to all "0" entries.
-static mut TABLES: TranslationTables<{ ENTRIES_512_MIB }> = TranslationTables {
-    lvl3: [[PageDescriptor(0); 8192]; ENTRIES_512_MIB],
-    lvl2: [TableDescriptor(0); ENTRIES_512_MIB],
-};
+static mut TABLES: TranslationTable = TranslationTable::new(0);
 
 trait BaseAddr {
     fn base_addr_u64(&self) -> u64;
@@ -134,12 +155,57 @@ mod mair {
     pub const DEVICE: u64 = 0;
     pub const NORMAL: u64 = 1;
     pub const NORMAL_NON_CACHEABLE: u64 = 2;
+    pub const WRITE_COMBINING: u64 = 3;
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// The translation granule, i.e. the page size.
+pub const GRANULE_SIZE: usize = 1 << SIXTYFOUR_KIB_SHIFT;
+
+/// Big monolithic struct for storing the translation tables. Individual levels must be 64 KiB
+/// aligned, hence the "reverse" order of appearance.
+#[repr(C)]
+#[repr(align(65536))]
+pub struct TranslationTables<const N: usize> {
+    /// Page descriptors, covering 64 KiB windows per entry.
+    lvl3: [[PageDescriptor; 8192]; N],
+
+    /// Table descriptors, covering 512 MiB windows.
+    lvl2: [TableDescriptor; N],
+
+    /// The address space identifier that is installed along with the tables. Lives in the
+    /// alignment padding, so it does not grow the struct.
+    asid: u8,
+}
+
+/// Translation tables that span the board's whole address space.
+pub type TranslationTable = TranslationTables<{ ENTRIES_512_MIB }>;
+
+/// A saved value of the translation table base register, for restoring it later.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct TableBase(u64);
+
+/// An ASID that was handed out by an [`AsidAllocator`].
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Asid {
+    value: u8,
+    generation: u32,
+}
+
+/// Hands out the 8 bit ASIDs for [`TranslationTables::new()`].
+///
+/// ASID `0` belongs to the kernel's tables and is never handed out. Once all others are in use, the
+/// allocator invalidates the whole TLB and starts a new generation with all ASIDs free again. ASIDs
+/// of older generations are stale from then on, see [`AsidAllocator::is_current()`]. Their owners
+/// must allocate a new one before activating their tables again.
+pub struct AsidAllocator {
+    in_use: [u64; NUM_ASIDS / 64],
+    generation: u32,
+}
+
 /// Memory Management Unit type.
 pub struct MemoryManagementUnit;
 
@@ -163,6 +229,14 @@ impl<T, const N: usize> BaseAddr for [T; N] {
     }
 }
 
+impl TableBase {
+    const ASID_SHIFT: u64 = 48;
+
+    fn new(base_addr: u64, asid: u8) -> Self {
+        Self(base_addr | ((asid as u64) << Self::ASID_SHIFT))
+    }
+}
+
 impl convert::From<usize> for TableDescriptor {
     fn from(next_lvl_table_addr: usize) -> Self {
         let shifted = next_lvl_table_addr >> SIXTYFOUR_KIB_SHIFT;
@@ -194,12 +268,23 @@ impl convert::From<AttributeFields>
                 STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
                     + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::DEVICE)
             }
+            MemAttributes::WriteCombining => {
+                STAGE1_PAGE_DESCRIPTOR::SH::OuterShareable
+                    + STAGE1_PAGE_DESCRIPTOR::AttrIndx.val(mair::WRITE_COMBINING)
+            }
         };
 
-        // Access Permissions.
+        // Access Permissions. EL0 may only execute from memory that it can access.
         desc += match attribute_fields.acc_perms {
-            AccessPermissions::ReadOnly => STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1,
-            AccessPermissions::ReadWrite => STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1,
+            AccessPermissions::ReadOnly => {
+                STAGE1_PAGE_DESCRIPTOR::AP::RO_EL1 + STAGE1_PAGE_DESCRIPTOR::UXN::True
+            }
+            AccessPermissions::ReadWrite => {
+                STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1 + STAGE1_PAGE_DESCRIPTOR::UXN::True
+            }
+            AccessPermissions::ReadWriteUser => {
+                STAGE1_PAGE_DESCRIPTOR::AP::RW_EL1_EL0 + STAGE1_PAGE_DESCRIPTOR::UXN::False
+            }
         };
 
         // Execute Never.
@@ -218,6 +303,7 @@ impl PageDescriptor {
         let shifted = output_addr >> SIXTYFOUR_KIB_SHIFT;
         let val = (STAGE1_PAGE_DESCRIPTOR::VALID::True
             + STAGE1_PAGE_DESCRIPTOR::AF::True
+            + STAGE1_PAGE_DESCRIPTOR::NG::True
             + attribute_fields.into()
             + STAGE1_PAGE_DESCRIPTOR::TYPE::Table
             + STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.val(shifted as u64))
@@ -231,6 +317,13 @@ impl PageDescriptor {
 fn set_up_mair() {
     // Define the memory types being mapped.
     MAIR_EL1.write(
+        // Attribute 3 - Write-combining device memory.
+        //
+        // Gathering, reordering and early write acknowledgement allow the core to merge writes to
+        // neighboring addresses into bursts. Unlike normal non-cacheable memory, device memory is
+        // never read speculatively.
+        MAIR_EL1::Attr3_Device::Gathering_Reordering_EarlyWriteAck +
+
         // Attribute 2 - Non-cacheable normal DRAM
         MAIR_EL1::Attr2_Normal_Outer::NonCacheable +
         MAIR_EL1::Attr2_Normal_Inner::NonCacheable +
@@ -250,32 +343,175 @@ fn set_up_mair() {
 ///
 /// - Modifies a `static mut`. Ensure it only happens from here.
 unsafe fn populate_tt_entries() -> Result<(), &'static str> {
-    for (l2_nr, l2_entry) in TABLES.lvl2.iter_mut().enumerate() {
-        *l2_entry = TABLES.lvl3[l2_nr].base_addr_usize().into();
+    TABLES.populate(|virt_addr| bsp::memory::mmu::virt_mem_layout().virt_addr_properties(virt_addr))
+}
 
-        for (l3_nr, l3_entry) in TABLES.lvl3[l2_nr].iter_mut().enumerate() {
-            let virt_addr = (l2_nr << FIVETWELVE_MIB_SHIFT) + (l3_nr << SIXTYFOUR_KIB_SHIFT);
+/// Invalidate the TLB entries of one ASID on the executing core.
+fn invalidate_asid(asid: u8) {
+    unsafe {
+        barrier::dsb(barrier::ISHST);
+        asm!("tlbi aside1, {}", in(reg) (asid as u64) << TableBase::ASID_SHIFT, options(nostack, preserves_flags));
+        barrier::dsb(barrier::ISH);
+        barrier::isb(barrier::SY);
+    }
+}
 
-            let (output_addr, attribute_fields) =
-                bsp::memory::mmu::virt_mem_layout().virt_addr_properties(virt_addr)?;
+/// Invalidate all EL1 TLB entries on the executing core.
+fn invalidate_all() {
+    unsafe {
+        barrier::dsb(barrier::ISHST);
+        asm!("tlbi vmalle1", options(nostack, preserves_flags));
+        barrier::dsb(barrier::ISH);
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Write the translation table base register.
+///
+/// The TLB keeps the entries of the incoming ASID. They are dropped on reuse, rollover and
+/// repopulation of the tables instead, see the module documentation.
+///
+/// # Safety
+///
+/// - The new tables must map the currently executing code and stack.
+unsafe fn switch_table_base(base: TableBase) -> TableBase {
+    let prev = TableBase(TTBR0_EL1.get());
+
+    // Make the table writes visible to the table walker before it can use them.
+    barrier::dsb(barrier::ISHST);
+
+    TTBR0_EL1.set(base.0);
+    barrier::isb(barrier::SY);
 
-            *l3_entry = PageDescriptor::new(output_addr, attribute_fields);
+    prev
+}
+
+/// Check the translation tables for self-consistency.
+fn validate_tables<const N: usize>(tables: &TranslationTables<N>) -> Inconsistencies {
+    const ONE_BLOCK_MASK: u64 = (1 << FIVETWELVE_MIB_SHIFT) - 1;
+
+    // Bits [15:12] of a 64 KiB page descriptor are RES0 without 52 bit addressing, so a set bit
+    // there means the output is not 64 KiB aligned.
+    const PAGE_OUTPUT_LOW_BITS: u64 = 0xF000;
+    const OUTPUT_ADDR_MASK: u64 = 0x0000_FFFF_FFFF_F000;
+
+    let mut findings = Inconsistencies::new();
+
+    // For each next level table, the lvl2 index that references it.
+    let mut referenced_by: [Option<usize>; N] = [None; N];
+
+    for (l2_nr, l2_entry) in tables.lvl2.iter().enumerate() {
+        let virt_addr = l2_nr << FIVETWELVE_MIB_SHIFT;
+        let desc = l2_entry.0;
+
+        if desc & STAGE1_TABLE_DESCRIPTOR::VALID::True.value == 0 {
+            continue;
+        }
+
+        // A 512 MiB block.
+        if desc & STAGE1_TABLE_DESCRIPTOR::TYPE::Table.value == 0 {
+            let output_addr = desc & OUTPUT_ADDR_MASK;
+            if output_addr & ONE_BLOCK_MASK != 0 {
+                findings.push(Inconsistency::MisalignedOutput {
+                    virt_addr,
+                    output_addr: output_addr as usize,
+                });
+            }
+            continue;
+        }
+
+        let table_addr = (STAGE1_TABLE_DESCRIPTOR::NEXT_LEVEL_TABLE_ADDR_64KiB.read(desc) as usize)
+            << SIXTYFOUR_KIB_SHIFT;
+        let l3_nr = match tables
+            .lvl3
+            .iter()
+            .position(|table| table.base_addr_usize() == table_addr)
+        {
+            Some(l3_nr) => l3_nr,
+            None => {
+                findings.push(Inconsistency::InvalidTableAddress {
+                    virt_addr,
+                    table_addr,
+                });
+                continue;
+            }
+        };
+
+        if let Some(other) = referenced_by[l3_nr] {
+            findings.push(Inconsistency::DuplicateMapping {
+                virt_addr,
+                other_virt_addr: other << FIVETWELVE_MIB_SHIFT,
+            });
+            continue;
+        }
+        referenced_by[l3_nr] = Some(l2_nr);
+
+        for (l3_idx, l3_entry) in tables.lvl3[l3_nr].iter().enumerate() {
+            let desc = l3_entry.0;
+            if desc & STAGE1_PAGE_DESCRIPTOR::VALID::True.value == 0 {
+                continue;
+            }
+
+            if desc & PAGE_OUTPUT_LOW_BITS != 0 {
+                findings.push(Inconsistency::MisalignedOutput {
+                    virt_addr: virt_addr + (l3_idx << SIXTYFOUR_KIB_SHIFT),
+                    output_addr: (desc & OUTPUT_ADDR_MASK) as usize,
+                });
+            }
         }
     }
 
-    Ok(())
+    findings
+}
+
+/// The encoding of `TCR_EL1.IRGN0` and `ORGN0`.
+fn rgn_bits(cacheability: WalkCacheability) -> u64 {
+    match cacheability {
+        WalkCacheability::NonCacheable => 0b00,
+        WalkCacheability::WriteBackWriteAllocate => 0b01,
+        WalkCacheability::WriteThrough => 0b10,
+        WalkCacheability::WriteBackNoWriteAllocate => 0b11,
+    }
+}
+
+fn rgn_from_bits(bits: u64) -> WalkCacheability {
+    match bits {
+        0b01 => WalkCacheability::WriteBackWriteAllocate,
+        0b10 => WalkCacheability::WriteThrough,
+        0b11 => WalkCacheability::WriteBackNoWriteAllocate,
+        _ => WalkCacheability::NonCacheable,
+    }
+}
+
+/// The encoding of `TCR_EL1.SH0`. `0b01` is reserved.
+fn sh_bits(shareability: WalkShareability) -> u64 {
+    match shareability {
+        WalkShareability::NonShareable => 0b00,
+        WalkShareability::OuterShareable => 0b10,
+        WalkShareability::InnerShareable => 0b11,
+    }
+}
+
+fn sh_from_bits(bits: u64) -> WalkShareability {
+    match bits {
+        0b10 => WalkShareability::OuterShareable,
+        0b11 => WalkShareability::InnerShareable,
+        _ => WalkShareability::NonShareable,
+    }
 }
 
 /// Configure various settings of stage 1 of the EL1 translation regime.
-fn configure_translation_control() {
+fn configure_translation_control(walk: TableWalkAttributes) {
     let ips = ID_AA64MMFR0_EL1.read(ID_AA64MMFR0_EL1::PARange);
     TCR_EL1.write(
         TCR_EL1::TBI0::Ignored
             + TCR_EL1::IPS.val(ips)
+            + TCR_EL1::AS::ASID8Bits
+            + TCR_EL1::A1::TTBR0
             + TCR_EL1::TG0::KiB_64
-            + TCR_EL1::SH0::Inner
-            + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
-            + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
+            + TCR_EL1::SH0.val(sh_bits(walk.shareability))
+            + TCR_EL1::ORGN0.val(rgn_bits(walk.outer))
+            + TCR_EL1::IRGN0.val(rgn_bits(walk.inner))
             + TCR_EL1::EPD0::EnableTTBR0Walks
             + TCR_EL1::T0SZ.val(32), // TTBR0 spans 4 GiB total.
     );
@@ -285,11 +521,220 @@ fn configure_translation_control() {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// Invalidate all EL1 TLB entries on the executing core, e.g. after another core changed the
+/// tables.
+pub fn invalidate_local_tlb() {
+    invalidate_all();
+}
+
+/// Invalidate the EL1 TLB entries of the granules in `range` on the executing core, for all ASIDs.
+pub fn invalidate_local_tlb_range(range: Range<usize>) {
+    unsafe {
+        barrier::dsb(barrier::ISHST);
+        for virt_addr in range.step_by(GRANULE_SIZE) {
+            // The operand holds VA[55:12].
+            asm!("tlbi vaae1, {}", in(reg) (virt_addr >> 12) as u64, options(nostack, preserves_flags));
+        }
+        barrier::dsb(barrier::ISH);
+        barrier::isb(barrier::SY);
+    }
+}
+
+/// Invalidate the EL1 TLB entries of the granules in `range` on all cores of the inner shareable
+/// domain, for all ASIDs.
+///
+/// The other cores need not cooperate, unlike for `memory::mmu::tlb_shootdown()`.
+pub fn invalidate_tlb_range_broadcast(range: Range<usize>) {
+    unsafe {
+        barrier::dsb(barrier::ISHST);
+        for virt_addr in range.step_by(GRANULE_SIZE) {
+            // The operand holds VA[55:12].
+            asm!("tlbi vaae1is, {}", in(reg) (virt_addr >> 12) as u64, options(nostack, preserves_flags));
+        }
+        barrier::dsb(barrier::ISH);
+        barrier::isb(barrier::SY);
+    }
+}
+
+impl<const N: usize> TranslationTables<N> {
+    /// Create an instance with all entries invalid.
+    ///
+    /// `asid` is the 8 bit address space identifier that is installed along with the tables.
+    pub const fn new(asid: u8) -> Self {
+        Self {
+            lvl3: [[PageDescriptor(0); 8192]; N],
+            lvl2: [TableDescriptor(0); N],
+            asid,
+        }
+    }
+
+    /// Fill all entries at once. `mapping` returns the output address and attributes for the
+    /// granule at a virtual address.
+    ///
+    /// The TLB entries of the tables' ASID are dropped afterwards, so that the new mappings apply
+    /// even if the tables were active before.
+    pub fn populate(
+        &mut self,
+        mapping: impl Fn(usize) -> Result<(usize, AttributeFields), &'static str>,
+    ) -> Result<(), &'static str> {
+        for (l2_nr, l2_entry) in self.lvl2.iter_mut().enumerate() {
+            *l2_entry = self.lvl3[l2_nr].base_addr_usize().into();
+
+            for (l3_nr, l3_entry) in self.lvl3[l2_nr].iter_mut().enumerate() {
+                let virt_addr = (l2_nr << FIVETWELVE_MIB_SHIFT) + (l3_nr << SIXTYFOUR_KIB_SHIFT);
+
+                let (output_addr, attribute_fields) = mapping(virt_addr)?;
+
+                *l3_entry = PageDescriptor::new(output_addr, attribute_fields);
+            }
+        }
+
+        invalidate_asid(self.asid);
+
+        Ok(())
+    }
+
+    /// The address space identifier of the tables.
+    pub fn asid(&self) -> u8 {
+        self.asid
+    }
+
+    /// Change the address space identifier, e.g. after the previous one went stale.
+    ///
+    /// Takes effect the next time the tables are activated.
+    pub fn set_asid(&mut self, asid: u8) {
+        self.asid = asid;
+    }
+}
+
+impl TableBase {
+    /// The physical address of the top level table.
+    pub fn base_addr(&self) -> usize {
+        (self.0 & ((1 << Self::ASID_SHIFT) - 1)) as usize
+    }
+
+    /// The address space identifier.
+    pub fn asid(&self) -> u8 {
+        (self.0 >> Self::ASID_SHIFT) as u8
+    }
+}
+
+impl Asid {
+    /// The value to pass to `TranslationTables::new()`.
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+}
+
+impl AsidAllocator {
+    /// Create an instance with all ASIDs free.
+    pub const fn new() -> Self {
+        Self {
+            in_use: [1, 0, 0, 0],
+            generation: 0,
+        }
+    }
+
+    /// Hand out a free ASID.
+    ///
+    /// If none is left, starts a new generation, which invalidates the whole TLB of the executing
+    /// core.
+    pub fn allocate(&mut self) -> Asid {
+        let value = match self.find_free() {
+            Some(value) => value,
+            None => {
+                self.in_use = Self::new().in_use;
+                self.generation = self.generation.wrapping_add(1);
+                invalidate_all();
+
+                self.find_free().unwrap()
+            }
+        };
+
+        self.in_use[value / 64] |= 1 << (value % 64);
+
+        Asid {
+            value: value as u8,
+            generation: self.generation,
+        }
+    }
+
+    /// Return an ASID for reuse. Stale ASIDs are ignored.
+    ///
+    /// The ASID's TLB entries are dropped, so its next owner does not see the old translations.
+    pub fn free(&mut self, asid: Asid) {
+        if !self.is_current(asid) {
+            return;
+        }
+
+        invalidate_asid(asid.value);
+        self.in_use[asid.value as usize / 64] &= !(1 << (asid.value % 64));
+    }
+
+    /// Check if `asid` belongs to the current generation and may still be used.
+    pub fn is_current(&self, asid: Asid) -> bool {
+        asid.generation == self.generation
+    }
+
+    /// The number of ASIDs that can be handed out before a new generation starts.
+    pub fn num_free(&self) -> usize {
+        self.in_use
+            .iter()
+            .map(|word| word.count_zeros() as usize)
+            .sum()
+    }
+
+    fn find_free(&self) -> Option<usize> {
+        self.in_use
+            .iter()
+            .enumerate()
+            .find(|(_, word)| **word != u64::MAX)
+            .map(|(i, word)| i * 64 + (!word).trailing_zeros() as usize)
+    }
+}
+
+/// Check that walks with `walk` are coherent with tables in memory of type `table_mem`.
+///
+/// The expectations follow from how `AttributeFields` are turned into page descriptors: DRAM is
+/// inner shareable, and either write-back cacheable or non-cacheable in both cache domains.
+pub fn check_table_walk_attributes(
+    walk: TableWalkAttributes,
+    table_mem: MemAttributes,
+) -> Result<(), &'static str> {
+    let cacheability = match table_mem {
+        MemAttributes::CacheableDRAM => WalkCacheability::WriteBackWriteAllocate,
+        MemAttributes::NonCacheableDRAM => WalkCacheability::NonCacheable,
+        MemAttributes::Device | MemAttributes::WriteCombining => {
+            return Err("Translation tables must be in normal memory")
+        }
+    };
+
+    if walk.shareability != WalkShareability::InnerShareable {
+        return Err("Table walks must be inner shareable, like the table memory");
+    }
+
+    if walk.inner != cacheability || walk.outer != cacheability {
+        return Err("Table walk cacheability does not match the table memory");
+    }
+
+    Ok(())
+}
+
 /// Return a reference to the MMU.
 pub fn mmu() -> &'static impl memory::mmu::interface::MMU {
     &MMU
 }
 
+/// Push out writes to write-combining memory.
+///
+/// Writes to `MemAttributes::WriteCombining` mappings may linger in the core's write buffers for
+/// gathering. After e.g. finishing a frame, call this before telling another bus master, like the
+/// VideoCore, to consume the data.
+#[inline(always)]
+pub fn flush_write_combining() {
+    unsafe { barrier::dsb(barrier::ST) };
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
@@ -301,6 +746,12 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
             return Err("64 KiB translation granule not supported");
         }
 
+        // All levels of the tables are in one struct, and thereby in the same memory type.
+        let walk = memory::mmu::table_walk_attributes();
+        let (_, table_attrs) = bsp::memory::mmu::virt_mem_layout()
+            .virt_addr_properties(TABLES.lvl2.base_addr_usize())?;
+        check_table_walk_attributes(walk, table_attrs.mem_attributes)?;
+
         // Prepare the memory attribute indirection register.
         set_up_mair();
 
@@ -310,7 +761,7 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
         // Set the "Translation Table Base Register".
         TTBR0_EL1.set_baddr(TABLES.lvl2.base_addr_u64());
 
-        configure_translation_control();
+        configure_translation_control(walk);
 
         // Switch the MMU on.
         //
@@ -325,4 +776,260 @@ impl memory::mmu::interface::MMU for MemoryManagementUnit {
 
         Ok(())
     }
+
+    unsafe fn disable(&self) {
+        use crate::cpu::cache::clean_invalidate_dcache_all_asm;
+
+        // An IRQ handler in the middle of the sequence would run with the caches half torn down.
+        assert!(exception::asynchronous::is_local_irq_masked());
+
+        // Everything from switching the MMU off up to completing the cache maintenance is done in
+        // one block, without any memory access in between. A stack access that was served from a
+        // dirty cache line before the switch would read stale memory afterwards.
+        //
+        // The data caches are cleaned and invalidated by set/way after the switch, so that nothing
+        // is allocated into them in the meantime.
+        clean_invalidate_dcache_all_asm!(
+            "mrs    {tmp}, SCTLR_EL1
+             bic    {tmp}, {tmp}, #(1 << 0)
+             bic    {tmp}, {tmp}, #(1 << 2)
+             msr    SCTLR_EL1, {tmp}
+             isb",
+            "tlbi   vmalle1
+             dsb    ish
+             ic     iallu
+             dsb    ish
+             isb"
+        );
+    }
+
+    fn is_enabled(&self) -> bool {
+        SCTLR_EL1.matches_all(SCTLR_EL1::M::Enable)
+    }
+
+    fn table_walk_attributes(&self) -> TableWalkAttributes {
+        TableWalkAttributes {
+            inner: rgn_from_bits(TCR_EL1.read(TCR_EL1::IRGN0)),
+            outer: rgn_from_bits(TCR_EL1.read(TCR_EL1::ORGN0)),
+            shareability: sh_from_bits(TCR_EL1.read(TCR_EL1::SH0)),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Inconsistencies> {
+        validate_tables(unsafe { &TABLES }).into_result()
+    }
+
+    unsafe fn activate_table(&self, table: &TranslationTable) -> Result<TableBase, &'static str> {
+        // The kernel is identity mapped, so the table's virtual address is its physical address.
+        let base_addr = table.lvl2.base_addr_usize();
+        if base_addr % GRANULE_SIZE != 0 {
+            return Err("Translation table base address not granule aligned");
+        }
+
+        Ok(switch_table_base(TableBase::new(
+            base_addr as u64,
+            table.asid,
+        )))
+    }
+
+    unsafe fn restore_table(&self, base: TableBase) {
+        switch_table_base(base);
+    }
+
+    unsafe fn set_attributes(
+        &self,
+        range: Range<usize>,
+        attributes: AttributeFields,
+    ) -> Result<(), &'static str> {
+        if range.start % GRANULE_SIZE != 0 || range.end % GRANULE_SIZE != 0 {
+            return Err("Range not granule aligned");
+        }
+        if range.end > ENTRIES_512_MIB << FIVETWELVE_MIB_SHIFT {
+            return Err("Range outside of the translation tables");
+        }
+        #[cfg(feature = "faultinject")]
+        if crate::faultinject::should_fail(crate::faultinject::FaultPoint::Mapping) {
+            return Err("Injected mapping fault");
+        }
+
+        for virt_addr in range.clone().step_by(GRANULE_SIZE) {
+            let l2_nr = virt_addr >> FIVETWELVE_MIB_SHIFT;
+            let l3_nr = (virt_addr >> SIXTYFOUR_KIB_SHIFT) & (8192 - 1);
+            let entry = &mut TABLES.lvl3[l2_nr][l3_nr];
+
+            let output_addr = (STAGE1_PAGE_DESCRIPTOR::OUTPUT_ADDR_64KiB.read(entry.0) as usize)
+                << SIXTYFOUR_KIB_SHIFT;
+            *entry = PageDescriptor::new(output_addr, attributes);
+        }
+
+        // Only permissions change, so the entries can be rewritten in place, without a
+        // break-before-make sequence. The barrier in here makes the writes visible to the walker.
+        invalidate_all();
+
+        // A core that does not ack in time, e.g. because it hangs with IRQs masked, must not keep
+        // the old translations either. The broadcast needs no cooperation.
+        if memory::mmu::tlb_shootdown(range.clone()).is_err() {
+            invalidate_tlb_range_broadcast(range);
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Tables to corrupt, separate from the live ones.
+    static mut TEST_TABLES: TranslationTables<2> = TranslationTables::new(0);
+
+    /// Fill the test tables with a consistent identity mapping.
+    unsafe fn populate_test_tables() {
+        TEST_TABLES
+            .populate(|virt_addr| Ok((virt_addr, AttributeFields::default())))
+            .unwrap();
+    }
+
+    /// The framebuffer must be mapped with the write-combining memory attributes.
+    #[kernel_test]
+    fn framebuffer_descriptor_uses_write_combining_attr_index() {
+        let fb_addr = bsp::memory::framebuffer_range().start;
+        let (output_addr, attribute_fields) = bsp::memory::mmu::virt_mem_layout()
+            .virt_addr_properties(fb_addr)
+            .unwrap();
+        let desc = PageDescriptor::new(output_addr, attribute_fields);
+
+        assert_eq!(
+            STAGE1_PAGE_DESCRIPTOR::AttrIndx.read(desc.0),
+            mair::WRITE_COMBINING
+        );
+    }
+
+    /// ASIDs are unique within a generation, and exhaustion starts a new one.
+    #[kernel_test]
+    fn asid_allocator_recycles_on_exhaustion() {
+        let mut allocator = AsidAllocator::new();
+        assert_eq!(allocator.num_free(), NUM_ASIDS - 1);
+
+        let first = allocator.allocate();
+        assert_eq!(first.value(), 1);
+        assert_eq!(allocator.allocate().value(), 2);
+
+        allocator.free(first);
+        assert_eq!(allocator.allocate().value(), 1);
+
+        while allocator.num_free() > 0 {
+            assert_ne!(allocator.allocate().value(), 0);
+        }
+        assert!(allocator.is_current(first));
+
+        let recycled = allocator.allocate();
+        assert_eq!(recycled.value(), 1);
+        assert!(!allocator.is_current(first));
+        assert!(allocator.is_current(recycled));
+        assert_eq!(allocator.num_free(), NUM_ASIDS - 2);
+
+        // Freeing a stale ASID must not free the recycled one.
+        allocator.free(first);
+        assert_eq!(allocator.num_free(), NUM_ASIDS - 2);
+    }
+
+    /// The TCR encodings must round-trip, and only walks that match the table memory be accepted.
+    #[kernel_test]
+    fn table_walk_attributes_must_match_table_memory() {
+        for c in [
+            WalkCacheability::NonCacheable,
+            WalkCacheability::WriteBackWriteAllocate,
+            WalkCacheability::WriteThrough,
+            WalkCacheability::WriteBackNoWriteAllocate,
+        ]
+        .iter()
+        {
+            assert_eq!(rgn_from_bits(rgn_bits(*c)), *c);
+        }
+        for s in [
+            WalkShareability::NonShareable,
+            WalkShareability::OuterShareable,
+            WalkShareability::InnerShareable,
+        ]
+        .iter()
+        {
+            assert_eq!(sh_from_bits(sh_bits(*s)), *s);
+        }
+
+        let default = TableWalkAttributes::default();
+        assert!(check_table_walk_attributes(default, MemAttributes::CacheableDRAM).is_ok());
+        assert!(check_table_walk_attributes(default, MemAttributes::NonCacheableDRAM).is_err());
+        assert!(check_table_walk_attributes(default, MemAttributes::Device).is_err());
+
+        let non_shareable = TableWalkAttributes {
+            shareability: WalkShareability::NonShareable,
+            ..default
+        };
+        assert!(check_table_walk_attributes(non_shareable, MemAttributes::CacheableDRAM).is_err());
+
+        let non_cacheable = TableWalkAttributes {
+            inner: WalkCacheability::NonCacheable,
+            outer: WalkCacheability::NonCacheable,
+            ..default
+        };
+        assert!(
+            check_table_walk_attributes(non_cacheable, MemAttributes::NonCacheableDRAM).is_ok()
+        );
+        assert!(check_table_walk_attributes(non_cacheable, MemAttributes::CacheableDRAM).is_err());
+    }
+
+    /// Returns the only finding of a validation run.
+    fn single_finding() -> Inconsistency {
+        let findings = validate_tables(unsafe { &TEST_TABLES });
+
+        assert_eq!(findings.total(), 1);
+        *findings.iter().next().unwrap()
+    }
+
+    /// Consistent tables validate fine, and each kind of corruption is reported.
+    #[kernel_test]
+    fn validate_reports_corrupted_descriptors() {
+        unsafe {
+            populate_test_tables();
+            assert!(validate_tables(&TEST_TABLES).into_result().is_ok());
+
+            // Second 512 MiB window reuses the first window's table.
+            TEST_TABLES.lvl2[1] = TEST_TABLES.lvl3[0].base_addr_usize().into();
+            assert!(
+                single_finding()
+                    == Inconsistency::DuplicateMapping {
+                        virt_addr: 1 << FIVETWELVE_MIB_SHIFT,
+                        other_virt_addr: 0,
+                    }
+            );
+
+            // Table descriptor pointing somewhere else.
+            populate_test_tables();
+            TEST_TABLES.lvl2[0] = 0x1234_0000.into();
+            assert!(
+                single_finding()
+                    == Inconsistency::InvalidTableAddress {
+                        virt_addr: 0,
+                        table_addr: 0x1234_0000,
+                    }
+            );
+
+            // Page with an output address that is not 64 KiB aligned.
+            populate_test_tables();
+            TEST_TABLES.lvl3[0][5].0 |= 0x1000;
+            assert!(
+                single_finding()
+                    == Inconsistency::MisalignedOutput {
+                        virt_addr: 5 << SIXTYFOUR_KIB_SHIFT,
+                        output_addr: (5 << SIXTYFOUR_KIB_SHIFT) + 0x1000,
+                    }
+            );
+        }
+    }
 }
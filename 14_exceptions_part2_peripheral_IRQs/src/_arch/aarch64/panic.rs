@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural panic diagnostics.
+
+use crate::exception::Syndrome;
+use core::fmt;
+use cortex_a::regs::*;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Walks stop after this many frames, in case the frame record chain is corrupted into a cycle.
+const MAX_FRAMES: usize = 32;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The registers of the panicking core, as captured on entry to the panic handler.
+///
+/// `SP`, `FP` and `LR` are the panic handler's own. The exception registers describe the last
+/// exception that was taken, which is not necessarily related to the panic.
+#[derive(Copy, Clone)]
+pub struct Registers {
+    pub sp: u64,
+    pub fp: u64,
+    pub lr: u64,
+    pub current_el: u64,
+    pub elr_el1: u64,
+    pub spsr_el1: u64,
+    pub esr_el1: u64,
+    pub far_el1: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Registers {
+    /// Capture the registers of the executing core.
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let (sp, fp, lr): (u64, u64, u64);
+
+        unsafe {
+            asm!(
+                "mov {sp}, sp",
+                "mov {fp}, x29",
+                "mov {lr}, x30",
+                sp = out(reg) sp,
+                fp = out(reg) fp,
+                lr = out(reg) lr,
+                options(nomem, nostack)
+            );
+        }
+
+        Self {
+            sp,
+            fp,
+            lr,
+            current_el: CurrentEL.get() as u64,
+            elr_el1: ELR_EL1.get(),
+            spsr_el1: SPSR_EL1.get() as u64,
+            esr_el1: ESR_EL1.get() as u64,
+            far_el1: FAR_EL1.get(),
+        }
+    }
+}
+
+impl fmt::Display for Registers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "CurrentEL: EL{}", (self.current_el >> 2) & 0b11)?;
+        writeln!(f, "SP:        {:#018x}", self.sp)?;
+        writeln!(f, "FP:        {:#018x}", self.fp)?;
+        writeln!(f, "LR:        {:#018x}", self.lr)?;
+        writeln!(f, "ELR_EL1:   {:#018x}", self.elr_el1)?;
+        writeln!(f, "SPSR_EL1:  {:#010x}", self.spsr_el1)?;
+        writeln!(
+            f,
+            "ESR_EL1:   {:#010x} ({})",
+            self.esr_el1,
+            Syndrome::from_esr(self.esr_el1)
+        )?;
+        write!(f, "FAR_EL1:   {:#018x}", self.far_el1)
+    }
+}
+
+/// Walk the chain of frame records that starts at `fp`, and call `f` with each return address.
+///
+/// A frame record is the pair of the caller's `FP` and the return address `LR`, which the prologue
+/// of a function stores at the address its `FP` points to. The walk stops at a null, misaligned or
+/// non-ascending `FP`, as the stack grows downwards, so the caller's record is always higher up.
+///
+/// The result is only complete if all code on the call chain keeps frame pointers, e.g. when built
+/// with `-C force-frame-pointers=yes`.
+///
+/// # Safety
+///
+/// - `fp` and the frame records it chains to must be in mapped memory.
+pub unsafe fn walk_frames(mut fp: u64, mut f: impl FnMut(u64)) {
+    for _ in 0..MAX_FRAMES {
+        if fp == 0 || fp % 8 != 0 {
+            return;
+        }
+
+        let record = fp as *const u64;
+        let next_fp = core::ptr::read_volatile(record);
+        let lr = core::ptr::read_volatile(record.add(1));
+
+        if lr == 0 {
+            return;
+        }
+        f(lr);
+
+        if next_fp <= fp {
+            return;
+        }
+        fp = next_fp;
+    }
+}
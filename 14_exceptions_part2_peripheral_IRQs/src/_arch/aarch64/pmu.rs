@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural performance monitors.
+//!
+//! The cycle counter `PMCCNTR_EL0` is dedicated to `CPU_CYCLES`. The other events are assigned to
+//! event counters 0 and 1 by writing their event numbers to `PMEVTYPER<n>_EL0`. In ARMv8.0, event
+//! counters are 32 bit wide, only the cycle counter has 64 bit.
+//!
+//! The kernel reads the counters from EL1, so user-mode access through `PMUSERENR_EL0` stays
+//! disabled. Access from EL1 is granted by `MDCR_EL2` in the EL2 to EL1 transition.
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// PMCR_EL0.E: Enable all counters.
+const PMCR_E: u64 = 1 << 0;
+
+/// PMCR_EL0.P: Reset the event counters.
+const PMCR_P: u64 = 1 << 1;
+
+/// PMCR_EL0.C: Reset the cycle counter.
+const PMCR_C: u64 = 1 << 2;
+
+/// PMCNTENSET_EL0.C: Enable the cycle counter.
+const PMCNTENSET_C: u64 = 1 << 31;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The events that the kernel counts.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    /// `CPU_CYCLES`, event number 0x11. Counted by `PMCCNTR_EL0`.
+    Cycles,
+
+    /// `INST_RETIRED`, event number 0x08. Counted by event counter 0.
+    InstructionsRetired,
+
+    /// `L1D_CACHE_REFILL`, event number 0x03. Counted by event counter 1.
+    L1DCacheRefill,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Event {
+    /// The architected event number.
+    pub const fn number(self) -> u64 {
+        match self {
+            Event::Cycles => 0x11,
+            Event::InstructionsRetired => 0x08,
+            Event::L1DCacheRefill => 0x03,
+        }
+    }
+
+    /// The bits of the counter that counts the event.
+    pub const fn counter_mask(self) -> u64 {
+        match self {
+            Event::Cycles => u64::MAX,
+            _ => u32::MAX as u64,
+        }
+    }
+}
+
+/// Configure, reset and start the executing core's counters.
+pub fn init() {
+    unsafe {
+        asm!(
+            "msr PMEVTYPER0_EL0, {inst}",
+            "msr PMEVTYPER1_EL0, {l1d}",
+            // Count the cycles at EL0 and EL1.
+            "msr PMCCFILTR_EL0, xzr",
+            "msr PMCNTENSET_EL0, {cnten}",
+            "msr PMCR_EL0, {pmcr}",
+            "isb",
+            inst = in(reg) Event::InstructionsRetired.number(),
+            l1d = in(reg) Event::L1DCacheRefill.number(),
+            cnten = in(reg) PMCNTENSET_C | 0b11,
+            pmcr = in(reg) PMCR_E | PMCR_P | PMCR_C,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+}
+
+/// Return whether the core implements `event`.
+///
+/// Emulators often implement a few events only, e.g. QEMU does not model caches.
+pub fn is_supported(event: Event) -> bool {
+    let pmceid0: u64;
+    unsafe {
+        asm!("mrs {}, PMCEID0_EL0", out(reg) pmceid0, options(nomem, nostack, preserves_flags))
+    };
+
+    pmceid0 & (1 << event.number()) != 0
+}
+
+/// Read the counter of `event`.
+///
+/// Use `pmu::delta()` to compute the difference of two reads.
+pub fn read(event: Event) -> u64 {
+    let val: u64;
+
+    unsafe {
+        match event {
+            Event::Cycles => {
+                asm!("mrs {}, PMCCNTR_EL0", out(reg) val, options(nomem, nostack, preserves_flags))
+            }
+            Event::InstructionsRetired => {
+                asm!("mrs {}, PMEVCNTR0_EL0", out(reg) val, options(nomem, nostack, preserves_flags))
+            }
+            Event::L1DCacheRefill => {
+                asm!("mrs {}, PMEVCNTR1_EL0", out(reg) val, options(nomem, nostack, preserves_flags))
+            }
+        }
+    }
+
+    val
+}
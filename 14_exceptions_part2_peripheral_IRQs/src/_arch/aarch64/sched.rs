@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Architectural context switching.
+
+use crate::sched;
+use core::mem;
+
+// A context switch is always a function call, so only the callee-saved registers x19-x30 and the
+// stack pointer need to be preserved. The kernel is built for a softfloat target, so there are no
+// FP/SIMD registers to save.
+global_asm!(
+    "
+.section .text
+
+// Save the current context to the `Context` pointed to by x0, then restore the `Context` pointed to
+// by x1 and continue there.
+.global __context_switch
+__context_switch:
+    mov    x9,  sp
+    stp    x19, x20, [x0, #16 * 0]
+    stp    x21, x22, [x0, #16 * 1]
+    stp    x23, x24, [x0, #16 * 2]
+    stp    x25, x26, [x0, #16 * 3]
+    stp    x27, x28, [x0, #16 * 4]
+    stp    x29, x30, [x0, #16 * 5]
+    str    x9,       [x0, #16 * 6]
+
+    ldp    x19, x20, [x1, #16 * 0]
+    ldp    x21, x22, [x1, #16 * 1]
+    ldp    x23, x24, [x1, #16 * 2]
+    ldp    x25, x26, [x1, #16 * 3]
+    ldp    x27, x28, [x1, #16 * 4]
+    ldp    x29, x30, [x1, #16 * 5]
+    ldr    x9,       [x1, #16 * 6]
+    mov    sp,  x9
+
+    ret
+
+// The first instruction of a new task. `Context::new_task()` stashed the entry function in x19.
+.global __task_entry
+__task_entry:
+    mov    x0,  x19
+    b      __task_main
+"
+);
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+extern "C" {
+    fn __context_switch(from: *mut Context, to: *const Context);
+    fn __task_entry();
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The saved register state of a task that is not running.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct Context {
+    /// x19 - x30. x30 is the link register, i.e. where the task continues when switched to.
+    gpr: [u64; 12],
+
+    /// The stack pointer.
+    sp: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Rust part of a new task's entry. Runs the task's function and retires the task afterwards.
+///
+/// # Safety
+///
+/// - Must only be called from `__task_entry`.
+#[no_mangle]
+unsafe extern "C" fn __task_main(entry: usize) -> ! {
+    let entry: fn() = mem::transmute(entry);
+
+    entry();
+    sched::exit()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Context {
+    /// Create an empty context. Used for tasks that are running already when they are first
+    /// switched away from.
+    pub const fn new() -> Self {
+        Self {
+            gpr: [0; 12],
+            sp: 0,
+        }
+    }
+
+    /// Create the initial context of a new task that starts executing `entry` on `stack`.
+    pub fn new_task(entry: fn(), stack: &'static mut [u8]) -> Self {
+        let stack_top = (stack.as_mut_ptr() as usize + stack.len()) & !0xF;
+        let mut ctx = Self::new();
+
+        ctx.gpr[0] = entry as usize as u64;
+        ctx.gpr[11] = __task_entry as usize as u64;
+        ctx.sp = stack_top as u64;
+
+        ctx
+    }
+
+    /// Save the current register state to `from` and continue with the state in `to`.
+    ///
+    /// Returns when another task switches back to `from`.
+    ///
+    /// # Safety
+    ///
+    /// - `to` must have been initialized by `new_task()` or by a previous `switch()`.
+    /// - Both pointers must stay valid until the corresponding task is switched to again.
+    pub unsafe fn switch(from: *mut Context, to: *const Context) {
+        __context_switch(from, to)
+    }
+}
@@ -3,10 +3,50 @@
 // Copyright (c) 2018-2020 Andre Richter <andre.o.richter@gmail.com>
 
 //! Architectural timer primitives.
+//!
+//! # Counter frequency
+//!
+//! All duration math depends on the counter frequency in CNTFRQ_EL0. The register does not
+//! configure the counter. It is only a hint that the firmware programs, and a wrong value makes all
+//! delays wrong by the same factor. [`set_frequency_override()`] replaces the value that is read
+//! from CNTFRQ_EL0. Known-good frequencies:
+//!
+//! | Board          | Frequency |
+//! |----------------|-----------|
+//! | Raspberry Pi 3 | 19.2 MHz  |
+//! | Raspberry Pi 4 | 54 MHz    |
+//! | QEMU `raspi3`  | 62.5 MHz  |
+//!
+//! # Counter selection
+//!
+//! The Generic Timer has a physical counter, `CNTPCT_EL0`, and a virtual one, `CNTVCT_EL0`, which
+//! is the physical count minus the offset in `CNTVOFF_EL2`. Each counter has its own comparator,
+//! `CNTP_*` and `CNTV_*`, with its own IRQ. Ticks, `uptime()`, deadlines and the timer programming
+//! of `spin_for()` and the sleeps all use the counter of one [`CounterKind`], which
+//! [`select_counter()`] picks:
+//!
+//! | Running at | `CNTVOFF_EL2` | Counter  | Reason                                   |
+//! |------------|---------------|----------|------------------------------------------|
+//! | EL2        | any           | Physical | The offset is meant for EL2's guests.    |
+//! | EL1        | zero          | Physical | Both counts are the same.                |
+//! | EL1        | nonzero       | Virtual  | A hypervisor presents time as an offset. |
+//!
+//! A hypervisor that sets an offset may also keep the physical timer for itself, through
+//! `CNTHCTL_EL2`.
+//!
+//! `CNTVOFF_EL2` can not be read at EL1, so a zero offset is detected by the virtual count lying
+//! between two reads of the physical count. The kernel's own boot code clears the offset before it
+//! drops to EL1, so the physical counter is picked unless a hypervisor booted the kernel.
+//!
+//! With [`CounterKind::Virtual`], the virtual timer is taken by the sleeps. [`arm_virtual_timer()`]
+//! fails then, and the profiler, which is built on it, is not available.
 
-use crate::{time, warn};
-use core::time::Duration;
-use cortex_a::regs::*;
+use crate::{bsp, cpu, exception, time, warn};
+use core::{
+    sync::atomic::{AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+use cortex_a::{barrier, regs::*};
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -14,6 +54,17 @@ use cortex_a::regs::*;
 
 const NS_PER_S: u64 = 1_000_000_000;
 
+/// CNTFRQ_EL0 values outside of this range are considered misprogrammed.
+const PLAUSIBLE_FREQUENCY_HZ: core::ops::RangeInclusive<u64> = 1_000_000..=1_000_000_000;
+
+/// `CNTP_CTL_EL0` and `CNTV_CTL_EL0` bits.
+const CTL_ENABLE: u64 = 1 << 0;
+const CTL_IMASK: u64 = 1 << 1;
+const CTL_ISTATUS: u64 = 1 << 2;
+
+const COUNTER_PHYSICAL: u8 = 0;
+const COUNTER_VIRTUAL: u8 = 1;
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -21,33 +72,368 @@ const NS_PER_S: u64 = 1_000_000_000;
 /// ARMv8 Generic Timer.
 pub struct GenericTimer;
 
+/// The counter that time is based on, together with its timer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CounterKind {
+    /// `CNTPCT_EL0`, with the `CNTP_*` timer.
+    Physical,
+
+    /// `CNTVCT_EL0`, with the `CNTV_*` timer.
+    Virtual,
+}
+
+/// Handler of the timer IRQ that ends a `sleep_until()`.
+struct SleepWakeup;
+
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
 static TIME_MANAGER: GenericTimer = GenericTimer;
 
+static SLEEP_WAKEUP: SleepWakeup = SleepWakeup;
+
+/// Zero if no override is set.
+static FREQUENCY_OVERRIDE_HZ: AtomicU64 = AtomicU64::new(0);
+
+static COUNTER_KIND: AtomicU8 = AtomicU8::new(COUNTER_PHYSICAL);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The counter frequency that duration math uses.
+#[inline(always)]
+fn frequency() -> u64 {
+    match FREQUENCY_OVERRIDE_HZ.load(Ordering::Relaxed) {
+        0 => CNTFRQ_EL0.get() as u64,
+        hz => hz,
+    }
+}
+
+/// Convert `duration` to counter ticks, saturating on overflow.
+///
+/// Rounds up, so that a compare value never fires before `duration` has passed.
+fn duration_to_ticks(duration: Duration) -> u64 {
+    let ticks =
+        (frequency() as u128 * duration.as_nanos() + (NS_PER_S as u128 - 1)) / NS_PER_S as u128;
+
+    core::cmp::min(ticks, u64::max_value().into()) as u64
+}
+
+/// Whether the virtual count equals the physical one, i.e. `CNTVOFF_EL2` is zero.
+fn virtual_offset_is_zero() -> bool {
+    let before = counter(CounterKind::Physical);
+    unsafe { barrier::isb(barrier::SY) };
+    let virt = counter(CounterKind::Virtual);
+    unsafe { barrier::isb(barrier::SY) };
+    let after = counter(CounterKind::Physical);
+
+    (before..=after).contains(&virt)
+}
+
+/// Read the control register of the timer of `kind`.
+fn timer_ctl(kind: CounterKind) -> u64 {
+    match kind {
+        CounterKind::Physical => CNTP_CTL_EL0.get() as u64,
+        CounterKind::Virtual => {
+            let ctl: u64;
+            unsafe { asm!("mrs {}, CNTV_CTL_EL0", out(reg) ctl, options(nomem, nostack)) };
+            ctl
+        }
+    }
+}
+
+/// Write the control register of the timer of `kind`.
+fn set_timer_ctl(kind: CounterKind, ctl: u64) {
+    match kind {
+        CounterKind::Physical => CNTP_CTL_EL0.set(ctl as u32),
+        CounterKind::Virtual => unsafe {
+            asm!("msr CNTV_CTL_EL0, {}", in(reg) ctl, options(nomem, nostack))
+        },
+    }
+}
+
+/// Program the timer of `kind` to fire once its counter reaches `cval`.
+fn set_timer_cval(kind: CounterKind, cval: u64) {
+    unsafe {
+        match kind {
+            CounterKind::Physical => {
+                asm!("msr CNTP_CVAL_EL0, {}", in(reg) cval, options(nomem, nostack))
+            }
+            CounterKind::Virtual => {
+                asm!("msr CNTV_CVAL_EL0, {}", in(reg) cval, options(nomem, nostack))
+            }
+        }
+    }
+}
+
+/// Program the timer of `kind` to fire after `tval` ticks.
+fn set_timer_tval(kind: CounterKind, tval: u32) {
+    match kind {
+        CounterKind::Physical => CNTP_TVAL_EL0.set(tval),
+        CounterKind::Virtual => unsafe {
+            asm!("msr CNTV_TVAL_EL0, {}", in(reg) tval as u64, options(nomem, nostack))
+        },
+    }
+}
+
+/// Enable the timer of the selected counter, with its IRQ unmasked, to fire at `cval`.
+fn arm_wakeup(cval: u64) {
+    let kind = counter_kind();
+
+    set_timer_cval(kind, cval);
+    set_timer_ctl(kind, CTL_ENABLE);
+}
+
+/// Stop the timer of the selected counter.
+fn disarm_wakeup() {
+    let kind = counter_kind();
+
+    set_timer_ctl(kind, timer_ctl(kind) & !CTL_ENABLE);
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// Register and enable the IRQ handler that `sleep_until()` is woken by.
+///
+/// Must be called during kernel init, after `select_counter()`. The handler is registered for the
+/// IRQ of the selected counter's timer. Without it, a sleep with IRQs unmasked ends up in the
+/// unhandled IRQ path once the deadline passes.
+pub fn init() -> Result<(), &'static str> {
+    use bsp::exception::asynchronous::{irq_manager, physical_timer_irq, virtual_timer_irq};
+    use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+    let descriptor = IRQDescriptor {
+        name: "Sleep wakeup",
+        handler: &SLEEP_WAKEUP,
+    };
+
+    let irq = match counter_kind() {
+        CounterKind::Physical => physical_timer_irq(),
+        CounterKind::Virtual => virtual_timer_irq(),
+    };
+    irq_manager().register_handler(irq, descriptor)?;
+    irq_manager().enable(irq);
+
+    Ok(())
+}
+
+/// Pick the counter that time is based on, see the module documentation, and return it.
+///
+/// Must be called during kernel init, before `init()`. Durations that span a change of the
+/// selection are meaningless if the offset is nonzero.
+pub fn select_counter() -> CounterKind {
+    let kind = match CurrentEL.read_as_enum(CurrentEL::EL) {
+        Some(CurrentEL::EL::Value::EL2) => CounterKind::Physical,
+        _ if virtual_offset_is_zero() => CounterKind::Physical,
+        _ => CounterKind::Virtual,
+    };
+    set_counter_kind(kind);
+
+    kind
+}
+
+/// Base time on the counter of `kind`, regardless of the exception level and offset.
+///
+/// Must not be called while a sleep or spin is in progress, as it would wait on the other timer.
+pub fn set_counter_kind(kind: CounterKind) {
+    let kind = match kind {
+        CounterKind::Physical => COUNTER_PHYSICAL,
+        CounterKind::Virtual => COUNTER_VIRTUAL,
+    };
+    COUNTER_KIND.store(kind, Ordering::Relaxed);
+}
+
+/// The counter that time is based on.
+pub fn counter_kind() -> CounterKind {
+    match COUNTER_KIND.load(Ordering::Relaxed) {
+        COUNTER_VIRTUAL => CounterKind::Virtual,
+        _ => CounterKind::Physical,
+    }
+}
+
+/// The current value of the counter of `kind`, whether it is selected or not.
+pub fn counter(kind: CounterKind) -> u64 {
+    match kind {
+        CounterKind::Physical => CNTPCT_EL0.get(),
+        CounterKind::Virtual => {
+            let cnt: u64;
+            unsafe { asm!("mrs {}, CNTVCT_EL0", out(reg) cnt, options(nomem, nostack)) };
+            cnt
+        }
+    }
+}
+
+/// The current value of the counter that deadlines are given in.
+pub fn ticks() -> u64 {
+    counter(counter_kind())
+}
+
+/// Convert a number of counter ticks to a duration.
+pub fn ticks_to_duration(ticks: u64) -> Duration {
+    Duration::from_nanos(((ticks as u128 * NS_PER_S as u128) / frequency() as u128) as u64)
+}
+
+/// The counter value once `duration` has passed from now, for `sleep_until()`.
+pub fn deadline_ticks(duration: Duration) -> u64 {
+    ticks().saturating_add(duration_to_ticks(duration))
+}
+
+/// Sleep the core until the counter reaches `deadline`.
+///
+/// The selected counter's timer is armed to fire at the deadline, and the core waits for interrupts
+/// in between. Every wakeup rechecks the counter against the deadline, so unrelated interrupts only
+/// lead to another wait. The timer is re-armed before each wait, in case an IRQ handler used it
+/// for `spin_for()` in the meantime.
+///
+/// Interrupts are serviced between the waits if they were unmasked on entry. If they were masked,
+/// a pending unrelated IRQ keeps waking the core, which turns the sleep into a busy wait.
+pub fn sleep_until(deadline: u64) {
+    use exception::asynchronous::{local_irq_mask_save, local_irq_restore};
+
+    loop {
+        // Masked, so that the timer IRQ can not be serviced between arming and waiting. It still
+        // wakes the core from the wait.
+        let saved = unsafe { local_irq_mask_save() };
+
+        if ticks() >= deadline {
+            unsafe { local_irq_restore(saved) };
+            break;
+        }
+
+        arm_wakeup(deadline);
+
+        cpu::wait_for_interrupt();
+
+        // Services the IRQ that caused the wakeup, if IRQs were unmasked on entry.
+        unsafe { local_irq_restore(saved) };
+    }
+
+    disarm_wakeup();
+}
+
+/// Wait for an interrupt, with the selected counter's timer armed to wake the core at the uptime
+/// `deadline`.
+///
+/// Unlike `sleep_until()`, this returns after the first wakeup, which might have been caused by an
+/// unrelated interrupt. Returns right away if the deadline has passed already.
+pub fn wait_for_interrupt_until(deadline: Duration) {
+    use exception::asynchronous::{local_irq_mask_save, local_irq_restore};
+
+    // Masked for the same reason as in `sleep_until()`.
+    let saved = unsafe { local_irq_mask_save() };
+
+    let cval = duration_to_ticks(deadline);
+    if ticks() < cval {
+        arm_wakeup(cval);
+
+        cpu::wait_for_interrupt();
+    }
+
+    unsafe { local_irq_restore(saved) };
+
+    disarm_wakeup();
+}
+
 /// Return a reference to the time manager.
 pub fn time_manager() -> &'static impl time::interface::TimeManager {
     &TIME_MANAGER
 }
 
+/// Use `hz` instead of CNTFRQ_EL0 as the counter frequency. `0` removes the override.
+pub fn set_frequency_override(hz: u64) {
+    FREQUENCY_OVERRIDE_HZ.store(hz, Ordering::Relaxed);
+}
+
+/// The frequency override, if one is set.
+pub fn frequency_override() -> Option<u64> {
+    match FREQUENCY_OVERRIDE_HZ.load(Ordering::Relaxed) {
+        0 => None,
+        hz => Some(hz),
+    }
+}
+
+/// Check that CNTFRQ_EL0 holds a plausible value, and fall back to the board's default otherwise.
+///
+/// Does nothing if an override is set already.
+pub fn check_frequency() {
+    if frequency_override().is_some() {
+        return;
+    }
+
+    let cntfrq = CNTFRQ_EL0.get() as u64;
+    if PLAUSIBLE_FREQUENCY_HZ.contains(&cntfrq) {
+        return;
+    }
+
+    let default = bsp::cpu::GENERIC_TIMER_FREQUENCY_HZ;
+    warn!(
+        "CNTFRQ_EL0 reads an implausible {} Hz. Using the board default of {} Hz",
+        cntfrq, default
+    );
+    set_frequency_override(default);
+}
+
+/// Arm the executing core's virtual timer to assert its IRQ once `duration` has passed.
+///
+/// The virtual timer is independent of the physical one that `spin_for()` uses. Fails while
+/// `CounterKind::Virtual` is selected, as the sleeps own the virtual timer then. Durations are
+/// clamped to what the timer supports.
+pub fn arm_virtual_timer(duration: Duration) -> Result<(), &'static str> {
+    if counter_kind() == CounterKind::Virtual {
+        return Err("The virtual timer is taken by the sleeps");
+    }
+
+    let frq = frequency();
+    let ticks = frq.saturating_mul(duration.as_nanos() as u64) / NS_PER_S;
+    let tval = core::cmp::max(1, core::cmp::min(ticks, u32::max_value().into()));
+
+    unsafe {
+        asm!("msr CNTV_TVAL_EL0, {}", in(reg) tval, options(nomem, nostack));
+
+        // ENABLE set, IMASK clear.
+        asm!("msr CNTV_CTL_EL0, {}", in(reg) 0b01u64, options(nomem, nostack));
+    }
+
+    Ok(())
+}
+
+/// Stop the executing core's virtual timer.
+///
+/// Does nothing while `CounterKind::Virtual` is selected, see `arm_virtual_timer()`.
+pub fn disarm_virtual_timer() {
+    if counter_kind() == CounterKind::Virtual {
+        return;
+    }
+
+    unsafe { asm!("msr CNTV_CTL_EL0, {}", in(reg) 0u64, options(nomem, nostack)) };
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
 
+impl exception::asynchronous::interface::IRQHandler for SleepWakeup {
+    fn handle(&self) -> Result<(), &'static str> {
+        // The timer IRQ is level triggered. Silence it, the sleeping code rechecks the deadline.
+        let kind = counter_kind();
+        set_timer_ctl(kind, timer_ctl(kind) | CTL_IMASK);
+
+        Ok(())
+    }
+}
+
 impl time::interface::TimeManager for GenericTimer {
     fn resolution(&self) -> Duration {
-        Duration::from_nanos(NS_PER_S / (CNTFRQ_EL0.get() as u64))
+        Duration::from_nanos(NS_PER_S / frequency())
     }
 
     fn uptime(&self) -> Duration {
-        let frq: u64 = CNTFRQ_EL0.get() as u64;
-        let current_count: u64 = CNTPCT_EL0.get() * NS_PER_S;
+        let frq: u64 = frequency();
+        let current_count: u64 = ticks() * NS_PER_S;
 
         Duration::from_nanos(current_count / frq)
     }
@@ -59,7 +445,7 @@ impl time::interface::TimeManager for GenericTimer {
         }
 
         // Calculate the register compare value.
-        let frq = CNTFRQ_EL0.get() as u64;
+        let frq = frequency();
         let x = match frq.checked_mul(duration.as_nanos() as u64) {
             None => {
                 warn!("Spin duration too long, skipping");
@@ -87,15 +473,20 @@ impl time::interface::TimeManager for GenericTimer {
         }
 
         // Set the compare value register.
-        CNTP_TVAL_EL0.set(tval as u32);
+        let kind = counter_kind();
+        set_timer_tval(kind, tval as u32);
 
         // Kick off the counting.                       // Disable timer interrupt.
-        CNTP_CTL_EL0.modify(CNTP_CTL_EL0::ENABLE::SET + CNTP_CTL_EL0::IMASK::SET);
+        set_timer_ctl(kind, CTL_ENABLE | CTL_IMASK);
 
         // ISTATUS will be '1' when cval ticks have passed. Busy-check it.
-        while !CNTP_CTL_EL0.matches_all(CNTP_CTL_EL0::ISTATUS::SET) {}
+        while timer_ctl(kind) & CTL_ISTATUS == 0 {}
 
         // Disable counting again.
-        CNTP_CTL_EL0.modify(CNTP_CTL_EL0::ENABLE::CLEAR);
+        set_timer_ctl(kind, timer_ctl(kind) & !CTL_ENABLE);
+    }
+
+    fn sleep(&self, duration: Duration) {
+        sleep_until(deadline_ticks(duration));
     }
 }
@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Self-tests and micro benchmarks.
+
+use crate::{bsp, console, info, pmu, time, time::interface::TimeManager};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const NS_PER_S: u64 = 1_000_000_000;
+
+/// Bits on the wire per character with 8N1 framing: One start bit, eight data bits, one stop bit.
+const BITS_PER_CHAR_8N1: u64 = 10;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Result of a throughput measurement.
+pub struct Throughput {
+    /// Number of bytes that were transferred.
+    pub bytes: usize,
+
+    /// Time it took to transfer `bytes`.
+    pub elapsed: Duration,
+
+    /// The theoretical maximum for the configuration under test, in bytes per second.
+    pub max_bytes_per_sec: u64,
+}
+
+/// Result of running a code section under the performance monitors.
+pub struct PmuMeasurement {
+    /// Number of times the code section ran.
+    pub iterations: usize,
+
+    /// Time all iterations took.
+    pub elapsed: Duration,
+
+    /// CPU cycles of all iterations.
+    pub cycles: u64,
+
+    /// Instructions retired in all iterations.
+    pub instructions: u64,
+
+    /// L1 data cache refills, i.e. misses, of all iterations.
+    pub l1d_misses: u64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Throughput {
+    /// The measured throughput in bytes per second, saturating at `u64::max_value()`, e.g. if no
+    /// time passed.
+    pub fn bytes_per_sec(&self) -> u64 {
+        let elapsed_ns = self.elapsed.as_nanos() as u64;
+        if elapsed_ns == 0 {
+            return u64::max_value();
+        }
+
+        (self.bytes as u64).saturating_mul(NS_PER_S) / elapsed_ns
+    }
+
+    /// The measured throughput as a percentage of the theoretical maximum, saturating like
+    /// `bytes_per_sec()`.
+    pub fn percent_of_max(&self) -> u64 {
+        self.bytes_per_sec().saturating_mul(100) / self.max_bytes_per_sec
+    }
+}
+
+/// Measure the console's transmit throughput.
+///
+/// Sends `num_bytes` times the character `U` and waits until the last one has left the wire. `U`
+/// (0x55) produces a square wave on the TX line, which makes it easy to verify the actual baud rate
+/// with a logic analyzer or scope as well.
+///
+/// The theoretical maximum is derived from the console's configured baud rate, assuming 8N1
+/// framing. A result far below it indicates a misconfigured baud rate divisor.
+pub fn console_tx_throughput(num_bytes: usize) -> Throughput {
+    use console::interface::Write;
+
+    let con = bsp::console::console();
+
+    // Start from an empty TX FIFO so that earlier output does not skew the measurement.
+    con.flush();
+
+    let start = time::time_manager().uptime();
+    for _ in 0..num_bytes {
+        con.write_char('U');
+    }
+    con.flush();
+    let elapsed = time::time_manager().uptime() - start;
+
+    con.write_char('\n');
+
+    Throughput {
+        bytes: num_bytes,
+        elapsed,
+        max_bytes_per_sec: u64::from(bsp::console::baud_rate()) / BITS_PER_CHAR_8N1,
+    }
+}
+
+impl PmuMeasurement {
+    /// The L1 data cache misses of one iteration, on average.
+    pub fn misses_per_iteration(&self) -> u64 {
+        if self.iterations == 0 {
+            return 0;
+        }
+
+        self.l1d_misses / self.iterations as u64
+    }
+}
+
+/// Run `f` `iterations` times and count the time, cycles, instructions and L1D cache misses.
+///
+/// `pmu::init()` must have been called on the executing core.
+pub fn measure_pmu(iterations: usize, mut f: impl FnMut()) -> PmuMeasurement {
+    use pmu::Event;
+
+    let start = time::time_manager().uptime();
+    let cycles = pmu::read(Event::Cycles);
+    let instructions = pmu::read(Event::InstructionsRetired);
+    let l1d_misses = pmu::read(Event::L1DCacheRefill);
+
+    for _ in 0..iterations {
+        f();
+    }
+
+    let l1d_misses = pmu::delta(
+        Event::L1DCacheRefill,
+        l1d_misses,
+        pmu::read(Event::L1DCacheRefill),
+    );
+    let instructions = pmu::delta(
+        Event::InstructionsRetired,
+        instructions,
+        pmu::read(Event::InstructionsRetired),
+    );
+    let cycles = pmu::delta(Event::Cycles, cycles, pmu::read(Event::Cycles));
+    let elapsed = time::time_manager().uptime() - start;
+
+    PmuMeasurement {
+        iterations,
+        elapsed,
+        cycles,
+        instructions,
+        l1d_misses,
+    }
+}
+
+/// Print a PMU measurement.
+pub fn print_pmu_measurement(name: &str, m: &PmuMeasurement) {
+    info!(
+        "{}: {} iterations in {} us, {} cycles, {} instructions, {} L1D misses ({}/iteration)",
+        name,
+        m.iterations,
+        m.elapsed.as_micros(),
+        m.cycles,
+        m.instructions,
+        m.l1d_misses,
+        m.misses_per_iteration()
+    );
+}
+
+/// Print a throughput measurement.
+pub fn print_throughput(name: &str, throughput: &Throughput) {
+    info!(
+        "{}: {} bytes in {} us -> {} bytes/s ({} % of theoretical {} bytes/s)",
+        name,
+        throughput.bytes,
+        throughput.elapsed.as_micros(),
+        throughput.bytes_per_sec(),
+        throughput.percent_of_max(),
+        throughput.max_bytes_per_sec
+    );
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// A transfer that took no measurable time must saturate instead of overflowing.
+    #[kernel_test]
+    fn zero_elapsed_saturates() {
+        let throughput = Throughput {
+            bytes: 16,
+            elapsed: Duration::from_secs(0),
+            max_bytes_per_sec: 1,
+        };
+
+        assert_eq!(throughput.bytes_per_sec(), u64::max_value());
+        assert_eq!(throughput.percent_of_max(), u64::max_value());
+    }
+}
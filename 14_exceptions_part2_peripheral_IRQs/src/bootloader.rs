@@ -0,0 +1,257 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A RAM loader that receives an image from a host tool over framed UART and executes it.
+//!
+//! This allows trying out a new build without swapping the SD card. The kernel enters the loader if
+//! the host sends [`BOOT_KEY`] within [`BOOT_KEY_WINDOW`] of the end of the kernel init, see
+//! [`boot_key_pressed()`].
+//!
+//! # Protocol
+//!
+//! All messages are frames of [`serial::Framer`], the first payload byte tells their kind:
+//!
+//! 1. The host sends a header: `H`, the load address as `u64`, the image size as `u32` and the
+//!    image's SHA-256 digest. All integers are little endian.
+//! 2. The kernel acknowledges it with `A` and the offset of the next expected byte as `u32`, `0`
+//!    here.
+//! 3. The host sends the image in chunks: `D`, the chunk's offset as `u32` and up to
+//!    [`MAX_CHUNK_SIZE`] bytes of data. The kernel acknowledges each one like the header.
+//! 4. After the last chunk, the kernel checks the integrity of the whole image against the digest,
+//!    answers with `V` and jumps to the load address with `exec_payload()`.
+//!
+//! The digest only detects transfer errors. It does not authenticate the host, which sends the
+//! digest along with the image.
+//!
+//! Either side may answer a corrupted frame with a retransmit request of the framer, and the other
+//! side then sends its last frame again. If an acknowledgement gets lost, the host sends the last
+//! chunk again, which the kernel acknowledges without writing it a second time. On any other error,
+//! the kernel sends `E` and a message, and waits for a new header.
+
+use crate::{console, exec, loader, println, serial, serial::FrameError, time};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const KIND_HEADER: u8 = b'H';
+const KIND_ACK: u8 = b'A';
+const KIND_DATA: u8 = b'D';
+const KIND_VERIFIED: u8 = b'V';
+const KIND_ERROR: u8 = b'E';
+
+/// Kind, address, size and digest.
+const HEADER_SIZE: usize = 1 + 8 + 4 + loader::DIGEST_SIZE;
+
+/// Kind and offset.
+const CHUNK_HEADER_SIZE: usize = 1 + 4;
+
+/// How long the host may pause within a frame, or between frames, before the upload is abandoned.
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How often a frame is sent or received again before the upload is abandoned.
+const MAX_ATTEMPTS: usize = 5;
+
+/// The upload's header.
+struct Header {
+    addr: usize,
+    len: usize,
+    digest: [u8; loader::DIGEST_SIZE],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The key that the host sends to enter the loader, `Ctrl-B`.
+pub const BOOT_KEY: u8 = 0x02;
+
+/// How long the kernel waits for `BOOT_KEY` during boot.
+pub const BOOT_KEY_WINDOW: Duration = Duration::from_millis(100);
+
+/// The largest amount of image data in a chunk.
+pub const MAX_CHUNK_SIZE: usize = serial::MAX_PAYLOAD_SIZE - CHUNK_HEADER_SIZE;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn u32_from_le(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+impl Header {
+    fn parse(frame: &[u8]) -> Result<Self, &'static str> {
+        if frame.len() != HEADER_SIZE || frame[0] != KIND_HEADER {
+            return Err("Expected a header");
+        }
+
+        let mut addr = [0u8; 8];
+        addr.copy_from_slice(&frame[1..9]);
+        let mut digest = [0u8; loader::DIGEST_SIZE];
+        digest.copy_from_slice(&frame[13..]);
+
+        Ok(Self {
+            addr: u64::from_le_bytes(addr) as usize,
+            len: u32_from_le(&frame[9..13]) as usize,
+            digest,
+        })
+    }
+}
+
+fn frame_error_msg(error: FrameError) -> &'static str {
+    match error {
+        FrameError::Timeout => "Timed out waiting for the host",
+        FrameError::TooLong => "Frame too long",
+        FrameError::Malformed | FrameError::Crc => "Too many corrupted frames",
+        FrameError::RetransmitRequested => "Too many retransmit requests",
+    }
+}
+
+/// Send `reply`, and receive the host's next frame into `buf`.
+///
+/// `reply` is sent again whenever the host requests a retransmit.
+fn exchange<C>(
+    framer: &mut serial::Framer<C>,
+    reply: &[u8],
+    buf: &mut [u8],
+) -> Result<usize, &'static str>
+where
+    C: console::interface::Write + console::interface::Read + ?Sized,
+{
+    for _ in 0..MAX_ATTEMPTS {
+        framer.send_frame(reply).map_err(frame_error_msg)?;
+
+        match framer.recv_frame_retrying(buf, MAX_ATTEMPTS) {
+            Err(FrameError::RetransmitRequested) => continue,
+            result => return result.map_err(frame_error_msg),
+        }
+    }
+
+    Err(frame_error_msg(FrameError::RetransmitRequested))
+}
+
+/// An acknowledgement of everything before `offset`.
+fn ack(offset: usize) -> [u8; CHUNK_HEADER_SIZE] {
+    let mut frame = [KIND_ACK; CHUNK_HEADER_SIZE];
+    frame[1..].copy_from_slice(&(offset as u32).to_le_bytes());
+
+    frame
+}
+
+/// Receive the chunks of the image into `dest` and verify it.
+fn receive_image<C>(
+    framer: &mut serial::Framer<C>,
+    dest: &mut [u8],
+    digest: &[u8; loader::DIGEST_SIZE],
+) -> Result<(), &'static str>
+where
+    C: console::interface::Write + console::interface::Read + ?Sized,
+{
+    let mut buf = [0u8; serial::MAX_PAYLOAD_SIZE];
+    let mut offset = 0;
+
+    while offset < dest.len() {
+        let len = exchange(framer, &ack(offset), &mut buf)?;
+        if len <= CHUNK_HEADER_SIZE || buf[0] != KIND_DATA {
+            return Err("Expected a data chunk");
+        }
+
+        let chunk_offset = u32_from_le(&buf[1..CHUNK_HEADER_SIZE]) as usize;
+        let data = &buf[CHUNK_HEADER_SIZE..len];
+
+        // The host did not get the last acknowledgement, and sent the previous chunk again.
+        if chunk_offset < offset {
+            continue;
+        }
+        if chunk_offset > offset {
+            return Err("Chunk out of order");
+        }
+        if data.len() > dest.len() - offset {
+            return Err("Chunk beyond the end of the image");
+        }
+
+        dest[offset..(offset + data.len())].copy_from_slice(data);
+        offset += data.len();
+    }
+
+    if !loader::verify(dest, digest) {
+        return Err("Image does not match its digest");
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Whether the host sent `BOOT_KEY` within `BOOT_KEY_WINDOW`.
+///
+/// Other input that arrives meanwhile is dropped. The console's RX IRQ must not be taken while
+/// this waits, see `console::interface::Read::read_char_timeout()`.
+pub fn boot_key_pressed<C>(console: &C) -> bool
+where
+    C: console::interface::Read + ?Sized,
+{
+    time::with_timeout(BOOT_KEY_WINDOW, || {
+        console.read_byte_nb().filter(|b| *b == BOOT_KEY)
+    })
+    .is_ok()
+}
+
+/// Receive one image and return its load address, which is also its entry point.
+///
+/// The image is verified, but not executed yet.
+pub fn receive<C>(framer: &mut serial::Framer<C>) -> Result<usize, &'static str>
+where
+    C: console::interface::Write + console::interface::Read + ?Sized,
+{
+    let mut buf = [0u8; serial::MAX_PAYLOAD_SIZE];
+
+    // The host may take its time to start the upload.
+    let len = loop {
+        match framer.recv_frame_retrying(&mut buf, MAX_ATTEMPTS) {
+            Err(FrameError::Timeout) => continue,
+            result => break result.map_err(frame_error_msg)?,
+        }
+    };
+    let header = Header::parse(&buf[..len])?;
+
+    exec::load_payload_at(header.addr, header.len, |dest| {
+        receive_image(framer, dest, &header.digest)
+    })
+}
+
+/// Receive images until one arrives intact, and execute it. Does not return.
+///
+/// IRQs must be masked, see `serial`.
+pub fn run<C>(console: &C) -> !
+where
+    C: console::interface::Write + console::interface::Read + ?Sized,
+{
+    let mut framer = serial::Framer::new(console, TIMEOUT);
+
+    println!("Waiting for image");
+
+    loop {
+        match receive(&mut framer) {
+            Ok(entry) => {
+                let _ = framer.send_frame(&[KIND_VERIFIED]);
+                console.flush();
+
+                // `receive()` verified the image.
+                unsafe { exec::exec_payload(entry, None) }
+            }
+            Err(msg) => {
+                let mut frame = [KIND_ERROR; 64];
+                let len = core::cmp::min(msg.len(), frame.len() - 1);
+                frame[1..=len].copy_from_slice(&msg.as_bytes()[..len]);
+
+                let _ = framer.send_frame(&frame[..=len]);
+            }
+        }
+    }
+}
@@ -75,11 +75,26 @@
 //!         CPU interface number. Of the banked interrupt IDs:
 //!           - 00..15 SGIs
 //!           - 16..31 PPIs
+//!
+//! # Security state
+//!
+//! With the Security Extensions, each interrupt is either in Group 0, which is Secure, or in Group
+//! 1, which is Non-secure. Non-secure software can neither see nor reconfigure Group 0 interrupts,
+//! and they never reach it.
+//!
+//! The RPi 4 firmware's armstub runs in EL3, puts all interrupts into Group 1 and enters the kernel
+//! in non-secure EL2. The driver relies on that then, and records a fault if it finds otherwise.
+//! Should the kernel run in the secure state instead, the driver puts all interrupts into Group 0
+//! itself.
 
 mod gicc;
 mod gicd;
 
-use crate::{bsp, cpu, driver, exception, synchronization, synchronization::InitStateLock};
+use crate::{
+    bsp, cpu, cpu::SecurityState, driver, exception, synchronization,
+    synchronization::InitStateLock,
+};
+use core::ops::Range;
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -94,6 +109,18 @@ type HandlerTable = [Option<exception::asynchronous::IRQDescriptor>; GICv2::NUM_
 /// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
 pub type IRQNumber = exception::asynchronous::IRQNumber<{ GICv2::MAX_IRQ_NUMBER }>;
 
+/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct SavedIrqState {
+    enable: [u32; GICv2::NUM_ENABLE_REGS],
+}
+
+/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct PendingIrqState {
+    pending: [u32; GICv2::NUM_ENABLE_REGS],
+}
+
 /// Representation of the GIC.
 pub struct GICv2 {
     /// The Distributor.
@@ -104,15 +131,40 @@ pub struct GICv2 {
 
     /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
     handler_table: InitStateLock<HandlerTable>,
+
+    /// Used for pending IRQs without a registered handler.
+    fallback: exception::asynchronous::IRQFallback<IRQNumber>,
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl PendingIrqState {
+    /// Return whether `irq` is pending.
+    pub fn contains(&self, irq: IRQNumber) -> bool {
+        let irq = irq.get();
+
+        self.pending[irq / 32] & (1 << (irq % 32)) != 0
+    }
+
+    /// Return whether no IRQ is pending.
+    pub fn is_empty(&self) -> bool {
+        self.pending.iter().all(|word| *word == 0)
+    }
+
+    /// Iterate over the pending IRQs, in ascending order.
+    pub fn iter(&self) -> impl Iterator<Item = IRQNumber> + '_ {
+        (0..GICv2::NUM_IRQS)
+            .filter(move |irq| self.pending[irq / 32] & (1 << (irq % 32)) != 0)
+            .map(IRQNumber::new)
+    }
+}
+
 impl GICv2 {
     const MAX_IRQ_NUMBER: usize = 300; // Normally 1019, but keep it lower to save some space.
     const NUM_IRQS: usize = Self::MAX_IRQ_NUMBER + 1;
+    const NUM_ENABLE_REGS: usize = (Self::NUM_IRQS + 31) / 32;
 
     /// Create an instance.
     ///
@@ -124,8 +176,24 @@ impl GICv2 {
             gicd: gicd::GICD::new(gicd_base_addr),
             gicc: gicc::GICC::new(gicc_base_addr),
             handler_table: InitStateLock::new([None; Self::NUM_IRQS]),
+            fallback: exception::asynchronous::IRQFallback::new(),
+        }
+    }
+
+    /// The security state that the GIC is set up for.
+    ///
+    /// Taken from `cpu::security_state()`, or probed at the distributor if the CPU can not tell.
+    pub fn security_state(&self) -> SecurityState {
+        match cpu::security_state() {
+            SecurityState::Unknown => self.gicd.probe_security_state(),
+            state => state,
         }
     }
+
+    /// Check whether the interrupt groups are the ones that `security_state()` receives.
+    pub fn groups_match_security_state(&self) -> bool {
+        self.gicd.groups_match(self.security_state())
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -138,9 +206,14 @@ impl driver::interface::DeviceDriver for GICv2 {
         "GICv2 (ARM Generic Interrupt Controller v2)"
     }
 
+    /// The Distributor and the CPU Interface are adjacent, so they are reported as one region.
+    fn mmio_region(&self) -> Option<Range<usize>> {
+        Some(self.gicd.mmio_range().start..self.gicc.mmio_range().end)
+    }
+
     fn init(&self) -> Result<(), ()> {
         if cpu::smp::core_id::<usize>() == bsp::cpu::BOOT_CORE_ID {
-            self.gicd.boot_core_init();
+            self.gicd.boot_core_init(self.security_state());
         }
 
         self.gicc.priority_accept_all();
@@ -152,6 +225,8 @@ impl driver::interface::DeviceDriver for GICv2 {
 
 impl exception::asynchronous::interface::IRQManager for GICv2 {
     type IRQNumberType = IRQNumber;
+    type SavedIrqState = SavedIrqState;
+    type PendingIrqState = PendingIrqState;
 
     fn register_handler(
         &self,
@@ -172,10 +247,48 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
         })
     }
 
+    fn deregister_handler(&self, irq_number: Self::IRQNumberType) -> Result<(), &'static str> {
+        self.disable(irq_number);
+
+        let mut r = &self.handler_table;
+        r.write(|table| match table[irq_number.get()].take() {
+            None => Err("No IRQ handler registered"),
+            Some(_) => Ok(()),
+        })
+    }
+
     fn enable(&self, irq_number: Self::IRQNumberType) {
         self.gicd.enable(irq_number);
     }
 
+    fn disable(&self, irq_number: Self::IRQNumberType) {
+        self.gicd.disable(irq_number);
+    }
+
+    fn is_enabled(&self, irq_number: Self::IRQNumberType) -> bool {
+        self.gicd.is_enabled(irq_number)
+    }
+
+    fn pending(&self) -> Self::PendingIrqState {
+        PendingIrqState {
+            pending: self.gicd.pending(),
+        }
+    }
+
+    fn disable_all(&self) -> Self::SavedIrqState {
+        SavedIrqState {
+            enable: self.gicd.disable_all(),
+        }
+    }
+
+    fn restore_all(&self, state: Self::SavedIrqState) {
+        self.gicd.restore_all(&state.enable);
+    }
+
+    fn set_fallback(&self, f: fn(Self::IRQNumberType)) {
+        self.fallback.set(f);
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         ic: &exception::asynchronous::IRQContext<'irq_context>,
@@ -189,14 +302,22 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
             return;
         }
 
-        // Call the IRQ handler. Panic if there is none.
+        // Call the IRQ handler. Use the fallback or mask the IRQ if there is none.
         let mut r = &self.handler_table;
         r.read(|table| {
             match table[irq_number] {
-                None => panic!("No handler registered for IRQ {}", irq_number),
+                None => {
+                    let irq = IRQNumber::new(irq_number);
+
+                    if !self.fallback.handle(irq) {
+                        self.gicd.disable(irq);
+                    }
+                }
                 Some(descriptor) => {
-                    // Call the IRQ handler. Panics on failure.
-                    descriptor.handler.handle().expect("Error handling IRQ");
+                    // Mask the IRQ of a handler that panicked.
+                    if !exception::asynchronous::call_handler(&descriptor) {
+                        self.gicd.disable(IRQNumber::new(irq_number));
+                    }
                 }
             }
         });
@@ -218,5 +339,24 @@ impl exception::asynchronous::interface::IRQManager for GICv2 {
                 }
             }
         });
+
+        info!(
+            "      Masked IRQs without handler: {}",
+            self.fallback.num_unhandled()
+        );
+    }
+
+    fn print_pending(&self) {
+        use crate::info;
+
+        info!("      Pending:");
+
+        let mut r = &self.handler_table;
+        r.read(|table| {
+            for irq in self.pending().iter() {
+                let name = table[irq.get()].map_or("No handler", |handler| handler.name);
+                info!("            {: >3}. {}", irq, name);
+            }
+        });
     }
 }
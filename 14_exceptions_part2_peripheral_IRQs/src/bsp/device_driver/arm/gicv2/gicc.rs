@@ -5,6 +5,7 @@
 //! GICC Driver - GIC CPU interface.
 
 use crate::{bsp::device_driver::common::MMIODerefWrapper, exception};
+use core::ops::Range;
 use register::{mmio::*, register_bitfields, register_structs};
 
 //--------------------------------------------------------------------------------------------------
@@ -75,6 +76,11 @@ impl GICC {
         }
     }
 
+    /// Return the address range of the MMIO registers.
+    pub fn mmio_range(&self) -> Range<usize> {
+        self.registers.mmio_range()
+    }
+
     /// Accept interrupts of any priority.
     ///
     /// Quoting the GICv2 Architecture Specification:
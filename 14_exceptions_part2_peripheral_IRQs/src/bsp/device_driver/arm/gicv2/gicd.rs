@@ -8,9 +8,10 @@
 //!   - SPI - Shared Peripheral Interrupt.
 
 use crate::{
-    bsp::device_driver::common::MMIODerefWrapper, state, synchronization,
-    synchronization::IRQSafeNullLock,
+    bsp::device_driver::common::MMIODerefWrapper, cpu::SecurityState, fault, state,
+    synchronization, synchronization::IRQSafeNullLock,
 };
+use core::ops::Range;
 use register::{mmio::*, register_bitfields, register_structs};
 
 //--------------------------------------------------------------------------------------------------
@@ -22,7 +23,11 @@ register_bitfields! {
 
     /// Distributor Control Register
     CTLR [
-        Enable OFFSET(0) NUMBITS(1) []
+        /// Enables Group 1 in the Non-secure view, and Group 0 in the Secure view.
+        Enable OFFSET(0) NUMBITS(1) [],
+
+        /// Enables Group 1 in the Secure view. RAZ/WI for Non-secure accesses.
+        EnableGrp1 OFFSET(1) NUMBITS(1) []
     ],
 
     /// Interrupt Controller Type Register
@@ -45,8 +50,14 @@ register_structs! {
         (0x000 => CTLR: ReadWrite<u32, CTLR::Register>),
         (0x004 => TYPER: ReadOnly<u32, TYPER::Register>),
         (0x008 => _reserved1),
+        (0x084 => IGROUPR: [ReadWrite<u32>; 31]),
+        (0x100 => _reserved2),
         (0x104 => ISENABLER: [ReadWrite<u32>; 31]),
-        (0x108 => _reserved2),
+        (0x180 => _reserved3),
+        (0x184 => ICENABLER: [WriteOnly<u32>; 31]),
+        (0x200 => _reserved4),
+        (0x204 => ISPENDR: [ReadOnly<u32>; 31]),
+        (0x280 => _reserved5),
         (0x820 => ITARGETSR: [ReadWrite<u32, ITARGETSR::Register>; 248]),
         (0xBFC => @END),
     }
@@ -56,8 +67,14 @@ register_structs! {
     #[allow(non_snake_case)]
     BankedRegisterBlock {
         (0x000 => _reserved1),
+        (0x080 => IGROUPR: ReadWrite<u32>),
+        (0x084 => _reserved2),
         (0x100 => ISENABLER: ReadWrite<u32>),
-        (0x104 => _reserved2),
+        (0x104 => _reserved3),
+        (0x180 => ICENABLER: WriteOnly<u32>),
+        (0x184 => _reserved4),
+        (0x200 => ISPENDR: ReadOnly<u32>),
+        (0x204 => _reserved5),
         (0x800 => ITARGETSR: [ReadOnly<u32, ITARGETSR::Register>; 8]),
         (0xBFC => @END),
     }
@@ -96,6 +113,12 @@ impl SharedRegisters {
         ((self.TYPER.read(TYPER::ITLinesNumber) as usize) + 1) * 32
     }
 
+    /// Return the number of implemented shared ISENABLER, capped at what the driver supports.
+    #[inline(always)]
+    fn num_shared_enable_regs(&mut self) -> usize {
+        core::cmp::min(self.num_irqs() / 32, super::GICv2::NUM_ENABLE_REGS) - 1
+    }
+
     /// Return a slice of the implemented ITARGETSR.
     #[inline(always)]
     fn implemented_itargets_slice(&mut self) -> &[ReadWrite<u32, ITARGETSR::Register>] {
@@ -131,6 +154,15 @@ impl GICD {
         }
     }
 
+    /// Return the address range of the MMIO registers.
+    pub fn mmio_range(&self) -> Range<usize> {
+        let mut r = &self.shared_registers;
+        let shared = r.lock(|regs| regs.mmio_range());
+        let banked = self.banked_registers.mmio_range();
+
+        shared.start.min(banked.start)..shared.end.max(banked.end)
+    }
+
     /// Use a banked ITARGETSR to retrieve the executing core's GIC target mask.
     ///
     /// Quoting the GICv2 Architecture Specification:
@@ -141,13 +173,87 @@ impl GICD {
         self.banked_registers.ITARGETSR[0].read(ITARGETSR::Offset0)
     }
 
-    /// Route all SPIs to the boot core and enable the distributor.
-    pub fn boot_core_init(&self) {
+    /// Tell the security state from the view of the distributor that the executing core gets.
+    ///
+    /// `CTLR.EnableGrp1` only sticks for Secure accesses. The register is restored afterwards.
+    pub fn probe_security_state(&self) -> SecurityState {
+        let mut r = &self.shared_registers;
+        r.lock(|regs| {
+            let saved = regs.CTLR.get();
+
+            regs.CTLR.modify(CTLR::EnableGrp1::SET);
+            let state = if regs.CTLR.is_set(CTLR::EnableGrp1) {
+                SecurityState::Secure
+            } else {
+                SecurityState::NonSecure
+            };
+
+            regs.CTLR.set(saved);
+            state
+        })
+    }
+
+    /// The group that all interrupts must be in, as a mask for `IGROUPR`.
+    ///
+    /// Group 0 interrupts are Secure, so only Group 1 ones reach a non-secure kernel.
+    fn group_mask(state: SecurityState) -> u32 {
+        match state {
+            SecurityState::Secure => 0,
+            _ => u32::MAX,
+        }
+    }
+
+    /// Check whether all interrupts of the executing core's bank and all shared interrupts are in
+    /// the group that `state` receives.
+    ///
+    /// Non-secure accesses to `IGROUPR` are RAZ/WI, so the groups can only be checked in the Secure
+    /// state. In any other state, the check is skipped and passes.
+    pub fn groups_match(&self, state: SecurityState) -> bool {
+        if state != SecurityState::Secure {
+            return true;
+        }
+
+        let mask = Self::group_mask(state);
+
+        if self.banked_registers.IGROUPR.get() != mask {
+            return false;
+        }
+
+        let mut r = &self.shared_registers;
+        r.lock(|regs| (0..regs.num_shared_enable_regs()).all(|i| regs.IGROUPR[i].get() == mask))
+    }
+
+    /// Route all SPIs to the boot core, put the interrupts into the group for `state` and enable
+    /// the distributor.
+    ///
+    /// Non-secure accesses to `IGROUPR` are RAZ/WI, so in the non-secure state, the groups are left
+    /// to the firmware and can not be checked. If it kept some interrupts in Group 0, they will
+    /// never arrive. In the Secure state, groups that did not take the written values are recorded
+    /// as a fault.
+    pub fn boot_core_init(&self, state: SecurityState) {
         assert!(
             state::state_manager().state() == state::State::Init,
             "Only allowed during kernel init phase"
         );
 
+        // Secure Group 0 interrupts are signaled as IRQs as long as `GICC_CTLR.FIQEn` is clear.
+        if state == SecurityState::Secure {
+            let mask = Self::group_mask(state);
+
+            self.banked_registers.IGROUPR.set(mask);
+
+            let mut r = &self.shared_registers;
+            r.lock(|regs| {
+                for i in 0..regs.num_shared_enable_regs() {
+                    regs.IGROUPR[i].set(mask);
+                }
+            });
+        }
+
+        if !self.groups_match(state) {
+            fault::record_fault("GIC", "Interrupt groups could not be set");
+        }
+
         // Target all SPIs to the boot core only.
         let mask = self.local_gic_target_mask();
 
@@ -194,4 +300,94 @@ impl GICD {
             }
         }
     }
+
+    /// Disable an interrupt.
+    pub fn disable(&self, irq_num: super::IRQNumber) {
+        let irq_num = irq_num.get();
+        let disable_bit: u32 = 1u32 << (irq_num % 32);
+
+        // Writing a 1 to ICENABLER disables the IRQ, a 0 has no effect.
+        match irq_num {
+            // Private.
+            0..=31 => self.banked_registers.ICENABLER.set(disable_bit),
+            // Shared.
+            _ => {
+                let mut r = &self.shared_registers;
+                r.lock(|regs| regs.ICENABLER[(irq_num >> 5) - 1].set(disable_bit));
+            }
+        }
+    }
+
+    /// Return whether an interrupt is enabled.
+    pub fn is_enabled(&self, irq_num: super::IRQNumber) -> bool {
+        let irq_num = irq_num.get();
+        let enable_bit: u32 = 1u32 << (irq_num % 32);
+
+        // Reading ISENABLER returns the enable state of the IRQs.
+        let enable_mask = match irq_num {
+            // Private.
+            0..=31 => self.banked_registers.ISENABLER.get(),
+            // Shared.
+            _ => {
+                let mut r = &self.shared_registers;
+                r.lock(|regs| regs.ISENABLER[(irq_num >> 5) - 1].get())
+            }
+        };
+
+        enable_mask & enable_bit != 0
+    }
+
+    /// Disable all interrupts of the executing core's bank and all shared interrupts.
+    ///
+    /// Returns the previous enable masks, indexed like the IRQ numbers, i.e. entry 0 is the banked
+    /// register.
+    pub fn disable_all(&self) -> [u32; super::GICv2::NUM_ENABLE_REGS] {
+        let mut saved = [0; super::GICv2::NUM_ENABLE_REGS];
+
+        saved[0] = self.banked_registers.ISENABLER.get();
+        self.banked_registers.ICENABLER.set(u32::MAX);
+
+        let mut r = &self.shared_registers;
+        r.lock(|regs| {
+            for i in 0..regs.num_shared_enable_regs() {
+                saved[i + 1] = regs.ISENABLER[i].get();
+                regs.ICENABLER[i].set(u32::MAX);
+            }
+        });
+
+        saved
+    }
+
+    /// Return the pending state of the executing core's bank and of all shared interrupts.
+    ///
+    /// Indexed like `disable_all()`. Reading ISPENDR has no side effects, unlike acknowledging an
+    /// interrupt through the CPU interface.
+    pub fn pending(&self) -> [u32; super::GICv2::NUM_ENABLE_REGS] {
+        let mut pending = [0; super::GICv2::NUM_ENABLE_REGS];
+
+        pending[0] = self.banked_registers.ISPENDR.get();
+
+        let mut r = &self.shared_registers;
+        r.lock(|regs| {
+            for i in 0..regs.num_shared_enable_regs() {
+                pending[i + 1] = regs.ISPENDR[i].get();
+            }
+        });
+
+        pending
+    }
+
+    /// Restore enable masks that were returned by `disable_all()`.
+    pub fn restore_all(&self, saved: &[u32; super::GICv2::NUM_ENABLE_REGS]) {
+        // Writing a 1 to ISENABLER enables the IRQ, a 0 has no effect. Everything is disabled
+        // since `disable_all()`, so this reproduces the saved masks exactly.
+        self.banked_registers.ISENABLER.set(saved[0]);
+
+        let mut r = &self.shared_registers;
+        r.lock(|regs| {
+            for i in 0..regs.num_shared_enable_regs() {
+                regs.ISENABLER[i].set(saved[i + 1]);
+            }
+        });
+    }
 }
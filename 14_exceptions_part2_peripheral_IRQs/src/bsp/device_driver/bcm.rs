@@ -4,14 +4,22 @@
 
 //! BCM driver top level.
 
+mod bcm2xxx_aux;
+mod bcm2xxx_dma;
 mod bcm2xxx_gpio;
 #[cfg(feature = "bsp_rpi3")]
 mod bcm2xxx_interrupt_controller;
 mod bcm2xxx_mbox;
 mod bcm2xxx_pl011_uart;
+mod bcm2xxx_pm;
+mod bcm2xxx_system_timer;
 
+pub use bcm2xxx_aux::*;
+pub use bcm2xxx_dma::*;
 pub use bcm2xxx_gpio::*;
 #[cfg(feature = "bsp_rpi3")]
 pub use bcm2xxx_interrupt_controller::*;
 pub use bcm2xxx_mbox::*;
 pub use bcm2xxx_pl011_uart::*;
+pub use bcm2xxx_pm::*;
+pub use bcm2xxx_system_timer::*;
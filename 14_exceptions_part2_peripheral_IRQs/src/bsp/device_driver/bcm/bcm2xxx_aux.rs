@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Auxiliary peripherals Driver.
+//!
+//! The mini UART, SPI1 and SPI2 share a single enable register (AUXENB). Writing it naively, e.g.
+//! `AUXENB = MINI_UART` while bringing up the mini UART, silently switches off SPI1 and SPI2. All
+//! drivers for aux peripherals must therefore enable and disable their device through this driver,
+//! which keeps track of the enabled peripherals and only ever changes the requested bit.
+
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper, driver, synchronization,
+    synchronization::IRQSafeNullLock,
+};
+use core::ops::Range;
+use register::{mmio::*, register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+// Auxiliary peripherals registers.
+//
+// Descriptions taken from
+// https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+register_bitfields! {
+    u32,
+
+    /// Auxiliary enables
+    AUXENB [
+        /// SPI 2 enable. If set the SPI 2 module is enabled. If clear the SPI 2 module is disabled.
+        /// That also disables any SPI 2 module register access.
+        SPI2 OFFSET(2) NUMBITS(1) [],
+
+        /// SPI 1 enable. If set the SPI 1 module is enabled. If clear the SPI 1 module is disabled.
+        /// That also disables any SPI 1 module register access.
+        SPI1 OFFSET(1) NUMBITS(1) [],
+
+        /// Mini UART enable. If set the mini UART is enabled. If clear the mini UART is disabled.
+        /// That also disables any mini UART register access.
+        MINI_UART OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => AUX_IRQ: ReadOnly<u32>),
+        (0x04 => AUXENB: ReadWrite<u32, AUXENB::Register>),
+        (0x08 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+struct AuxInner {
+    registers: Registers,
+
+    /// Shadow copy of the enable bits that were handed out so far.
+    enabled: u32,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The peripherals that live in the auxiliary block.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum AuxPeripheral {
+    MiniUart,
+    Spi1,
+    Spi2,
+}
+
+/// Representation of the auxiliary peripherals block.
+pub struct Aux {
+    inner: IRQSafeNullLock<AuxInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl AuxPeripheral {
+    /// The peripheral's bit in the AUXENB register.
+    fn enable_bit(self) -> u32 {
+        match self {
+            AuxPeripheral::MiniUart => AUXENB::MINI_UART::SET.value,
+            AuxPeripheral::Spi1 => AUXENB::SPI1::SET.value,
+            AuxPeripheral::Spi2 => AUXENB::SPI2::SET.value,
+        }
+    }
+}
+
+impl AuxInner {
+    const ALL_ENABLE_BITS: u32 = 0b111;
+
+    const unsafe fn new(base_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(base_addr),
+            enabled: 0,
+        }
+    }
+
+    /// Write the shadow copy to the hardware.
+    fn update(&mut self, enabled: u32) {
+        self.enabled = enabled & Self::ALL_ENABLE_BITS;
+        self.registers.AUXENB.set(self.enabled);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Aux {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide the correct `base_addr`.
+    pub const unsafe fn new(base_addr: usize) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(AuxInner::new(base_addr)),
+        }
+    }
+
+    /// Enable an aux peripheral. Enable bits of the other aux peripherals are left untouched.
+    pub fn enable(&self, peripheral: AuxPeripheral) {
+        let mut r = &self.inner;
+        r.lock(|inner| {
+            let enabled = inner.enabled | peripheral.enable_bit();
+            inner.update(enabled)
+        })
+    }
+
+    /// Disable an aux peripheral. Enable bits of the other aux peripherals are left untouched.
+    pub fn disable(&self, peripheral: AuxPeripheral) {
+        let mut r = &self.inner;
+        r.lock(|inner| {
+            let enabled = inner.enabled & !peripheral.enable_bit();
+            inner.update(enabled)
+        })
+    }
+
+    /// Return whether an aux peripheral is enabled.
+    pub fn is_enabled(&self, peripheral: AuxPeripheral) -> bool {
+        (self.enabled_mask() & peripheral.enable_bit()) != 0
+    }
+
+    /// Return the AUXENB value that was last written to the hardware.
+    pub fn enabled_mask(&self) -> u32 {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.enabled)
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for Aux {
+    fn compatible(&self) -> &str {
+        "BCM Auxiliaries"
+    }
+
+    fn mmio_region(&self) -> Option<Range<usize>> {
+        let mut r = &self.inner;
+        Some(r.lock(|inner| inner.registers.mmio_range()))
+    }
+
+    fn init(&self) -> Result<(), ()> {
+        // The firmware might have enabled aux peripherals already, e.g. the mini UART when
+        // `enable_uart=1` is set in `config.txt`. Start tracking from the current hardware state.
+        let mut r = &self.inner;
+        r.lock(|inner| inner.enabled = inner.registers.AUXENB.get() & AuxInner::ALL_ENABLE_BITS);
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsp;
+    use test_macros::kernel_test;
+
+    /// Enabling an aux peripheral must not disable the other ones.
+    #[kernel_test]
+    fn enabling_two_aux_peripherals_keeps_both_enabled() {
+        bsp::AUX.enable(AuxPeripheral::MiniUart);
+        bsp::AUX.enable(AuxPeripheral::Spi1);
+
+        let both = AUXENB::MINI_UART::SET.value | AUXENB::SPI1::SET.value;
+        assert_eq!(bsp::AUX.enabled_mask() & both, both);
+
+        bsp::AUX.disable(AuxPeripheral::Spi1);
+        assert!(bsp::AUX.is_enabled(AuxPeripheral::MiniUart));
+        assert!(!bsp::AUX.is_enabled(AuxPeripheral::Spi1));
+    }
+}
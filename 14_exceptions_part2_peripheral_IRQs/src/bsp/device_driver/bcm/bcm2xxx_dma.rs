@@ -0,0 +1,366 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! DMA Controller Driver.
+//!
+//! Only memory-to-memory copies on a single channel are supported. The engine fetches its work from
+//! chains of control blocks in memory, which must be 32 byte aligned. All addresses that the engine
+//! sees are VideoCore bus addresses.
+//!
+//! Descriptions taken from
+//! https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+
+use crate::{
+    bsp::device_driver::common::{clear_bits, MMIODerefWrapper},
+    cpu, dma, driver, synchronization,
+    synchronization::IRQSafeNullLock,
+    time,
+};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use register::{mmio::*, register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Control and Status
+    CS [
+        /// Write 1 to reset the channel.
+        RESET OFFSET(31) NUMBITS(1) [],
+
+        /// Wait for the write responses of a transfer before signalling its end.
+        WAIT_FOR_OUTSTANDING_WRITES OFFSET(28) NUMBITS(1) [],
+
+        /// The channel has an error flag set in its DEBUG register.
+        ERROR OFFSET(8) NUMBITS(1) [],
+
+        /// Set when the transfer of the last control block completed. Write 1 to clear.
+        END OFFSET(1) NUMBITS(1) [],
+
+        /// Write 1 to start fetching from CONBLK_AD. Clears when the chain is done.
+        ACTIVE OFFSET(0) NUMBITS(1) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    ChannelRegisterBlock {
+        (0x00 => CS: ReadWrite<u32, CS::Register>),
+        (0x04 => CONBLK_AD: ReadWrite<u32>),
+        (0x08 => _reserved1),
+        (0x20 => DEBUG: ReadWrite<u32>),
+        (0x24 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    GlobalRegisterBlock {
+        (0x00 => _reserved1),
+        (0xFF0 => ENABLE: ReadWrite<u32>),
+        (0xFF4 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers of the used channel.
+type ChannelRegisters = MMIODerefWrapper<ChannelRegisterBlock>;
+
+/// Abstraction for the associated MMIO registers that are shared by all channels.
+type GlobalRegisters = MMIODerefWrapper<GlobalRegisterBlock>;
+
+/// A DMA control block, as fetched by the engine.
+#[derive(Copy, Clone)]
+#[repr(C, align(32))]
+struct ControlBlock {
+    transfer_info: u32,
+    source_ad: u32,
+    dest_ad: u32,
+    txfr_len: u32,
+    stride: u32,
+    nextconbk: u32,
+    _reserved: [u32; 2],
+}
+
+struct DMAInner {
+    channel: ChannelRegisters,
+    global: GlobalRegisters,
+    control_blocks: [ControlBlock; DMAInner::NUM_CONTROL_BLOCKS],
+    initialized: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the DMA controller.
+pub struct DMA {
+    base_addr: usize,
+    inner: IRQSafeNullLock<DMAInner>,
+
+    /// Held by a `memmove()` for the whole transfer, which the lock is not.
+    busy: AtomicBool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl ControlBlock {
+    /// Transfer info: Increment the source and destination address, wait for write responses.
+    const TI_MEMCPY: u32 = (1 << 8) | (1 << 4) | (1 << 3);
+
+    const fn zeroed() -> Self {
+        Self {
+            transfer_info: 0,
+            source_ad: 0,
+            dest_ad: 0,
+            txfr_len: 0,
+            stride: 0,
+            nextconbk: 0,
+            _reserved: [0; 2],
+        }
+    }
+}
+
+/// Translate an ARM physical address into the bus address that the DMA engine uses.
+///
+/// The `0xC` alias is the VideoCore's uncached view of the low 1 GiB of DRAM.
+fn bus_address(phys_addr: usize) -> u32 {
+    (phys_addr as u32 & 0x3FFF_FFFF) | DMA::BUS_ALIAS
+}
+
+impl DMAInner {
+    /// The number of control blocks that are chained per run of the engine.
+    const NUM_CONTROL_BLOCKS: usize = 64;
+
+    /// The largest transfer length of a single control block.
+    const MAX_CHUNK_LEN: usize = 1 << 29;
+
+    /// Upper bound for the duration of a single run of the engine.
+    const TIMEOUT: Duration = Duration::from_secs(1);
+
+    const fn new(channel_base_addr: usize, base_addr: usize) -> Self {
+        Self {
+            channel: unsafe { ChannelRegisters::new(channel_base_addr) },
+            global: unsafe { GlobalRegisters::new(base_addr) },
+            control_blocks: [ControlBlock::zeroed(); Self::NUM_CONTROL_BLOCKS],
+            initialized: false,
+        }
+    }
+
+    fn init(&mut self) {
+        self.global
+            .ENABLE
+            .set(self.global.ENABLE.get() | (1 << DMA::CHANNEL));
+        self.channel.CS.write(CS::RESET::SET);
+
+        self.initialized = true;
+    }
+
+    /// Chain the first `num` control blocks and start the engine on them.
+    fn start(&mut self, num: usize) {
+        for i in 0..num {
+            self.control_blocks[i].nextconbk = if i + 1 < num {
+                bus_address(&self.control_blocks[i + 1] as *const _ as usize)
+            } else {
+                0
+            };
+        }
+
+        // The engine fetches the control blocks from memory, not from the D-cache.
+        let cbs = &self.control_blocks[..num];
+        let cbs_range = cbs.as_ptr_range();
+        cpu::cache::clean_invalidate_dcache_range_to_poc(
+            cbs_range.start as usize..cbs_range.end as usize,
+        );
+
+        clear_bits(&self.channel.CS, CS::END::SET.value);
+        self.channel
+            .CONBLK_AD
+            .set(bus_address(self.control_blocks.as_ptr() as usize));
+        self.channel
+            .CS
+            .write(CS::WAIT_FOR_OUTSTANDING_WRITES::SET + CS::ACTIVE::SET);
+    }
+
+    /// The result of the run that `start()` began, `None` while it is still active.
+    fn poll(&mut self) -> Option<Result<(), dma::Error>> {
+        if self.channel.CS.is_set(CS::ACTIVE) {
+            if !self.channel.CS.is_set(CS::ERROR) {
+                return None;
+            }
+
+            self.abort();
+            return Some(Err(dma::Error::Failed));
+        }
+
+        if self.channel.CS.is_set(CS::ERROR) {
+            self.channel.DEBUG.set(self.channel.DEBUG.get());
+            return Some(Err(dma::Error::Failed));
+        }
+
+        Some(Ok(()))
+    }
+
+    /// Stop the engine.
+    fn abort(&mut self) {
+        self.channel.CS.write(CS::RESET::SET);
+    }
+}
+
+impl DMA {
+    /// Run the first `num` control blocks and wait for completion.
+    ///
+    /// The lock is only held to start the engine and to check on it, so that IRQs are not masked
+    /// for the whole transfer.
+    fn run(&self, num: usize) -> Result<(), dma::Error> {
+        use time::interface::TimeManager;
+
+        let mut r = &self.inner;
+        r.lock(|inner| inner.start(num));
+
+        let deadline = time::time_manager().uptime() + DMAInner::TIMEOUT;
+        loop {
+            if let Some(result) = r.lock(|inner| inner.poll()) {
+                return result;
+            }
+
+            if time::time_manager().uptime() > deadline {
+                r.lock(|inner| inner.abort());
+                return Err(dma::Error::Failed);
+            }
+
+            cpu::nop();
+        }
+    }
+
+    /// See [`dma::interface::DmaEngine::memmove`]. The caller must own the `busy` flag.
+    unsafe fn transfer(&self, dst: usize, src: usize, len: usize) -> Result<(), dma::Error> {
+        let mut r = &self.inner;
+        if !r.lock(|inner| inner.initialized) {
+            return Err(dma::Error::Unavailable);
+        }
+
+        if len == 0 || dst == src {
+            return Ok(());
+        }
+
+        // Chunks that are not larger than the distance of source and destination do not overlap
+        // with themselves. Executing them in the right order makes sure that no chunk overwrites
+        // source data that a later one still has to read.
+        let distance = if dst < src { src - dst } else { dst - src };
+        let chunk_len = core::cmp::min(distance, DMAInner::MAX_CHUNK_LEN);
+        let num_chunks = (len + chunk_len - 1) / chunk_len;
+
+        let dst_range = dst..(dst + len);
+        let src_range = src..(src + len);
+        cpu::cache::clean_invalidate_dcache_range_to_poc(src_range);
+        cpu::cache::clean_invalidate_dcache_range_to_poc(dst_range.clone());
+
+        let mut num = 0;
+        for i in 0..num_chunks {
+            // Ascending order when moving down, descending when moving up.
+            let chunk = if dst < src { i } else { num_chunks - 1 - i };
+            let offset = chunk * chunk_len;
+
+            let cb = ControlBlock {
+                transfer_info: ControlBlock::TI_MEMCPY,
+                source_ad: bus_address(src + offset),
+                dest_ad: bus_address(dst + offset),
+                txfr_len: core::cmp::min(chunk_len, len - offset) as u32,
+                ..ControlBlock::zeroed()
+            };
+            r.lock(|inner| inner.control_blocks[num] = cb);
+            num += 1;
+
+            if num == DMAInner::NUM_CONTROL_BLOCKS {
+                self.run(num)?;
+                num = 0;
+            }
+        }
+
+        if num > 0 {
+            self.run(num)?;
+        }
+
+        // Drop lines that were speculatively fetched while the engine was writing.
+        cpu::cache::clean_invalidate_dcache_range_to_poc(dst_range);
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl DMA {
+    /// The channel that the driver uses. It is not used by the firmware.
+    const CHANNEL: usize = 5;
+
+    /// Offset between two channels' register blocks.
+    const CHANNEL_STRIDE: usize = 0x100;
+
+    /// Bus address alias of the low 1 GiB of DRAM, bypassing the VideoCore's L2 cache.
+    const BUS_ALIAS: u32 = 0xC000_0000;
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide the correct `base_addr`.
+    pub const unsafe fn new(base_addr: usize) -> Self {
+        Self {
+            base_addr,
+            inner: IRQSafeNullLock::new(DMAInner::new(
+                base_addr + Self::CHANNEL * Self::CHANNEL_STRIDE,
+                base_addr,
+            )),
+            busy: AtomicBool::new(false),
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl driver::interface::DeviceDriver for DMA {
+    fn compatible(&self) -> &str {
+        "BCM DMA Controller"
+    }
+
+    /// The channels' register blocks and the global enable register share one 4 KiB page.
+    fn mmio_region(&self) -> Option<Range<usize>> {
+        Some(self.base_addr..(self.base_addr + 0x1000))
+    }
+
+    fn init(&self) -> Result<(), ()> {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.init());
+
+        Ok(())
+    }
+}
+
+impl dma::interface::DmaEngine for DMA {
+    unsafe fn memmove(&self, dst: usize, src: usize, len: usize) -> Result<(), dma::Error> {
+        if self.busy.swap(true, Ordering::Acquire) {
+            return Err(dma::Error::Unavailable);
+        }
+
+        let result = self.transfer(dst, src, len);
+        self.busy.store(false, Ordering::Release);
+
+        result
+    }
+}
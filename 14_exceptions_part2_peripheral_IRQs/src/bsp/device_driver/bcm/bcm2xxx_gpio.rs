@@ -8,6 +8,7 @@ use crate::{
     bsp::device_driver::common::MMIODerefWrapper, cpu, driver, synchronization,
     synchronization::IRQSafeNullLock,
 };
+use core::ops::Range;
 use register::{mmio::*, register_bitfields, register_structs};
 
 //--------------------------------------------------------------------------------------------------
@@ -65,6 +66,10 @@ register_structs! {
         (0x10 => GPFSEL4: ReadWrite<u32>),
         (0x14 => GPFSEL5: ReadWrite<u32>),
         (0x18 => _reserved1),
+        (0x1C => GPSET: [WriteOnly<u32>; 2]),
+        (0x24 => _reserved2),
+        (0x28 => GPCLR: [WriteOnly<u32>; 2]),
+        (0x30 => _reserved3),
         (0x94 => GPPUD: ReadWrite<u32>),
         (0x98 => GPPUDCLK0: ReadWrite<u32, GPPUDCLK0::Register>),
         (0x9C => GPPUDCLK1: ReadWrite<u32>),
@@ -75,6 +80,9 @@ register_structs! {
 /// Abstraction for the associated MMIO registers.
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
+/// The number of pins in each bank. Bank 1 has pins 32 to 53 only.
+const BANK_SIZES: [u32; GPIO::NUM_BANKS] = [32, 22];
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -84,11 +92,69 @@ pub struct GPIO {
     registers: IRQSafeNullLock<Registers>,
 }
 
+/// The GPSET and GPCLR masks for a batch of pin writes, indexed by bank.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PinMasks {
+    pub set: [u32; GPIO::NUM_BANKS],
+    pub clear: [u32; GPIO::NUM_BANKS],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Check that `bank` exists and `pins_mask` only contains pins of it.
+fn check_mask(pins_mask: u32, bank: u8) -> Result<usize, &'static str> {
+    let bank = bank as usize;
+    if bank >= GPIO::NUM_BANKS {
+        return Err("No such GPIO bank");
+    }
+
+    let size = BANK_SIZES[bank];
+    if size < 32 && pins_mask >> size != 0 {
+        return Err("Mask contains pins outside of the bank");
+    }
+
+    Ok(bank)
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl PinMasks {
+    /// Collect pin/value pairs into per bank masks. A pin that appears twice takes the last value.
+    pub fn from_pairs(pins: &[(u8, bool)]) -> Result<Self, &'static str> {
+        let mut masks = Self::default();
+
+        for &(pin, value) in pins {
+            if u32::from(pin) >= GPIO::NUM_PINS {
+                return Err("No such GPIO pin");
+            }
+
+            let bank = (pin / 32) as usize;
+            let bit = 1 << (pin % 32);
+
+            if value {
+                masks.set[bank] |= bit;
+                masks.clear[bank] &= !bit;
+            } else {
+                masks.clear[bank] |= bit;
+                masks.set[bank] &= !bit;
+            }
+        }
+
+        Ok(masks)
+    }
+}
+
 impl GPIO {
+    /// The number of banks of 32 pins.
+    pub const NUM_BANKS: usize = 2;
+
+    /// The number of pins.
+    pub const NUM_PINS: u32 = 54;
+
     /// Create an instance.
     ///
     /// # Safety
@@ -124,6 +190,52 @@ impl GPIO {
             registers.GPPUDCLK0.set(0);
         })
     }
+
+    /// Drive the output pins of `bank` that are set in `pins_mask` high, all with a single write.
+    ///
+    /// Bit `n` of the mask is pin `32 * bank + n`. Other pins are unaffected.
+    pub fn set_mask(&self, pins_mask: u32, bank: u8) -> Result<(), &'static str> {
+        let bank = check_mask(pins_mask, bank)?;
+
+        let mut r = &self.registers;
+        r.lock(|registers| registers.GPSET[bank].set(pins_mask));
+
+        Ok(())
+    }
+
+    /// Drive the output pins of `bank` that are set in `pins_mask` low, all with a single write.
+    ///
+    /// Bit `n` of the mask is pin `32 * bank + n`. Other pins are unaffected.
+    pub fn clear_mask(&self, pins_mask: u32, bank: u8) -> Result<(), &'static str> {
+        let bank = check_mask(pins_mask, bank)?;
+
+        let mut r = &self.registers;
+        r.lock(|registers| registers.GPCLR[bank].set(pins_mask));
+
+        Ok(())
+    }
+
+    /// Write a batch of pin/value pairs with the minimal number of register writes.
+    ///
+    /// That is one GPCLR and one GPSET write per bank, and none for a register whose mask is empty.
+    /// Within a bank, the pins that go low change first, then the pins that go high.
+    pub fn write_pins(&self, pins: &[(u8, bool)]) -> Result<(), &'static str> {
+        let masks = PinMasks::from_pairs(pins)?;
+
+        let mut r = &self.registers;
+        r.lock(|registers| {
+            for bank in 0..Self::NUM_BANKS {
+                if masks.clear[bank] != 0 {
+                    registers.GPCLR[bank].set(masks.clear[bank]);
+                }
+                if masks.set[bank] != 0 {
+                    registers.GPSET[bank].set(masks.set[bank]);
+                }
+            }
+        });
+
+        Ok(())
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -135,4 +247,70 @@ impl driver::interface::DeviceDriver for GPIO {
     fn compatible(&self) -> &str {
         "BCM GPIO"
     }
+
+    fn mmio_region(&self) -> Option<Range<usize>> {
+        let mut r = &self.registers;
+        Some(r.lock(|registers| registers.mmio_range()))
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// RAM that stands in for the register block. Write-only registers keep the last value.
+    static mut FAKE_REGISTERS: [u32; 40] = [0; 40];
+
+    const GPSET0: usize = 0x1C / 4;
+    const GPSET1: usize = 0x20 / 4;
+    const GPCLR0: usize = 0x28 / 4;
+
+    fn fake_gpio() -> GPIO {
+        unsafe {
+            FAKE_REGISTERS = [0; 40];
+            GPIO::new(FAKE_REGISTERS.as_ptr() as usize)
+        }
+    }
+
+    /// A mask write must land in the bank's GPSET register as a whole, and touch nothing else.
+    #[kernel_test]
+    fn set_mask_writes_all_pins_at_once() {
+        let gpio = fake_gpio();
+
+        gpio.set_mask(0x0FF0_0000, 0).unwrap();
+
+        let regs = unsafe { FAKE_REGISTERS };
+        assert_eq!(regs[GPSET0], 0x0FF0_0000);
+        assert!(regs
+            .iter()
+            .enumerate()
+            .all(|(i, word)| i == GPSET0 || *word == 0));
+
+        assert!(gpio.set_mask(1 << 22, 1).is_err());
+        assert!(gpio.clear_mask(1, 2).is_err());
+    }
+
+    /// Pin/value pairs must be split into one mask per register, with the last value winning.
+    #[kernel_test]
+    fn write_pins_splits_into_masks() {
+        let pins = [(20, true), (21, false), (22, true), (33, true), (20, false)];
+        let masks = PinMasks::from_pairs(&pins).unwrap();
+
+        assert_eq!(masks.set, [1 << 22, 1 << 1]);
+        assert_eq!(masks.clear, [(1 << 20) | (1 << 21), 0]);
+        assert!(PinMasks::from_pairs(&[(54, true)]).is_err());
+
+        let gpio = fake_gpio();
+        gpio.write_pins(&pins).unwrap();
+
+        let regs = unsafe { FAKE_REGISTERS };
+        assert_eq!(regs[GPSET0], 1 << 22);
+        assert_eq!(regs[GPSET1], 1 << 1);
+        assert_eq!(regs[GPCLR0], (1 << 20) | (1 << 21));
+    }
 }
@@ -4,9 +4,11 @@
 
 //! Interrupt Controller Driver.
 
+mod local_ic;
 mod peripheral_ic;
 
 use crate::{driver, exception};
+use core::ops::Range;
 
 //--------------------------------------------------------------------------------------------------
 // Private Definitions
@@ -26,16 +28,36 @@ pub type LocalIRQ =
 pub type PeripheralIRQ =
     exception::asynchronous::IRQNumber<{ InterruptController::MAX_PERIPHERAL_IRQ_NUMBER }>;
 
-/// Used for the associated type of trait  [`exception::asynchronous::interface::IRQManager`].
-#[derive(Copy, Clone)]
+/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
+#[derive(Copy, Clone, PartialEq)]
 pub enum IRQNumber {
     Local(LocalIRQ),
     Peripheral(PeripheralIRQ),
 }
 
+/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct SavedIrqState {
+    local: local_ic::SavedIrqState,
+    periph: peripheral_ic::SavedIrqState,
+}
+
+/// Used for the associated type of trait [`exception::asynchronous::interface::IRQManager`].
+///
+/// The pending bits of the executing core's local sources and of the peripheral IRQs.
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct PendingIrqState {
+    local: u32,
+    periph: u64,
+}
+
 /// Representation of the Interrupt Controller.
 pub struct InterruptController {
+    local: local_ic::LocalIC,
     periph: peripheral_ic::PeripheralIC,
+
+    /// Used for pending IRQs without a registered handler.
+    fallback: exception::asynchronous::IRQFallback<IRQNumber>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -48,6 +70,22 @@ impl PendingIRQs {
     }
 }
 
+impl InterruptController {
+    /// Pass a pending IRQ without handler to the fallback, or mask it if there is none.
+    fn handle_unhandled(&self, irq: IRQNumber) {
+        use exception::asynchronous::interface::IRQManager;
+
+        if self.fallback.handle(irq) {
+            return;
+        }
+
+        match irq {
+            IRQNumber::Local(lirq) => self.local.disable(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.disable(pirq),
+        }
+    }
+}
+
 impl Iterator for PendingIRQs {
     type Item = usize;
 
@@ -69,21 +107,66 @@ impl Iterator for PendingIRQs {
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl PendingIrqState {
+    /// Return whether `irq` is pending.
+    pub fn contains(&self, irq: IRQNumber) -> bool {
+        match irq {
+            IRQNumber::Local(lirq) => self.local & (1 << lirq.get()) != 0,
+            IRQNumber::Peripheral(pirq) => self.periph & (1 << pirq.get()) != 0,
+        }
+    }
+
+    /// Return whether no IRQ is pending.
+    pub fn is_empty(&self) -> bool {
+        self.local == 0 && self.periph == 0
+    }
+
+    /// Iterate over the pending IRQs, local ones first.
+    pub fn iter(&self) -> impl Iterator<Item = IRQNumber> {
+        PendingIRQs::new(u64::from(self.local))
+            .map(|i| IRQNumber::Local(LocalIRQ::new(i)))
+            .chain(
+                PendingIRQs::new(self.periph).map(|i| IRQNumber::Peripheral(PeripheralIRQ::new(i))),
+            )
+    }
+}
+
 impl InterruptController {
     const MAX_LOCAL_IRQ_NUMBER: usize = 11;
+    const NUM_LOCAL_IRQS: usize = Self::MAX_LOCAL_IRQ_NUMBER + 1;
     const MAX_PERIPHERAL_IRQ_NUMBER: usize = 63;
     const NUM_PERIPHERAL_IRQS: usize = Self::MAX_PERIPHERAL_IRQ_NUMBER + 1;
 
+    /// The mailbox of each core that carries IPIs. The others are unused.
+    const IPI_MAILBOX: usize = 0;
+
     /// Create an instance.
     ///
     /// # Safety
     ///
     /// - The user must ensure to provide the correct `base_addr`.
-    pub const unsafe fn new(_local_base_addr: usize, periph_base_addr: usize) -> Self {
+    pub const unsafe fn new(local_base_addr: usize, periph_base_addr: usize) -> Self {
         Self {
+            local: local_ic::LocalIC::new(local_base_addr),
             periph: peripheral_ic::PeripheralIC::new(periph_base_addr),
+            fallback: exception::asynchronous::IRQFallback::new(),
         }
     }
+
+    /// The local IRQ of the executing core's IPI mailbox.
+    pub fn ipi_irq(&self) -> IRQNumber {
+        IRQNumber::Local(local_ic::LocalIC::mailbox_irq(Self::IPI_MAILBOX))
+    }
+
+    /// Set `bits` in the IPI mailbox of `core`, which interrupts it.
+    pub fn send_ipi(&self, core: usize, bits: u32) {
+        self.local.mailbox_send(core, Self::IPI_MAILBOX, bits);
+    }
+
+    /// Return and clear the bits of the executing core's IPI mailbox.
+    pub fn take_ipis(&self) -> u32 {
+        self.local.mailbox_take(Self::IPI_MAILBOX)
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -94,10 +177,19 @@ impl driver::interface::DeviceDriver for InterruptController {
     fn compatible(&self) -> &str {
         "BCM Interrupt Controller"
     }
+
+    /// Only the peripheral interrupt controller is reported. The local one is outside of the
+    /// peripheral block, behind the end of DRAM, and therefore can not overlap with a driver or the
+    /// heap.
+    fn mmio_region(&self) -> Option<Range<usize>> {
+        Some(self.periph.mmio_range())
+    }
 }
 
 impl exception::asynchronous::interface::IRQManager for InterruptController {
     type IRQNumberType = IRQNumber;
+    type SavedIrqState = SavedIrqState;
+    type PendingIrqState = PendingIrqState;
 
     fn register_handler(
         &self,
@@ -105,27 +197,86 @@ impl exception::asynchronous::interface::IRQManager for InterruptController {
         descriptor: exception::asynchronous::IRQDescriptor,
     ) -> Result<(), &'static str> {
         match irq {
-            IRQNumber::Local(_) => unimplemented!("Local IRQ controller not implemented."),
+            IRQNumber::Local(lirq) => self.local.register_handler(lirq, descriptor),
             IRQNumber::Peripheral(pirq) => self.periph.register_handler(pirq, descriptor),
         }
     }
 
+    fn deregister_handler(&self, irq: Self::IRQNumberType) -> Result<(), &'static str> {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.deregister_handler(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.deregister_handler(pirq),
+        }
+    }
+
     fn enable(&self, irq: Self::IRQNumberType) {
         match irq {
-            IRQNumber::Local(_) => unimplemented!("Local IRQ controller not implemented."),
+            IRQNumber::Local(lirq) => self.local.enable(lirq),
             IRQNumber::Peripheral(pirq) => self.periph.enable(pirq),
         }
     }
 
+    fn disable(&self, irq: Self::IRQNumberType) {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.disable(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.disable(pirq),
+        }
+    }
+
+    fn is_enabled(&self, irq: Self::IRQNumberType) -> bool {
+        match irq {
+            IRQNumber::Local(lirq) => self.local.is_enabled(lirq),
+            IRQNumber::Peripheral(pirq) => self.periph.is_enabled(pirq),
+        }
+    }
+
+    fn pending(&self) -> Self::PendingIrqState {
+        PendingIrqState {
+            local: self.local.pending(),
+            periph: self.periph.pending(),
+        }
+    }
+
+    fn disable_all(&self) -> Self::SavedIrqState {
+        SavedIrqState {
+            local: self.local.disable_all(),
+            periph: self.periph.disable_all(),
+        }
+    }
+
+    fn restore_all(&self, state: Self::SavedIrqState) {
+        self.periph.restore_all(state.periph);
+        self.local.restore_all(state.local);
+    }
+
+    fn set_fallback(&self, f: fn(Self::IRQNumberType)) {
+        self.fallback.set(f);
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
-        ic: &exception::asynchronous::IRQContext<'irq_context>,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
-        // It can only be a peripheral IRQ pending because enable() does not support local IRQs yet.
-        self.periph.handle_pending_irqs(ic)
+        self.local
+            .dispatch_pending_irqs(|lirq| self.handle_unhandled(IRQNumber::Local(lirq)));
+        self.periph
+            .dispatch_pending_irqs(|pirq| self.handle_unhandled(IRQNumber::Peripheral(pirq)));
     }
 
     fn print_handler(&self) {
+        use crate::info;
+
+        self.local.print_handler();
         self.periph.print_handler();
+
+        info!(
+            "      Masked IRQs without handler: {}",
+            self.fallback.num_unhandled()
+        );
+    }
+
+    fn print_pending(&self) {
+        self.local.print_pending();
+        self.periph.print_pending();
     }
 }
@@ -0,0 +1,343 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Local Interrupt Controller Driver.
+//!
+//! The ARM local peripherals of the BCM2836/7 route the per-core interrupts, e.g. of the ARMv8
+//! Generic Timer, to the cores. The timer and the mailbox interrupts are supported so far.
+//!
+//! # Mailboxes
+//!
+//! Each core has four 32 bit mailboxes, which other cores use to interrupt it. Mailbox `m` of core
+//! `c` is written through `CORE_MAILBOX_SET[4 * c + m]` and read through
+//! `CORE_MAILBOX_RDCLR[4 * c + m]`:
+//!
+//! - Writing to the set register sets the written `1` bits, so that senders do not need a lock.
+//! - Writing to the read/clear register clears the written `1` bits.
+//! - The mailbox asserts its interrupt while any bit is set, if the mailbox's bit in the core's
+//!   `CORE_MAILBOX_INTCTL` is set. It is local IRQ `4 + m` of that core.
+//!
+//! Descriptions taken from
+//! https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+//! and the "Quad-A7 control" document.
+
+use super::{InterruptController, LocalIRQ, PendingIRQs};
+use crate::{
+    bsp::device_driver::common::MMIODerefWrapper,
+    cpu, exception, synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+};
+use register::{mmio::*, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x40 => CORE_TIMER_INTCTL: [ReadWrite<u32>; 4]),
+        (0x50 => CORE_MAILBOX_INTCTL: [ReadWrite<u32>; 4]),
+        (0x60 => CORE_IRQ_SOURCE: [ReadOnly<u32>; 4]),
+        (0x70 => _reserved2),
+        (0x80 => CORE_MAILBOX_SET: [WriteOnly<u32>; 16]),
+        (0xC0 => CORE_MAILBOX_RDCLR: [ReadWrite<u32>; 16]),
+        (0x100 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+type HandlerTable =
+    [Option<exception::asynchronous::IRQDescriptor>; InterruptController::NUM_LOCAL_IRQS];
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The timer interrupt control registers of all cores, followed by their mailbox ones.
+pub type SavedIrqState = [u32; 8];
+
+/// Representation of the local interrupt controller.
+pub struct LocalIC {
+    /// Enabling IRQs is a read-modify-write, so write access is guarded with a lock.
+    registers: IRQSafeNullLock<Registers>,
+
+    /// Reading the IRQ sources, and the mailboxes, which are set and cleared bitwise, is
+    /// unguarded.
+    ro_registers: Registers,
+
+    /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
+    handler_table: InitStateLock<HandlerTable>,
+
+    /// Used for pending IRQs without a registered handler.
+    fallback: exception::asynchronous::IRQFallback<LocalIRQ>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl LocalIC {
+    /// The local IRQ numbers of the four timer interrupts (CNTPS, CNTPNS, CNTHP, CNTV), which are
+    /// also their bit positions in the timer interrupt control and the IRQ source registers.
+    const TIMER_IRQS_MASK: u32 = 0b1111;
+
+    /// The local IRQ numbers of the four mailbox interrupts.
+    const MAILBOX_IRQS_MASK: u32 = 0b1111_0000;
+
+    const MAILBOX_IRQS_SHIFT: usize = 4;
+
+    const NUM_MAILBOXES: usize = 4;
+
+    /// All sources of the IRQ source registers: Timers, mailboxes, GPU, PMU, AXI and local timer.
+    const SOURCES_MASK: u32 = 0xFFF;
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide the correct `base_addr`.
+    pub const unsafe fn new(base_addr: usize) -> Self {
+        Self {
+            registers: IRQSafeNullLock::new(Registers::new(base_addr)),
+            ro_registers: Registers::new(base_addr),
+            handler_table: InitStateLock::new([None; InterruptController::NUM_LOCAL_IRQS]),
+            fallback: exception::asynchronous::IRQFallback::new(),
+        }
+    }
+
+    /// Call the handlers of the executing core's pending IRQs.
+    ///
+    /// Pending IRQs without a registered handler are passed to `unhandled`.
+    pub fn dispatch_pending_irqs(&self, unhandled: impl Fn(LocalIRQ)) {
+        use exception::asynchronous::interface::IRQManager;
+
+        let mut r = &self.handler_table;
+        r.read(|table| {
+            for irq_number in self.pending_irqs() {
+                match table[irq_number] {
+                    None => unhandled(LocalIRQ::new(irq_number)),
+                    Some(descriptor) => {
+                        // Mask the IRQ of a handler that panicked.
+                        if !exception::asynchronous::call_handler(&descriptor) {
+                            self.disable(LocalIRQ::new(irq_number));
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Query the list of pending, supported IRQs of the executing core.
+    fn pending_irqs(&self) -> PendingIRQs {
+        use exception::asynchronous::interface::IRQManager;
+
+        let supported = Self::TIMER_IRQS_MASK | Self::MAILBOX_IRQS_MASK;
+        PendingIRQs::new(u64::from(self.pending() & supported))
+    }
+
+    /// The control register and bit of a timer or mailbox IRQ of `core`.
+    fn intctl(regs: &Registers, core: usize, irq: LocalIRQ) -> (&ReadWrite<u32>, u32) {
+        let bit: u32 = 1 << irq.get();
+
+        if bit & Self::TIMER_IRQS_MASK != 0 {
+            (&regs.CORE_TIMER_INTCTL[core], bit)
+        } else if bit & Self::MAILBOX_IRQS_MASK != 0 {
+            (
+                &regs.CORE_MAILBOX_INTCTL[core],
+                bit >> Self::MAILBOX_IRQS_SHIFT,
+            )
+        } else {
+            unimplemented!("Only the local timer and mailbox IRQs are implemented.");
+        }
+    }
+
+    /// The local IRQ number of the executing core's `mailbox`.
+    pub fn mailbox_irq(mailbox: usize) -> LocalIRQ {
+        LocalIRQ::new(Self::MAILBOX_IRQS_SHIFT + mailbox)
+    }
+
+    /// Set `bits` in `mailbox` of `core`.
+    pub fn mailbox_send(&self, core: usize, mailbox: usize, bits: u32) {
+        assert!(mailbox < Self::NUM_MAILBOXES);
+
+        self.ro_registers.CORE_MAILBOX_SET[Self::NUM_MAILBOXES * core + mailbox].set(bits);
+    }
+
+    /// Return and clear the bits of the executing core's `mailbox`.
+    ///
+    /// Only the returned bits are cleared, so bits that are set in between are kept.
+    pub fn mailbox_take(&self, mailbox: usize) -> u32 {
+        assert!(mailbox < Self::NUM_MAILBOXES);
+
+        let core: usize = cpu::smp::core_id();
+        let rdclr = &self.ro_registers.CORE_MAILBOX_RDCLR[Self::NUM_MAILBOXES * core + mailbox];
+
+        let bits = rdclr.get();
+        rdclr.set(bits);
+
+        bits
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl exception::asynchronous::interface::IRQManager for LocalIC {
+    type IRQNumberType = LocalIRQ;
+    type SavedIrqState = SavedIrqState;
+    type PendingIrqState = u32;
+
+    fn register_handler(
+        &self,
+        irq: Self::IRQNumberType,
+        descriptor: exception::asynchronous::IRQDescriptor,
+    ) -> Result<(), &'static str> {
+        let mut r = &self.handler_table;
+        r.write(|table| {
+            let irq_number = irq.get();
+
+            if table[irq_number].is_some() {
+                return Err("IRQ handler already registered");
+            }
+
+            table[irq_number] = Some(descriptor);
+
+            Ok(())
+        })
+    }
+
+    fn deregister_handler(&self, irq: Self::IRQNumberType) -> Result<(), &'static str> {
+        self.disable(irq);
+
+        let mut r = &self.handler_table;
+        r.write(|table| match table[irq.get()].take() {
+            None => Err("No IRQ handler registered"),
+            Some(_) => Ok(()),
+        })
+    }
+
+    /// Enable a timer or mailbox IRQ for the executing core.
+    fn enable(&self, irq: Self::IRQNumberType) {
+        let core: usize = cpu::smp::core_id();
+
+        let mut r = &self.registers;
+        r.lock(|regs| {
+            let (ctl, bit) = Self::intctl(regs, core, irq);
+            ctl.set(ctl.get() | bit);
+        });
+    }
+
+    /// Disable a timer or mailbox IRQ for the executing core.
+    fn disable(&self, irq: Self::IRQNumberType) {
+        let core: usize = cpu::smp::core_id();
+
+        let mut r = &self.registers;
+        r.lock(|regs| {
+            let (ctl, bit) = Self::intctl(regs, core, irq);
+            ctl.set(ctl.get() & !bit);
+        });
+    }
+
+    /// Return whether a timer or mailbox IRQ is enabled for the executing core.
+    fn is_enabled(&self, irq: Self::IRQNumberType) -> bool {
+        let core: usize = cpu::smp::core_id();
+
+        let mut r = &self.registers;
+        r.lock(|regs| {
+            let (ctl, bit) = Self::intctl(regs, core, irq);
+            ctl.get() & bit != 0
+        })
+    }
+
+    /// The executing core's IRQ source register, including the sources without driver support.
+    fn pending(&self) -> Self::PendingIrqState {
+        let core: usize = cpu::smp::core_id();
+
+        self.ro_registers.CORE_IRQ_SOURCE[core].get() & Self::SOURCES_MASK
+    }
+
+    /// Disable the timer and mailbox IRQs and FIQs of all cores.
+    fn disable_all(&self) -> Self::SavedIrqState {
+        let mut saved: SavedIrqState = [0; 8];
+
+        let mut r = &self.registers;
+        r.lock(|regs| {
+            let ctls = regs
+                .CORE_TIMER_INTCTL
+                .iter()
+                .chain(regs.CORE_MAILBOX_INTCTL.iter());
+
+            for (ctl, save) in ctls.zip(saved.iter_mut()) {
+                *save = ctl.get();
+                ctl.set(0);
+            }
+        });
+
+        saved
+    }
+
+    fn set_fallback(&self, f: fn(Self::IRQNumberType)) {
+        self.fallback.set(f);
+    }
+
+    fn restore_all(&self, state: Self::SavedIrqState) {
+        let mut r = &self.registers;
+        r.lock(|regs| {
+            let ctls = regs
+                .CORE_TIMER_INTCTL
+                .iter()
+                .chain(regs.CORE_MAILBOX_INTCTL.iter());
+
+            for (ctl, saved) in ctls.zip(state.iter()) {
+                ctl.set(*saved);
+            }
+        });
+    }
+
+    fn handle_pending_irqs<'irq_context>(
+        &'irq_context self,
+        _ic: &exception::asynchronous::IRQContext<'irq_context>,
+    ) {
+        self.dispatch_pending_irqs(|irq| {
+            if !self.fallback.handle(irq) {
+                self.disable(irq);
+            }
+        })
+    }
+
+    fn print_handler(&self) {
+        use crate::info;
+
+        info!("      Local handler:");
+
+        let mut r = &self.handler_table;
+        r.read(|table| {
+            for (i, opt) in table.iter().enumerate() {
+                if let Some(handler) = opt {
+                    info!("            {: >3}. {}", i, handler.name);
+                }
+            }
+        });
+    }
+
+    fn print_pending(&self) {
+        use crate::info;
+
+        info!("      Local pending:");
+
+        let mut r = &self.handler_table;
+        r.read(|table| {
+            for i in PendingIRQs::new(u64::from(self.pending())) {
+                let name = table[i].map_or("No handler", |handler| handler.name);
+                info!("            {: >3}. {}", i, name);
+            }
+        });
+    }
+}
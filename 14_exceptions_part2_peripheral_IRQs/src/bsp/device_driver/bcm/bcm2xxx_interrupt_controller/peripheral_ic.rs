@@ -10,6 +10,7 @@ use crate::{
     exception, synchronization,
     synchronization::{IRQSafeNullLock, InitStateLock},
 };
+use core::ops::Range;
 use register::{mmio::*, register_structs};
 
 //--------------------------------------------------------------------------------------------------
@@ -20,9 +21,13 @@ register_structs! {
     #[allow(non_snake_case)]
     WORegisterBlock {
         (0x00 => _reserved1),
-        (0x10 => ENABLE_1: WriteOnly<u32>),
-        (0x14 => ENABLE_2: WriteOnly<u32>),
-        (0x24 => @END),
+        (0x10 => ENABLE_1: ReadWrite<u32>),
+        (0x14 => ENABLE_2: ReadWrite<u32>),
+        (0x18 => ENABLE_BASIC: ReadWrite<u32>),
+        (0x1c => DISABLE_1: WriteOnly<u32>),
+        (0x20 => DISABLE_2: WriteOnly<u32>),
+        (0x24 => DISABLE_BASIC: WriteOnly<u32>),
+        (0x28 => @END),
     }
 }
 
@@ -37,6 +42,8 @@ register_structs! {
 }
 
 /// Abstraction for the WriteOnly parts of the associated MMIO registers.
+///
+/// The enable registers read back the current enable mask, but writing them only sets bits.
 type WriteOnlyRegisters = MMIODerefWrapper<WORegisterBlock>;
 
 /// Abstraction for the ReadOnly parts of the associated MMIO registers.
@@ -49,6 +56,9 @@ type HandlerTable =
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// The enable masks in the order `ENABLE_1`, `ENABLE_2`, `ENABLE_BASIC`.
+pub type SavedIrqState = [u32; 3];
+
 /// Representation of the peripheral interrupt regsler.
 pub struct PeripheralIC {
     /// Access to write registers is guarded with a lock.
@@ -59,6 +69,9 @@ pub struct PeripheralIC {
 
     /// Stores registered IRQ handlers. Writable only during kernel init. RO afterwards.
     handler_table: InitStateLock<HandlerTable>,
+
+    /// Used for pending IRQs without a registered handler.
+    fallback: exception::asynchronous::IRQFallback<PeripheralIRQ>,
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -76,15 +89,44 @@ impl PeripheralIC {
             wo_registers: IRQSafeNullLock::new(WriteOnlyRegisters::new(base_addr)),
             ro_registers: ReadOnlyRegisters::new(base_addr),
             handler_table: InitStateLock::new([None; InterruptController::NUM_PERIPHERAL_IRQS]),
+            fallback: exception::asynchronous::IRQFallback::new(),
         }
     }
 
+    /// Return the address range of the MMIO registers.
+    pub fn mmio_range(&self) -> Range<usize> {
+        // The write-only block spans the read-only one.
+        let mut r = &self.wo_registers;
+        r.lock(|regs| regs.mmio_range())
+    }
+
     /// Query the list of pending IRQs.
     fn pending_irqs(&self) -> PendingIRQs {
-        let pending_mask: u64 = (u64::from(self.ro_registers.PENDING_2.get()) << 32)
-            | u64::from(self.ro_registers.PENDING_1.get());
+        use exception::asynchronous::interface::IRQManager;
 
-        PendingIRQs::new(pending_mask)
+        PendingIRQs::new(self.pending())
+    }
+
+    /// Call the handlers of all pending IRQs.
+    ///
+    /// Pending IRQs without a registered handler are passed to `unhandled`.
+    pub fn dispatch_pending_irqs(&self, unhandled: impl Fn(PeripheralIRQ)) {
+        use exception::asynchronous::interface::IRQManager;
+
+        let mut r = &self.handler_table;
+        r.read(|table| {
+            for irq_number in self.pending_irqs() {
+                match table[irq_number] {
+                    None => unhandled(PeripheralIRQ::new(irq_number)),
+                    Some(descriptor) => {
+                        // Mask the IRQ of a handler that panicked.
+                        if !exception::asynchronous::call_handler(&descriptor) {
+                            self.disable(PeripheralIRQ::new(irq_number));
+                        }
+                    }
+                }
+            }
+        })
     }
 }
 
@@ -95,6 +137,8 @@ use synchronization::interface::{Mutex, ReadWriteEx};
 
 impl exception::asynchronous::interface::IRQManager for PeripheralIC {
     type IRQNumberType = PeripheralIRQ;
+    type SavedIrqState = SavedIrqState;
+    type PendingIrqState = u64;
 
     fn register_handler(
         &self,
@@ -115,6 +159,16 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         })
     }
 
+    fn deregister_handler(&self, irq: Self::IRQNumberType) -> Result<(), &'static str> {
+        self.disable(irq);
+
+        let mut r = &self.handler_table;
+        r.write(|table| match table[irq.get()].take() {
+            None => Err("No IRQ handler registered"),
+            Some(_) => Ok(()),
+        })
+    }
+
     fn enable(&self, irq: Self::IRQNumberType) {
         let mut r = &self.wo_registers;
         r.lock(|regs| {
@@ -132,20 +186,76 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
         });
     }
 
+    fn disable(&self, irq: Self::IRQNumberType) {
+        let mut r = &self.wo_registers;
+        r.lock(|regs| {
+            let disable_reg = if irq.get() <= 31 {
+                &regs.DISABLE_1
+            } else {
+                &regs.DISABLE_2
+            };
+
+            // Like for enabling, only the bits written as 1 are affected.
+            disable_reg.set(1 << (irq.get() % 32));
+        });
+    }
+
+    fn is_enabled(&self, irq: Self::IRQNumberType) -> bool {
+        let mut r = &self.wo_registers;
+        r.lock(|regs| {
+            let enable_reg = if irq.get() <= 31 {
+                &regs.ENABLE_1
+            } else {
+                &regs.ENABLE_2
+            };
+
+            enable_reg.get() & (1 << (irq.get() % 32)) != 0
+        })
+    }
+
+    /// The GPU IRQs 0-63 from the pending registers 1 and 2.
+    fn pending(&self) -> Self::PendingIrqState {
+        (u64::from(self.ro_registers.PENDING_2.get()) << 32)
+            | u64::from(self.ro_registers.PENDING_1.get())
+    }
+
+    fn disable_all(&self) -> Self::SavedIrqState {
+        let mut r = &self.wo_registers;
+        r.lock(|regs| {
+            let saved = [
+                regs.ENABLE_1.get(),
+                regs.ENABLE_2.get(),
+                regs.ENABLE_BASIC.get(),
+            ];
+
+            regs.DISABLE_1.set(u32::MAX);
+            regs.DISABLE_2.set(u32::MAX);
+            regs.DISABLE_BASIC.set(u32::MAX);
+
+            saved
+        })
+    }
+
+    fn set_fallback(&self, f: fn(Self::IRQNumberType)) {
+        self.fallback.set(f);
+    }
+
+    fn restore_all(&self, state: Self::SavedIrqState) {
+        let mut r = &self.wo_registers;
+        r.lock(|regs| {
+            regs.ENABLE_1.set(state[0]);
+            regs.ENABLE_2.set(state[1]);
+            regs.ENABLE_BASIC.set(state[2]);
+        });
+    }
+
     fn handle_pending_irqs<'irq_context>(
         &'irq_context self,
         _ic: &exception::asynchronous::IRQContext<'irq_context>,
     ) {
-        let mut r = &self.handler_table;
-        r.read(|table| {
-            for irq_number in self.pending_irqs() {
-                match table[irq_number] {
-                    None => panic!("No handler registered for IRQ {}", irq_number),
-                    Some(descriptor) => {
-                        // Call the IRQ handler. Panics on failure.
-                        descriptor.handler.handle().expect("Error handling IRQ");
-                    }
-                }
+        self.dispatch_pending_irqs(|irq| {
+            if !self.fallback.handle(irq) {
+                self.disable(irq);
             }
         })
     }
@@ -164,4 +274,18 @@ impl exception::asynchronous::interface::IRQManager for PeripheralIC {
             }
         });
     }
+
+    fn print_pending(&self) {
+        use crate::info;
+
+        info!("      Peripheral pending:");
+
+        let mut r = &self.handler_table;
+        r.read(|table| {
+            for i in self.pending_irqs() {
+                let name = table[i].map_or("No handler", |handler| handler.name);
+                info!("            {: >3}. {}", i, name);
+            }
+        });
+    }
 }
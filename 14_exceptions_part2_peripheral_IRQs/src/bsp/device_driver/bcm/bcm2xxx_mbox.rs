@@ -4,7 +4,7 @@ extern crate alloc;
 use cortex_a::barrier::{dmb, dsb, SY};
 use register::{mmio::*, register_bitfields, register_structs};
 
-use self::alloc::{alloc::alloc_zeroed, boxed::Box};
+use self::alloc::alloc::{alloc_zeroed, dealloc};
 use crate::driver;
 use core::{
     alloc::Layout,
@@ -59,11 +59,16 @@ impl Mailbox {
         Self { base_addr }
     }
 
-    pub fn send<'a, T: Tag>(
+    pub fn send<'m, 'a, T: Tag>(
         &self,
         channel: u32,
-        message: &'a mut Message<'a, T>,
-    ) -> Result<&'a T, ()> {
+        message: &'m mut Message<'a, T>,
+    ) -> Result<&'m T, ()> {
+        #[cfg(feature = "faultinject")]
+        if crate::faultinject::should_fail(crate::faultinject::FaultPoint::MailboxSend) {
+            return Err(());
+        }
+
         unsafe {
             dsb(SY);
             dmb(SY);
@@ -75,18 +80,11 @@ impl Mailbox {
             }
         }
 
-        let msg: Option<Box<RawMessage>>;
-
-        unsafe {
-            msg = message.marshal();
-        }
-
-        if msg.is_none() {
-            return Result::Err(());
-        }
+        let msg = match unsafe { message.marshal() } {
+            Some(msg) => msg,
+            None => return Result::Err(()),
+        };
 
-        let opt = msg.unwrap();
-        let msg = opt.as_ref();
         let contents_addr = msg as *const RawMessage as u32;
         let val = (contents_addr & !0xF) | (channel & 0xF);
 
@@ -104,11 +102,7 @@ impl Mailbox {
             let response: u32 = self.READ.get();
 
             if ((response & 0xF) == channel) && ((response & !0xF) == contents_addr) {
-                return if msg.request_code != 0x80000000 {
-                    Err(())
-                } else {
-                    unsafe { Ok(message.read()) }
-                };
+                return unsafe { message.response() };
             }
         }
     }
@@ -159,9 +153,26 @@ impl PropertyTags {
     pub const SET_POWER_STATE: u32 = 0x00028001;
     pub const GET_CLOCK_RATE: u32 = 0x00030002;
     pub const GET_TEMPERATURE: u32 = 0x00030006;
+    pub const ALLOCATE_MEMORY: u32 = 0x0003000C;
+    pub const LOCK_MEMORY: u32 = 0x0003000D;
+    pub const UNLOCK_MEMORY: u32 = 0x0003000E;
+    pub const RELEASE_MEMORY: u32 = 0x0003000F;
+    pub const GET_GPIO_STATE: u32 = 0x00030041;
     pub const GET_EDID_BLOCK: u32 = 0x00030020;
-    pub const GET_DISPLAY_DIMENSIONS: u32 = 0x00040003;
+    pub const GET_CUSTOMER_OTP: u32 = 0x00030021;
+    pub const ALLOCATE_BUFFER: u32 = 0x00040001;
+    pub const GET_PHYSICAL_SIZE: u32 = 0x00040003;
+    pub const GET_PIXEL_ORDER: u32 = 0x00040006;
+    pub const GET_PITCH: u32 = 0x00040008;
+    pub const GET_VIRTUAL_OFFSET: u32 = 0x00040009;
+    pub const SET_PHYSICAL_SIZE: u32 = 0x00048003;
+    pub const SET_VIRTUAL_SIZE: u32 = 0x00048004;
+    pub const SET_DEPTH: u32 = 0x00048005;
+    pub const SET_VIRTUAL_OFFSET: u32 = 0x00048009;
+    pub const SET_VSYNC: u32 = 0x0004800E;
     pub const GET_COMMAND_LINE: u32 = 0x00050001;
+    pub const SET_GPIO_STATE: u32 = 0x00038041;
+    pub const NOTIFY_XHCI_RESET: u32 = 0x00030058;
 }
 
 #[repr(C)]
@@ -198,6 +209,425 @@ impl Tag for PropertyTagTemperature {
     }
 }
 
+/// The rate of a clock that the firmware manages.
+#[repr(C)]
+pub struct PropertyTagClockRate {
+    pub clock_id: u32,
+    pub rate: u32,
+}
+
+impl PropertyTagClockRate {
+    pub const CLOCK_ID_UART: u32 = 2;
+
+    pub fn new(clock_id: u32) -> Self {
+        Self { clock_id, rate: 0 }
+    }
+}
+
+impl Tag for PropertyTagClockRate {
+    fn value_length(&self) -> usize {
+        return 4;
+    }
+}
+
+/// The board's MAC address as programmed into the OTP.
+#[repr(C)]
+pub struct PropertyTagMacAddress {
+    pub mac: [u8; 6],
+    _padding: [u8; 2],
+}
+
+impl PropertyTagMacAddress {
+    pub fn new() -> Self {
+        Self {
+            mac: [0; 6],
+            _padding: [0; 2],
+        }
+    }
+}
+
+impl Tag for PropertyTagMacAddress {
+    fn value_length(&self) -> usize {
+        return 0;
+    }
+}
+
+/// A range of rows of the customer OTP region.
+///
+/// The row indices are relative to the region, which is `NUM_ROWS` rows of 32 bits. The firmware
+/// answers with the same start and count, followed by the rows.
+#[repr(C)]
+pub struct PropertyTagGetCustomerOtp {
+    pub start: u32,
+    pub count: u32,
+    pub rows: [u32; PropertyTagGetCustomerOtp::NUM_ROWS],
+}
+
+impl PropertyTagGetCustomerOtp {
+    pub const NUM_ROWS: usize = 8;
+
+    pub fn new(start: u32, count: u32) -> Self {
+        Self {
+            start,
+            count,
+            rows: [0; Self::NUM_ROWS],
+        }
+    }
+}
+
+impl Tag for PropertyTagGetCustomerOtp {
+    fn value_length(&self) -> usize {
+        return 8;
+    }
+}
+
+/// The command line that the firmware passes to the kernel.
+///
+/// Includes `cmdline.txt` and the parameters that the firmware adds by itself. Not necessarily NUL
+/// terminated, use the firmware reported response length.
+#[repr(C)]
+pub struct PropertyTagCommandLine {
+    pub buf: [u8; PropertyTagCommandLine::MAX_LEN],
+}
+
+impl PropertyTagCommandLine {
+    pub const MAX_LEN: usize = 1024;
+
+    pub fn new() -> Self {
+        Self {
+            buf: [0; Self::MAX_LEN],
+        }
+    }
+}
+
+impl Tag for PropertyTagCommandLine {
+    fn value_length(&self) -> usize {
+        return 0;
+    }
+}
+
+/// Read a GPIO of the GPIO expander that is only reachable through the firmware.
+///
+/// `gpio` is the expander GPIO number as understood by the firmware, starting at 128.
+#[repr(C)]
+pub struct PropertyTagGetGpioState {
+    pub gpio: u32,
+    pub state: u32,
+}
+
+impl PropertyTagGetGpioState {
+    pub fn new(gpio: u32) -> Self {
+        Self { gpio, state: 0 }
+    }
+}
+
+impl Tag for PropertyTagGetGpioState {
+    fn value_length(&self) -> usize {
+        return 4;
+    }
+}
+
+/// Drive a GPIO of the GPIO expander that is only reachable through the firmware.
+///
+/// `gpio` is the expander GPIO number as understood by the firmware, starting at 128.
+#[repr(C)]
+pub struct PropertyTagSetGpioState {
+    pub gpio: u32,
+    pub state: u32,
+}
+
+impl PropertyTagSetGpioState {
+    pub const STATE_LOW: u32 = 0;
+    pub const STATE_HIGH: u32 = 1;
+
+    pub fn new(gpio: u32, high: bool) -> Self {
+        Self {
+            gpio,
+            state: if high {
+                Self::STATE_HIGH
+            } else {
+                Self::STATE_LOW
+            },
+        }
+    }
+}
+
+impl Tag for PropertyTagSetGpioState {
+    fn value_length(&self) -> usize {
+        return 8;
+    }
+}
+
+/// Tell the firmware that the xHCI controller behind PCIe was reset.
+///
+/// The firmware then loads the VL805's firmware from the EEPROM. Must be sent after PCIe init and
+/// before the xHCI driver touches the controller.
+///
+/// Only the RPi 4's firmware knows the tag. It is built for every board, so that its encoding is
+/// tested on the RPi 3 under QEMU, too. `bsp::notify_xhci_reset()` sends it on the RPi 4 only.
+#[repr(C)]
+pub struct PropertyTagNotifyXhciReset {
+    pub pci_dev_addr: u32,
+}
+
+impl PropertyTagNotifyXhciReset {
+    /// The VL805 is the only device on the Pi 4's PCIe bus 1.
+    pub const VL805_PCI_DEV_ADDR: u32 = Self::pci_dev_addr(1, 0, 0);
+
+    pub fn new(pci_dev_addr: u32) -> Self {
+        Self { pci_dev_addr }
+    }
+
+    /// Encode a PCI device address like the firmware expects it.
+    pub const fn pci_dev_addr(bus: u32, slot: u32, func: u32) -> u32 {
+        (bus << 20) | (slot << 15) | (func << 12)
+    }
+}
+
+impl Tag for PropertyTagNotifyXhciReset {
+    fn value_length(&self) -> usize {
+        return 4;
+    }
+}
+
+/// A request for GPU memory from the relocatable heap. The firmware answers with a handle in
+/// `size`, or `0` on failure.
+#[repr(C)]
+pub struct PropertyTagAllocateMemory {
+    pub size: u32,
+    pub alignment: u32,
+    pub flags: u32,
+}
+
+impl PropertyTagAllocateMemory {
+    /// The memory can be reclaimed by the GPU while it is unlocked.
+    pub const FLAG_DISCARDABLE: u32 = 1 << 0;
+
+    /// Accessed through the GPU's L1 and L2 caches.
+    pub const FLAG_NORMAL: u32 = 0 << 2;
+
+    /// Accessed uncached, which is what the ARM needs to see the GPU's writes.
+    pub const FLAG_DIRECT: u32 = 1 << 2;
+
+    /// Accessed through the GPU's L2 cache only, coherent with other L2 users.
+    pub const FLAG_COHERENT: u32 = 2 << 2;
+
+    /// Zero the memory on allocation.
+    pub const FLAG_ZERO: u32 = 1 << 4;
+
+    pub fn new(size: u32, alignment: u32, flags: u32) -> Self {
+        Self {
+            size,
+            alignment,
+            flags,
+        }
+    }
+}
+
+impl Tag for PropertyTagAllocateMemory {
+    fn value_length(&self) -> usize {
+        return 12;
+    }
+}
+
+/// A GPU memory handle, for the lock, unlock and release tags.
+///
+/// The firmware answers lock with the bus address, and unlock and release with a status that is
+/// `0` on success, in place of the handle.
+#[repr(C)]
+pub struct PropertyTagMemoryHandle {
+    pub handle: u32,
+}
+
+impl PropertyTagMemoryHandle {
+    pub fn new(handle: u32) -> Self {
+        Self { handle }
+    }
+}
+
+impl Tag for PropertyTagMemoryHandle {
+    fn value_length(&self) -> usize {
+        return 4;
+    }
+}
+
+/// The physical display size in pixels.
+///
+/// Before a framebuffer is allocated, this is the display's native mode, which is the largest
+/// resolution that can be shown without scaling. The firmware has no separate tag for the maximum
+/// supported size. Without a display attached, the firmware reports `0` x `0`.
+#[repr(C)]
+pub struct PropertyTagGetPhysicalSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PropertyTagGetPhysicalSize {
+    pub fn new() -> Self {
+        Self {
+            width: 0,
+            height: 0,
+        }
+    }
+
+    /// Parse a response payload into (width, height).
+    ///
+    /// Returns `None` if the firmware did not respond, or if no display is attached.
+    pub fn parse(payload: &[u32]) -> Option<(u32, u32)> {
+        if payload.len() < 2 || payload[0] == 0 || payload[1] == 0 {
+            return None;
+        }
+
+        Some((payload[0], payload[1]))
+    }
+}
+
+impl Tag for PropertyTagGetPhysicalSize {
+    fn value_length(&self) -> usize {
+        return 0;
+    }
+}
+
+/// A framebuffer size in pixels, for the set-physical-size and set-virtual-size tags.
+#[repr(C)]
+pub struct PropertyTagSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PropertyTagSize {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+}
+
+impl Tag for PropertyTagSize {
+    fn value_length(&self) -> usize {
+        return 8;
+    }
+}
+
+/// The framebuffer depth in bits per pixel.
+#[repr(C)]
+pub struct PropertyTagDepth {
+    pub bits_per_pixel: u32,
+}
+
+impl PropertyTagDepth {
+    pub fn new(bits_per_pixel: u32) -> Self {
+        Self { bits_per_pixel }
+    }
+}
+
+impl Tag for PropertyTagDepth {
+    fn value_length(&self) -> usize {
+        return 4;
+    }
+}
+
+/// Allocate the framebuffer with the configured size and depth.
+///
+/// The request carries the alignment, the response the VideoCore bus address and the size in bytes.
+#[repr(C)]
+pub struct PropertyTagAllocateBuffer {
+    pub base_addr: u32,
+    pub size: u32,
+}
+
+impl PropertyTagAllocateBuffer {
+    pub fn new(alignment: u32) -> Self {
+        Self {
+            base_addr: alignment,
+            size: 0,
+        }
+    }
+}
+
+impl Tag for PropertyTagAllocateBuffer {
+    fn value_length(&self) -> usize {
+        return 4;
+    }
+}
+
+/// The number of bytes per framebuffer row.
+#[repr(C)]
+pub struct PropertyTagPitch {
+    pub pitch: u32,
+}
+
+impl PropertyTagPitch {
+    pub fn new() -> Self {
+        Self { pitch: 0 }
+    }
+}
+
+impl Tag for PropertyTagPitch {
+    fn value_length(&self) -> usize {
+        return 0;
+    }
+}
+
+/// The order of the color components in a framebuffer pixel.
+#[repr(C)]
+pub struct PropertyTagPixelOrder {
+    pub order: u32,
+}
+
+impl PropertyTagPixelOrder {
+    pub const ORDER_BGR: u32 = 0;
+    pub const ORDER_RGB: u32 = 1;
+
+    pub fn new() -> Self {
+        Self { order: 0 }
+    }
+}
+
+impl Tag for PropertyTagPixelOrder {
+    fn value_length(&self) -> usize {
+        return 0;
+    }
+}
+
+/// The offset of the displayed window into the virtual framebuffer.
+///
+/// Used for both getting and setting. The firmware responds with the offset that it applied, which
+/// can differ from the requested one if the window would not fit.
+#[repr(C)]
+pub struct PropertyTagVirtualOffset {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl PropertyTagVirtualOffset {
+    pub fn new(x: u32, y: u32) -> Self {
+        Self { x, y }
+    }
+}
+
+impl Tag for PropertyTagVirtualOffset {
+    fn value_length(&self) -> usize {
+        return 8;
+    }
+}
+
+/// Wait for the next vertical sync. Not implemented by all firmware versions.
+#[repr(C)]
+pub struct PropertyTagVsync {
+    pub value: u32,
+}
+
+impl PropertyTagVsync {
+    pub fn new() -> Self {
+        Self { value: 0 }
+    }
+}
+
+impl Tag for PropertyTagVsync {
+    fn value_length(&self) -> usize {
+        return 4;
+    }
+}
+
 #[repr(C)]
 struct RawMessage {
     size: u32,
@@ -207,12 +637,61 @@ struct RawMessage {
     end: u32,
 }
 
+/// Bit 31 of a tag's value length is set by the firmware when it wrote a response. The remaining
+/// bits hold the response length in bytes.
+const TAG_RESPONSE_BIT: u32 = 1 << 31;
+
+/// Word index of the first tag's value length in a raw message buffer: Buffer size, request code,
+/// tag id, tag buffer size, value length.
+const VALUE_LENGTH_INDEX: usize = 4;
+
+/// Word index of the first tag's value buffer in a raw message buffer.
+const VALUE_BUFFER_INDEX: usize = 5;
+
+/// A property message. Owns the buffer that is handed to the firmware, so that the response stays
+/// valid for as long as the message lives.
 #[repr(C)]
 pub struct Message<'a, T: Tag> {
     size: u32,
     request_code: u32,
     tag: &'a PropertyTag<'a, T>,
     tag_location: *mut u32,
+    buffer: *mut u32,
+    layout: Option<Layout>,
+}
+
+/// Return the response payload of the first tag in a raw message `buffer`.
+///
+/// The payload length is taken from the firmware reported response length, capped at the tag's
+/// value buffer size and at the end of `buffer`. An empty slice is returned if the firmware did not
+/// respond to the tag.
+fn response_payload(buffer: &[u32]) -> &[u32] {
+    if buffer.len() <= VALUE_BUFFER_INDEX {
+        return &[];
+    }
+
+    let value_length = buffer[VALUE_LENGTH_INDEX];
+    if value_length & TAG_RESPONSE_BIT == 0 {
+        return &[];
+    }
+
+    let response_len = (value_length & !TAG_RESPONSE_BIT) as usize;
+    let buf_size = buffer[VALUE_LENGTH_INDEX - 1] as usize;
+    let num_words = (core::cmp::min(response_len, buf_size) + 3) / 4;
+    let end = core::cmp::min(VALUE_BUFFER_INDEX + num_words, buffer.len());
+
+    &buffer[VALUE_BUFFER_INDEX..end]
+}
+
+/// Return the response length of the first tag in bytes, capped like in `response_payload()`.
+fn response_len_bytes(buffer: &[u32]) -> usize {
+    let payload = response_payload(buffer);
+    if payload.is_empty() {
+        return 0;
+    }
+
+    let response_len = (buffer[VALUE_LENGTH_INDEX] & !TAG_RESPONSE_BIT) as usize;
+    core::cmp::min(response_len, payload.len() * 4)
 }
 
 impl<'a, T: Tag> Message<'a, T> {
@@ -222,10 +701,52 @@ impl<'a, T: Tag> Message<'a, T> {
             request_code: 0,
             tag,
             tag_location: 0 as *mut u32,
+            buffer: 0 as *mut u32,
+            layout: None,
+        }
+    }
+
+    /// The tag's response payload as reported by the firmware.
+    ///
+    /// Only the value buffer is returned, without the tag header. The length is the firmware
+    /// reported response length rounded up to full words, so reading beyond the response into
+    /// adjacent tags is impossible. Empty if the message was not sent or the firmware did not
+    /// respond.
+    pub fn response_slice(&self) -> &[u32] {
+        response_payload(self.raw_buffer())
+    }
+
+    /// Like `response_slice()`, but as bytes and exactly the firmware reported response length.
+    pub fn response_bytes(&self) -> &[u8] {
+        let buffer = self.raw_buffer();
+        let payload = response_payload(buffer);
+        let len = response_len_bytes(buffer);
+
+        unsafe { core::slice::from_raw_parts(payload.as_ptr() as *const u8, len) }
+    }
+
+    /// The whole marshalled message buffer.
+    fn raw_buffer(&self) -> &[u32] {
+        match self.layout {
+            None => &[],
+            Some(layout) => unsafe {
+                core::slice::from_raw_parts(self.buffer, layout.size() / size_of::<u32>())
+            },
+        }
+    }
+
+    /// Free a previously marshalled buffer.
+    fn release(&mut self) {
+        if let Some(layout) = self.layout.take() {
+            unsafe { dealloc(self.buffer as *mut u8, layout) };
+            self.buffer = 0 as *mut u32;
+            self.tag_location = 0 as *mut u32;
         }
     }
 
-    unsafe fn marshal(&mut self) -> Option<Box<RawMessage>> {
+    unsafe fn marshal(&mut self) -> Option<*const RawMessage> {
+        self.release();
+
         let mut size = size_of::<RawMessage>();
         let tag_contents_size = size_of_val(self.tag.tag);
 
@@ -238,7 +759,12 @@ impl<'a, T: Tag> Message<'a, T> {
         };
 
         let raw = alloc_zeroed(layout);
+        if raw.is_null() {
+            return None;
+        }
         let raw_msg = raw as *mut RawMessage;
+        self.buffer = raw as *mut u32;
+        self.layout = Some(layout);
 
         (*raw_msg).size = size as u32;
         (*raw_msg).request_code = self.request_code;
@@ -262,7 +788,32 @@ impl<'a, T: Tag> Message<'a, T> {
 
         // We've allocated zeroed memory, the tag end is already set to zero
 
-        Some(Box::from_raw(raw_msg))
+        Some(raw_msg)
+    }
+
+    /// Exchange the message with a mock firmware instead of the VideoCore.
+    ///
+    /// `firmware` gets the marshalled buffer, as the VideoCore would see it, and writes its
+    /// response into it. The response is then checked like in `Mailbox::send()`. Lets tests
+    /// round-trip a tag without hardware.
+    pub fn send_to_mock(&mut self, firmware: impl FnOnce(&mut [u32])) -> Result<&T, ()> {
+        if unsafe { self.marshal() }.is_none() {
+            return Err(());
+        }
+
+        let len = self.raw_buffer().len();
+        firmware(unsafe { core::slice::from_raw_parts_mut(self.buffer, len) });
+
+        unsafe { self.response() }
+    }
+
+    /// Check the request code of a marshalled message after the firmware answered it.
+    unsafe fn response(&mut self) -> Result<&T, ()> {
+        if (*(self.buffer as *const RawMessage)).request_code != 0x80000000 {
+            Err(())
+        } else {
+            Ok(self.read())
+        }
     }
 
     unsafe fn read(&mut self) -> &T {
@@ -271,6 +822,12 @@ impl<'a, T: Tag> Message<'a, T> {
     }
 }
 
+impl<'a, T: Tag> Drop for Message<'a, T> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
@@ -284,3 +841,193 @@ impl driver::interface::DeviceDriver for Mailbox {
         Ok(())
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Toggling the ACT LED must produce the documented set-gpio-state payload.
+    #[cfg(feature = "bsp_rpi3")]
+    #[kernel_test]
+    fn set_gpio_state_tag_toggles_act_led() {
+        use crate::bsp::gpio_expander;
+
+        for &on in [true, false].iter() {
+            let state_tag = &mut PropertyTagSetGpioState::new(gpio_expander::ACT_LED, on);
+            let tag = PropertyTag::new(PropertyTags::SET_GPIO_STATE, state_tag);
+
+            assert_eq!(tag.id, 0x00038041);
+            assert_eq!(tag.buf_size, 8);
+            assert_eq!(tag.value_length, 8);
+            assert_eq!(tag.tag.gpio, 130);
+            assert_eq!(tag.tag.state, on as u32);
+        }
+    }
+
+    /// The get-gpio-state request only carries the GPIO number.
+    #[kernel_test]
+    fn get_gpio_state_tag_requests_gpio_only() {
+        let state_tag = &mut PropertyTagGetGpioState::new(130);
+        let tag = PropertyTag::new(PropertyTags::GET_GPIO_STATE, state_tag);
+
+        assert_eq!(tag.id, 0x00030041);
+        assert_eq!(tag.buf_size, 8);
+        assert_eq!(tag.value_length, 4);
+        assert_eq!(tag.tag.gpio, 130);
+    }
+
+    /// The xHCI reset notification carries the VL805's PCI address on bus 1.
+    #[kernel_test]
+    fn notify_xhci_reset_tag_carries_pci_dev_addr() {
+        let notify_tag =
+            &mut PropertyTagNotifyXhciReset::new(PropertyTagNotifyXhciReset::VL805_PCI_DEV_ADDR);
+        let tag = PropertyTag::new(PropertyTags::NOTIFY_XHCI_RESET, notify_tag);
+
+        assert_eq!(tag.id, 0x00030058);
+        assert_eq!(tag.buf_size, 4);
+        assert_eq!(tag.value_length, 4);
+        assert_eq!(tag.tag.pci_dev_addr, 0x0010_0000);
+    }
+
+    /// The set-virtual-offset request carries both coordinates.
+    #[kernel_test]
+    fn set_virtual_offset_tag_carries_offset() {
+        let offset_tag = &mut PropertyTagVirtualOffset::new(0, 480);
+        let tag = PropertyTag::new(PropertyTags::SET_VIRTUAL_OFFSET, offset_tag);
+
+        assert_eq!(tag.id, 0x00048009);
+        assert_eq!(tag.buf_size, 8);
+        assert_eq!(tag.value_length, 8);
+        assert_eq!(tag.tag.x, 0);
+        assert_eq!(tag.tag.y, 480);
+    }
+
+    /// The get-customer-otp request carries the row range, and leaves room for the whole region.
+    #[kernel_test]
+    fn get_customer_otp_tag_requests_row_range() {
+        let otp_tag = &mut PropertyTagGetCustomerOtp::new(2, 3);
+        let tag = PropertyTag::new(PropertyTags::GET_CUSTOMER_OTP, otp_tag);
+
+        assert_eq!(tag.id, 0x00030021);
+        assert_eq!(tag.buf_size, 8 + 8 * 4);
+        assert_eq!(tag.value_length, 8);
+        assert_eq!(tag.tag.start, 2);
+        assert_eq!(tag.tag.count, 3);
+    }
+
+    /// The allocate-memory request carries size, alignment and flags, the others only the handle.
+    #[kernel_test]
+    fn memory_tags_have_documented_layout() {
+        let alloc_tag = &mut PropertyTagAllocateMemory::new(
+            4096,
+            4096,
+            PropertyTagAllocateMemory::FLAG_DIRECT | PropertyTagAllocateMemory::FLAG_ZERO,
+        );
+        let tag = PropertyTag::new(PropertyTags::ALLOCATE_MEMORY, alloc_tag);
+
+        assert_eq!(tag.id, 0x0003000C);
+        assert_eq!(tag.buf_size, 12);
+        assert_eq!(tag.value_length, 12);
+        assert_eq!(tag.tag.flags, 0b1_0100);
+
+        for &id in [
+            PropertyTags::LOCK_MEMORY,
+            PropertyTags::UNLOCK_MEMORY,
+            PropertyTags::RELEASE_MEMORY,
+        ]
+        .iter()
+        {
+            let handle_tag = &mut PropertyTagMemoryHandle::new(7);
+            let tag = PropertyTag::new(id, handle_tag);
+
+            assert_eq!(tag.buf_size, 4);
+            assert_eq!(tag.value_length, 4);
+            assert_eq!(tag.tag.handle, 7);
+        }
+    }
+
+    /// A get-physical-size response must parse into the display size, and `0` x `0` into none.
+    #[kernel_test]
+    fn get_physical_size_response_parses() {
+        let size_tag = &mut PropertyTagGetPhysicalSize::new();
+        let tag = PropertyTag::new(PropertyTags::GET_PHYSICAL_SIZE, size_tag);
+
+        assert_eq!(tag.id, 0x00040003);
+        assert_eq!(tag.buf_size, 8);
+        assert_eq!(tag.value_length, 0);
+
+        let buffer: [u32; 8] = [
+            8 * 4,
+            0x8000_0000,
+            PropertyTags::GET_PHYSICAL_SIZE,
+            8,
+            TAG_RESPONSE_BIT | 8,
+            1920,
+            1080,
+            0,
+        ];
+        let payload = response_payload(&buffer);
+        assert_eq!(
+            PropertyTagGetPhysicalSize::parse(payload),
+            Some((1920, 1080))
+        );
+
+        // No display attached.
+        let mut headless = buffer;
+        headless[VALUE_BUFFER_INDEX] = 0;
+        headless[VALUE_BUFFER_INDEX + 1] = 0;
+        assert_eq!(
+            PropertyTagGetPhysicalSize::parse(response_payload(&headless)),
+            None
+        );
+
+        // The firmware does not know the tag.
+        let mut unknown = buffer;
+        unknown[VALUE_LENGTH_INDEX] = 0;
+        assert_eq!(
+            PropertyTagGetPhysicalSize::parse(response_payload(&unknown)),
+            None
+        );
+    }
+
+    /// The response view must cover exactly the firmware reported response, without the header.
+    #[kernel_test]
+    fn response_slice_matches_reported_response_size() {
+        // A GET_BOARD_SERIAL response: 16 byte value buffer, firmware responded with 8 bytes.
+        // Followed by a second tag that must not be part of the view.
+        let buffer: [u32; 12] = [
+            12 * 4,
+            0x8000_0000,
+            PropertyTags::GET_BOARD_SERIAL,
+            16,
+            TAG_RESPONSE_BIT | 8,
+            0x1111_1111,
+            0x2222_2222,
+            0,
+            0,
+            PropertyTags::GET_BOARD_MODEL,
+            4,
+            0,
+        ];
+
+        let payload = response_payload(&buffer);
+        assert_eq!(payload.len(), 2);
+        assert_eq!(payload, &[0x1111_1111, 0x2222_2222]);
+        assert_eq!(response_len_bytes(&buffer), 8);
+
+        // A response that claims more than the value buffer is capped at the value buffer.
+        let mut too_long = buffer;
+        too_long[VALUE_LENGTH_INDEX] = TAG_RESPONSE_BIT | 64;
+        assert_eq!(response_payload(&too_long).len(), 4);
+
+        // No response bit, no payload.
+        let mut no_response = buffer;
+        no_response[VALUE_LENGTH_INDEX] = 8;
+        assert!(response_payload(&no_response).is_empty());
+    }
+}
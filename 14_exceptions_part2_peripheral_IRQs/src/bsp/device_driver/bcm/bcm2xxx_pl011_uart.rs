@@ -5,10 +5,18 @@
 //! PL011 UART driver.
 
 use crate::{
-    bsp, bsp::device_driver::common::MMIODerefWrapper, console, cpu, driver, exception,
-    synchronization, synchronization::IRQSafeNullLock,
+    bsp,
+    bsp::device_driver::common::MMIODerefWrapper,
+    console,
+    console::{RxErrorCounts, RxErrors},
+    cpu, driver, exception, synchronization,
+    synchronization::IRQSafeNullLock,
+};
+use core::{
+    fmt,
+    ops::Range,
+    sync::atomic::{AtomicU32, Ordering},
 };
-use core::fmt;
 use register::{mmio::*, register_bitfields, register_structs};
 
 //--------------------------------------------------------------------------------------------------
@@ -22,6 +30,37 @@ use register::{mmio::*, register_bitfields, register_structs};
 register_bitfields! {
     u32,
 
+    /// Data Register
+    DR [
+        /// Overrun error. Set if data is received and the receive FIFO is already full.
+        OE OFFSET(11) NUMBITS(1) [],
+
+        /// Break error. Set if a break condition was detected, indicating that the received data
+        /// input was held LOW for longer than a full-word transmission time.
+        BE OFFSET(10) NUMBITS(1) [],
+
+        /// Parity error. Set if the parity of the received data character does not match the
+        /// parity that the EPS and SPS bits in the Line Control Register select.
+        PE OFFSET(9) NUMBITS(1) [],
+
+        /// Framing error. Set if the received character did not have a valid stop bit.
+        FE OFFSET(8) NUMBITS(1) [],
+
+        /// Receive (read) data character. Transmit (write) data character.
+        DATA OFFSET(0) NUMBITS(8) []
+    ],
+
+    /// Receive Status Register / Error Clear Register
+    ///
+    /// A read returns the error flags of the character that was read from DR last. Overrun is
+    /// flagged as soon as it happens. A write of any value clears all flags.
+    RSRECR [
+        OE OFFSET(3) NUMBITS(1) [],
+        BE OFFSET(2) NUMBITS(1) [],
+        PE OFFSET(1) NUMBITS(1) [],
+        FE OFFSET(0) NUMBITS(1) []
+    ],
+
     /// Flag Register
     FR [
         /// Transmit FIFO empty. The meaning of this bit depends on the state of the FEN bit in the
@@ -44,7 +83,12 @@ register_bitfields! {
         ///
         /// If the FIFO is disabled, this bit is set when the receive holding register is empty. If
         /// the FIFO is enabled, the RXFE bit is set when the receive FIFO is empty.
-        RXFE OFFSET(4) NUMBITS(1) []
+        RXFE OFFSET(4) NUMBITS(1) [],
+
+        /// UART busy. If this bit is set to 1, the UART is busy transmitting data. This bit remains
+        /// set until the complete byte, including all the stop bits, has been sent from the shift
+        /// register.
+        BUSY OFFSET(3) NUMBITS(1) []
     ],
 
     /// Integer Baud rate divisor
@@ -161,14 +205,15 @@ register_bitfields! {
 register_structs! {
     #[allow(non_snake_case)]
     pub RegisterBlock {
-        (0x00 => DR: ReadWrite<u32>),
-        (0x04 => _reserved1),
+        (0x00 => DR: ReadWrite<u32, DR::Register>),
+        (0x04 => RSRECR: ReadWrite<u32, RSRECR::Register>),
+        (0x08 => _reserved1),
         (0x18 => FR: ReadOnly<u32, FR::Register>),
         (0x1c => _reserved2),
-        (0x24 => IBRD: WriteOnly<u32, IBRD::Register>),
-        (0x28 => FBRD: WriteOnly<u32, FBRD::Register>),
-        (0x2c => LCRH: WriteOnly<u32, LCRH::Register>),
-        (0x30 => CR: WriteOnly<u32, CR::Register>),
+        (0x24 => IBRD: ReadWrite<u32, IBRD::Register>),
+        (0x28 => FBRD: ReadWrite<u32, FBRD::Register>),
+        (0x2c => LCRH: ReadWrite<u32, LCRH::Register>),
+        (0x30 => CR: ReadWrite<u32, CR::Register>),
         (0x34 => IFLS: ReadWrite<u32, IFLS::Register>),
         (0x38 => IMSC: ReadWrite<u32, IMSC::Register>),
         (0x3C => _reserved3),
@@ -181,6 +226,17 @@ register_structs! {
 /// Abstraction for the associated MMIO registers.
 type Registers = MMIODerefWrapper<RegisterBlock>;
 
+/// The configuration registers, as saved by `Suspendable::suspend()`.
+#[derive(Copy, Clone)]
+struct SavedConfig {
+    ibrd: u32,
+    fbrd: u32,
+    lcrh: u32,
+    cr: u32,
+    ifls: u32,
+    imsc: u32,
+}
+
 #[derive(PartialEq)]
 enum BlockingMode {
     Blocking,
@@ -195,8 +251,20 @@ pub struct PL011UartInner {
     registers: Registers,
     chars_written: usize,
     chars_read: usize,
+
+    /// Errors of the characters read since the last `clear_rx_errors()`.
+    rx_errors: RxErrors,
+    rx_error_counts: RxErrorCounts,
+
+    /// The configuration while suspended.
+    saved: Option<SavedConfig>,
 }
 
+/// The UART reference clock that the divisors are computed from.
+///
+/// Shared by all instances, so that the panic UART uses the same clock as the console.
+static UART_CLOCK_HZ: AtomicU32 = AtomicU32::new(PL011UartInner::DEFAULT_UART_CLOCK_HZ);
+
 // Export the inner struct so that BSPs can use it for the panic handler.
 pub use PL011UartInner as PanicUart;
 
@@ -211,6 +279,15 @@ pub struct PL011Uart {
 //--------------------------------------------------------------------------------------------------
 
 impl PL011UartInner {
+    /// The assumed UART reference clock. Set to 48 MHz through `init_uart_clock` in `config.txt`.
+    const DEFAULT_UART_CLOCK_HZ: u32 = 48_000_000;
+
+    /// The target baud rate.
+    const BAUD_RATE: u32 = 230_400;
+
+    /// Upper bound for the number of polls of the TX flags in `deinit()` and `reinit()`.
+    const REINIT_BUSY_SPINS: usize = 100_000;
+
     /// Create an instance.
     ///
     /// # Safety
@@ -221,24 +298,47 @@ impl PL011UartInner {
             registers: Registers::new(base_addr),
             chars_written: 0,
             chars_read: 0,
+            rx_errors: RxErrors {
+                framing: false,
+                parity: false,
+                break_condition: false,
+                overrun: false,
+            },
+            rx_error_counts: RxErrorCounts {
+                framing: 0,
+                parity: 0,
+                break_condition: 0,
+                overrun: 0,
+            },
+            saved: None,
         }
     }
 
-    /// Set up baud rate and characteristics.
+    /// Return the integer and fractional baud rate divisors for `clock_hz`.
     ///
     /// The calculation for the BRD given a target rate of 2300400 and a clock set to 48 MHz is:
     /// `(48_000_000/16)/230400 = 13,02083`. `13` goes to the `IBRD` (integer field). The `FBRD`
     /// (fractional field) is only 6 bits so `0,0208*64 = 1,3312 rounded to 1` will give the best
     /// approximation we can get. A 5 % error margin is acceptable for UART and we're now at 0,01 %.
+    const fn divisors(clock_hz: u32) -> (u32, u32) {
+        let baud = Self::BAUD_RATE as u64;
+        let div_64ths = ((clock_hz as u64) * 4 + baud / 2) / baud;
+
+        ((div_64ths / 64) as u32, (div_64ths % 64) as u32)
+    }
+
+    /// Set up baud rate and characteristics.
     ///
-    /// This results in 8N1 and 230400 baud (we set the clock to 48 MHz in config.txt).
+    /// This results in 8N1 and 230400 baud with the divisors for the current UART clock.
     pub fn init(&mut self) {
+        let (ibrd, fbrd) = Self::divisors(UART_CLOCK_HZ.load(Ordering::Relaxed));
+
         // Turn it off temporarily.
         self.registers.CR.set(0);
 
         self.registers.ICR.write(ICR::ALL::CLEAR);
-        self.registers.IBRD.write(IBRD::IBRD.val(13));
-        self.registers.FBRD.write(FBRD::FBRD.val(1));
+        self.registers.IBRD.write(IBRD::IBRD.val(ibrd));
+        self.registers.FBRD.write(FBRD::FBRD.val(fbrd));
         self.registers
             .LCRH
             .write(LCRH::WLEN::EightBit + LCRH::FEN::FifosEnabled); // 8N1 + Fifo on
@@ -251,21 +351,205 @@ impl PL011UartInner {
             .write(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled);
     }
 
-    /// Send a character.
+    /// Let pending output drain, then switch the UART off, mask its interrupts and forget the
+    /// statistics, so that the next `init()` starts from scratch.
+    pub fn deinit(&mut self) {
+        for _ in 0..Self::REINIT_BUSY_SPINS {
+            if !self.registers.FR.is_set(FR::BUSY) {
+                break;
+            }
+            cpu::nop();
+        }
+
+        self.registers.CR.set(0);
+        self.registers.IMSC.set(0);
+        self.registers.ICR.write(ICR::ALL::CLEAR);
+
+        // Disabling the FIFOs flushes them.
+        self.registers.LCRH.set(0);
+
+        self.chars_written = 0;
+        self.chars_read = 0;
+        self.rx_errors = RxErrors::default();
+        self.rx_error_counts = RxErrorCounts::default();
+    }
+
+    /// Force the UART back into the state set up by `init()`, no matter what state it is in.
+    ///
+    /// Used on the emergency path of the panic handler. Unlike `init()`, this does not rely on the
+    /// UART being idle or correctly configured. Pending output is given a bounded amount of time to
+    /// drain before the UART is switched off, so that it is not cut off. Whatever is still stuck
+    /// after that is flushed, so that left-over garbage does not precede the panic message.
+    pub fn reinit(&mut self) {
+        for _ in 0..Self::REINIT_BUSY_SPINS {
+            if self.registers.FR.matches_all(FR::TXFE::SET) && !self.registers.FR.is_set(FR::BUSY) {
+                break;
+            }
+            cpu::nop();
+        }
+
+        self.registers.CR.set(0);
+
+        // Disabling the FIFOs flushes them.
+        self.registers.LCRH.set(0);
+
+        self.init();
+    }
+
+    /// Return whether the UART is configured the way `init()` left it.
+    pub fn config_is_default(&self) -> bool {
+        let (ibrd, fbrd) = Self::divisors(UART_CLOCK_HZ.load(Ordering::Relaxed));
+
+        self.divisor_registers() == (ibrd, fbrd)
+            && self
+                .registers
+                .LCRH
+                .matches_all(LCRH::WLEN::EightBit + LCRH::FEN::FifosEnabled)
+            && self
+                .registers
+                .CR
+                .matches_all(CR::UARTEN::Enabled + CR::TXE::Enabled + CR::RXE::Enabled)
+    }
+
+    /// Block until a character arrives. Used by the panic handler's debug shell.
+    pub fn read_char_blocking(&mut self) -> char {
+        self.read_char_converting(BlockingMode::Blocking).unwrap()
+    }
+
+    /// Scramble the line configuration, for testing only.
+    pub fn corrupt_config(&mut self) {
+        self.registers.CR.set(0);
+        self.registers.IBRD.write(IBRD::IBRD.val(1));
+        self.registers
+            .LCRH
+            .write(LCRH::WLEN::FiveBit + LCRH::FEN::FifosDisabled);
+    }
+
+    /// The baud rate that results from the configured divisors.
+    ///
+    /// `BAUDDIV = UART_CLOCK / (16 * baud)`, with the fractional part of BAUDDIV stored in 1/64ths.
+    fn baud_rate(&self) -> u32 {
+        let clock_hz = UART_CLOCK_HZ.load(Ordering::Relaxed);
+        let (ibrd, fbrd) = Self::divisors(clock_hz);
+
+        ((u64::from(clock_hz) * 4) / u64::from((ibrd * 64) + fbrd)) as u32
+    }
+
+    /// The divisors as currently programmed.
+    fn divisor_registers(&self) -> (u32, u32) {
+        (
+            self.registers.IBRD.read(IBRD::IBRD),
+            self.registers.FBRD.read(FBRD::FBRD),
+        )
+    }
+
+    /// Block until the TX FIFO is empty and the last character has left the shift register.
+    fn flush(&self) {
+        while !self.registers.FR.matches_all(FR::TXFE::SET) || self.registers.FR.is_set(FR::BUSY) {
+            cpu::nop();
+        }
+    }
+
+    /// Send a character. Only its lower eight bits reach the line.
     fn write_char(&mut self, c: char) {
+        self.write_byte(c as u8)
+    }
+
+    /// Send a raw byte. Dropped while suspended, as the disabled UART never drains the FIFO.
+    fn write_byte(&mut self, b: u8) {
+        if self.saved.is_some() {
+            return;
+        }
+
         // Spin while TX FIFO full is set, waiting for an empty slot.
         while self.registers.FR.matches_all(FR::TXFF::SET) {
             cpu::nop();
         }
 
-        // Write the character to the buffer.
-        self.registers.DR.set(c as u32);
+        // Write the byte to the buffer.
+        self.registers.DR.set(b as u32);
 
         self.chars_written += 1;
     }
 
-    /// Retrieve a character.
-    fn read_char_converting(&mut self, blocking_mode: BlockingMode) -> Option<char> {
+    /// Save the configuration, then disable the UART and its IRQs.
+    fn suspend(&mut self) {
+        if self.saved.is_some() {
+            return;
+        }
+
+        self.flush();
+
+        self.saved = Some(SavedConfig {
+            ibrd: self.registers.IBRD.get(),
+            fbrd: self.registers.FBRD.get(),
+            lcrh: self.registers.LCRH.get(),
+            cr: self.registers.CR.get(),
+            ifls: self.registers.IFLS.get(),
+            imsc: self.registers.IMSC.get(),
+        });
+
+        self.registers.CR.set(0);
+        self.registers.IMSC.set(0);
+        self.registers.ICR.write(ICR::ALL::CLEAR);
+    }
+
+    /// Restore the configuration that `suspend()` saved.
+    ///
+    /// The UART stays disabled until all other registers are written. A write to LCRH latches the
+    /// divisors, so it comes after IBRD and FBRD.
+    fn resume(&mut self) {
+        let saved = match self.saved.take() {
+            None => return,
+            Some(saved) => saved,
+        };
+
+        self.registers.CR.set(0);
+        self.registers.ICR.write(ICR::ALL::CLEAR);
+        self.registers.IBRD.set(saved.ibrd);
+        self.registers.FBRD.set(saved.fbrd);
+        self.registers.LCRH.set(saved.lcrh);
+        self.registers.IFLS.set(saved.ifls);
+        self.registers.IMSC.set(saved.imsc);
+        self.registers.CR.set(saved.cr);
+    }
+
+    /// Accumulate the error flags of a received character and count them.
+    fn record_rx_errors(&mut self, errors: RxErrors) {
+        if errors.framing {
+            self.rx_errors.framing = true;
+            self.rx_error_counts.framing += 1;
+        }
+        if errors.parity {
+            self.rx_errors.parity = true;
+            self.rx_error_counts.parity += 1;
+        }
+        if errors.break_condition {
+            self.rx_errors.break_condition = true;
+            self.rx_error_counts.break_condition += 1;
+        }
+        if errors.overrun {
+            self.rx_errors.overrun = true;
+            self.rx_error_counts.overrun += 1;
+        }
+    }
+
+    /// The errors seen since the last `clear_rx_errors()`, including a pending overrun.
+    fn last_rx_errors(&self) -> RxErrors {
+        let mut errors = self.rx_errors;
+        errors.overrun |= self.registers.RSRECR.is_set(RSRECR::OE);
+
+        errors
+    }
+
+    /// Clear the error flags in the UART and the accumulated ones.
+    fn clear_rx_errors(&mut self) {
+        self.registers.RSRECR.set(0);
+        self.rx_errors = RxErrors::default();
+    }
+
+    /// Retrieve a raw byte.
+    fn read_byte(&mut self, blocking_mode: BlockingMode) -> Option<u8> {
         // If RX FIFO is empty,
         if self.registers.FR.matches_all(FR::RXFE::SET) {
             // immediately return in non-blocking mode.
@@ -279,17 +563,30 @@ impl PL011UartInner {
             }
         }
 
-        // Read one character.
-        let mut ret = self.registers.DR.get() as u8 as char;
+        // Read one character, together with its error flags.
+        let data = self.registers.DR.extract();
+        self.record_rx_errors(RxErrors {
+            framing: data.is_set(DR::FE),
+            parity: data.is_set(DR::PE),
+            break_condition: data.is_set(DR::BE),
+            overrun: data.is_set(DR::OE),
+        });
+
+        // Update statistics.
+        self.chars_read += 1;
+
+        Some(data.read(DR::DATA) as u8)
+    }
+
+    /// Retrieve a character.
+    fn read_char_converting(&mut self, blocking_mode: BlockingMode) -> Option<char> {
+        let mut ret = self.read_byte(blocking_mode)? as char;
 
         // Convert carrige return to newline.
         if ret == '\r' {
             ret = '\n'
         }
 
-        // Update statistics.
-        self.chars_read += 1;
-
         Some(ret)
     }
 }
@@ -323,6 +620,44 @@ impl PL011Uart {
             irq_number,
         }
     }
+
+    /// The configured baud rate.
+    pub fn baud_rate(&self) -> u32 {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.baud_rate())
+    }
+
+    /// The integer and fractional baud rate divisors as currently programmed.
+    pub fn divisors(&self) -> (u32, u32) {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.divisor_registers())
+    }
+
+    /// Compare the assumed UART clock with `clock_hz`, e.g. as reported by the firmware.
+    ///
+    /// If they differ, the divisors are recomputed for `clock_hz` and the UART is reprogrammed.
+    /// Returns the previously assumed clock in that case. Clocks too slow for the baud rate are
+    /// rejected.
+    pub fn update_clock_rate(&self, clock_hz: u32) -> Result<Option<u32>, &'static str> {
+        let assumed_hz = UART_CLOCK_HZ.load(Ordering::Relaxed);
+        if clock_hz == assumed_hz {
+            return Ok(None);
+        }
+
+        if PL011UartInner::divisors(clock_hz).0 == 0 {
+            return Err("UART clock too slow for the baud rate");
+        }
+
+        let mut r = &self.inner;
+        r.lock(|inner| {
+            inner.flush();
+
+            UART_CLOCK_HZ.store(clock_hz, Ordering::Relaxed);
+            inner.init();
+        });
+
+        Ok(Some(assumed_hz))
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -335,6 +670,11 @@ impl driver::interface::DeviceDriver for PL011Uart {
         "BCM PL011 UART"
     }
 
+    fn mmio_region(&self) -> Option<Range<usize>> {
+        let mut r = &self.inner;
+        Some(r.lock(|inner| inner.registers.mmio_range()))
+    }
+
     fn init(&self) -> Result<(), ()> {
         let mut r = &self.inner;
         r.lock(|inner| inner.init());
@@ -356,6 +696,48 @@ impl driver::interface::DeviceDriver for PL011Uart {
 
         Ok(())
     }
+
+    fn deregister_irq_handler(&self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::interface::IRQManager;
+
+        irq_manager().deregister_handler(self.irq_number)
+    }
+
+    fn disable_irqs(&self) {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::interface::IRQManager;
+
+        irq_manager().disable(self.irq_number);
+    }
+
+    fn enable_irqs(&self) {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::interface::IRQManager;
+
+        irq_manager().enable(self.irq_number);
+    }
+
+    fn deinit(&self) {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.deinit());
+    }
+
+    fn as_suspendable(&self) -> Option<&dyn driver::interface::Suspendable> {
+        Some(self)
+    }
+}
+
+impl driver::interface::Suspendable for PL011Uart {
+    fn suspend(&self) {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.suspend());
+    }
+
+    fn resume(&self) {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.resume());
+    }
 }
 
 impl console::interface::Write for PL011Uart {
@@ -366,6 +748,20 @@ impl console::interface::Write for PL011Uart {
         r.lock(|inner| inner.write_char(c));
     }
 
+    fn write_byte(&self, b: u8) {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.write_byte(b));
+    }
+
+    fn write_bytes(&self, bytes: &[u8]) {
+        let mut r = &self.inner;
+        r.lock(|inner| {
+            for b in bytes {
+                inner.write_byte(*b)
+            }
+        });
+    }
+
     fn write_fmt(&self, args: core::fmt::Arguments) -> fmt::Result {
         // Fully qualified syntax for the call to `core::fmt::Write::write:fmt()` to increase
         // readability.
@@ -374,13 +770,9 @@ impl console::interface::Write for PL011Uart {
     }
 
     fn flush(&self) {
-        // Spin until TX FIFO empty is set.
+        // Spin until TX FIFO empty is set and the UART is not busy anymore.
         let mut r = &self.inner;
-        r.lock(|inner| {
-            while !inner.registers.FR.matches_all(FR::TXFE::SET) {
-                cpu::nop();
-            }
-        });
+        r.lock(|inner| inner.flush());
     }
 }
 
@@ -390,6 +782,33 @@ impl console::interface::Read for PL011Uart {
         r.lock(|inner| inner.read_char_converting(BlockingMode::Blocking).unwrap())
     }
 
+    fn read_char_nb(&self) -> Option<char> {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.read_char_converting(BlockingMode::NonBlocking))
+    }
+
+    fn read_byte_nb(&self) -> Option<u8> {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.read_byte(BlockingMode::NonBlocking))
+    }
+
+    fn read_bytes(&self, buf: &mut [u8]) -> usize {
+        let mut r = &self.inner;
+        r.lock(|inner| {
+            let mut len = 0;
+
+            while len < buf.len() {
+                match inner.read_byte(BlockingMode::NonBlocking) {
+                    Some(b) => buf[len] = b,
+                    None => break,
+                }
+                len += 1;
+            }
+
+            len
+        })
+    }
+
     fn clear(&self) {
         let mut r = &self.inner;
         r.lock(|inner| {
@@ -399,6 +818,16 @@ impl console::interface::Read for PL011Uart {
             }
         })
     }
+
+    fn last_rx_errors(&self) -> RxErrors {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.last_rx_errors())
+    }
+
+    fn clear_rx_errors(&self) {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.clear_rx_errors())
+    }
 }
 
 impl console::interface::Statistics for PL011Uart {
@@ -411,6 +840,11 @@ impl console::interface::Statistics for PL011Uart {
         let mut r = &self.inner;
         r.lock(|inner| inner.chars_read)
     }
+
+    fn rx_error_counts(&self) -> RxErrorCounts {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.rx_error_counts)
+    }
 }
 
 impl exception::asynchronous::interface::IRQHandler for PL011Uart {
@@ -434,3 +868,76 @@ impl exception::asynchronous::interface::IRQHandler for PL011Uart {
         Ok(())
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// A clock that differs from the assumed one must lead to recomputed divisors.
+    #[kernel_test]
+    fn divisors_are_recomputed_for_different_clock() {
+        let uart = unsafe {
+            PL011Uart::new(
+                bsp::memory::uart0_base(),
+                bsp::exception::asynchronous::irq_map::PL011_UART,
+            )
+        };
+        let default_hz = PL011UartInner::DEFAULT_UART_CLOCK_HZ;
+
+        // The clock that a mocked firmware reports: 24 MHz, i.e. a divisor of 6 + 33/64.
+        assert_eq!(uart.update_clock_rate(24_000_000), Ok(Some(default_hz)));
+        assert_eq!(uart.divisors(), (6, 33));
+        assert_eq!(uart.update_clock_rate(24_000_000), Ok(None));
+
+        assert!(uart.update_clock_rate(3_000_000).is_err());
+        assert_eq!(uart.divisors(), (6, 33));
+
+        assert_eq!(uart.update_clock_rate(default_hz), Ok(Some(24_000_000)));
+        assert_eq!(uart.divisors(), (13, 1));
+    }
+
+    /// RAM that stands in for the register block.
+    static mut FAKE_REGISTERS: [u32; 0x48 / 4] = [0; 0x48 / 4];
+
+    /// A character with a framing error must be flagged and counted, and clearing must reset the
+    /// flag but keep the count.
+    #[kernel_test]
+    fn framing_error_is_flagged_until_cleared() {
+        use console::interface::{Read, Statistics};
+
+        let uart = unsafe {
+            PL011Uart::new(
+                FAKE_REGISTERS.as_ptr() as usize,
+                bsp::exception::asynchronous::irq_map::PL011_UART,
+            )
+        };
+
+        // RX FIFO not empty, and the received character has its framing error bit set.
+        unsafe { FAKE_REGISTERS[0] = (1 << 8) | 'a' as u32 };
+
+        assert_eq!(uart.read_char_nb(), Some('a'));
+        assert_eq!(
+            uart.last_rx_errors(),
+            RxErrors {
+                framing: true,
+                ..RxErrors::default()
+            }
+        );
+        assert_eq!(uart.rx_error_counts().framing, 1);
+        assert_eq!(uart.rx_error_counts().parity, 0);
+
+        uart.clear_rx_errors();
+        assert!(!uart.last_rx_errors().any());
+        assert_eq!(uart.rx_error_counts().framing, 1);
+
+        // An error-free character must not set anything.
+        unsafe { FAKE_REGISTERS[0] = 'b' as u32 };
+        assert_eq!(uart.read_char_nb(), Some('b'));
+        assert!(!uart.last_rx_errors().any());
+    }
+}
@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Power Management Driver.
+//!
+//! Only the watchdog is used, to reset the board. Every write to a PM register must carry the
+//! password in its upper byte, or it is ignored.
+//!
+//! The board has no way to cut its own power. Instead, the reset status tells the firmware which
+//! partition to boot after a watchdog reset, and the partition number 63 makes it halt.
+
+use crate::{bsp::device_driver::common::MMIODerefWrapper, cpu};
+use register::{mmio::*, register_bitfields, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_bitfields! {
+    u32,
+
+    /// Reset Control.
+    RSTC [
+        PASSWORD OFFSET(24) NUMBITS(8) [
+            Key = 0x5A
+        ],
+
+        WRCFG OFFSET(4) NUMBITS(2) [
+            Clear = 0b00,
+            FullReset = 0b10
+        ]
+    ],
+
+    /// Reset Status.
+    RSTS [
+        PASSWORD OFFSET(24) NUMBITS(8) [
+            Key = 0x5A
+        ],
+
+        /// The boot partition, spread over the even bits 0 to 10. All set means halt.
+        PARTITION OFFSET(0) NUMBITS(11) [
+            Halt = 0x555
+        ]
+    ],
+
+    /// Watchdog.
+    WDOG [
+        PASSWORD OFFSET(24) NUMBITS(8) [
+            Key = 0x5A
+        ],
+
+        /// Ticks of the 65.536 kHz watchdog clock until the watchdog fires.
+        TIME OFFSET(0) NUMBITS(20) []
+    ]
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        (0x00 => _reserved1),
+        (0x1C => RSTC: ReadWrite<u32, RSTC::Register>),
+        (0x20 => RSTS: ReadWrite<u32, RSTS::Register>),
+        (0x24 => WDOG: ReadWrite<u32, WDOG::Register>),
+        (0x28 => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the power management block.
+pub struct PowerManagement {
+    registers: Registers,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl PowerManagement {
+    /// Watchdog ticks until the reset. Short, but long enough for the writes to settle.
+    const RESET_TICKS: u32 = 10;
+
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide the correct `base_addr`.
+    pub const unsafe fn new(base_addr: usize) -> Self {
+        Self {
+            registers: Registers::new(base_addr),
+        }
+    }
+
+    /// Reset the board through the watchdog.
+    ///
+    /// Needs no lock and no memory allocation, so that it is usable from the panic handler.
+    pub fn reset(&self) -> ! {
+        self.registers
+            .WDOG
+            .write(WDOG::PASSWORD::Key + WDOG::TIME.val(Self::RESET_TICKS));
+        self.registers
+            .RSTC
+            .modify(RSTC::PASSWORD::Key + RSTC::WRCFG::FullReset);
+
+        // The watchdog takes over from here.
+        cpu::wait_forever()
+    }
+
+    /// Reset the board through the watchdog, into a halt.
+    ///
+    /// Usable from the panic handler, like `reset()`.
+    pub fn power_off(&self) -> ! {
+        // The odd bits hold other status, which is kept.
+        let halt = RSTS::PASSWORD::Key + RSTS::PARTITION::Halt;
+        self.registers
+            .RSTS
+            .set(self.registers.RSTS.get() | halt.value);
+
+        self.reset()
+    }
+}
@@ -0,0 +1,232 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! System Timer Driver.
+//!
+//! The system timer is a free running 64 bit counter at a fixed 1 MHz, independent of the core
+//! clock, with four 32 bit compare channels that raise an IRQ when they match the counter's low
+//! word. Channels 0 and 2 are used by the VideoCore, so only channels 1 and 3 are available to the
+//! ARM.
+//!
+//! Descriptions taken from
+//! https://github.com/raspberrypi/documentation/files/1888662/BCM2837-ARM-Peripherals.-.Revised.-.V2-1.pdf
+
+use crate::{
+    bsp,
+    bsp::device_driver::common::{clear_bits, MMIODerefWrapper},
+    driver, exception, synchronization,
+    synchronization::IRQSafeNullLock,
+};
+use core::{ops::Range, time::Duration};
+use register::{mmio::*, register_structs};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+register_structs! {
+    #[allow(non_snake_case)]
+    RegisterBlock {
+        /// Match bits M0-M3 of the compare channels. Write-1-to-clear.
+        (0x00 => CS: ReadWrite<u32>),
+        (0x04 => CLO: ReadOnly<u32>),
+        (0x08 => CHI: ReadOnly<u32>),
+        (0x0C => C: [ReadWrite<u32>; 4]),
+        (0x1C => @END),
+    }
+}
+
+/// Abstraction for the associated MMIO registers.
+type Registers = MMIODerefWrapper<RegisterBlock>;
+
+/// The compare channels that the ARM may use.
+const ARM_CHANNELS: [usize; 2] = [1, 3];
+
+/// Delays below are rounded up, so that the compare value is not already in the past when it is
+/// written.
+const MIN_TICKS: u32 = 10;
+
+struct SystemTimerInner {
+    registers: Registers,
+
+    /// Callbacks of the pending one-shots, indexed like `ARM_CHANNELS`.
+    pending: [Option<fn()>; 2],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Representation of the system timer.
+pub struct SystemTimer {
+    inner: IRQSafeNullLock<SystemTimerInner>,
+    irq_numbers: [bsp::device_driver::IRQNumber; 2],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl SystemTimer {
+    /// The counter frequency.
+    pub const FREQUENCY_HZ: u64 = 1_000_000;
+
+    /// Create an instance.
+    ///
+    /// `irq_numbers` are the IRQs of the compare channels 1 and 3.
+    ///
+    /// # Safety
+    ///
+    /// - The user must ensure to provide the correct `base_addr`.
+    pub const unsafe fn new(
+        base_addr: usize,
+        irq_numbers: [bsp::device_driver::IRQNumber; 2],
+    ) -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(SystemTimerInner {
+                registers: Registers::new(base_addr),
+                pending: [None; 2],
+            }),
+            irq_numbers,
+        }
+    }
+
+    /// The counter value.
+    pub fn ticks(&self) -> u64 {
+        let mut r = &self.inner;
+        r.lock(|inner| {
+            // CHI may increment between the two reads. Retry until it is stable.
+            loop {
+                let hi = inner.registers.CHI.get();
+                let lo = inner.registers.CLO.get();
+
+                if hi == inner.registers.CHI.get() {
+                    return (u64::from(hi) << 32) | u64::from(lo);
+                }
+            }
+        })
+    }
+
+    /// Call `f` once from IRQ context after `d` has passed.
+    ///
+    /// Takes one of the two ARM compare channels until `f` was called and fails if none is free.
+    /// The IRQs must have been registered with `register_and_enable_irq_handler()`.
+    pub fn after(&self, d: Duration, f: fn()) -> Result<(), &'static str> {
+        let ticks = d.as_micros();
+        if ticks > u128::from(u32::MAX) {
+            return Err("Delay exceeds the compare range");
+        }
+        let ticks = core::cmp::max(ticks as u32, MIN_TICKS);
+
+        let mut r = &self.inner;
+        r.lock(|inner| {
+            let slot = match inner.pending.iter().position(|p| p.is_none()) {
+                None => return Err("No free system timer channel"),
+                Some(slot) => slot,
+            };
+            let channel = ARM_CHANNELS[slot];
+
+            inner.pending[slot] = Some(f);
+
+            // Acknowledge an old match before arming, so that it does not fire right away.
+            clear_bits(&inner.registers.CS, 1 << channel);
+            inner.registers.C[channel].set(inner.registers.CLO.get().wrapping_add(ticks));
+
+            Ok(())
+        })
+    }
+
+    /// Return the number of compare channels that are free for `after()`.
+    pub fn num_free_channels(&self) -> usize {
+        let mut r = &self.inner;
+        r.lock(|inner| inner.pending.iter().filter(|p| p.is_none()).count())
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl driver::interface::DeviceDriver for SystemTimer {
+    fn compatible(&self) -> &str {
+        "BCM System Timer"
+    }
+
+    fn mmio_region(&self) -> Option<Range<usize>> {
+        let mut r = &self.inner;
+        Some(r.lock(|inner| inner.registers.mmio_range()))
+    }
+
+    fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+        let descriptor = IRQDescriptor {
+            name: "BCM System Timer",
+            handler: self,
+        };
+
+        for irq_number in self.irq_numbers.iter() {
+            irq_manager().register_handler(*irq_number, descriptor)?;
+            irq_manager().enable(*irq_number);
+        }
+
+        Ok(())
+    }
+
+    fn deregister_irq_handler(&self) -> Result<(), &'static str> {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::interface::IRQManager;
+
+        for irq_number in self.irq_numbers.iter() {
+            irq_manager().deregister_handler(*irq_number)?;
+        }
+
+        Ok(())
+    }
+
+    fn disable_irqs(&self) {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::interface::IRQManager;
+
+        for irq_number in self.irq_numbers.iter() {
+            irq_manager().disable(*irq_number);
+        }
+    }
+
+    fn enable_irqs(&self) {
+        use bsp::exception::asynchronous::irq_manager;
+        use exception::asynchronous::interface::IRQManager;
+
+        for irq_number in self.irq_numbers.iter() {
+            irq_manager().enable(*irq_number);
+        }
+    }
+}
+
+impl exception::asynchronous::interface::IRQHandler for SystemTimer {
+    fn handle(&self) -> Result<(), &'static str> {
+        let mut expired: [Option<fn()>; 2] = [None; 2];
+
+        let mut r = &self.inner;
+        r.lock(|inner| {
+            let matched = inner.registers.CS.get();
+
+            for (slot, channel) in ARM_CHANNELS.iter().enumerate() {
+                if matched & (1 << channel) != 0 {
+                    clear_bits(&inner.registers.CS, 1 << channel);
+                    expired[slot] = inner.pending[slot].take();
+                }
+            }
+        });
+
+        // Call outside of the lock, so that callbacks can schedule the next one-shot.
+        for f in expired.iter().flatten() {
+            f();
+        }
+
+        Ok(())
+    }
+}
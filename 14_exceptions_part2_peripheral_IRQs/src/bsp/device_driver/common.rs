@@ -3,8 +3,37 @@
 // Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
 
 //! Common device driver code.
+//!
+//! # Write-1-to-clear registers
+//!
+//! Some registers contain bits with write-1-to-clear (W1C) semantics, for example the status bits
+//! of interrupt status registers or the change bits of the DWHCI host port register (HPRT). Reading
+//! such a register returns pending W1C bits as `1`. A naive read-modify-write therefore writes them
+//! back as `1` and clears them unintentionally.
+//!
+//! - Use [`clear_bits()`] to acknowledge W1C bits. It writes the mask only and never reads the
+//!   register.
+//! - Use [`modify()`] for registers without W1C bits.
+//! - For registers that mix W1C bits with normal read-write bits, use [`modify()`] and mask out the
+//!   W1C bits in the closure.
+//!
+//! # Registers shared with an IRQ handler
+//!
+//! A read-modify-write is not atomic. If the driver's IRQ handler writes the same register between
+//! the read and the write, one of the two updates is lost. Local IRQ masking does not help when the
+//! handler runs on another core.
+//!
+//! - Use [`MMIODerefWrapper::with_masked_modify()`] for registers that the driver's IRQ handler
+//!   also writes. It masks the IRQ at the interrupt controller for the duration of the update, so
+//!   the handler cannot preempt the update on the executing core. Masking does not stop a handler
+//!   that is already running on another core. Where the IRQ can be routed to another core, also
+//!   hold the lock that the handler takes.
+//! - Use [`modify()`] everywhere else, including inside the IRQ handler itself, and during init
+//!   before the IRQ is enabled.
 
-use core::{marker::PhantomData, ops};
+use crate::exception::asynchronous::interface::IRQManager;
+use core::{marker::PhantomData, mem, ops, ops::Range};
+use register::{mmio::ReadWrite, RegisterLongName};
 
 pub struct MMIODerefWrapper<T> {
     base_addr: usize,
@@ -24,6 +53,40 @@ impl<T> MMIODerefWrapper<T> {
     fn ptr(&self) -> *const T {
         self.base_addr as *const _
     }
+
+    /// Return the address range covered by the register block.
+    pub fn mmio_range(&self) -> Range<usize> {
+        self.base_addr..(self.base_addr + mem::size_of::<T>())
+    }
+
+    /// Read-modify-write of a register while `irq` is masked at the interrupt controller.
+    ///
+    /// `reg` selects the register from the block. The IRQ is re-enabled afterwards only if it was
+    /// enabled before, so this is safe to use before the handler is registered.
+    ///
+    /// This only protects against the handler on the executing core. An instance of the handler
+    /// that already runs on another core is not stopped by the mask and can still interleave with
+    /// the update.
+    pub fn with_masked_modify<R, M>(
+        &self,
+        reg: impl FnOnce(&T) -> &ReadWrite<u32, R>,
+        irq_manager: &M,
+        irq: M::IRQNumberType,
+        f: impl FnOnce(u32) -> u32,
+    ) where
+        R: RegisterLongName,
+        M: IRQManager + ?Sized,
+        M::IRQNumberType: Copy,
+    {
+        let was_enabled = irq_manager.is_enabled(irq);
+        irq_manager.disable(irq);
+
+        modify(reg(self), f);
+
+        if was_enabled {
+            irq_manager.enable(irq);
+        }
+    }
 }
 
 impl<T> ops::Deref for MMIODerefWrapper<T> {
@@ -33,3 +96,135 @@ impl<T> ops::Deref for MMIODerefWrapper<T> {
         unsafe { &*self.ptr() }
     }
 }
+
+/// Clear write-1-to-clear bits of a register.
+///
+/// Writes exactly `mask`. A `1` clears the respective bit, a `0` leaves it untouched.
+pub fn clear_bits<R: RegisterLongName>(reg: &ReadWrite<u32, R>, mask: u32) {
+    reg.set(mask);
+}
+
+/// Read-modify-write of a register.
+///
+/// Must not be used on registers that contain write-1-to-clear bits, unless `f` masks them out.
+pub fn modify<R: RegisterLongName>(reg: &ReadWrite<u32, R>, f: impl FnOnce(u32) -> u32) {
+    reg.set(f(reg.get()));
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exception;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    use test_macros::kernel_test;
+
+    /// An interrupt controller with a single IRQ that only tracks its enable state.
+    struct MockIrqManager {
+        enabled: AtomicBool,
+    }
+
+    impl IRQManager for MockIrqManager {
+        type IRQNumberType = usize;
+        type SavedIrqState = bool;
+        type PendingIrqState = ();
+
+        fn register_handler(
+            &self,
+            _irq_number: Self::IRQNumberType,
+            _descriptor: exception::asynchronous::IRQDescriptor,
+        ) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        fn deregister_handler(&self, _irq_number: Self::IRQNumberType) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        fn enable(&self, _irq_number: Self::IRQNumberType) {
+            self.enabled.store(true, Ordering::Relaxed);
+        }
+
+        fn disable(&self, _irq_number: Self::IRQNumberType) {
+            self.enabled.store(false, Ordering::Relaxed);
+        }
+
+        fn is_enabled(&self, _irq_number: Self::IRQNumberType) -> bool {
+            self.enabled.load(Ordering::Relaxed)
+        }
+
+        fn pending(&self) -> Self::PendingIrqState {}
+
+        fn disable_all(&self) -> Self::SavedIrqState {
+            self.enabled.swap(false, Ordering::Relaxed)
+        }
+
+        fn restore_all(&self, state: Self::SavedIrqState) {
+            self.enabled.store(state, Ordering::Relaxed);
+        }
+
+        fn set_fallback(&self, _f: fn(Self::IRQNumberType)) {}
+
+        fn handle_pending_irqs<'irq_context>(
+            &'irq_context self,
+            _ic: &exception::asynchronous::IRQContext<'irq_context>,
+        ) {
+        }
+
+        fn print_handler(&self) {}
+
+        fn print_pending(&self) {}
+    }
+
+    /// Return a register that is backed by `backing`.
+    fn reg_at(backing: &mut u32) -> &ReadWrite<u32> {
+        unsafe { &*(backing as *mut u32 as *const ReadWrite<u32>) }
+    }
+
+    /// `clear_bits()` must write the mask only, not a read-back-modified value.
+    #[kernel_test]
+    fn clear_bits_writes_exactly_the_mask() {
+        let mut backing: u32 = 0xFFFF_FFFF;
+
+        clear_bits(reg_at(&mut backing), 0b1010);
+
+        assert_eq!(backing, 0b1010);
+    }
+
+    /// `modify()` must preserve all bits that are not changed by the closure.
+    #[kernel_test]
+    fn modify_preserves_untouched_bits() {
+        let mut backing: u32 = 0xF0;
+
+        modify(reg_at(&mut backing), |val| val | 0b1);
+
+        assert_eq!(backing, 0xF1);
+    }
+
+    /// The IRQ must be masked during the update, and re-enabled with the register combined.
+    #[kernel_test]
+    fn with_masked_modify_masks_irq_during_update() {
+        let mut backing: u32 = 0xF0;
+        let regs: MMIODerefWrapper<ReadWrite<u32>> =
+            unsafe { MMIODerefWrapper::new(&mut backing as *mut u32 as usize) };
+        let irq_manager = MockIrqManager {
+            enabled: AtomicBool::new(true),
+        };
+
+        regs.with_masked_modify(
+            |reg| reg,
+            &irq_manager,
+            7,
+            |val| {
+                assert!(!irq_manager.is_enabled(7));
+                val | 0b1
+            },
+        );
+
+        assert!(irq_manager.is_enabled(7));
+        assert_eq!(backing, 0xF1);
+    }
+}
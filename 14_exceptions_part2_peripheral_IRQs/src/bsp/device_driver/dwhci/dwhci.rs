@@ -2,17 +2,17 @@ use crate::{
     bsp,
     bsp::{
         device_driver::{
-            common::MMIODerefWrapper, IRQNumber, Mailbox, Message, PropertyTag,
+            common, common::MMIODerefWrapper, IRQNumber, Mailbox, Message, PropertyTag,
             PropertyTagPowerState, PropertyTags,
         },
         MAILBOX,
     },
-    driver, exception, println, time,
+    driver, exception, fault, println, time,
     time::interface::TimeManager,
 };
-use core::{fmt, ops, time::Duration, u32::MAX};
+use core::{fmt, ops, ops::Range, time::Duration, u32::MAX};
 use cortex_a::barrier::{dmb, SY};
-use register::{mmio::*, register_bitfields, register_structs};
+use register::{mmio::*, register_bitfields, register_structs, FieldValue, LocalRegisterCopy};
 
 register_bitfields! {
     u32,
@@ -89,12 +89,24 @@ register_bitfields! {
         ]
     ],
 
+    /// Host port control and status (HPRT).
+    ///
+    /// Mixes read-write bits with write-1-to-clear bits. Never use a plain `modify()` on it, but
+    /// `DWHCIHost::modify_port()` or `DWHCIHost::clear_port_changes()`.
     HOST_PORT [
         CONNECT OFFSET(0) NUMBITS(1) [],
+
+        /// Write-1-to-clear.
         CONNECT_CHANGED OFFSET(1) NUMBITS(1) [],
+
+        /// Write-1-to-clear. Writing a 1 disables the port.
         ENABLE OFFSET(2) NUMBITS(1) [],
+
+        /// Write-1-to-clear.
         ENABLE_CHANGED OFFSET(3) NUMBITS(1) [],
         OVERCURRENT OFFSET(4) NUMBITS(1) [],
+
+        /// Write-1-to-clear.
         OVERCURRENT_CHANGED OFFSET(5) NUMBITS(1) [],
         RESET OFFSET(8) NUMBITS(1) [],
         POWER OFFSET(12) NUMBITS(1) [],
@@ -115,6 +127,7 @@ register_structs! {
         (0x008 => CORE_AHB_CFG: ReadWrite<u32, CORE_AHB_CFG::Register>),
         (0x00C => CORE_USB_CFG: ReadWrite<u32, CORE_USB_CFG::Register>),
         (0x010 => CORE_RESET: ReadWrite<u32, CORE_RESET::Register>),
+        // Write-1-to-clear. Acknowledge with `common::clear_bits()`.
         (0x014 => CORE_INT_STAT: ReadWrite<u32>),
         (0x018 => CORE_INT_MASK: ReadWrite<u32, CORE_INT_MASK::Register>),
         (0x01C => CORE_RX_STAT_RD: ReadOnly<u32>),
@@ -140,7 +153,24 @@ register_structs! {
         (0x000 => HOST_CFG: ReadWrite<u32, HOST_CFG::Register>),
         (0x004 => _reserved1),
         (0x040 => HOST_PORT: ReadWrite<u32, HOST_PORT::Register>),
-        (0x044 => @END),
+        (0x044 => _reserved2),
+        // DWHCI::MAX_CHANNELS channels.
+        (0x100 => CHANNELS: [ChannelRegisterBlock; 16]),
+        (0x300 => @END),
+    }
+}
+
+register_structs! {
+    #[allow(non_snake_case)]
+    pub ChannelRegisterBlock {
+        (0x000 => HCCHAR: ReadWrite<u32>),
+        (0x004 => HCSPLT: ReadWrite<u32>),
+        (0x008 => HCINT: ReadWrite<u32>),
+        (0x00C => HCINTMSK: ReadWrite<u32>),
+        (0x010 => HCTSIZ: ReadWrite<u32>),
+        (0x014 => HCDMA: ReadWrite<u32>),
+        (0x018 => _reserved1),
+        (0x020 => @END),
     }
 }
 
@@ -152,6 +182,25 @@ register_structs! {
     }
 }
 
+/// Number of reset and init sequences that are tried before giving up on the controller.
+const INIT_ATTEMPTS: usize = 3;
+
+/// Call `f` until it succeeds, at most `attempts` times. `f` is passed the zero-based attempt.
+///
+/// Returns the result of the last attempt.
+fn retry<T, E>(attempts: usize, mut f: impl FnMut(usize) -> Result<T, E>) -> Result<T, E> {
+    let mut attempt = 0;
+
+    loop {
+        let result = f(attempt);
+        attempt += 1;
+
+        if result.is_ok() || attempt >= attempts {
+            return result;
+        }
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -171,6 +220,16 @@ impl ops::Deref for DWHCI {
     }
 }
 
+/// Synthesis parameters of the core, as reported by the hardware config registers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct CoreInfo {
+    /// Number of host channels, capped at `DWHCI::MAX_CHANNELS`.
+    pub num_host_channels: usize,
+}
+
+/// Table of the host channels' registers, one row per channel.
+struct ChannelTable<'a>(&'a [ChannelRegisterBlock]);
+
 /// Representation of the DWHCI HW.
 pub struct DWHCIHost {
     base_addr: usize,
@@ -218,6 +277,21 @@ impl DWHCI {
         return self.CORE_VENDOR_ID.get();
     }
 
+    /// Return the core's synthesis parameters.
+    pub fn core_info(&self) -> CoreInfo {
+        CoreInfo::from_hw_cfg2(self.CORE_HW_CFG2.get())
+    }
+
+    /// Print the registers of all host channels that the hardware implements.
+    ///
+    /// Helps to find out why a transfer hangs, e.g. a channel that is still enabled in HCCHAR or a
+    /// pending HCINT that is masked in HCINTMSK.
+    pub fn dump_channels(&self) {
+        let num_channels = self.core_info().num_host_channels;
+
+        println!("{}", ChannelTable(&self.host.CHANNELS[..num_channels]));
+    }
+
     fn power_on(&self) -> Result<(), &str> {
         let power_on_tag = &mut PropertyTagPowerState {
             device_id: PropertyTagPowerState::DEVICE_ID_USB_HCD,
@@ -247,7 +321,7 @@ impl DWHCI {
     }
 
     fn enable_common_interrupts(&self) {
-        self.CORE_INT_STAT.set(MAX);
+        common::clear_bits(&self.CORE_INT_STAT, MAX);
     }
 
     fn enable_host_interrupts(&self) {
@@ -257,14 +331,13 @@ impl DWHCI {
         self.CORE_INT_MASK.modify(CORE_INT_MASK::HC_INTR::SET);
     }
 
-    fn init_core(&self) -> Result<(), &str> {
+    fn init_core(&self) -> Result<(), &'static str> {
         self.CORE_USB_CFG.write(
             CORE_USB_CFG::ULPI_EXT_VBUS_DRV::Disabled + CORE_USB_CFG::TERM_SEL_DL_PULSE::Disabled,
         );
 
-        match self.reset() {
-            Err(_) => return Err("failed to reset core"),
-            _ => (),
+        if let Err(msg) = self.reset_core() {
+            return Err(msg);
         }
 
         self.CORE_USB_CFG
@@ -304,7 +377,7 @@ impl DWHCI {
         Ok(())
     }
 
-    fn init_host(&self) -> Result<(), &str> {
+    fn init_host(&self) -> Result<(), &'static str> {
         self.host.power_regs.CFG.set(0);
 
         self.host.HOST_CFG.write(HOST_CFG::FSLS_PCLK_SEL.val(0));
@@ -324,13 +397,7 @@ impl DWHCI {
         self.flush_rx_fifo();
 
         if !self.host.HOST_PORT.is_set(HOST_PORT::POWER) {
-            self.host.HOST_PORT.modify(
-                HOST_PORT::CONNECT_CHANGED::CLEAR
-                    + HOST_PORT::ENABLE::CLEAR
-                    + HOST_PORT::ENABLE_CHANGED::CLEAR
-                    + HOST_PORT::OVERCURRENT_CHANGED::CLEAR
-                    + HOST_PORT::POWER::SET,
-            );
+            self.host.modify_port(HOST_PORT::POWER::SET);
         }
 
         self.enable_host_interrupts();
@@ -372,26 +439,18 @@ impl DWHCI {
 
         time::time_manager().spin_for(Duration::from_millis(100)); // see USB 2.0 spec
 
-        self.host.HOST_PORT.modify(
-            HOST_PORT::CONNECT_CHANGED::CLEAR
-                + HOST_PORT::ENABLE::CLEAR
-                + HOST_PORT::ENABLE_CHANGED::CLEAR
-                + HOST_PORT::OVERCURRENT_CHANGED::CLEAR
-                + HOST_PORT::RESET::SET,
-        );
+        self.host.modify_port(HOST_PORT::RESET::SET);
 
         time::time_manager().spin_for(Duration::from_millis(100)); // see USB 2.0 spec (tDRSTR)
 
-        self.host.HOST_PORT.modify(
-            HOST_PORT::CONNECT_CHANGED::CLEAR
-                + HOST_PORT::ENABLE::CLEAR
-                + HOST_PORT::ENABLE_CHANGED::CLEAR
-                + HOST_PORT::OVERCURRENT_CHANGED::CLEAR
-                + HOST_PORT::RESET::CLEAR,
-        );
+        self.host.modify_port(HOST_PORT::RESET::CLEAR);
 
         time::time_manager().spin_for(Duration::from_millis(20)); // see USB 2.0 spec (tRSTRCY)
 
+        // Acknowledge the connect and enable changes caused by attach and reset.
+        self.host
+            .clear_port_changes(HOST_PORT::CONNECT_CHANGED::SET + HOST_PORT::ENABLE_CHANGED::SET);
+
         true
     }
 
@@ -404,9 +463,8 @@ impl DWHCI {
         true
     }
 
-    fn reset(&self) -> Result<(), &str> {
-        let mut wait_ms = 100;
-
+    /// Wait until the AHB master is idle, for at most `wait_ms` milliseconds.
+    fn wait_for_ahb_idle(&self, mut wait_ms: u32) -> Result<(), &'static str> {
         while !self.CORE_RESET.is_set(CORE_RESET::AHB_IDLE) {
             wait_ms -= 1;
             if wait_ms == 0 {
@@ -416,9 +474,26 @@ impl DWHCI {
             time::time_manager().spin_for(Duration::from_millis(1));
         }
 
+        Ok(())
+    }
+
+    /// Soft reset the core (GRSTCTL.CSftRst).
+    ///
+    /// Resets the core's state machines and FIFOs, but not the configuration registers. Timing:
+    ///
+    /// - The reset must only be started while the AHB master is idle. Otherwise, an ongoing DMA
+    ///   transfer is cut short. Waits up to 100 ms.
+    /// - CSftRst is cleared by the core once the reset completed, which takes a few PHY clock
+    ///   cycles. Waits up to 10 ms.
+    /// - The AHB master then needs to become idle again before registers can be accessed.
+    /// - After that, the PHY clock needs time to stabilize before the core is usable. 100 ms are
+    ///   waited, as done by other DWHCI drivers.
+    pub fn reset_core(&self) -> Result<(), &'static str> {
+        self.wait_for_ahb_idle(100)?;
+
         self.CORE_RESET.write(CORE_RESET::SOFT_RESET::SET);
 
-        wait_ms = 10;
+        let mut wait_ms = 10;
 
         while self.CORE_RESET.is_set(CORE_RESET::SOFT_RESET) {
             wait_ms -= 1;
@@ -429,6 +504,8 @@ impl DWHCI {
             time::time_manager().spin_for(Duration::from_millis(1));
         }
 
+        self.wait_for_ahb_idle(100)?;
+
         time::time_manager().spin_for(Duration::from_millis(100));
 
         Ok(())
@@ -457,6 +534,41 @@ impl fmt::Display for DWHCI {
     }
 }
 
+impl CoreInfo {
+    /// Decode the GHWCFG2 register.
+    fn from_hw_cfg2(val: u32) -> Self {
+        let hw_cfg2: LocalRegisterCopy<u32, CORE_HW_CFG2::Register> = LocalRegisterCopy::new(val);
+
+        // The register holds the number of channels minus one.
+        let num_host_channels = hw_cfg2.read(CORE_HW_CFG2::NUM_HOST_CHANNELS) + 1;
+
+        Self {
+            num_host_channels: core::cmp::min(num_host_channels, DWHCI::MAX_CHANNELS) as usize,
+        }
+    }
+}
+
+impl fmt::Display for ChannelTable<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "CH  HCCHAR    HCINT     HCINTMSK  HCTSIZ    HCDMA")?;
+
+        for (i, ch) in self.0.iter().enumerate() {
+            writeln!(
+                f,
+                "{:>2}  {:08X}  {:08X}  {:08X}  {:08X}  {:08X}",
+                i,
+                ch.HCCHAR.get(),
+                ch.HCINT.get(),
+                ch.HCINTMSK.get(),
+                ch.HCTSIZ.get(),
+                ch.HCDMA.get()
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
 impl DWHCIHost {
     /// Create an instance.
     ///
@@ -477,6 +589,30 @@ impl DWHCIHost {
     fn ptr(&self) -> *const HostRegisterBlock {
         self.base_addr as *const _
     }
+
+    /// The write-1-to-clear bits of HOST_PORT.
+    const HOST_PORT_W1C_MASK: u32 = HOST_PORT::CONNECT_CHANGED::SET.value
+        | HOST_PORT::ENABLE::SET.value
+        | HOST_PORT::ENABLE_CHANGED::SET.value
+        | HOST_PORT::OVERCURRENT_CHANGED::SET.value;
+
+    /// Read-modify-write of HOST_PORT that writes all write-1-to-clear bits as zero, so that
+    /// pending changes are neither acknowledged nor the port disabled as a side effect.
+    fn modify_port(&self, field: FieldValue<u32, HOST_PORT::Register>) {
+        common::modify(&self.HOST_PORT, |val| {
+            field.modify(val & !Self::HOST_PORT_W1C_MASK)
+        });
+    }
+
+    /// Acknowledge HOST_PORT change bits.
+    ///
+    /// The other bits are written back as read, e.g. so that the port keeps its power, except for
+    /// the remaining write-1-to-clear bits, which are written as zero.
+    fn clear_port_changes(&self, changes: FieldValue<u32, HOST_PORT::Register>) {
+        common::modify(&self.HOST_PORT, |val| {
+            (val & !Self::HOST_PORT_W1C_MASK) | (changes.value & Self::HOST_PORT_W1C_MASK)
+        });
+    }
 }
 
 //------------------------------------------------------------------------------
@@ -488,6 +624,11 @@ impl driver::interface::DeviceDriver for DWHCI {
         "BCM DWHCI"
     }
 
+    /// Core, host and power registers are laid out back to back.
+    fn mmio_region(&self) -> Option<Range<usize>> {
+        Some(self.base_addr..self.host.power_regs.mmio_range().end)
+    }
+
     fn init(&self) -> Result<(), ()> {
         unsafe {
             dmb(SY);
@@ -502,16 +643,24 @@ impl driver::interface::DeviceDriver for DWHCI {
             _ => (),
         }
 
-        match self.init_core() {
-            Err(msg) => panic!("failed to init core: {}", msg),
-            _ => (),
-        }
+        // Bring-up on real HW occasionally fails. Every attempt starts with a core reset.
+        let result = retry(INIT_ATTEMPTS, |attempt| {
+            if attempt > 0 {
+                fault::record_fault("USB", "Core init failed, retrying after reset");
+            }
 
-        // self.enable_global_interrupts();
+            self.init_core()?;
 
-        match self.init_host() {
-            Err(msg) => panic!("failed to init host port: {}", msg),
-            _ => (),
+            // self.enable_global_interrupts();
+
+            self.init_host()
+        });
+
+        if let Err(msg) = result {
+            panic!(
+                "failed to init USB core after {} attempts: {}",
+                INIT_ATTEMPTS, msg
+            );
         }
 
         if !self.enable_root_port() {
@@ -544,3 +693,116 @@ impl driver::interface::DeviceDriver for DWHCI {
         Ok(())
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use test_macros::kernel_test;
+
+    /// Collects formatted output in a fixed buffer.
+    struct Buffer {
+        data: [u8; 512],
+        len: usize,
+    }
+
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.data.len() {
+                return Err(fmt::Error);
+            }
+
+            self.data[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+
+            Ok(())
+        }
+    }
+
+    /// A failure in the first attempt must be retried, and the retry's result returned.
+    #[kernel_test]
+    fn init_retries_after_simulated_first_attempt_failure() {
+        let mut calls = 0;
+        let result = retry(INIT_ATTEMPTS, |attempt| {
+            calls += 1;
+
+            if attempt == 0 {
+                Err("simulated failure")
+            } else {
+                Ok(attempt)
+            }
+        });
+
+        assert_eq!(result, Ok(1));
+        assert_eq!(calls, 2);
+
+        // Persistent failures give up after the configured number of attempts.
+        let mut calls = 0;
+        let result: Result<(), _> = retry(INIT_ATTEMPTS, |_| {
+            calls += 1;
+            Err("simulated failure")
+        });
+
+        assert_eq!(result, Err("simulated failure"));
+        assert_eq!(calls, INIT_ATTEMPTS);
+    }
+
+    /// GHWCFG2 reports the number of host channels minus one.
+    #[kernel_test]
+    fn core_info_decodes_num_host_channels() {
+        assert_eq!(CoreInfo::from_hw_cfg2(7 << 14).num_host_channels, 8);
+        assert_eq!(CoreInfo::from_hw_cfg2(15 << 14).num_host_channels, 16);
+    }
+
+    /// The dump must have one row per channel that shows the channel's register values.
+    #[kernel_test]
+    fn channel_table_has_one_row_per_channel() {
+        // Three channels of eight words each: HCCHAR, HCSPLT, HCINT, HCINTMSK, HCTSIZ, HCDMA.
+        let mut regs = [0u32; 3 * 8];
+        for (ch, words) in regs.chunks_mut(8).enumerate() {
+            let ch = ch as u32;
+
+            words[0] = 0x8000_0000 | ch;
+            words[2] = 0x0000_0020 + ch;
+            words[3] = 0x0000_07FF;
+            words[4] = 0x0008_0040;
+            words[5] = 0x0010_0000 + ch * 0x40;
+        }
+        let channels: &[ChannelRegisterBlock] =
+            unsafe { core::slice::from_raw_parts(regs.as_ptr() as *const _, 3) };
+
+        let mut buf = Buffer {
+            data: [0; 512],
+            len: 0,
+        };
+        write!(buf, "{}", ChannelTable(channels)).unwrap();
+        let table = core::str::from_utf8(&buf.data[..buf.len]).unwrap();
+
+        // Header plus one row per channel.
+        assert_eq!(table.lines().count(), 1 + 3);
+        assert_eq!(
+            table.lines().nth(2),
+            Some(" 1  80000001  00000021  000007FF  00080040  00100040")
+        );
+    }
+
+    /// After a soft reset, the reset bit must have self-cleared and the AHB master be idle.
+    ///
+    /// Only checked if the emulator or board provides the controller.
+    #[kernel_test]
+    fn reset_core_clears_the_core() {
+        let dwhci = &bsp::DWHCI;
+        if dwhci.core_vendor_id() != DWHCI::VENDOR_ID {
+            return;
+        }
+
+        assert_eq!(dwhci.reset_core(), Ok(()));
+        assert!(!dwhci.CORE_RESET.is_set(CORE_RESET::SOFT_RESET));
+        assert!(dwhci.CORE_RESET.is_set(CORE_RESET::AHB_IDLE));
+    }
+}
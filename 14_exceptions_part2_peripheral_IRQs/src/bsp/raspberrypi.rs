@@ -4,10 +4,15 @@
 
 //! Top-level BSP file for the Raspberry Pi 3 and 4.
 
+pub mod cmdline;
 pub mod console;
 pub mod cpu;
+pub mod dma;
 pub mod driver;
 pub mod exception;
+pub mod framebuffer;
+pub mod gpio_expander;
+pub mod gpu_memory;
 pub mod memory;
 
 //--------------------------------------------------------------------------------------------------
@@ -15,31 +20,44 @@ pub mod memory;
 //--------------------------------------------------------------------------------------------------
 use super::device_driver;
 
-static GPIO: device_driver::GPIO =
-    unsafe { device_driver::GPIO::new(memory::map::mmio::GPIO_BASE) };
+pub static AUX: device_driver::Aux = unsafe { device_driver::Aux::new(memory::aux_base()) };
+
+static GPIO: device_driver::GPIO = unsafe { device_driver::GPIO::new(memory::gpio_base()) };
 
 static PL011_UART: device_driver::PL011Uart = unsafe {
     device_driver::PL011Uart::new(
-        memory::map::mmio::PL011_UART_BASE,
+        memory::uart0_base(),
         exception::asynchronous::irq_map::PL011_UART,
     )
 };
 
 pub static DWHCI: device_driver::DWHCI = unsafe {
-    device_driver::DWHCI::new(
-        memory::map::mmio::USB_BASE,
-        exception::asynchronous::irq_map::DWHCI,
+    device_driver::DWHCI::new(memory::usb_base(), exception::asynchronous::irq_map::DWHCI)
+};
+
+pub static SYSTEM_TIMER: device_driver::SystemTimer = unsafe {
+    device_driver::SystemTimer::new(
+        memory::system_timer_base(),
+        [
+            exception::asynchronous::irq_map::SYSTEM_TIMER_1,
+            exception::asynchronous::irq_map::SYSTEM_TIMER_3,
+        ],
     )
 };
 
+pub static DMA: device_driver::DMA = unsafe { device_driver::DMA::new(memory::dma_base()) };
+
 pub static MAILBOX: device_driver::Mailbox =
-    unsafe { device_driver::Mailbox::new(memory::map::mmio::MAILBOX_BASE) };
+    unsafe { device_driver::Mailbox::new(memory::mailbox_base()) };
+
+static PM: device_driver::PowerManagement =
+    unsafe { device_driver::PowerManagement::new(memory::pm_base()) };
 
 #[cfg(feature = "bsp_rpi3")]
 static INTERRUPT_CONTROLLER: device_driver::InterruptController = unsafe {
     device_driver::InterruptController::new(
         memory::map::mmio::LOCAL_INTERRUPT_CONTROLLER_BASE,
-        memory::map::mmio::PERIPHERAL_INTERRUPT_CONTROLLER_BASE,
+        memory::peripheral_ic_base(),
     )
 };
 
@@ -48,10 +66,48 @@ static INTERRUPT_CONTROLLER: device_driver::GICv2 = unsafe {
     device_driver::GICv2::new(memory::map::mmio::GICD_BASE, memory::map::mmio::GICC_BASE)
 };
 
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The registers that the firmware hands over to the kernel.
+///
+/// The firmware's ARM stub follows the arm64 Linux boot protocol:
+///
+/// - `x0`: The physical address of the device tree blob, or `0` if none was loaded, e.g. with
+///   `device_tree=` in `config.txt` or when QEMU runs without `-dtb`.
+/// - `x1`-`x3`: Reserved, always `0`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BootArgs {
+    pub x0: u64,
+    pub x1: u64,
+    pub x2: u64,
+    pub x3: u64,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+pub use memory::with_reserved_regions;
+
+impl BootArgs {
+    /// The address of the device tree blob, if the firmware passed one.
+    pub fn dtb_addr(&self) -> Option<usize> {
+        match self.x0 {
+            0 | u64::MAX => None,
+            addr => Some(addr as usize),
+        }
+    }
+}
+
+/// The registers that the firmware handed over to the kernel, as saved at kernel entry.
+pub fn boot_args() -> BootArgs {
+    let [x0, x1, x2, x3] = crate::cpu::boot_regs();
+
+    BootArgs { x0, x1, x2, x3 }
+}
+
 /// Board identification.
 pub fn board_name() -> &'static str {
     #[cfg(feature = "bsp_rpi3")]
@@ -64,3 +120,133 @@ pub fn board_name() -> &'static str {
         "Raspberry Pi 4"
     }
 }
+
+/// The arm64 Linux image header at the start of the kernel image.
+///
+/// `None` if the image was built without one.
+pub fn image_header() -> Option<crate::cpu::ImageHeader> {
+    unsafe { crate::cpu::ImageHeader::from_addr(memory::kernel_image_range().start) }
+}
+
+/// Reset the board, through PSCI if there is one, else through the watchdog.
+pub fn reset() -> ! {
+    let _ = crate::cpu::psci::system_reset();
+
+    PM.reset()
+}
+
+/// Power off the board, through PSCI if there is one.
+///
+/// Else, the watchdog resets the board into a halt that the firmware keeps it in, until power is
+/// cycled.
+pub fn power_off() -> ! {
+    let _ = crate::cpu::psci::system_off();
+
+    PM.power_off()
+}
+
+/// The MAC address from a `macaddr=` parameter on the kernel command line, if any.
+pub fn mac_override() -> Option<[u8; 6]> {
+    cmdline::with_command_line(cmdline::mac_override).flatten()
+}
+
+/// The MAC address for the board's network interface.
+///
+/// A command line override takes precedence over the address that the firmware reports.
+pub fn mac_address() -> Option<[u8; 6]> {
+    use device_driver::{Mailbox, Message, PropertyTag, PropertyTagMacAddress, PropertyTags};
+
+    if let Some(mac) = mac_override() {
+        return Some(mac);
+    }
+
+    let mac_tag = &mut PropertyTagMacAddress::new();
+    let tag = PropertyTag::new(PropertyTags::GET_MAC_ADDRESS, mac_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|reply| reply.mac)
+        .ok()
+}
+
+/// Read `rows.len()` rows of the customer OTP region, starting at row `start` of the region.
+///
+/// OTP is read-only here, there is deliberately no way to program it. The firmware only exposes
+/// the customer region (OTP rows 36 to 43) through this tag. The factory rows, like the serial
+/// number in rows 28 and 29, are not reachable. The firmware reports their contents through
+/// dedicated tags, like the board serial and revision tags, instead.
+pub fn read_customer_otp(start: usize, rows: &mut [u32]) -> Result<(), ()> {
+    read_customer_otp_via(start, rows, |msg| {
+        MAILBOX
+            .send(device_driver::Mailbox::BCM_MAILBOX_PROP_CHANNEL, msg)
+            .map(|_| ())
+    })
+}
+
+/// Like `read_customer_otp()`, but `send` exchanges the message, e.g. with a mock firmware.
+pub fn read_customer_otp_via(
+    start: usize,
+    rows: &mut [u32],
+    send: impl FnOnce(
+        &mut device_driver::Message<device_driver::PropertyTagGetCustomerOtp>,
+    ) -> Result<(), ()>,
+) -> Result<(), ()> {
+    use device_driver::{Message, PropertyTag, PropertyTagGetCustomerOtp, PropertyTags};
+
+    if start + rows.len() > PropertyTagGetCustomerOtp::NUM_ROWS {
+        return Err(());
+    }
+
+    let otp_tag = &mut PropertyTagGetCustomerOtp::new(start as u32, rows.len() as u32);
+    let tag = PropertyTag::new(PropertyTags::GET_CUSTOMER_OTP, otp_tag);
+    let mut msg = Message::new(&tag);
+
+    send(&mut msg)?;
+
+    // The start and count, followed by the rows. Unknown tags are left untouched, without the
+    // response bit set, and have an empty response.
+    let reply = msg.response_slice();
+    if reply.len() < 2 + rows.len() {
+        return Err(());
+    }
+    if reply[0] as usize != start || reply[1] as usize != rows.len() {
+        return Err(());
+    }
+    rows.copy_from_slice(&reply[2..2 + rows.len()]);
+
+    Ok(())
+}
+
+/// The SoC's temperature, as reported by the firmware.
+pub fn temperature() -> Result<crate::thermal::Temperature, ()> {
+    use device_driver::{Mailbox, Message, PropertyTag, PropertyTagTemperature, PropertyTags};
+
+    let temperature_tag = &mut PropertyTagTemperature {
+        temperature_id: PropertyTagTemperature::TEMPERATURE_ID,
+        value: 0,
+    };
+    let tag = PropertyTag::new(PropertyTags::GET_TEMPERATURE, temperature_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|reply| crate::thermal::Temperature::from_millicelsius(reply.value))
+}
+
+/// Notify the firmware that the xHCI controller was reset, so that it reloads the VL805 firmware.
+///
+/// Must be called after PCIe init, before initializing the xHCI controller.
+#[cfg(feature = "bsp_rpi4")]
+pub fn notify_xhci_reset() -> Result<(), ()> {
+    use device_driver::{Mailbox, Message, PropertyTag, PropertyTagNotifyXhciReset, PropertyTags};
+
+    let notify_tag =
+        &mut PropertyTagNotifyXhciReset::new(PropertyTagNotifyXhciReset::VL805_PCI_DEV_ADDR);
+    let tag = PropertyTag::new(PropertyTags::NOTIFY_XHCI_RESET, notify_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|_| ())
+}
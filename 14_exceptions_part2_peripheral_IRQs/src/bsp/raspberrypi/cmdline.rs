@@ -0,0 +1,280 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! BSP kernel command line.
+//!
+//! The firmware assembles the command line from `cmdline.txt` and its own additions, and hands it
+//! to the kernel through the get-command-line mailbox tag. Parameters are whitespace separated
+//! `name=value` pairs or bare flags, e.g. `quiet`. Like in Linux, a name can carry a module prefix,
+//! e.g. `smsc95xx.macaddr`.
+//!
+//! [`init()`] keeps a copy of the command line, so that any subsystem can read its configuration
+//! with [`get()`], [`get_bool()`] and [`get_u64()`] later on, without a mailbox call or an
+//! allocation. The copy is only lent out to closures, as `init()` may replace it during kernel
+//! init.
+
+use super::{
+    device_driver::{Mailbox, Message, PropertyTag, PropertyTagCommandLine, PropertyTags},
+    MAILBOX,
+};
+use crate::{synchronization, synchronization::InitStateLock};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct CommandLineCopy {
+    buf: [u8; PropertyTagCommandLine::MAX_LEN],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Writable only during kernel init. RO afterwards.
+static COMMAND_LINE: InitStateLock<CommandLineCopy> = InitStateLock::new(CommandLineCopy::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl CommandLineCopy {
+    const fn new() -> Self {
+        Self {
+            buf: [0; PropertyTagCommandLine::MAX_LEN],
+            len: 0,
+        }
+    }
+
+    fn set(&mut self, cmdline: &str) {
+        // The firmware's buffer is no larger than the copy.
+        let len = core::cmp::min(cmdline.len(), self.buf.len());
+
+        self.buf[..len].copy_from_slice(&cmdline.as_bytes()[..len]);
+        self.len = len;
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.buf[..self.len]).unwrap_or("")
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::ReadWriteEx;
+
+/// Fetch the command line from the firmware and pass it to `f`.
+///
+/// Returns `None` if the firmware did not answer or the command line is not valid UTF-8.
+pub fn with_command_line<R>(f: impl FnOnce(&str) -> R) -> Option<R> {
+    let cmdline_tag = &mut PropertyTagCommandLine::new();
+    let tag = PropertyTag::new(PropertyTags::GET_COMMAND_LINE, cmdline_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .ok()?;
+
+    let bytes = msg.response_bytes();
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+
+    core::str::from_utf8(&bytes[..len]).ok().map(f)
+}
+
+/// Fetch the command line from the firmware and keep it for `get()` and its siblings.
+///
+/// Must be called during kernel init, once the mailbox driver is loaded.
+pub fn init() -> Result<(), &'static str> {
+    let mut r = &COMMAND_LINE;
+
+    with_command_line(|cmdline| r.write(|copy| copy.set(cmdline))).ok_or("Command line unavailable")
+}
+
+/// Call `f` with the command line that `init()` kept. Empty before.
+pub fn with_saved_command_line<R>(f: impl FnOnce(&str) -> R) -> R {
+    let mut r = &COMMAND_LINE;
+    r.read(|copy| f(copy.as_str()))
+}
+
+/// Call `f` with the value of parameter `key` on the command line, see `param()`.
+pub fn get<R>(key: &str, f: impl FnOnce(&str) -> R) -> Option<R> {
+    with_saved_command_line(|cmdline| param(cmdline, key).map(f))
+}
+
+/// The last value of parameter `key` on the command line that `parse` accepts, see
+/// `last_accepted()`.
+pub fn get_last_accepted<R>(key: &str, parse: impl FnMut(&str) -> Option<R>) -> Option<R> {
+    with_saved_command_line(|cmdline| last_accepted(cmdline, key, parse))
+}
+
+/// The value of parameter `key` on the command line as a boolean, see `parse_bool()`.
+pub fn get_bool(key: &str) -> Option<bool> {
+    get(key, parse_bool).flatten()
+}
+
+/// The value of parameter `key` on the command line as an integer, see `parse_u64()`.
+pub fn get_u64(key: &str) -> Option<u64> {
+    get(key, parse_u64).flatten()
+}
+
+/// Return the value of parameter `name` in `cmdline`, or an empty one for a bare flag.
+///
+/// A parameter matches with or without a module prefix. If it is given more than once, the last
+/// one wins.
+pub fn param<'a>(cmdline: &'a str, name: &str) -> Option<&'a str> {
+    params(cmdline, name).last()
+}
+
+/// The last value of parameter `name` in `cmdline` that `parse` accepts.
+///
+/// For parameters that may be given more than once with values meant for different consumers,
+/// e.g. the firmware's `console=serial0,115200 console=tty1`.
+pub fn last_accepted<R>(
+    cmdline: &str,
+    name: &str,
+    parse: impl FnMut(&str) -> Option<R>,
+) -> Option<R> {
+    params(cmdline, name).filter_map(parse).last()
+}
+
+/// All values of parameter `name` in `cmdline`, in order. See `param()`.
+pub fn params<'a: 'b, 'b>(cmdline: &'a str, name: &'b str) -> impl Iterator<Item = &'a str> + 'b {
+    cmdline.split_whitespace().filter_map(move |token| {
+        let mut split = token.splitn(2, '=');
+        let key = split.next()?;
+        let value = split.next().unwrap_or("");
+
+        let unprefixed = match key.rfind('.') {
+            Some(dot) => &key[dot + 1..],
+            None => key,
+        };
+
+        if unprefixed == name {
+            Some(value)
+        } else {
+            None
+        }
+    })
+}
+
+/// Parse a boolean value. A bare flag, i.e. an empty value, is `true`.
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match s {
+        "" | "1" | "y" | "yes" | "on" | "true" => Some(true),
+        "0" | "n" | "no" | "off" | "false" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parse an integer value, decimal or hex with a `0x` prefix.
+pub fn parse_u64(s: &str) -> Option<u64> {
+    if s.starts_with("0x") || s.starts_with("0X") {
+        return u64::from_str_radix(&s[2..], 16).ok();
+    }
+
+    s.parse().ok()
+}
+
+/// Parse a MAC address in the colon separated hex format, e.g. `b8:27:eb:12:34:56`.
+pub fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let mut mac = [0; 6];
+    let mut octets = s.split(':');
+
+    for byte in mac.iter_mut() {
+        let octet = octets.next()?;
+        if octet.len() != 2 || !octet.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        *byte = u8::from_str_radix(octet, 16).ok()?;
+    }
+
+    if octets.next().is_some() {
+        return None;
+    }
+
+    Some(mac)
+}
+
+/// Return the MAC address of a `macaddr=` parameter in `cmdline`.
+///
+/// Malformed values are rejected.
+pub fn mac_override(cmdline: &str) -> Option<[u8; 6]> {
+    param(cmdline, "macaddr").and_then(parse_mac)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// A well-formed `macaddr=` parameter is found, also with a module prefix.
+    #[kernel_test]
+    fn valid_mac_is_parsed_from_command_line() {
+        let cmdline = "console=ttyS0,115200 smsc95xx.macaddr=b8:27:EB:01:02:0a quiet";
+
+        assert_eq!(
+            mac_override(cmdline),
+            Some([0xb8, 0x27, 0xeb, 0x01, 0x02, 0x0a])
+        );
+        assert_eq!(
+            mac_override("macaddr=00:11:22:33:44:55"),
+            Some([0, 0x11, 0x22, 0x33, 0x44, 0x55])
+        );
+    }
+
+    /// Malformed values and missing parameters yield no override.
+    #[kernel_test]
+    fn invalid_mac_is_rejected() {
+        assert_eq!(mac_override("smsc95xx.macaddr=b8:27:eb:01:02"), None);
+        assert_eq!(mac_override("smsc95xx.macaddr=b8:27:eb:01:02:03:04"), None);
+        assert_eq!(mac_override("smsc95xx.macaddr=b8:27:eb:01:2:003"), None);
+        assert_eq!(mac_override("smsc95xx.macaddr=b8:27:eb:01:02:zz"), None);
+        assert_eq!(mac_override("smsc95xx.macaddr=+8:27:eb:01:02:03"), None);
+        assert_eq!(mac_override("console=ttyS0,115200 quiet"), None);
+    }
+
+    /// Strings, booleans and integers are found by key, and missing keys yield nothing.
+    #[kernel_test]
+    fn options_are_parsed_from_command_line() {
+        let cmdline = "loglevel=debug console=uart0 quiet smsc95xx.turbo=off 8250.nr=0x20 mem=512";
+
+        assert_eq!(param(cmdline, "loglevel"), Some("debug"));
+        assert_eq!(param(cmdline, "console"), Some("uart0"));
+        assert_eq!(param(cmdline, "quiet"), Some(""));
+        assert_eq!(param(cmdline, "splash"), None);
+
+        assert_eq!(param(cmdline, "quiet").and_then(parse_bool), Some(true));
+        assert_eq!(param(cmdline, "turbo").and_then(parse_bool), Some(false));
+        assert_eq!(param(cmdline, "console").and_then(parse_bool), None);
+        assert_eq!(param(cmdline, "splash").and_then(parse_bool), None);
+
+        assert_eq!(param(cmdline, "mem").and_then(parse_u64), Some(512));
+        assert_eq!(param(cmdline, "nr").and_then(parse_u64), Some(0x20));
+        assert_eq!(param(cmdline, "loglevel").and_then(parse_u64), None);
+        assert_eq!(param(cmdline, "splash").and_then(parse_u64), None);
+    }
+
+    /// Of a parameter that is given more than once, the last accepted value must be found.
+    #[kernel_test]
+    fn last_accepted_value_is_found() {
+        let cmdline = "console=ttyS0 console=serial0,115200 console=tty1 quiet";
+        let known = |value: &str| match value.split(',').next() {
+            Some("ttyS0") => Some(0),
+            Some("serial0") => Some(1),
+            _ => None,
+        };
+
+        assert_eq!(param(cmdline, "console"), Some("tty1"));
+        assert_eq!(last_accepted(cmdline, "console", known), Some(1));
+        assert_eq!(last_accepted("console=tty1", "console", known), None);
+        assert_eq!(params(cmdline, "console").count(), 3);
+    }
+}
@@ -3,10 +3,47 @@
 // Copyright (c) 2018-2020 Andre Richter <andre.o.richter@gmail.com>
 
 //! BSP console facilities.
+//!
+//! The PL011 UART is the only console the kernel drives. The mini UART has no console driver, and
+//! the screen is used by the panic handler only. The `console=` command line parameter can
+//! therefore pick the UART, by any of its names, or turn console output off, see
+//! [`parse_backend()`]. Panic output always goes to the UART.
 
 use super::memory;
-use crate::{bsp::device_driver, console};
-use core::fmt;
+use crate::{
+    bsp::{
+        device_driver,
+        device_driver::{Mailbox, Message, PropertyTag, PropertyTagClockRate, PropertyTags},
+    },
+    console, warn,
+};
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Where `print!` and its siblings write to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Backend {
+    /// The PL011 UART.
+    Uart0,
+
+    /// Nowhere. The kernel log still records everything.
+    Null,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static OUTPUT_OFF: AtomicBool = AtomicBool::new(false);
+
+/// Set once the panic path has reset the UART.
+static PANIC_UART_REINITIALIZED: AtomicBool = AtomicBool::new(false);
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
@@ -15,20 +52,96 @@ use core::fmt;
 /// In case of a panic, the panic handler uses this function to take a last shot at printing
 /// something before the system is halted.
 ///
+/// On the first call, the UART is forcefully reset to its default configuration, so that the panic
+/// message is readable even if a fault left the UART misconfigured or with a stuck FIFO. Later
+/// calls, e.g. from the panic shell, reuse the UART as it is, so that they do not cut off what was
+/// printed before.
+///
 /// # Safety
 ///
 /// - Use only for printing during a panic.
 pub unsafe fn panic_console_out() -> impl fmt::Write {
-    let mut uart = device_driver::PanicUart::new(memory::map::mmio::PL011_UART_BASE);
-    uart.init();
+    let mut uart = device_driver::PanicUart::new(memory::uart0_base());
+    if !PANIC_UART_REINITIALIZED.swap(true, Ordering::Relaxed) {
+        uart.reinit();
+    }
     uart
 }
 
+/// In case of a panic, read a character from the UART, bypassing the console's lock.
+///
+/// Blocks until a character arrives. Does not reinitialize the UART, so `panic_console_out()` must
+/// have been called before.
+///
+/// # Safety
+///
+/// - Use only for reading during a panic.
+pub unsafe fn panic_read_char() -> char {
+    device_driver::PanicUart::new(memory::uart0_base()).read_char_blocking()
+}
+
 /// Return a reference to the console.
 pub fn console() -> &'static impl console::interface::All {
     &super::PL011_UART
 }
 
+/// Make sure that the console's baud rate divisors match the clock that the firmware runs the UART
+/// at.
+///
+/// A firmware config change, e.g. of `init_uart_clock`, would otherwise silently garble the output.
+pub fn verify_uart_clock() {
+    let clock_tag = &mut PropertyTagClockRate::new(PropertyTagClockRate::CLOCK_ID_UART);
+    let tag = PropertyTag::new(PropertyTags::GET_CLOCK_RATE, clock_tag);
+    let mut msg = Message::new(&tag);
+
+    let clock_hz = match super::MAILBOX.send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg) {
+        Ok(reply) => reply.rate,
+        Err(()) => {
+            warn!("UART clock: Query failed");
+            return;
+        }
+    };
+
+    match super::PL011_UART.update_clock_rate(clock_hz) {
+        Ok(None) => (),
+        Ok(Some(assumed_hz)) => warn!(
+            "UART clock: {} Hz instead of the assumed {} Hz. Divisors recomputed",
+            clock_hz, assumed_hz
+        ),
+        Err(msg) => warn!("UART clock: {} Hz: {}", clock_hz, msg),
+    }
+}
+
+/// Return the console's configured baud rate.
+pub fn baud_rate() -> u32 {
+    super::PL011_UART.baud_rate()
+}
+
+/// Parse the value of a `console=` parameter, e.g. `ttyAMA0,115200`. Options after a comma are
+/// ignored.
+pub fn parse_backend(name: &str) -> Option<Backend> {
+    match name.split(',').next() {
+        // The kernel has no driver for the mini UART, `ttyS0`, so it is served by the PL011, too.
+        Some("uart0") | Some("serial0") | Some("ttyAMA0") | Some("ttyS0") => Some(Backend::Uart0),
+        Some("null") | Some("none") => Some(Backend::Null),
+        _ => None,
+    }
+}
+
+/// Write console output to `backend` from now on. `Backend::Uart0` is the default.
+pub fn select_backend(backend: Backend) {
+    OUTPUT_OFF.store(backend == Backend::Null, Ordering::Relaxed);
+}
+
+/// Where console output goes.
+pub fn backend() -> Backend {
+    if OUTPUT_OFF.load(Ordering::Relaxed) {
+        Backend::Null
+    } else {
+        Backend::Uart0
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
@@ -38,3 +151,21 @@ pub fn console() -> &'static impl console::interface::All {
 ///
 /// For the RPi, nothing needs to be done.
 pub fn qemu_bring_up_console() {}
+
+/// Scramble the console's line configuration, bypassing the console's lock (for testing only).
+///
+/// # Safety
+///
+/// - The console is unusable afterwards until it is reinitialized.
+pub unsafe fn qemu_corrupt_console_config() {
+    device_driver::PanicUart::new(memory::uart0_base()).corrupt_config();
+}
+
+/// Return whether the console is in its default configuration (for testing only).
+///
+/// # Safety
+///
+/// - Bypasses the console's lock.
+pub unsafe fn qemu_console_config_is_default() -> bool {
+    device_driver::PanicUart::new(memory::uart0_base()).config_is_default()
+}
@@ -10,3 +10,17 @@
 
 /// Used by `arch` code to find the early boot core.
 pub const BOOT_CORE_ID: usize = 0;
+
+/// The number of processor cores, if `cpu::num_cores()` can not tell. Also the most it reports.
+pub const NUM_CORES: usize = 4;
+
+/// The spin table of the firmware's armstub, where the secondary cores wait for an entry address.
+pub const SPIN_TABLE_BASE: usize = 0xD8;
+
+/// The ARMv8 Generic Timer's counter frequency, used if CNTFRQ_EL0 is misprogrammed.
+#[cfg(feature = "bsp_rpi3")]
+pub const GENERIC_TIMER_FREQUENCY_HZ: u64 = 19_200_000;
+
+/// The ARMv8 Generic Timer's counter frequency, used if CNTFRQ_EL0 is misprogrammed.
+#[cfg(feature = "bsp_rpi4")]
+pub const GENERIC_TIMER_FREQUENCY_HZ: u64 = 54_000_000;
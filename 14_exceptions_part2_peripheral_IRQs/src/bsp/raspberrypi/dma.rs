@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! BSP DMA.
+
+use crate::dma;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Return a reference to the DMA engine.
+///
+/// The engine is only usable after its driver was initialized.
+pub fn dma_engine() -> &'static impl dma::interface::DmaEngine {
+    &super::DMA
+}
@@ -10,22 +10,31 @@ use crate::driver;
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// Number of device drivers.
+pub const NUM_DRIVERS: usize = 7;
+
 /// Device Driver Manager type.
 pub struct BSPDriverManager {
-    device_drivers: [&'static (dyn DeviceDriver + Sync); 4],
+    device_drivers: &'static [&'static (dyn DeviceDriver + Sync); NUM_DRIVERS],
 }
 
 //--------------------------------------------------------------------------------------------------
 // Global instances
 //--------------------------------------------------------------------------------------------------
 
+/// The driver table. Visible to the crate so that `debug::KernelInfo` can point to it.
+pub(crate) static DEVICE_DRIVERS: [&'static (dyn DeviceDriver + Sync); NUM_DRIVERS] = [
+    &super::GPIO,
+    &super::AUX,
+    &super::PL011_UART,
+    &super::INTERRUPT_CONTROLLER,
+    &super::DWHCI,
+    &super::DMA,
+    &super::SYSTEM_TIMER,
+];
+
 static BSP_DRIVER_MANAGER: BSPDriverManager = BSPDriverManager {
-    device_drivers: [
-        &super::GPIO,
-        &super::PL011_UART,
-        &super::INTERRUPT_CONTROLLER,
-        &super::DWHCI,
-    ],
+    device_drivers: &DEVICE_DRIVERS,
 };
 
 //--------------------------------------------------------------------------------------------------
@@ -50,5 +59,7 @@ impl driver::interface::DriverManager for BSPDriverManager {
     fn post_device_driver_init(&self) {
         // Configure PL011Uart's output pins.
         super::GPIO.map_pl011_uart();
+
+        super::console::verify_uart_clock();
     }
 }
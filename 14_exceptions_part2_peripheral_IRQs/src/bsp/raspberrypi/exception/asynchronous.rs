@@ -12,28 +12,142 @@ use crate::{bsp, exception};
 
 #[cfg(feature = "bsp_rpi3")]
 pub(in crate::bsp) mod irq_map {
-    use super::bsp::device_driver::{IRQNumber, PeripheralIRQ};
+    use super::bsp::device_driver::{IRQNumber, LocalIRQ, PeripheralIRQ};
 
+    pub const PHYSICAL_TIMER: IRQNumber = IRQNumber::Local(LocalIRQ::new(1));
+    pub const VIRTUAL_TIMER: IRQNumber = IRQNumber::Local(LocalIRQ::new(3));
     pub const PL011_UART: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(57));
     pub const DWHCI: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(9));
+    pub const SYSTEM_TIMER_1: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(1));
+    pub const SYSTEM_TIMER_3: IRQNumber = IRQNumber::Peripheral(PeripheralIRQ::new(3));
 }
 
 #[cfg(feature = "bsp_rpi4")]
 pub(in crate::bsp) mod irq_map {
     use super::bsp::device_driver::IRQNumber;
 
+    pub const PHYSICAL_TIMER: IRQNumber = IRQNumber::new(30);
+    pub const VIRTUAL_TIMER: IRQNumber = IRQNumber::new(27);
     pub const PL011_UART: IRQNumber = IRQNumber::new(153);
     // TODO check if correct
     pub const DWHCI: IRQNumber = IRQNumber::new(9);
+    // The VideoCore IRQs start at SPI 96.
+    pub const SYSTEM_TIMER_1: IRQNumber = IRQNumber::new(97);
+    pub const SYSTEM_TIMER_3: IRQNumber = IRQNumber::new(99);
 }
 
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+/// Return the IRQ number of the executing core's ARMv8 Generic Timer non-secure physical timer.
+pub fn physical_timer_irq() -> bsp::device_driver::IRQNumber {
+    irq_map::PHYSICAL_TIMER
+}
+
+/// Return the IRQ number of the executing core's ARMv8 Generic Timer virtual timer.
+pub fn virtual_timer_irq() -> bsp::device_driver::IRQNumber {
+    irq_map::VIRTUAL_TIMER
+}
+
+/// Return the IRQ number that IPIs arrive at on the executing core, if IPIs are supported.
+#[cfg(feature = "bsp_rpi3")]
+pub fn ipi_irq() -> Option<bsp::device_driver::IRQNumber> {
+    Some(super::super::INTERRUPT_CONTROLLER.ipi_irq())
+}
+
+/// Return the IRQ number that IPIs arrive at on the executing core, if IPIs are supported.
+///
+/// IPIs are RPi 3 only, so there is none. See `cpu::smp`.
+#[cfg(feature = "bsp_rpi4")]
+pub fn ipi_irq() -> Option<bsp::device_driver::IRQNumber> {
+    None
+}
+
+/// Set `bits` in the pending IPIs of `core`, and interrupt it.
+#[cfg(feature = "bsp_rpi3")]
+pub fn send_ipi(core: usize, bits: u32) -> Result<(), &'static str> {
+    super::super::INTERRUPT_CONTROLLER.send_ipi(core, bits);
+
+    Ok(())
+}
+
+/// Set `bits` in the pending IPIs of `core`, and interrupt it.
+///
+/// IPIs are RPi 3 only, so this always fails. See `cpu::smp`.
+#[cfg(feature = "bsp_rpi4")]
+pub fn send_ipi(_core: usize, _bits: u32) -> Result<(), &'static str> {
+    Err("IPIs are only supported on the RPi 3")
+}
+
+/// Return and clear the executing core's pending IPIs.
+#[cfg(feature = "bsp_rpi3")]
+pub fn take_ipis() -> u32 {
+    super::super::INTERRUPT_CONTROLLER.take_ipis()
+}
+
+/// Return and clear the executing core's pending IPIs.
+///
+/// IPIs are RPi 3 only, so there are none. See `cpu::smp`.
+#[cfg(feature = "bsp_rpi4")]
+pub fn take_ipis() -> u32 {
+    0
+}
+
 /// Return a reference to the IRQ manager.
 pub fn irq_manager() -> &'static impl exception::asynchronous::interface::IRQManager<
     IRQNumberType = bsp::device_driver::IRQNumber,
+    SavedIrqState = bsp::device_driver::SavedIrqState,
+    PendingIrqState = bsp::device_driver::PendingIrqState,
 > {
     &super::super::INTERRUPT_CONTROLLER
 }
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exception::asynchronous::interface::IRQManager;
+    use test_macros::kernel_test;
+
+    /// `disable_all()` must clear every enable bit, and `restore_all()` bring back exactly the
+    /// masks from before.
+    #[kernel_test]
+    fn disable_all_and_restore_all_roundtrip() {
+        let irqm = irq_manager();
+
+        // IRQs are masked at the CPU, so enabling them at the controller is harmless.
+        irqm.enable(irq_map::PL011_UART);
+        irqm.enable(irq_map::VIRTUAL_TIMER);
+
+        let saved = irqm.disable_all();
+        assert!(saved != bsp::device_driver::SavedIrqState::default());
+
+        // Everything is off now, so a second snapshot must be empty.
+        let cleared = irqm.disable_all();
+        assert!(cleared == bsp::device_driver::SavedIrqState::default());
+
+        irqm.restore_all(saved);
+        assert!(irqm.disable_all() == saved);
+        irqm.restore_all(saved);
+    }
+
+    /// After init, the interrupt groups must be the ones that the detected security state receives.
+    ///
+    /// In the non-secure state, the groups can not be read back, and only the detection is checked.
+    #[cfg(feature = "bsp_rpi4")]
+    #[kernel_test]
+    fn gic_group_config_matches_security_state() {
+        use crate::{cpu::SecurityState, driver::interface::DeviceDriver};
+        use bsp::memory::map::mmio;
+
+        let gic = unsafe { bsp::device_driver::GICv2::new(mmio::GICD_BASE, mmio::GICC_BASE) };
+        assert!(gic.security_state() != SecurityState::Unknown);
+
+        assert!(gic.init().is_ok());
+        assert!(gic.groups_match_security_state());
+    }
+}
@@ -0,0 +1,213 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! BSP framebuffer.
+//!
+//! The framebuffer is allocated and configured by the VideoCore firmware through the framebuffer
+//! mailbox tags. The displayed window can be moved around in a virtual framebuffer that is larger
+//! than the physical one, which is what page flipping is built upon.
+
+use super::{
+    device_driver::{
+        Mailbox, Message, PropertyTag, PropertyTagAllocateBuffer, PropertyTagDepth,
+        PropertyTagGetPhysicalSize, PropertyTagPitch, PropertyTagPixelOrder, PropertyTagSize,
+        PropertyTagVirtualOffset, PropertyTagVsync, PropertyTags,
+    },
+    MAILBOX,
+};
+use crate::gfx::{PixelFormat, PixelOrder};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const BITS_PER_PIXEL: u32 = 32;
+
+/// Alignment that is requested for the framebuffer allocation.
+const ALIGNMENT: u32 = 16;
+
+/// Clearing bits 30 and 31 translates a VideoCore bus address to an ARM physical address.
+const BUS_ADDR_MASK: u32 = 0x3FFF_FFFF;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A framebuffer as allocated by the firmware.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FramebufferInfo {
+    /// The ARM physical address of the virtual framebuffer.
+    pub base_addr: usize,
+
+    /// The size of the virtual framebuffer in bytes.
+    pub size: usize,
+
+    /// The displayed width in pixels.
+    pub width: usize,
+
+    /// The displayed height in pixels.
+    pub height: usize,
+
+    /// The virtual height in pixels, which can be a multiple of `height`.
+    pub virtual_height: usize,
+
+    /// The number of bytes per row.
+    pub pitch: usize,
+
+    /// The layout of a pixel, as reported by the firmware.
+    pub pixel_format: PixelFormat,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn set_size(id: u32, width: u32, height: u32) -> Result<(u32, u32), ()> {
+    let size_tag = &mut PropertyTagSize::new(width, height);
+    let tag = PropertyTag::new(id, size_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|reply| (reply.width, reply.height))
+}
+
+fn set_depth(bits_per_pixel: u32) -> Result<u32, ()> {
+    let depth_tag = &mut PropertyTagDepth::new(bits_per_pixel);
+    let tag = PropertyTag::new(PropertyTags::SET_DEPTH, depth_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|reply| reply.bits_per_pixel)
+}
+
+fn pixel_order() -> Result<PixelOrder, ()> {
+    let order_tag = &mut PropertyTagPixelOrder::new();
+    let tag = PropertyTag::new(PropertyTags::GET_PIXEL_ORDER, order_tag);
+    let mut msg = Message::new(&tag);
+
+    let order = MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)?
+        .order;
+
+    match order {
+        PropertyTagPixelOrder::ORDER_BGR => Ok(PixelOrder::Bgr),
+        PropertyTagPixelOrder::ORDER_RGB => Ok(PixelOrder::Rgb),
+        _ => Err(()),
+    }
+}
+
+fn allocate_buffer() -> Result<(u32, u32), ()> {
+    let alloc_tag = &mut PropertyTagAllocateBuffer::new(ALIGNMENT);
+    let tag = PropertyTag::new(PropertyTags::ALLOCATE_BUFFER, alloc_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|reply| (reply.base_addr, reply.size))
+}
+
+fn pitch() -> Result<u32, ()> {
+    let pitch_tag = &mut PropertyTagPitch::new();
+    let tag = PropertyTag::new(PropertyTags::GET_PITCH, pitch_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|reply| reply.pitch)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Allocate a 32 bits per pixel framebuffer of `width` x `height` pixels, inside a virtual
+/// framebuffer of `width` x `virtual_height` pixels.
+///
+/// The firmware may adjust the sizes, e.g. if it is short on memory. The returned info holds the
+/// sizes that are actually in effect.
+pub fn allocate(width: u32, height: u32, virtual_height: u32) -> Result<FramebufferInfo, ()> {
+    let (width, height) = set_size(PropertyTags::SET_PHYSICAL_SIZE, width, height)?;
+    let (_, virtual_height) = set_size(PropertyTags::SET_VIRTUAL_SIZE, width, virtual_height)?;
+
+    let bits_per_pixel = set_depth(BITS_PER_PIXEL)?;
+    if bits_per_pixel != BITS_PER_PIXEL {
+        return Err(());
+    }
+
+    let (bus_addr, size) = allocate_buffer()?;
+    if bus_addr == 0 {
+        return Err(());
+    }
+
+    Ok(FramebufferInfo {
+        base_addr: (bus_addr & BUS_ADDR_MASK) as usize,
+        size: size as usize,
+        width: width as usize,
+        height: height as usize,
+        virtual_height: virtual_height as usize,
+        pitch: pitch()? as usize,
+        pixel_format: PixelFormat {
+            order: pixel_order()?,
+            bits_per_pixel,
+        },
+    })
+}
+
+/// The size of the attached display in pixels, or `None` if there is none.
+///
+/// Must be called before `allocate()` to get the display's native mode, which is the largest size
+/// that is worth allocating. Firmware that does not know the tag is treated like no display.
+pub fn display_size() -> Result<Option<(u32, u32)>, ()> {
+    let size_tag = &mut PropertyTagGetPhysicalSize::new();
+    let tag = PropertyTag::new(PropertyTags::GET_PHYSICAL_SIZE, size_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX.send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)?;
+
+    Ok(PropertyTagGetPhysicalSize::parse(msg.response_slice()))
+}
+
+/// Move the displayed window to (`x`, `y`) in the virtual framebuffer.
+///
+/// Returns the offset that the firmware applied.
+pub fn set_virtual_offset(x: u32, y: u32) -> Result<(u32, u32), ()> {
+    let offset_tag = &mut PropertyTagVirtualOffset::new(x, y);
+    let tag = PropertyTag::new(PropertyTags::SET_VIRTUAL_OFFSET, offset_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|reply| (reply.x, reply.y))
+}
+
+/// The offset of the displayed window in the virtual framebuffer.
+pub fn virtual_offset() -> Result<(u32, u32), ()> {
+    let offset_tag = &mut PropertyTagVirtualOffset::new(0, 0);
+    let tag = PropertyTag::new(PropertyTags::GET_VIRTUAL_OFFSET, offset_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|reply| (reply.x, reply.y))
+}
+
+/// Block until the next vertical sync.
+///
+/// Returns an error if the firmware does not implement the vsync tag.
+pub fn wait_for_vsync() -> Result<(), ()> {
+    let vsync_tag = &mut PropertyTagVsync::new();
+    let tag = PropertyTag::new(PropertyTags::SET_VSYNC, vsync_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX.send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)?;
+
+    // Unknown tags are left untouched, without the response bit set.
+    if msg.response_slice().is_empty() {
+        return Err(());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! BSP GPIO expander.
+//!
+//! Some pins, like the activity LED on the Raspberry Pi 3, are not wired to the SoC's GPIO block
+//! but to a GPIO expander that is owned by the VideoCore firmware. They can only be reached through
+//! the get-gpio-state and set-gpio-state mailbox tags.
+
+use super::{
+    device_driver::{
+        Mailbox, Message, PropertyTag, PropertyTagGetGpioState, PropertyTagSetGpioState,
+        PropertyTags,
+    },
+    MAILBOX,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Bluetooth enable.
+pub const BT_ON: u32 = 128;
+
+/// WiFi enable.
+pub const WL_ON: u32 = 129;
+
+/// The green activity LED.
+#[cfg(feature = "bsp_rpi3")]
+pub const ACT_LED: u32 = 130;
+
+/// The red power LED. Active low.
+#[cfg(feature = "bsp_rpi4")]
+pub const PWR_LED_OFF: u32 = 130;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Drive an expander GPIO high or low.
+pub fn set(gpio: u32, high: bool) -> Result<(), ()> {
+    let state_tag = &mut PropertyTagSetGpioState::new(gpio, high);
+    let tag = PropertyTag::new(PropertyTags::SET_GPIO_STATE, state_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|_| ())
+}
+
+/// Read the current state of an expander GPIO.
+pub fn get(gpio: u32) -> Result<bool, ()> {
+    let state_tag = &mut PropertyTagGetGpioState::new(gpio);
+    let tag = PropertyTag::new(PropertyTags::GET_GPIO_STATE, state_tag);
+    let mut msg = Message::new(&tag);
+
+    MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map(|reply| reply.state != 0)
+}
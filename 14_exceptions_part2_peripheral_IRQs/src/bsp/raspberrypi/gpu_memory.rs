@@ -0,0 +1,192 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! BSP GPU memory.
+//!
+//! GPU buffers beyond the framebuffer are allocated from the VideoCore's relocatable heap through
+//! the memory mailbox tags. An allocation is identified by a handle. The firmware may move the
+//! memory around while it is unlocked, so it must be locked to get a bus address that stays valid,
+//! and unlocked again once the GPU is done with it.
+//!
+//! [`GpuBuffer`] owns a handle. Dropping it unlocks and releases the memory, so that a handle can
+//! not be leaked by an early return. [`num_allocated()`] counts the handles that have not been
+//! released yet.
+
+use super::{
+    device_driver::{
+        Mailbox, Message, PropertyTag, PropertyTagAllocateMemory, PropertyTagMemoryHandle,
+        PropertyTags,
+    },
+    MAILBOX,
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Why a GPU memory request failed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The firmware does not implement the memory tags.
+    Unsupported,
+
+    /// The mailbox transfer failed, or the firmware refused the request.
+    Refused,
+}
+
+/// An allocation from the GPU's relocatable heap.
+pub struct GpuBuffer {
+    /// `0` once released.
+    handle: u32,
+    size: u32,
+    bus_addr: Option<u32>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static NUM_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Send one of the tags that only carry a handle, and return the firmware's answer.
+fn handle_call(id: u32, handle: u32) -> Result<u32, Error> {
+    let handle_tag = &mut PropertyTagMemoryHandle::new(handle);
+    let tag = PropertyTag::new(id, handle_tag);
+    let mut msg = Message::new(&tag);
+
+    let value = MAILBOX
+        .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+        .map_err(|_| Error::Refused)?
+        .handle;
+
+    // Unknown tags are left untouched, without the response bit set.
+    if msg.response_slice().is_empty() {
+        return Err(Error::Unsupported);
+    }
+
+    Ok(value)
+}
+
+impl GpuBuffer {
+    /// Unlock and release the handle.
+    ///
+    /// The handle is given up even if the firmware refuses the release, in which case it stays
+    /// counted in `num_allocated()`.
+    fn free(&mut self) -> Result<(), Error> {
+        if self.handle == 0 {
+            return Ok(());
+        }
+
+        let unlocked = self.unlock();
+        let handle = core::mem::replace(&mut self.handle, 0);
+
+        if handle_call(PropertyTags::RELEASE_MEMORY, handle)? != 0 {
+            return Err(Error::Refused);
+        }
+        NUM_ALLOCATED.fetch_sub(1, Ordering::Relaxed);
+
+        unlocked
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// The number of GPU buffers that were allocated and not released yet.
+pub fn num_allocated() -> usize {
+    NUM_ALLOCATED.load(Ordering::Relaxed)
+}
+
+impl GpuBuffer {
+    /// Allocate `size` bytes aligned to `alignment`, with `PropertyTagAllocateMemory::FLAG_*`
+    /// `flags`.
+    pub fn allocate(size: u32, alignment: u32, flags: u32) -> Result<Self, Error> {
+        let alloc_tag = &mut PropertyTagAllocateMemory::new(size, alignment, flags);
+        let tag = PropertyTag::new(PropertyTags::ALLOCATE_MEMORY, alloc_tag);
+        let mut msg = Message::new(&tag);
+
+        let handle = MAILBOX
+            .send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut msg)
+            .map_err(|_| Error::Refused)?
+            .size;
+
+        if msg.response_slice().is_empty() {
+            return Err(Error::Unsupported);
+        }
+        if handle == 0 {
+            return Err(Error::Refused);
+        }
+        NUM_ALLOCATED.fetch_add(1, Ordering::Relaxed);
+
+        Ok(Self {
+            handle,
+            size,
+            bus_addr: None,
+        })
+    }
+
+    /// The firmware's handle.
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    /// The requested size in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The bus address, if the buffer is locked.
+    pub fn bus_addr(&self) -> Option<u32> {
+        self.bus_addr
+    }
+
+    /// Pin the buffer and return its bus address. Locking a locked buffer returns the same address.
+    pub fn lock(&mut self) -> Result<u32, Error> {
+        if let Some(addr) = self.bus_addr {
+            return Ok(addr);
+        }
+
+        let addr = handle_call(PropertyTags::LOCK_MEMORY, self.handle)?;
+        if addr == 0 {
+            return Err(Error::Refused);
+        }
+        self.bus_addr = Some(addr);
+
+        Ok(addr)
+    }
+
+    /// Allow the firmware to move the buffer again. The bus address becomes invalid.
+    pub fn unlock(&mut self) -> Result<(), Error> {
+        if self.bus_addr.is_none() {
+            return Ok(());
+        }
+
+        // Given up either way, as a refused unlock leaves nothing to retry with.
+        self.bus_addr = None;
+        if handle_call(PropertyTags::UNLOCK_MEMORY, self.handle)? != 0 {
+            return Err(Error::Refused);
+        }
+
+        Ok(())
+    }
+
+    /// Unlock and release the buffer, and report whether the firmware accepted it.
+    ///
+    /// Dropping the buffer does the same, but ignores errors.
+    pub fn release(mut self) -> Result<(), Error> {
+        self.free()
+    }
+}
+
+impl Drop for GpuBuffer {
+    fn drop(&mut self) {
+        let _ = self.free();
+    }
+}
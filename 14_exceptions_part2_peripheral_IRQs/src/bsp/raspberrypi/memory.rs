@@ -6,6 +6,23 @@
 
 pub mod mmu;
 
+use crate::{
+    fdt::Fdt,
+    memory::{Region, RegionList},
+    synchronization,
+    synchronization::InitStateLock,
+};
+use core::ops::Range;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+struct MmioTable {
+    entries: [MmioRegion; MAX_MMIO_REGIONS],
+    len: usize,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -13,28 +30,48 @@ pub mod mmu;
 /// The early boot core's stack address.
 pub const BOOT_CORE_STACK_START: usize = 0x80_000;
 
+/// The maximum number of coalesced firmware reserved regions.
+pub const MAX_RESERVED_REGIONS: usize = 16;
+
+/// The maximum number of MMIO regions that can be registered with `register_mmio()`.
+pub const MAX_MMIO_REGIONS: usize = 16;
+
+/// A named MMIO region that was registered at runtime, in addition to the static memory layout.
+#[derive(Copy, Clone, PartialEq)]
+pub struct MmioRegion {
+    pub name: &'static str,
+    pub region: Region,
+}
+
 /// The board's memory map.
 #[rustfmt::skip]
 pub(super) mod map {
     pub const END_INCLUSIVE:                            usize =        0xFFFF_FFFF;
 
+    pub const PAYLOAD_START:                            usize =        0x0060_0000;
+    pub const PAYLOAD_END_INCLUSIVE:                    usize =        0x0067_FFFF;
+
+    pub const USER_START:                               usize =        0x0068_0000;
+    pub const USER_END_INCLUSIVE:                       usize =        0x006F_FFFF;
+
+    pub const SYSTEM_TIMER_OFFSET:                      usize =        0x0000_3000;
+    pub const DMA_OFFSET:                               usize =        0x0000_7000;
+    pub const PERIPHERAL_IC_OFFSET:                     usize =        0x0000_B200;
+    pub const MAILBOX_OFFSET:                           usize =        0x0000_B880;
+    pub const PM_OFFSET:                                usize =        0x0010_0000;
     pub const GPIO_OFFSET:                              usize =        0x0020_0000;
     pub const UART_OFFSET:                              usize =        0x0020_1000;
+    pub const AUX_OFFSET:                               usize =        0x0021_5000;
     pub const USB_OFFSET:                               usize =        0x0098_0000;
 
     /// Physical devices.
     #[cfg(feature = "bsp_rpi3")]
     pub mod mmio {
-        use super::*;
-
         pub const DMA_HEAP_START:                       usize =        0x0020_0000;
         pub const DMA_HEAP_END_INCLUSIVE:               usize =        0x005F_FFFF;
+        pub const VC_MEMORY_START:                      usize =        0x3C00_0000;
+        pub const VC_MEMORY_END_INCLUSIVE:              usize =        0x3EFF_FFFF;
         pub const BASE:                                 usize =        0x3F00_0000;
-        pub const PERIPHERAL_INTERRUPT_CONTROLLER_BASE: usize = BASE + 0x0000_B200;
-        pub const MAILBOX_BASE:                         usize = BASE + 0x0000_B880;
-        pub const GPIO_BASE:                            usize = BASE + GPIO_OFFSET;
-        pub const PL011_UART_BASE:                      usize = BASE + UART_OFFSET;
-        pub const USB_BASE:                             usize = BASE + USB_OFFSET;
         pub const LOCAL_INTERRUPT_CONTROLLER_BASE:      usize =        0x4000_0000;
         pub const END_INCLUSIVE:                        usize =        0x4000_FFFF;
     }
@@ -42,13 +79,259 @@ pub(super) mod map {
     /// Physical devices.
     #[cfg(feature = "bsp_rpi4")]
     pub mod mmio {
-        use super::*;
-
+        pub const DMA_HEAP_START:                       usize =        0x0020_0000;
+        pub const DMA_HEAP_END_INCLUSIVE:               usize =        0x005F_FFFF;
+        pub const VC_MEMORY_START:                      usize =        0x3C00_0000;
+        pub const VC_MEMORY_END_INCLUSIVE:              usize =        0x3FFF_FFFF;
         pub const BASE:                                 usize =        0xFE00_0000;
-        pub const GPIO_BASE:                            usize = BASE + GPIO_OFFSET;
-        pub const PL011_UART_BASE:                      usize = BASE + UART_OFFSET;
         pub const GICD_BASE:                            usize =        0xFF84_1000;
         pub const GICC_BASE:                            usize =        0xFF84_2000;
         pub const END_INCLUSIVE:                        usize =        0xFF84_FFFF;
     }
 }
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Memory that the firmware owns. Writable only during kernel init. RO afterwards.
+static RESERVED_REGIONS: InitStateLock<RegionList<MAX_RESERVED_REGIONS>> =
+    InitStateLock::new(RegionList::new());
+
+/// MMIO regions that drivers registered. Writable only during kernel init. RO afterwards.
+static MMIO_REGIONS: InitStateLock<MmioTable> = InitStateLock::new(MmioTable::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl MmioTable {
+    const fn new() -> Self {
+        Self {
+            entries: [MmioRegion {
+                name: "",
+                region: Region::new(0, 0),
+            }; MAX_MMIO_REGIONS],
+            len: 0,
+        }
+    }
+
+    fn as_slice(&self) -> &[MmioRegion] {
+        &self.entries[..self.len]
+    }
+
+    fn push(&mut self, entry: MmioRegion) -> Result<(), &'static str> {
+        let overlaps = self
+            .as_slice()
+            .iter()
+            .any(|e| e.region.start < entry.region.end && entry.region.start < e.region.end);
+        if overlaps {
+            return Err("MMIO region overlaps a registered one");
+        }
+
+        if self.len == MAX_MMIO_REGIONS {
+            return Err("MMIO region table full");
+        }
+
+        self.entries[self.len] = entry;
+        self.len += 1;
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::ReadWriteEx;
+
+/// The physical base address of the SoC peripherals, as seen by the ARM cores.
+///
+/// This is the parent bus address that the `ranges` property of the `/soc` node in the board's
+/// device tree maps the peripherals' bus address `0x7E00_0000` to. It is the only board-dependent
+/// value that drivers' base addresses derive from. The offsets below are the same on all supported
+/// boards.
+pub const fn peripheral_base() -> usize {
+    map::mmio::BASE
+}
+
+/// The PL011 UART's base address.
+pub const fn uart0_base() -> usize {
+    peripheral_base() + map::UART_OFFSET
+}
+
+/// The GPIO controller's base address.
+pub const fn gpio_base() -> usize {
+    peripheral_base() + map::GPIO_OFFSET
+}
+
+/// The auxiliary peripherals' (mini UART, SPI1, SPI2) base address.
+pub const fn aux_base() -> usize {
+    peripheral_base() + map::AUX_OFFSET
+}
+
+/// The VideoCore mailbox's base address.
+pub const fn mailbox_base() -> usize {
+    peripheral_base() + map::MAILBOX_OFFSET
+}
+
+/// The power management block's base address.
+pub const fn pm_base() -> usize {
+    peripheral_base() + map::PM_OFFSET
+}
+
+/// The DWHCI USB host controller's base address.
+pub const fn usb_base() -> usize {
+    peripheral_base() + map::USB_OFFSET
+}
+
+/// The system timer's base address.
+pub const fn system_timer_base() -> usize {
+    peripheral_base() + map::SYSTEM_TIMER_OFFSET
+}
+
+/// The DMA controller's base address.
+pub const fn dma_base() -> usize {
+    peripheral_base() + map::DMA_OFFSET
+}
+
+/// The peripheral interrupt controller's base address. The RPi 4 uses the GIC instead.
+pub const fn peripheral_ic_base() -> usize {
+    peripheral_base() + map::PERIPHERAL_IC_OFFSET
+}
+
+/// The address range of the kernel's code and RO data, as exported by the linker script.
+pub fn ro_range() -> Range<usize> {
+    extern "C" {
+        static __ro_start: usize;
+        static __ro_end: usize;
+    }
+
+    unsafe { (&__ro_start as *const _ as usize)..(&__ro_end as *const _ as usize) }
+}
+
+/// The address range of the whole kernel image, from its header to the end of `.bss`, as exported
+/// by the linker script.
+pub fn kernel_image_range() -> Range<usize> {
+    extern "C" {
+        static __image_header: usize;
+        static __bss_end: usize;
+    }
+
+    unsafe { (&__image_header as *const _ as usize)..(&__bss_end as *const _ as usize) }
+}
+
+/// The address range that payloads are loaded to and executed from.
+pub fn payload_range() -> Range<usize> {
+    map::PAYLOAD_START..(map::PAYLOAD_END_INCLUSIVE + 1)
+}
+
+/// The address range that is accessible from EL0, for unprivileged code and its stack.
+pub fn user_range() -> Range<usize> {
+    map::USER_START..(map::USER_END_INCLUSIVE + 1)
+}
+
+/// The address range that the firmware allocates framebuffers from.
+///
+/// This is the VideoCore's share of the low 1 GiB of DRAM with the default `gpu_mem=64` in
+/// `config.txt`. The firmware reports framebuffer addresses as VideoCore bus addresses, which
+/// translate to ARM physical addresses by clearing bits 30 and 31.
+pub const fn framebuffer_range() -> Range<usize> {
+    map::mmio::VC_MEMORY_START..(map::mmio::VC_MEMORY_END_INCLUSIVE + 1)
+}
+
+/// The address range of the kernel heap.
+pub const fn heap_range() -> Range<usize> {
+    map::mmio::DMA_HEAP_START..(map::mmio::DMA_HEAP_END_INCLUSIVE + 1)
+}
+
+/// Record the firmware reserved regions of `fdt`, including the blob itself.
+///
+/// Must be called during kernel init, before memory is handed to allocators.
+pub fn init_reserved_regions(fdt: &Fdt) -> Result<(), &'static str> {
+    let mut r = &RESERVED_REGIONS;
+    r.write(|regions| {
+        regions.insert(fdt.blob_region())?;
+        fdt.for_each_reserved_region(|region| regions.insert(region))
+    })
+}
+
+/// Call `f` with the regions that the firmware reserved, sorted and coalesced.
+///
+/// Empty until `init_reserved_regions()` was called.
+pub fn with_reserved_regions<R>(f: impl FnOnce(&[Region]) -> R) -> R {
+    let mut r = &RESERVED_REGIONS;
+    r.read(|regions| f(regions.as_slice()))
+}
+
+/// Register the MMIO region `phys..phys + size` under `name`.
+///
+/// Registered regions show up in `mmu::virt_mem_layout().print_layout()` and are part of the
+/// overlap check in `driver::validate_mmio_regions()`. Must be called during kernel init.
+pub fn register_mmio(name: &'static str, phys: usize, size: usize) -> Result<(), &'static str> {
+    if size == 0 {
+        return Err("Empty MMIO region");
+    }
+
+    let end = match phys.checked_add(size) {
+        None => return Err("MMIO region wraps around"),
+        Some(end) => end,
+    };
+
+    let mut r = &MMIO_REGIONS;
+    r.write(|table| {
+        table.push(MmioRegion {
+            name,
+            region: Region::new(phys, end),
+        })
+    })
+}
+
+/// Call `f` with the MMIO regions that were registered with `register_mmio()`, in registration
+/// order.
+pub fn with_mmio_regions<R>(f: impl FnOnce(&[MmioRegion]) -> R) -> R {
+    let mut r = &MMIO_REGIONS;
+    r.read(|table| f(table.as_slice()))
+}
+
+/// The largest part of the heap range that does not overlap a reserved region.
+pub fn allocatable_heap_range() -> Range<usize> {
+    with_reserved_regions(|regions| {
+        crate::memory::unreserved(heap_range(), regions)
+            .max_by_key(|range| range.end - range.start)
+            .unwrap_or(0..0)
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Each peripheral's address must be the peripheral base plus its documented offset.
+    #[kernel_test]
+    fn peripheral_addresses_derive_from_base() {
+        let base = peripheral_base();
+
+        assert_eq!(uart0_base(), base + 0x0020_1000);
+        assert_eq!(gpio_base(), base + 0x0020_0000);
+        assert_eq!(aux_base(), base + 0x0021_5000);
+        assert_eq!(mailbox_base(), base + 0x0000_B880);
+        assert_eq!(pm_base(), base + 0x0010_0000);
+        assert_eq!(usb_base(), base + 0x0098_0000);
+        assert_eq!(peripheral_ic_base(), base + 0x0000_B200);
+        assert_eq!(dma_base(), base + 0x0000_7000);
+        assert_eq!(system_timer_base(), base + 0x0000_3000);
+    }
+
+    /// The RPi 3 maps the peripherals' bus address 0x7E00_0000 to 0x3F00_0000.
+    #[cfg(feature = "bsp_rpi3")]
+    #[kernel_test]
+    fn rpi3_peripheral_base_matches_soc_ranges() {
+        assert_eq!(peripheral_base(), 0x3F00_0000);
+    }
+}
@@ -12,7 +12,7 @@ use core::ops::RangeInclusive;
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
-const NUM_MEM_RANGES: usize = 3;
+const NUM_MEM_RANGES: usize = 6;
 
 /// The virtual memory layout.
 ///
@@ -69,6 +69,46 @@ pub static LAYOUT: KernelVirtualLayout<{ NUM_MEM_RANGES }> = KernelVirtualLayout
                 execute_never: true,
             },
         },
+        RangeDescriptor {
+            name: "Payload area",
+            virtual_range: || {
+                RangeInclusive::new(memory_map::PAYLOAD_START, memory_map::PAYLOAD_END_INCLUSIVE)
+            },
+            translation: Translation::Identity,
+            // Made executable, and read-only, by `exec::exec_payload()` only.
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            },
+        },
+        RangeDescriptor {
+            name: "User area",
+            virtual_range: || {
+                RangeInclusive::new(memory_map::USER_START, memory_map::USER_END_INCLUSIVE)
+            },
+            translation: Translation::Identity,
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::CacheableDRAM,
+                acc_perms: AccessPermissions::ReadWriteUser,
+                execute_never: true,
+            },
+        },
+        RangeDescriptor {
+            name: "Framebuffer",
+            virtual_range: || {
+                RangeInclusive::new(
+                    memory_map::mmio::VC_MEMORY_START,
+                    memory_map::mmio::VC_MEMORY_END_INCLUSIVE,
+                )
+            },
+            translation: Translation::Identity,
+            attribute_fields: AttributeFields {
+                mem_attributes: MemAttributes::WriteCombining,
+                acc_perms: AccessPermissions::ReadWrite,
+                execute_never: true,
+            },
+        },
         RangeDescriptor {
             name: "Device MMIO",
             virtual_range: || {
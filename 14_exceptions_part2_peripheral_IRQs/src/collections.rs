@@ -0,0 +1,253 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Fixed-capacity collections that do not allocate.
+//!
+//! # FixedMap
+//!
+//! [`FixedMap`] is a hash map with open addressing and linear probing, for small integer keys like
+//! IRQ or system call numbers. A key's home slot is its value modulo the capacity `N`, so dense
+//! keys below `N` never collide. A colliding key takes the next free slot after its home slot,
+//! wrapping around at the end.
+//!
+//! All `N` slots can be used, there is no maximum load factor. Lookups get slower as the map fills
+//! up, because probe sequences grow. Removing an entry leaves a tombstone that keeps the probe
+//! sequences of other keys intact, and that is reused by later insertions. A lookup probes at most
+//! `N` slots, so even a map full of tombstones stays bounded.
+//!
+//! Inserting a new key into a full map fails with an error. The map never grows.
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+#[derive(Copy, Clone)]
+enum Slot<K, V> {
+    Empty,
+    Tombstone,
+    Occupied(K, V),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Keys of a [`FixedMap`].
+pub trait FixedMapKey: Copy + PartialEq {
+    /// The value that selects the key's home slot.
+    fn hash(&self) -> usize;
+}
+
+/// A hash map with a capacity of `N` entries.
+pub struct FixedMap<K, V, const N: usize> {
+    slots: [Slot<K, V>; N],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+macro_rules! impl_fixed_map_key {
+    ($($t:ty),*) => {
+        $(
+            impl FixedMapKey for $t {
+                fn hash(&self) -> usize {
+                    *self as usize
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_map_key!(u8, u16, u32, u64, usize);
+
+impl<K: FixedMapKey, V: Copy, const N: usize> FixedMap<K, V, N> {
+    /// The slot indices of the probe sequence of `key`.
+    fn probe(key: &K) -> impl Iterator<Item = usize> {
+        let home = key.hash() % N;
+
+        (0..N).map(move |i| (home + i) % N)
+    }
+
+    /// The slot that holds `key`.
+    fn find(&self, key: &K) -> Option<usize> {
+        for i in Self::probe(key) {
+            match self.slots[i] {
+                Slot::Empty => return None,
+                Slot::Occupied(k, _) if k == *key => return Some(i),
+                _ => (),
+            }
+        }
+
+        None
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<K: FixedMapKey, V: Copy, const N: usize> FixedMap<K, V, N> {
+    /// Create an empty instance.
+    pub const fn new() -> Self {
+        Self {
+            slots: [Slot::Empty; N],
+            len: 0,
+        }
+    }
+
+    /// Insert `value` for `key`, returning the value that `key` had before, if any.
+    ///
+    /// Fails if `key` is new and the map is full.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, &'static str> {
+        if let Some(i) = self.find(&key) {
+            if let Slot::Occupied(_, old) = self.slots[i] {
+                self.slots[i] = Slot::Occupied(key, value);
+                return Ok(Some(old));
+            }
+        }
+
+        let free = Self::probe(&key).find(|&i| !matches!(self.slots[i], Slot::Occupied(..)));
+        match free {
+            None => Err("FixedMap is full"),
+            Some(i) => {
+                self.slots[i] = Slot::Occupied(key, value);
+                self.len += 1;
+                Ok(None)
+            }
+        }
+    }
+
+    /// The value for `key`.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        match self.find(key).map(|i| &self.slots[i]) {
+            Some(Slot::Occupied(_, value)) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// The value for `key`, mutable.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        match self.find(key) {
+            None => None,
+            Some(i) => match &mut self.slots[i] {
+                Slot::Occupied(_, value) => Some(value),
+                _ => None,
+            },
+        }
+    }
+
+    /// Remove `key` and return its value.
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let i = self.find(key)?;
+
+        match self.slots[i] {
+            Slot::Occupied(_, value) => {
+                self.slots[i] = Slot::Tombstone;
+                self.len -= 1;
+                Some(value)
+            }
+            _ => None,
+        }
+    }
+
+    /// The number of entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the map has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The maximum number of entries.
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// Iterate over all entries, in slot order.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> + '_ {
+        self.slots.iter().filter_map(|slot| match slot {
+            Slot::Occupied(key, value) => Some((key, value)),
+            _ => None,
+        })
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Inserted values must be found, replaced and removed by key.
+    #[kernel_test]
+    fn fixed_map_insert_get_remove() {
+        let mut map: FixedMap<u32, u32, 8> = FixedMap::new();
+        assert!(map.is_empty());
+
+        assert_eq!(map.insert(3, 30), Ok(None));
+        assert_eq!(map.insert(5, 50), Ok(None));
+        assert_eq!(map.get(&3), Some(&30));
+        assert_eq!(map.get(&4), None);
+
+        assert_eq!(map.insert(3, 31), Ok(Some(30)));
+        assert_eq!(map.len(), 2);
+
+        *map.get_mut(&5).unwrap() += 1;
+        assert_eq!(map.remove(&5), Some(51));
+        assert_eq!(map.remove(&5), None);
+        assert_eq!(map.get(&5), None);
+        assert_eq!(map.len(), 1);
+    }
+
+    /// Keys with the same home slot must not shadow each other, also after removals.
+    #[kernel_test]
+    fn fixed_map_handles_collisions() {
+        let mut map: FixedMap<u32, u32, 8> = FixedMap::new();
+
+        // All three have home slot 1.
+        map.insert(1, 10).unwrap();
+        map.insert(9, 90).unwrap();
+        map.insert(17, 170).unwrap();
+
+        assert_eq!(map.get(&1), Some(&10));
+        assert_eq!(map.get(&9), Some(&90));
+        assert_eq!(map.get(&17), Some(&170));
+
+        // The tombstone must keep 17 reachable.
+        assert_eq!(map.remove(&9), Some(90));
+        assert_eq!(map.get(&17), Some(&170));
+
+        // Reinserting an existing key behind the tombstone must not duplicate it.
+        assert_eq!(map.insert(17, 171), Ok(Some(170)));
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.iter().count(), 2);
+    }
+
+    /// A full map must reject new keys, but still accept updates and insertions after removal.
+    #[kernel_test]
+    fn fixed_map_full_behavior() {
+        let mut map: FixedMap<u32, u32, 4> = FixedMap::new();
+
+        for key in 0..4 {
+            map.insert(key * 3, key).unwrap();
+        }
+        assert_eq!(map.len(), map.capacity());
+
+        assert!(map.insert(100, 0).is_err());
+        assert_eq!(map.get(&100), None);
+        assert_eq!(map.insert(6, 42), Ok(Some(2)));
+
+        map.remove(&0);
+        assert_eq!(map.insert(100, 0), Ok(None));
+        assert_eq!(map.get(&100), Some(&0));
+        assert_eq!(map.get(&9), Some(&3));
+    }
+}
@@ -3,6 +3,16 @@
 // Copyright (c) 2018-2020 Andre Richter <andre.o.richter@gmail.com>
 
 //! System console.
+//!
+//! # Raw bytes
+//!
+//! Besides characters, the console reads and writes raw bytes, see
+//! `interface::Write::write_bytes()` and `interface::Read::read_bytes()`. They bypass the
+//! conversion of line endings, so binary protocols like the one of `serial` pass unmodified.
+//!
+//! Both modes share the same FIFOs. Reading in one mode consumes what the other would have
+//! returned, and the echo of the console's RX IRQ handler consumes received bytes before either.
+//! Mask IRQs, or leave the RX IRQ disabled, while using a binary protocol.
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -10,13 +20,26 @@
 
 /// Console interfaces.
 pub mod interface {
-    use core::fmt;
+    use crate::time;
+    use core::{fmt, time::Duration};
 
     /// Console write functions.
     pub trait Write {
         /// Write a single character.
         fn write_char(&self, c: char);
 
+        /// Write a raw byte, for binary protocols on the console's line.
+        fn write_byte(&self, b: u8) {
+            self.write_char(b as char)
+        }
+
+        /// Write raw bytes, see `write_byte()`.
+        fn write_bytes(&self, bytes: &[u8]) {
+            for b in bytes {
+                self.write_byte(*b)
+            }
+        }
+
         /// Write a Rust format string.
         fn write_fmt(&self, args: fmt::Arguments) -> fmt::Result;
 
@@ -32,8 +55,56 @@ pub mod interface {
             ' '
         }
 
+        /// Read a single character if one was received, without waiting.
+        fn read_char_nb(&self) -> Option<char> {
+            None
+        }
+
+        /// Read a raw byte if one was received, without waiting.
+        ///
+        /// Unlike `read_char_nb()`, the byte is passed on as is, without any conversion of line
+        /// endings. The default can only forward `read_char_nb()`, so consoles that convert must
+        /// override it.
+        fn read_byte_nb(&self) -> Option<u8> {
+            self.read_char_nb().map(|c| c as u8)
+        }
+
+        /// Read the raw bytes that were received into `buf`, without waiting, and return how many.
+        ///
+        /// Stops when `buf` is full or no more bytes are pending, see `read_byte_nb()`.
+        fn read_bytes(&self, buf: &mut [u8]) -> usize {
+            let mut len = 0;
+
+            while len < buf.len() {
+                match self.read_byte_nb() {
+                    Some(b) => buf[len] = b,
+                    None => break,
+                }
+                len += 1;
+            }
+
+            len
+        }
+
+        /// Read a single character, waiting at most `timeout` for it to arrive.
+        ///
+        /// Polls `read_char_nb()` with `time::with_timeout()`. While the console's RX IRQ is
+        /// enabled, its handler consumes received characters first, so they never show up here. Use
+        /// this while IRQs are masked, or before the RX IRQ is enabled.
+        fn read_char_timeout(&self, timeout: Duration) -> Option<char> {
+            time::with_timeout(timeout, || self.read_char_nb()).ok()
+        }
+
         /// Clear RX buffers, if any.
         fn clear(&self);
+
+        /// The receive errors that were seen since the last `clear_rx_errors()`.
+        fn last_rx_errors(&self) -> super::RxErrors {
+            super::RxErrors::default()
+        }
+
+        /// Reset the receive errors reported by `last_rx_errors()`.
+        fn clear_rx_errors(&self) {}
     }
 
     /// Console statistics.
@@ -47,8 +118,51 @@ pub mod interface {
         fn chars_read(&self) -> usize {
             0
         }
+
+        /// Return the number of received characters with errors, per kind of error.
+        fn rx_error_counts(&self) -> super::RxErrorCounts {
+            super::RxErrorCounts::default()
+        }
     }
 
     /// Trait alias for a full-fledged console.
     pub trait All = Write + Read + Statistics;
 }
+
+/// Receive errors, as flagged by the UART.
+///
+/// Bursts of framing errors usually mean a baud rate mismatch with the other end of the line.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RxErrors {
+    /// A character was missing its stop bit.
+    pub framing: bool,
+
+    /// A character's parity did not match.
+    pub parity: bool,
+
+    /// The line was held low for longer than a full character.
+    pub break_condition: bool,
+
+    /// A character arrived while the RX FIFO was full, and was lost.
+    pub overrun: bool,
+}
+
+/// Numbers of received characters with errors, per kind of error.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RxErrorCounts {
+    pub framing: usize,
+    pub parity: usize,
+    pub break_condition: usize,
+    pub overrun: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl RxErrors {
+    /// Whether any error is flagged.
+    pub fn any(&self) -> bool {
+        self.framing || self.parity || self.break_condition || self.overrun
+    }
+}
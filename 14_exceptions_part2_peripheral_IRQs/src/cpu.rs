@@ -9,4 +9,45 @@
 mod arch_cpu;
 pub use arch_cpu::*;
 
+pub mod cache;
+pub mod id;
+pub mod psci;
 pub mod smp;
+
+pub use smp::{num_cores, num_online_cores};
+
+use crate::{percpu, time, time::interface::TimeManager};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The security state that the executing core runs in.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SecurityState {
+    Secure,
+    NonSecure,
+
+    /// The state can not be told from the kernel's exception level.
+    Unknown,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Wait for an interrupt and account the time spent waiting as idle time of the executing core.
+pub fn idle() {
+    let start = time::time_manager().uptime();
+    wait_for_interrupt();
+    percpu::stats().add_idle_time(time::time_manager().uptime() - start);
+}
+
+/// Like `idle()`, but the timer wakes the core at the uptime `deadline` at the latest.
+pub fn idle_until(deadline: Duration) {
+    let start = time::time_manager().uptime();
+    time::wait_for_interrupt_until(deadline);
+    percpu::stats().add_idle_time(time::time_manager().uptime() - start);
+}
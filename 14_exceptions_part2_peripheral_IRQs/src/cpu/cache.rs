@@ -0,0 +1,10 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Cache maintenance.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/cpu/cache.rs"]
+mod arch_cpu_cache;
+pub use arch_cpu_cache::*;
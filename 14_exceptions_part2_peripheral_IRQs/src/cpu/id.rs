@@ -0,0 +1,176 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Processor identification.
+//!
+//! The ID registers tell what the running SoC supports, e.g. which translation granules the MMU
+//! code may use, or whether CRC-32 can be accelerated.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/cpu/id.rs"]
+mod arch_cpu_id;
+pub use arch_cpu_id::*;
+
+use crate::info;
+use core::fmt;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Which execution states an exception level supports.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ElSupport {
+    NotImplemented,
+    AArch64,
+    AArch64AndAArch32,
+}
+
+/// The features of a core that the kernel cares about.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FeatureSummary {
+    /// The size of the physical address space, `None` for an encoding that is unknown.
+    pub pa_range_bits: Option<u8>,
+
+    /// Whether the MMU supports the 4 KiB translation granule.
+    pub granule_4k: bool,
+
+    /// Whether the MMU supports the 16 KiB translation granule.
+    pub granule_16k: bool,
+
+    /// Whether the MMU supports the 64 KiB translation granule.
+    pub granule_64k: bool,
+
+    /// The support of EL0 to EL3, indexed by exception level.
+    pub el: [ElSupport; 4],
+
+    /// Whether floating point is implemented.
+    pub fp: bool,
+
+    /// Whether Advanced SIMD is implemented.
+    pub simd: bool,
+
+    /// Whether the AES instructions are implemented.
+    pub aes: bool,
+
+    /// Whether the 64 bit polynomial multiply instructions are implemented.
+    pub pmull: bool,
+
+    /// Whether the CRC-32 instructions are implemented.
+    pub crc32: bool,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn yes_no(supported: bool) -> &'static str {
+    if supported {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+impl ElSupport {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ElSupport::NotImplemented => "not implemented",
+            ElSupport::AArch64 => "AArch64",
+            ElSupport::AArch64AndAArch32 => "AArch64 + AArch32",
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl FeatureSummary {
+    /// Format the summary, handing each line of the report to `line`.
+    pub fn report(&self, mut line: impl FnMut(fmt::Arguments)) {
+        match self.pa_range_bits {
+            Some(bits) => line(format_args!("PA range:   {} bit", bits)),
+            None => line(format_args!("PA range:   unknown")),
+        }
+        line(format_args!(
+            "Granules:   4 KiB: {}, 16 KiB: {}, 64 KiB: {}",
+            yes_no(self.granule_4k),
+            yes_no(self.granule_16k),
+            yes_no(self.granule_64k)
+        ));
+        for (level, support) in self.el.iter().enumerate() {
+            line(format_args!("EL{}:        {}", level, support.as_str()));
+        }
+        line(format_args!(
+            "FP: {}, Advanced SIMD: {}",
+            yes_no(self.fp),
+            yes_no(self.simd)
+        ));
+        line(format_args!(
+            "CRC-32: {}, AES: {}, PMULL: {}",
+            yes_no(self.crc32),
+            yes_no(self.aes),
+            yes_no(self.pmull)
+        ));
+    }
+}
+
+/// Print the features of the executing core.
+pub fn print_feature_summary() {
+    info!("CPU features:");
+    feature_summary().report(|line| info!("      {}", line));
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+    use test_macros::kernel_test;
+
+    /// Collects formatted output in a fixed buffer.
+    struct Buffer {
+        data: [u8; 512],
+        len: usize,
+    }
+
+    impl Write for Buffer {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.data.len() {
+                return Err(fmt::Error);
+            }
+
+            self.data[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+
+            Ok(())
+        }
+    }
+
+    /// QEMU's `raspi3` models a Cortex-A53, which has a 40 bit physical address space and supports
+    /// the 4 KiB and 64 KiB granules, but not the 16 KiB one.
+    #[kernel_test]
+    fn report_matches_cortex_a53() {
+        let mut buf = Buffer {
+            data: [0; 512],
+            len: 0,
+        };
+        feature_summary().report(|line| writeln!(buf, "{}", line).unwrap());
+        let report = core::str::from_utf8(&buf.data[..buf.len]).unwrap();
+
+        // PA range, granules, EL0 to EL3, FP/SIMD and the crypto extensions.
+        assert_eq!(report.lines().count(), 1 + 1 + 4 + 1 + 1);
+        assert_eq!(report.lines().next(), Some("PA range:   40 bit"));
+        assert_eq!(
+            report.lines().nth(1),
+            Some("Granules:   4 KiB: yes, 16 KiB: no, 64 KiB: yes")
+        );
+    }
+}
@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Power State Coordination Interface.
+//!
+//! Firmware that implements PSCI starts and stops cores on request, instead of parking them in a
+//! spin table. It announces itself with a `/psci` node in the device tree, whose `method` tells the
+//! conduit, see [`init()`]. Only PSCI 0.2 and later are used, which have standard function IDs.
+//! PSCI 0.1 nodes, which are only `compatible` with `arm,psci` and carry their own IDs, are treated
+//! as if there was no PSCI.
+//!
+//! # Calling convention
+//!
+//! PSCI functions follow the SMC Calling Convention, ARM DEN 0028:
+//!
+//! - The function ID goes into `w0`, the arguments into `x1` to `x3`.
+//! - The kernel traps into the firmware with `smc #0`, or with `hvc #0` if a hypervisor implements
+//!   PSCI.
+//! - The result comes back in `x0`. `0` is success, the negative values are listed in [`Error`].
+//! - Up to SMCCC 1.0, `x4` to `x17` may be corrupted, too. Everything else is preserved.
+//!
+//! # CPU_ON
+//!
+//! [`cpu_on()`] asks the firmware to power up the core with the given `MPIDR_EL1` affinity at an
+//! entry address. The core arrives at the exception level of the caller, with the MMU and caches
+//! off, and with the context ID in `x0`.
+//!
+//! # CPU_OFF
+//!
+//! [`cpu_off()`] takes the executing core offline, and does not return if the firmware accepts. The
+//! kernel side of the power down is to stop serving IPIs and to clean the core's D-cache, so that
+//! other cores see what it wrote. The firmware then turns the caches off, cleans them once more and
+//! takes the core out of coherency, as PSCI requires of it. A stopped core comes back only through
+//! another `CPU_ON`, at its entry address.
+//!
+//! [`system_off()`] and [`system_reset()`] act on the whole system, and do not return either.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "../_arch/aarch64/cpu/psci.rs"]
+mod arch_cpu_psci;
+pub use arch_cpu_psci::*;
+
+use crate::{cpu, exception, fdt::Fdt};
+use core::sync::atomic::{AtomicU8, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const FN_CPU_OFF: u32 = 0x8400_0002;
+const FN_SYSTEM_OFF: u32 = 0x8400_0008;
+const FN_SYSTEM_RESET: u32 = 0x8400_0009;
+
+/// `CPU_ON`, SMC64 version.
+const FN_CPU_ON: u32 = 0xC400_0003;
+
+const CONDUIT_NONE: u8 = 0;
+const CONDUIT_SMC: u8 = 1;
+const CONDUIT_HVC: u8 = 2;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How the kernel traps into the PSCI implementation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Conduit {
+    /// Secure monitor call, for firmware at EL3.
+    Smc,
+
+    /// Hypervisor call, for a PSCI implementation at EL2.
+    Hvc,
+}
+
+/// Why a PSCI call failed.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    NotSupported,
+    InvalidParameters,
+    Denied,
+    AlreadyOn,
+    OnPending,
+    InternalFailure,
+    NotPresent,
+    Disabled,
+    InvalidAddress,
+
+    /// A result that the specification does not define.
+    Unknown(i64),
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static CONDUIT: AtomicU8 = AtomicU8::new(CONDUIT_NONE);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The conduit of a `/psci` node with `compatible` and `method` properties.
+///
+/// `Ok(None)` if the node does not describe PSCI 0.2 or later.
+fn parse_node(compatible: &[u8], method: Option<&[u8]>) -> Result<Option<Conduit>, &'static str> {
+    // A string list, e.g. "arm,psci-1.0\0arm,psci-0.2\0arm,psci\0".
+    let standard_ids = compatible
+        .split(|b| *b == 0)
+        .any(|entry| entry.starts_with(b"arm,psci-"));
+    if !standard_ids {
+        return Ok(None);
+    }
+
+    match method {
+        Some(b"smc\0") => Ok(Some(Conduit::Smc)),
+        Some(b"hvc\0") => Ok(Some(Conduit::Hvc)),
+        _ => Err("PSCI: Unknown method"),
+    }
+}
+
+/// Issue `SYSTEM_OFF` or `SYSTEM_RESET`, which take no arguments and only return on failure.
+fn system_call(function_id: u32) -> Error {
+    let conduit = match conduit() {
+        None => return Error::NotSupported,
+        Some(conduit) => conduit,
+    };
+
+    match Error::from_result(unsafe { call(conduit, function_id, [0; 3]) }) {
+        // Returning with success violates the specification.
+        Ok(()) => Error::Unknown(0),
+        Err(e) => e,
+    }
+}
+
+impl Error {
+    fn from_result(result: i64) -> Result<(), Self> {
+        match result {
+            0 => Ok(()),
+            -1 => Err(Self::NotSupported),
+            -2 => Err(Self::InvalidParameters),
+            -3 => Err(Self::Denied),
+            -4 => Err(Self::AlreadyOn),
+            -5 => Err(Self::OnPending),
+            -6 => Err(Self::InternalFailure),
+            -7 => Err(Self::NotPresent),
+            -8 => Err(Self::Disabled),
+            -9 => Err(Self::InvalidAddress),
+            other => Err(Self::Unknown(other)),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Look for PSCI in `fdt`, and use it from here on if it is there.
+///
+/// Must be called during kernel init, before secondary cores are started.
+pub fn init(fdt: &Fdt) -> Result<(), &'static str> {
+    let compatible = match fdt.property("/psci", "compatible")? {
+        None => return Ok(()),
+        Some(compatible) => compatible,
+    };
+
+    let conduit = match parse_node(compatible, fdt.property("/psci", "method")?)? {
+        None => CONDUIT_NONE,
+        Some(Conduit::Smc) => CONDUIT_SMC,
+        Some(Conduit::Hvc) => CONDUIT_HVC,
+    };
+    CONDUIT.store(conduit, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// The conduit to the PSCI implementation, `None` if there is none.
+pub fn conduit() -> Option<Conduit> {
+    match CONDUIT.load(Ordering::Relaxed) {
+        CONDUIT_SMC => Some(Conduit::Smc),
+        CONDUIT_HVC => Some(Conduit::Hvc),
+        _ => None,
+    }
+}
+
+/// Start the core with affinity `target_mpidr` at `entry`, with `context_id` in its `x0`.
+///
+/// Fails with `Error::NotSupported` if there is no PSCI.
+///
+/// # Safety
+///
+/// - `entry` must be code that runs with the MMU off and sets up its own stack.
+pub unsafe fn cpu_on(target_mpidr: u64, entry: usize, context_id: u64) -> Result<(), Error> {
+    let conduit = conduit().ok_or(Error::NotSupported)?;
+
+    Error::from_result(call(
+        conduit,
+        FN_CPU_ON,
+        [target_mpidr, entry as u64, context_id],
+    ))
+}
+
+/// Take the executing core offline. Does not return if the firmware accepts.
+///
+/// Without PSCI, returns `Error::NotSupported` right away. On a failure of the call itself, e.g.
+/// `Error::Denied` for the last core of a trusted OS, the core is left with IRQs masked and out of
+/// `cpu::smp::ipi_cores()`, and is not counted in `cpu::num_online_cores()` anymore. The caller may
+/// park it.
+///
+/// # Safety
+///
+/// - Nothing may depend on the executing core afterwards, e.g. a lock it holds.
+pub unsafe fn cpu_off() -> Error {
+    let conduit = match conduit() {
+        None => return Error::NotSupported,
+        Some(conduit) => conduit,
+    };
+
+    exception::asynchronous::local_irq_mask();
+    cpu::smp::stop_serving_ipis();
+    cpu::smp::mark_core_offline();
+    cpu::cache::clean_invalidate_dcache_all();
+
+    match Error::from_result(call(conduit, FN_CPU_OFF, [0; 3])) {
+        // Returning with success violates the specification.
+        Ok(()) => Error::Unknown(0),
+        Err(e) => e,
+    }
+}
+
+/// Power off the whole system. Returns only if there is no PSCI, or the firmware failed.
+pub fn system_off() -> Error {
+    system_call(FN_SYSTEM_OFF)
+}
+
+/// Reset the whole system. Returns only if there is no PSCI, or the firmware failed.
+pub fn system_reset() -> Error {
+    system_call(FN_SYSTEM_RESET)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Only nodes with standard function IDs and a known method may select a conduit.
+    #[kernel_test]
+    fn psci_nodes_are_parsed() {
+        let compatible = b"arm,psci-1.0\0arm,psci-0.2\0arm,psci\0";
+
+        assert_eq!(
+            parse_node(compatible, Some(&b"smc\0"[..])),
+            Ok(Some(Conduit::Smc))
+        );
+        assert_eq!(
+            parse_node(compatible, Some(&b"hvc\0"[..])),
+            Ok(Some(Conduit::Hvc))
+        );
+        assert!(parse_node(compatible, Some(&b"spin\0"[..])).is_err());
+        assert!(parse_node(compatible, None).is_err());
+        assert_eq!(parse_node(b"arm,psci\0", Some(&b"smc\0"[..])), Ok(None));
+
+        assert_eq!(Error::from_result(0), Ok(()));
+        assert_eq!(Error::from_result(-4), Err(Error::AlreadyOn));
+        assert_eq!(Error::from_result(-42), Err(Error::Unknown(-42)));
+    }
+}
@@ -3,8 +3,311 @@
 // Copyright (c) 2018-2020 Andre Richter <andre.o.richter@gmail.com>
 
 //! Symmetric multiprocessing.
+//!
+//! # Inter-processor interrupts
+//!
+//! [`send_ipi()`] sets the bit of an [`IpiKind`] in the target core's pending IPIs, which the BSP
+//! keeps in a place that interrupts the target, e.g. a mailbox register of the local interrupt
+//! controller. Sending the same kind again before the target handled it has no further effect.
+//!
+//! The receiving core calls [`handle_ipis()`], which takes all pending IPIs and dispatches them:
+//!
+//! - `TlbFlush` invalidates the executing core's TLB, or the range of a
+//!   `memory::mmu::tlb_shootdown()` that waits for the core.
+//! - `Halt` parks the core for good, after all other pending IPIs were handled.
+//! - Every kind, including the two above, is passed to the handler of [`set_ipi_handler()`].
+//!
+//! Cores with exception handling get `handle_ipis()` called from an IRQ handler, see
+//! [`init_ipis()`]. The secondary cores run without exception vectors, so they serve IPIs in
+//! [`ipi_loop()`] with IRQs masked at the core. A pending IRQ still ends a `wfi`.
+//!
+//! ## RPi 3 only
+//!
+//! IPIs are only supported on the RPi 3, whose local interrupt controller carries the pending bits
+//! in its mailbox registers. The secondary cores run uncached, so they can not share pending bits
+//! in memory with the boot core, and the GIC of the RPi 4 has no such registers. On the RPi 4, no
+//! core serves IPIs, `send_ipi()` fails, and `memory::mmu::tlb_shootdown()` has no targets.
 
 #[cfg(target_arch = "aarch64")]
 #[path = "../_arch/aarch64/cpu/smp.rs"]
 mod arch_cpu_smp;
 pub use arch_cpu_smp::*;
+
+use crate::{bsp, cpu, exception, memory};
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const RESCHEDULE_BIT: usize = 0;
+const TLB_FLUSH_BIT: usize = 1;
+const HALT_BIT: usize = 2;
+const CUSTOM_FIRST_BIT: usize = 3;
+
+/// Calls `handle_ipis()` for the IPI IRQ.
+struct IpiIrq;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The number of `IpiKind::Custom` kinds, which are numbered from `0`.
+pub const NUM_CUSTOM_IPIS: u8 = (32 - CUSTOM_FIRST_BIT) as u8;
+
+/// What an inter-processor interrupt asks the target core to do.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IpiKind {
+    /// Pick another task to run.
+    Reschedule,
+
+    /// Another core changed the translation tables.
+    TlbFlush,
+
+    /// Stop executing.
+    Halt,
+
+    /// Meaning is up to the handler. Must be below `NUM_CUSTOM_IPIS`.
+    Custom(u8),
+}
+
+/// What keeps the executing core from taking part in coherent SMP.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CoherencyError {
+    /// The data cache is off.
+    DCacheOff,
+
+    /// The MMU is off, so all data accesses are non-cacheable.
+    MmuOff,
+
+    /// The MMU does not walk the translation tables inner shareable.
+    TableWalksNotInnerShareable,
+
+    /// Kernel data is not mapped inner shareable.
+    DataNotInnerShareable,
+
+    /// Kernel data is not mapped write-back cacheable normal memory.
+    DataNotCacheable,
+
+    /// The core does not take part in the cluster's coherency.
+    SmpCoherencyOff,
+}
+
+/// Function that `handle_ipis()` passes every IPI to.
+pub type IpiHandler = fn(IpiKind);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static IPI_IRQ: IpiIrq = IpiIrq;
+
+/// The `IpiHandler`, as address. Zero if none is set.
+static IPI_HANDLER: AtomicUsize = AtomicUsize::new(0);
+
+/// One bit per core that serves IPIs.
+static IPI_CORES: AtomicU32 = AtomicU32::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl IpiKind {
+    fn bit(self) -> Result<usize, &'static str> {
+        match self {
+            Self::Reschedule => Ok(RESCHEDULE_BIT),
+            Self::TlbFlush => Ok(TLB_FLUSH_BIT),
+            Self::Halt => Ok(HALT_BIT),
+            Self::Custom(n) if n < NUM_CUSTOM_IPIS => Ok(CUSTOM_FIRST_BIT + n as usize),
+            Self::Custom(_) => Err("Custom IPI number out of range"),
+        }
+    }
+
+    fn from_bit(bit: usize) -> Self {
+        match bit {
+            RESCHEDULE_BIT => Self::Reschedule,
+            TLB_FLUSH_BIT => Self::TlbFlush,
+            HALT_BIT => Self::Halt,
+            _ => Self::Custom((bit - CUSTOM_FIRST_BIT) as u8),
+        }
+    }
+}
+
+fn ipi_handler() -> Option<IpiHandler> {
+    match IPI_HANDLER.load(Ordering::Acquire) {
+        0 => None,
+        addr => Some(unsafe { core::mem::transmute(addr) }),
+    }
+}
+
+impl CoherencyError {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::DCacheOff => "D-cache is off",
+            Self::MmuOff => "MMU is off",
+            Self::TableWalksNotInnerShareable => "Table walks are not inner shareable",
+            Self::DataNotInnerShareable => "Kernel data is not mapped inner shareable",
+            Self::DataNotCacheable => "Kernel data is not mapped write-back cacheable",
+            Self::SmpCoherencyOff => "Core does not take part in the cluster's coherency",
+        }
+    }
+}
+
+/// Take the executing core out of `ipi_cores()`, so that no other core waits for it.
+pub(crate) fn stop_serving_ipis() {
+    IPI_CORES.fetch_and(!(1 << core_id::<usize>()), Ordering::Release);
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Interrupt `target_core` with `ipi`.
+pub fn send_ipi(target_core: u8, ipi: IpiKind) -> Result<(), &'static str> {
+    if target_core as usize >= bsp::cpu::NUM_CORES {
+        return Err("Target core does not exist");
+    }
+
+    let bit = ipi.bit()?;
+    bsp::exception::asynchronous::send_ipi(target_core as usize, 1 << bit)
+}
+
+/// Panic with a specific message if the executing core is not set up for coherent SMP.
+///
+/// Must be called before cores are started that share data with their caches on.
+/// `start_secondary_cores_with()` calls it if the boot core has its D-cache on.
+pub fn verify_coherency() {
+    if let Err(e) = CoherencySetup::capture().check() {
+        panic!("SMP coherency: {}", e.as_str());
+    }
+}
+
+/// The cores that serve IPIs, one bit per core.
+pub fn ipi_cores() -> u32 {
+    IPI_CORES.load(Ordering::Acquire)
+}
+
+/// Set the function that every received IPI is passed to, on all cores.
+pub fn set_ipi_handler(handler: IpiHandler) {
+    IPI_HANDLER.store(handler as usize, Ordering::Release);
+}
+
+/// Take the executing core's pending IPIs and dispatch them. Returns how many were pending.
+///
+/// Does not return if `IpiKind::Halt` was among them.
+pub fn handle_ipis() -> usize {
+    let mut pending = bsp::exception::asynchronous::take_ipis();
+    let num_pending = pending.count_ones() as usize;
+    let handler = ipi_handler();
+
+    let halt = pending & (1 << HALT_BIT) != 0;
+    pending &= !(1 << HALT_BIT);
+
+    while pending != 0 {
+        let bit = pending.trailing_zeros() as usize;
+        pending &= !(1 << bit);
+
+        let ipi = IpiKind::from_bit(bit);
+        if ipi == IpiKind::TlbFlush {
+            memory::mmu::handle_tlb_flush();
+        }
+        if let Some(handler) = handler {
+            handler(ipi);
+        }
+    }
+
+    if halt {
+        if let Some(handler) = handler {
+            handler(IpiKind::Halt);
+        }
+        stop_serving_ipis();
+        cpu::wait_forever()
+    }
+
+    num_pending
+}
+
+/// Register and enable the IRQ handler that receives IPIs on the boot core.
+///
+/// Must be called during kernel init. Does nothing if the BSP does not support IPIs.
+pub fn init_ipis() -> Result<(), &'static str> {
+    use bsp::exception::asynchronous::{ipi_irq, irq_manager};
+    use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+    let irq = match ipi_irq() {
+        None => return Ok(()),
+        Some(irq) => irq,
+    };
+
+    let descriptor = IRQDescriptor {
+        name: "IPI",
+        handler: &IPI_IRQ,
+    };
+
+    irq_manager().register_handler(irq, descriptor)?;
+    irq_manager().enable(irq);
+    IPI_CORES.fetch_or(1 << core_id::<usize>(), Ordering::Release);
+
+    Ok(())
+}
+
+/// Serve IPIs on the executing core forever, for work of `start_secondary_cores_with()`.
+///
+/// The core waits for interrupts with IRQs masked, so it needs no exception vectors.
+pub fn ipi_loop() -> ! {
+    use bsp::exception::asynchronous::{ipi_irq, irq_manager};
+    use exception::asynchronous::interface::IRQManager;
+
+    if let Some(irq) = ipi_irq() {
+        irq_manager().enable(irq);
+        IPI_CORES.fetch_or(1 << core_id::<usize>(), Ordering::Release);
+    }
+
+    loop {
+        cpu::wait_for_interrupt();
+        handle_ipis();
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl exception::asynchronous::interface::IRQHandler for IpiIrq {
+    fn handle(&self) -> Result<(), &'static str> {
+        handle_ipis();
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Every kind must map to a bit of its own and back, and custom numbers must be bounded.
+    #[kernel_test]
+    fn ipi_kinds_roundtrip_through_bits() {
+        let kinds = [
+            IpiKind::Reschedule,
+            IpiKind::TlbFlush,
+            IpiKind::Halt,
+            IpiKind::Custom(0),
+            IpiKind::Custom(NUM_CUSTOM_IPIS - 1),
+        ];
+
+        for &kind in kinds.iter() {
+            let bit = kind.bit().unwrap();
+            assert!(bit < 32);
+            assert_eq!(IpiKind::from_bit(bit), kind);
+        }
+
+        assert!(IpiKind::Custom(NUM_CUSTOM_IPIS).bit().is_err());
+        assert!(send_ipi(bsp::cpu::NUM_CORES as u8, IpiKind::Reschedule).is_err());
+    }
+}
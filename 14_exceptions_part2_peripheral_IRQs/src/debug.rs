@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Debugger support.
+//!
+//! [`KERNEL_INFO`] lets a debugger script locate kernel state in a raw memory dump or through JTAG,
+//! without needing the kernel's symbols. The linker script places it at [`KERNEL_INFO_ADDR`], 2 KiB
+//! into the kernel image. A script checks for [`KernelInfo::MAGIC`] there, and then follows the
+//! pointers.
+//!
+//! The layout is stable. Fields are only ever appended, in which case `layout_version` is
+//! incremented and `size` grows.
+//!
+//! # Hardware breakpoints and watchpoints
+//!
+//! [`set_hw_breakpoint()`] and [`set_watchpoint()`] trap on an instruction fetch from, or a data
+//! access to, an address without modifying memory. This finds whoever corrupts a variable. The
+//! trap is a synchronous exception with `Syndrome::HwBreakpoint` or `Syndrome::Watchpoint`, which a
+//! handler registered with `exception::register_sync_handler()` takes. It must clear the breakpoint
+//! or watchpoint before it returns, or the access traps again.
+//!
+//! How many exist depends on the core, see [`num_hw_breakpoints()`] and [`num_watchpoints()`]. They
+//! are per core, and only programmed on the executing one.
+//!
+//! # Single-stepping
+//!
+//! A synchronous exception handler can step the code it returns to with [`single_step()`]. After
+//! each instruction, a `Syndrome::SoftwareStep` exception is taken, whose handler steps again or
+//! calls [`end_single_step()`]. The architecture's module docs describe the state machine behind
+//! it.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/debug.rs"]
+mod arch_debug;
+pub use arch_debug::{end_single_step, num_hw_breakpoints, num_watchpoints, single_step};
+
+use crate::{
+    bsp, fault,
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+use core::mem::size_of;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The address of [`KERNEL_INFO`], as arranged by the linker script.
+pub const KERNEL_INFO_ADDR: usize = 0x8_0800;
+
+/// Pointers into kernel state for external debuggers.
+#[repr(C)]
+pub struct KernelInfo {
+    /// Always [`KernelInfo::MAGIC`].
+    pub magic: [u8; 8],
+
+    /// Incremented whenever fields are appended.
+    pub layout_version: u32,
+
+    /// Size of this structure in bytes.
+    pub size: u32,
+
+    /// UTF-8 version string, not NUL terminated.
+    pub version: *const u8,
+
+    /// Length of the version string in bytes.
+    pub version_len: usize,
+
+    /// The fault log ring.
+    pub log_ring: *const u8,
+
+    /// Start of the kernel heap.
+    pub heap_start: usize,
+
+    /// End of the kernel heap, exclusive.
+    pub heap_end: usize,
+
+    /// The device driver table, an array of `&dyn DeviceDriver` fat pointers.
+    pub driver_table: *const u8,
+
+    /// Number of entries in the driver table.
+    pub num_drivers: usize,
+}
+
+/// The kind of data access that a watchpoint traps on.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WatchAccess {
+    Load,
+    Store,
+    LoadStore,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+const VERSION: &str = concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION"));
+
+/// Bitmaps of the breakpoint and watchpoint slots in use.
+static SLOTS_IN_USE: IRQSafeNullLock<(u16, u16)> = IRQSafeNullLock::new((0, 0));
+
+/// The kernel info instance that debuggers look for.
+#[no_mangle]
+#[used]
+#[link_section = ".kernel_info"]
+pub static KERNEL_INFO: KernelInfo = KernelInfo {
+    magic: KernelInfo::MAGIC,
+    layout_version: 1,
+    size: size_of::<KernelInfo>() as u32,
+    version: VERSION.as_ptr(),
+    version_len: VERSION.len(),
+    log_ring: &fault::FAULT_LOG as *const _ as *const u8,
+    heap_start: bsp::memory::heap_range().start,
+    heap_end: bsp::memory::heap_range().end,
+    driver_table: &bsp::driver::DEVICE_DRIVERS as *const _ as *const u8,
+    num_drivers: bsp::driver::NUM_DRIVERS,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl KernelInfo {
+    /// The magic that identifies the structure.
+    pub const MAGIC: [u8; 8] = *b"RPIKINFO";
+}
+
+/// The only instance is immutable and only holds addresses of statics.
+unsafe impl Sync for KernelInfo {}
+
+/// Claim the first free slot of `num` in `bitmap`.
+fn claim_slot(bitmap: &mut u16, num: usize) -> Result<usize, &'static str> {
+    let slot = (0..num)
+        .find(|slot| *bitmap & (1 << slot) == 0)
+        .ok_or("No free debug register")?;
+    *bitmap |= 1 << slot;
+
+    Ok(slot)
+}
+
+/// Trap on instruction fetches from `addr`, and return the slot to clear it with.
+pub fn set_hw_breakpoint(addr: usize) -> Result<usize, &'static str> {
+    if addr % 4 != 0 {
+        return Err("Instruction address not 4 byte aligned");
+    }
+
+    let mut r = &SLOTS_IN_USE;
+    r.lock(|(breakpoints, _)| {
+        let slot = claim_slot(breakpoints, num_hw_breakpoints())?;
+
+        unsafe {
+            arch_debug::enable_monitor_debug();
+            arch_debug::program_hw_breakpoint(slot, Some(addr));
+        }
+
+        Ok(slot)
+    })
+}
+
+/// Stop trapping on the breakpoint in `slot`.
+pub fn clear_hw_breakpoint(slot: usize) {
+    let mut r = &SLOTS_IN_USE;
+    r.lock(|(breakpoints, _)| {
+        if slot < num_hw_breakpoints() && *breakpoints & (1 << slot) != 0 {
+            unsafe { arch_debug::program_hw_breakpoint(slot, None) };
+            *breakpoints &= !(1 << slot);
+        }
+    })
+}
+
+/// Trap on `access`es to the `size` bytes at `addr`, and return the slot to clear it with.
+///
+/// `size` must be 1, 2, 4 or 8, and `addr` aligned to it.
+pub fn set_watchpoint(
+    addr: usize,
+    access: WatchAccess,
+    size: usize,
+) -> Result<usize, &'static str> {
+    if !size.is_power_of_two() || size > 8 {
+        return Err("Watched size must be 1, 2, 4 or 8 bytes");
+    }
+    if addr % size != 0 {
+        return Err("Watched address not aligned to the size");
+    }
+
+    let mut r = &SLOTS_IN_USE;
+    r.lock(|(_, watchpoints)| {
+        let slot = claim_slot(watchpoints, num_watchpoints())?;
+
+        unsafe {
+            arch_debug::enable_monitor_debug();
+            arch_debug::program_watchpoint(slot, Some((addr, access, size)));
+        }
+
+        Ok(slot)
+    })
+}
+
+/// Stop trapping on the watchpoint in `slot`.
+pub fn clear_watchpoint(slot: usize) {
+    let mut r = &SLOTS_IN_USE;
+    r.lock(|(_, watchpoints)| {
+        if slot < num_watchpoints() && *watchpoints & (1 << slot) != 0 {
+            unsafe { arch_debug::program_watchpoint(slot, None) };
+            *watchpoints &= !(1 << slot);
+        }
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// The kernel info must be at the documented address and carry valid pointers.
+    #[kernel_test]
+    fn kernel_info_is_located_and_populated() {
+        let info = unsafe { &*(KERNEL_INFO_ADDR as *const KernelInfo) };
+
+        assert_eq!(&KERNEL_INFO as *const _ as usize, KERNEL_INFO_ADDR);
+        assert_eq!(info.magic, KernelInfo::MAGIC);
+        assert_eq!(info.size as usize, size_of::<KernelInfo>());
+
+        assert!(!info.version.is_null());
+        assert!(info.version_len > 0);
+        assert!(!info.log_ring.is_null());
+        assert!(!info.driver_table.is_null());
+        assert!(info.heap_start < info.heap_end);
+        assert_eq!(info.num_drivers, bsp::driver::NUM_DRIVERS);
+    }
+
+    /// Misaligned or odd-sized watchpoints must be rejected before a slot is claimed.
+    #[kernel_test]
+    fn invalid_watchpoints_are_rejected() {
+        assert!(set_watchpoint(0x8_0001, WatchAccess::Store, 4).is_err());
+        assert!(set_watchpoint(0x8_0000, WatchAccess::Store, 3).is_err());
+        assert!(set_watchpoint(0x8_0000, WatchAccess::Store, 16).is_err());
+        assert!(set_hw_breakpoint(0x8_0002).is_err());
+
+        let mut r = &SLOTS_IN_USE;
+        assert_eq!(r.lock(|slots| *slots), (0, 0));
+    }
+}
@@ -0,0 +1,40 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Direct memory access.
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Why a DMA transfer did not complete.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// The engine is not initialized, or busy with another transfer. Nothing was copied.
+    Unavailable,
+
+    /// The transfer stopped partway, e.g. on a bus error or a timeout. The destination is partly
+    /// written, and so is the source where the two overlap.
+    Failed,
+}
+
+/// DMA interfaces.
+pub mod interface {
+    use super::Error;
+
+    /// Bulk memory copies that offload the CPU.
+    ///
+    /// The `BSP` is supposed to supply one global instance.
+    pub trait DmaEngine {
+        /// Copy `len` bytes from physical address `src` to `dst`, like `core::ptr::copy()`.
+        ///
+        /// Source and destination may overlap. Cache maintenance is done by the implementation.
+        /// See [`Error`] for what is left of the ranges if the copy did not complete.
+        ///
+        /// # Safety
+        ///
+        /// - Both ranges must be valid memory that is not concurrently accessed by the CPU.
+        unsafe fn memmove(&self, dst: usize, src: usize, len: usize) -> Result<(), Error>;
+    }
+}
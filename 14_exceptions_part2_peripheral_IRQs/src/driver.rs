@@ -4,12 +4,30 @@
 
 //! Driver support.
 
+use crate::{bsp, memory};
+use alloc::vec::Vec;
+use core::ops::Range;
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
 /// Driver interfaces.
 pub mod interface {
+    use core::ops::Range;
+
+    /// Device drivers that can save their device's state before it is powered down.
+    pub trait Suspendable {
+        /// Save the device's register state to a driver-owned buffer and quiesce the device.
+        ///
+        /// Afterwards, the device may be power-gated. A second call without `resume()` in between
+        /// does not overwrite the saved state.
+        fn suspend(&self);
+
+        /// Restore the register state that `suspend()` saved. Does nothing if the device is not
+        /// suspended.
+        fn resume(&self);
+    }
 
     /// Device Driver functions.
     pub trait DeviceDriver {
@@ -21,6 +39,18 @@ pub mod interface {
             Ok(())
         }
 
+        /// Return the physical address range of the device's MMIO registers, if any.
+        fn mmio_region(&self) -> Option<Range<usize>> {
+            None
+        }
+
+        /// Called by the kernel for slow setup, e.g. enumeration or link-up, after boot finished.
+        ///
+        /// Runs in the main phase with IRQs unmasked, from [`DriverManager::run_late_init()`].
+        fn late_init(&self) -> Result<(), &'static str> {
+            Ok(())
+        }
+
         /// Called by the kernel to register and enable the device's IRQ handlers, if any.
         ///
         /// Rust's type system will prevent a call to this function unless the calling instance
@@ -28,6 +58,30 @@ pub mod interface {
         fn register_and_enable_irq_handler(&'static self) -> Result<(), &'static str> {
             Ok(())
         }
+
+        /// Undo `register_and_enable_irq_handler()`. Drivers that register handlers must
+        /// implement this, or `DriverManager::reinit()` fails to register them again.
+        fn deregister_irq_handler(&self) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        /// Disable the device's IRQs at the interrupt controller, but keep their handlers.
+        ///
+        /// Used by `DriverManager::reinit()` after kernel init, when the handler tables are
+        /// read-only. Drivers that register handlers must implement this.
+        fn disable_irqs(&self) {}
+
+        /// Undo `disable_irqs()`.
+        fn enable_irqs(&self) {}
+
+        /// Quiesce the device before `init()` is called again, e.g. by disabling it and masking
+        /// its interrupts.
+        fn deinit(&self) {}
+
+        /// Return the driver's suspend/resume interface, if it has one.
+        fn as_suspendable(&self) -> Option<&dyn Suspendable> {
+            None
+        }
     }
 
     /// Device driver management functions.
@@ -45,5 +99,123 @@ pub mod interface {
         ///
         /// For example, device driver code that depends on other drivers already being online.
         fn post_device_driver_init(&self);
+
+        /// Call `DeviceDriver::late_init()` of all drivers, in the order of `all_device_drivers()`.
+        ///
+        /// The counterpart of `post_device_driver_init()` for work that does not need to block
+        /// boot. Must be called after kernel init, typically from a task spawned by
+        /// `kernel_main()`.
+        fn run_late_init(&self) {
+            use crate::{info, state, warn};
+
+            assert!(
+                state::state_manager().state() != state::State::Init,
+                "run_late_init called during kernel init phase"
+            );
+
+            for driver in self.all_device_drivers() {
+                match driver.late_init() {
+                    Ok(()) => info!("Late init done: {}", driver.compatible()),
+                    Err(msg) => warn!("Late init failed: {}: {}", driver.compatible(), msg),
+                }
+            }
+        }
+
+        /// Run the init of the driver with the compatible string `compatible` once more, e.g. after
+        /// changing its configuration.
+        ///
+        /// The driver's IRQ handler is deregistered and `DeviceDriver::deinit()` quiesces the
+        /// device. Then `init()` and `register_and_enable_irq_handler()` run like during boot.
+        ///
+        /// The IRQ handler tables are read-only after kernel init. From then on, the handler stays
+        /// registered, and its IRQs are disabled at the interrupt controller around the re-init
+        /// instead. If `init()` fails, they stay disabled.
+        fn reinit(&self, compatible: &str) -> Result<(), &'static str> {
+            use crate::state;
+
+            let booting = state::state_manager().state() == state::State::Init;
+
+            let driver = self
+                .all_device_drivers()
+                .iter()
+                .find(|d| d.compatible() == compatible)
+                .ok_or("No driver with this compatible string")?;
+
+            if booting {
+                driver.deregister_irq_handler()?;
+            } else {
+                driver.disable_irqs();
+            }
+            driver.deinit();
+
+            super::init_driver(*driver).map_err(|_| "Driver init failed")?;
+
+            if booting {
+                driver.register_and_enable_irq_handler()
+            } else {
+                driver.enable_irqs();
+                Ok(())
+            }
+        }
+
+        /// Call `Suspendable::suspend()` of all drivers that support it, in the reverse order of
+        /// `all_device_drivers()`.
+        ///
+        /// Drivers that depend on others come later in the init order, so they are quiesced before
+        /// the devices they depend on. The console may be among them, so nothing must be printed
+        /// until `resume_all()` is called.
+        fn suspend_all(&self) {
+            for driver in self.all_device_drivers().iter().rev() {
+                if let Some(driver) = driver.as_suspendable() {
+                    driver.suspend();
+                }
+            }
+        }
+
+        /// Call `Suspendable::resume()` of all drivers that support it, in the order of
+        /// `all_device_drivers()`, i.e. the init order.
+        fn resume_all(&self) {
+            for driver in self.all_device_drivers() {
+                if let Some(driver) = driver.as_suspendable() {
+                    driver.resume();
+                }
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Run `init()` of `driver`, unless the `DriverInit` fault point fails it first.
+///
+/// Used for the first init during kernel init, and by `DriverManager::reinit()`.
+pub fn init_driver(driver: &(dyn interface::DeviceDriver + Sync)) -> Result<(), ()> {
+    #[cfg(feature = "faultinject")]
+    if crate::faultinject::should_fail(crate::faultinject::FaultPoint::DriverInit) {
+        return Err(());
     }
+
+    driver.init()
+}
+
+/// Panic if the MMIO regions of any two drivers, or of the regions registered with
+/// `bsp::memory::register_mmio()`, overlap each other or the heap.
+///
+/// Must be called after the heap has been initialized.
+pub fn validate_mmio_regions(
+    drivers: &[&'static (dyn interface::DeviceDriver + Sync)],
+    heap: Range<usize>,
+) {
+    let mut regions: Vec<(&str, Range<usize>)> = drivers
+        .iter()
+        .filter_map(|d| d.mmio_region().map(|r| (d.compatible(), r)))
+        .collect();
+    bsp::memory::with_mmio_regions(|mmio| {
+        regions.extend(mmio.iter().map(|m| (m.name, m.region.range())))
+    });
+    regions.push(("Heap", heap));
+
+    memory::assert_no_overlaps(&regions);
 }
@@ -10,11 +10,30 @@ mod arch_exception;
 pub use arch_exception::*;
 
 pub mod asynchronous;
+#[cfg(feature = "irq_off_trace")]
+pub(crate) mod irq_off_trace;
+pub mod syscall;
+
+#[cfg(feature = "irq_off_trace")]
+pub use irq_off_trace::{max_irq_off_duration, reset_max_irq_off_duration};
+pub use syscall::register_syscall;
+
+use crate::{
+    bsp,
+    percpu::{CacheLinePadded, PerCpu},
+    warn,
+};
+use core::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
 
+/// IRQ nesting deeper than this is reported as runaway nesting.
+///
+/// IRQs are masked while a handler runs, so for now every depth above `1` is unintended.
+pub const MAX_IRQ_NESTING_DEPTH: u8 = 4;
+
 /// Kernel privilege levels.
 #[allow(missing_docs)]
 #[derive(PartialEq)]
@@ -25,6 +44,69 @@ pub enum PrivilegeLevel {
     Unknown,
 }
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// The number of IRQ handlers that are active on a core.
+static IRQ_NESTING_DEPTH: PerCpu<AtomicU8, { bsp::cpu::NUM_CORES }> = PerCpu::new([
+    CacheLinePadded::new(AtomicU8::new(0)),
+    CacheLinePadded::new(AtomicU8::new(0)),
+    CacheLinePadded::new(AtomicU8::new(0)),
+    CacheLinePadded::new(AtomicU8::new(0)),
+]);
+
+/// How often `MAX_IRQ_NESTING_DEPTH` was exceeded, on all cores.
+static IRQ_NESTING_OVERRUNS: AtomicUsize = AtomicUsize::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Account the entry into an IRQ handler on the executing core. Returns the new depth.
+///
+/// Called by the IRQ vector, before dispatching to a handler. Warns if the depth exceeds
+/// [`MAX_IRQ_NESTING_DEPTH`].
+///
+/// The counter is only touched by its own core, and a nested IRQ restores it before returning. A
+/// plain load and store therefore suffices, which is cheaper than an atomic read-modify-write.
+#[inline(always)]
+pub fn irq_enter() -> u8 {
+    let counter = IRQ_NESTING_DEPTH.current();
+    let depth = counter.load(Ordering::Relaxed).saturating_add(1);
+    counter.store(depth, Ordering::Relaxed);
+
+    if depth > MAX_IRQ_NESTING_DEPTH {
+        IRQ_NESTING_OVERRUNS.fetch_add(1, Ordering::Relaxed);
+        warn!(
+            "IRQ nesting depth {} exceeds {}",
+            depth, MAX_IRQ_NESTING_DEPTH
+        );
+    }
+
+    depth
+}
+
+/// Account the exit from an IRQ handler on the executing core. Counterpart of `irq_enter()`.
+#[inline(always)]
+pub fn irq_exit() {
+    let counter = IRQ_NESTING_DEPTH.current();
+    counter.store(
+        counter.load(Ordering::Relaxed).saturating_sub(1),
+        Ordering::Relaxed,
+    );
+}
+
+/// The number of IRQ handlers that are active on the executing core. Zero outside of IRQ context.
+pub fn irq_nesting_depth() -> u8 {
+    IRQ_NESTING_DEPTH.current().load(Ordering::Relaxed)
+}
+
+/// How often the IRQ nesting depth exceeded [`MAX_IRQ_NESTING_DEPTH`] since boot.
+pub fn irq_nesting_overruns() -> usize {
+    IRQ_NESTING_OVERRUNS.load(Ordering::Relaxed)
+}
+
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
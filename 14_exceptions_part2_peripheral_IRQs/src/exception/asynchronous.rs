@@ -3,13 +3,49 @@
 // Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
 
 //! Asynchronous exception handling.
+//!
+//! # Handler panics
+//!
+//! The interrupt controller drivers call IRQ handlers through [`call_handler()`]. If a handler
+//! panics, the panic handler prints the message and resumes after the call, instead of halting the
+//! kernel. The handler is marked as faulted and never called again, and its IRQ is masked.
+//!
+//! There is no unwinding in the kernel, so this is a best-effort longjmp. Nothing of what the
+//! handler was in the middle of is cleaned up. Locks it held stay taken, and the data that it
+//! guards might be inconsistent. This contains a buggy driver, but does not make it safe to keep
+//! using it.
 
 #[cfg(target_arch = "aarch64")]
 #[path = "../_arch/aarch64/exception/asynchronous.rs"]
 mod arch_exception_async;
 pub use arch_exception_async::*;
 
-use core::{fmt, marker::PhantomData};
+use crate::{
+    bsp, fault,
+    percpu::{CacheLinePadded, PerCpu},
+    synchronization,
+    synchronization::{IRQSafeNullLock, InitStateLock},
+    warn,
+};
+use core::{
+    fmt,
+    marker::PhantomData,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of handlers that are remembered as faulted.
+const MAX_FAULTED_HANDLERS: usize = 8;
+
+/// The handlers that panicked, by the address of their handler object.
+struct FaultedHandlers {
+    addrs: [usize; MAX_FAULTED_HANDLERS],
+    len: usize,
+}
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -32,6 +68,12 @@ pub mod interface {
         /// The IRQ number type depends on the implementation.
         type IRQNumberType;
 
+        /// Snapshot of the controller's enable masks, as returned by `disable_all()`.
+        type SavedIrqState;
+
+        /// Snapshot of the controller's pending IRQs, as returned by `pending()`.
+        type PendingIrqState;
+
         /// Register a handler.
         fn register_handler(
             &self,
@@ -39,9 +81,41 @@ pub mod interface {
             descriptor: super::IRQDescriptor,
         ) -> Result<(), &'static str>;
 
+        /// Disable an interrupt in the controller and remove its handler, so that another one can
+        /// be registered.
+        ///
+        /// Like `register_handler()`, only possible during kernel init.
+        fn deregister_handler(&self, irq_number: Self::IRQNumberType) -> Result<(), &'static str>;
+
         /// Enable an interrupt in the controller.
         fn enable(&self, irq_number: Self::IRQNumberType);
 
+        /// Disable an interrupt in the controller.
+        fn disable(&self, irq_number: Self::IRQNumberType);
+
+        /// Return whether an interrupt is enabled in the controller.
+        fn is_enabled(&self, irq_number: Self::IRQNumberType) -> bool;
+
+        /// Read which IRQs the controller sees as pending.
+        ///
+        /// Tells whether a peripheral raises its IRQ line at all, apart from whether the IRQ gets
+        /// handled. Only reads status registers, so it is safe to call anytime.
+        fn pending(&self) -> Self::PendingIrqState;
+
+        /// Disable all interrupts at the controller and return the previous enable masks.
+        ///
+        /// In contrast to `local_irq_mask_save()`, which masks IRQs at the executing core only,
+        /// this stops interrupts at their source. This allows reconfiguring the controller itself.
+        fn disable_all(&self) -> Self::SavedIrqState;
+
+        /// Restore the enable masks that a previous `disable_all()` returned.
+        fn restore_all(&self, state: Self::SavedIrqState);
+
+        /// Set a catch-all that is called for pending IRQs without a registered handler.
+        ///
+        /// Without a fallback, such IRQs are masked in the controller and counted.
+        fn set_fallback(&self, f: fn(Self::IRQNumberType));
+
         /// Handle pending interrupts.
         ///
         /// This function is called directly from the CPU's IRQ exception vector. On AArch64,
@@ -57,6 +131,9 @@ pub mod interface {
 
         /// Print list of registered handlers.
         fn print_handler(&self);
+
+        /// Print the pending IRQs, together with the names of their handlers.
+        fn print_pending(&self);
     }
 }
 
@@ -83,12 +160,116 @@ pub struct IRQContext<'irq_context> {
 }
 
 /// A wrapper type for IRQ numbers with integrated range sanity check.
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct IRQNumber<const MAX_INCLUSIVE: usize>(usize);
 
+/// Catch-all for pending IRQs that have no registered handler.
+///
+/// Holds the optional fallback of an [`interface::IRQManager`] and counts the IRQs that arrived
+/// while none was set.
+pub struct IRQFallback<T> {
+    /// Writable only during kernel init. RO afterwards.
+    handler: InitStateLock<Option<fn(T)>>,
+    num_unhandled: AtomicUsize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Where the innermost running `call_handler()` of a core resumes after a panic. Null outside of
+/// handlers.
+///
+/// Each core only ever touches its own slot, so that a panic resumes on the stack of the core that
+/// panicked.
+static RECOVERY_POINT: PerCpu<AtomicPtr<RecoveryPoint>, { bsp::cpu::NUM_CORES }> = PerCpu::new([
+    CacheLinePadded::new(AtomicPtr::new(ptr::null_mut())),
+    CacheLinePadded::new(AtomicPtr::new(ptr::null_mut())),
+    CacheLinePadded::new(AtomicPtr::new(ptr::null_mut())),
+    CacheLinePadded::new(AtomicPtr::new(ptr::null_mut())),
+]);
+
+static FAULTED_HANDLERS: IRQSafeNullLock<FaultedHandlers> =
+    IRQSafeNullLock::new(FaultedHandlers::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl FaultedHandlers {
+    const fn new() -> Self {
+        Self {
+            addrs: [0; MAX_FAULTED_HANDLERS],
+            len: 0,
+        }
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        self.addrs[..self.len].contains(&addr)
+    }
+
+    /// Returns `false` if there is no space left.
+    fn insert(&mut self, addr: usize) -> bool {
+        if self.len == MAX_FAULTED_HANDLERS {
+            return false;
+        }
+
+        self.addrs[self.len] = addr;
+        self.len += 1;
+        true
+    }
+}
+
+impl IRQDescriptor {
+    /// Identifies the handler, independent of the descriptor it is registered with.
+    fn handler_addr(&self) -> usize {
+        self.handler as *const _ as *const () as usize
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
+use synchronization::interface::{Mutex, ReadWriteEx};
+
+impl<T> IRQFallback<T> {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            handler: InitStateLock::new(None),
+            num_unhandled: AtomicUsize::new(0),
+        }
+    }
+
+    /// Set the fallback. Replaces a previously set one.
+    pub fn set(&self, f: fn(T)) {
+        let mut r = &self.handler;
+        r.write(|handler| *handler = Some(f));
+    }
+
+    /// Pass an IRQ without handler to the fallback.
+    ///
+    /// Returns `false` if no fallback is set. The IRQ is counted then, and the caller is expected
+    /// to mask it.
+    pub fn handle(&self, irq: T) -> bool {
+        let mut r = &self.handler;
+        match r.read(|handler| *handler) {
+            Some(f) => {
+                f(irq);
+                true
+            }
+            None => {
+                self.num_unhandled.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// The number of IRQs that were masked because no fallback was set.
+    pub fn num_unhandled(&self) -> usize {
+        self.num_unhandled.load(Ordering::Relaxed)
+    }
+}
 
 impl<'irq_context> IRQContext<'irq_context> {
     /// Creates an IRQContext token.
@@ -132,6 +313,7 @@ impl<const MAX_INCLUSIVE: usize> fmt::Display for IRQNumber<{ MAX_INCLUSIVE }> {
 /// While the function temporarily changes the HW state of the executing core, it restores it to the
 /// previous state before returning, so this is deemed safe.
 #[inline(always)]
+#[track_caller]
 pub fn exec_with_irq_masked<T>(f: impl FnOnce() -> T) -> T {
     let ret: T;
 
@@ -143,3 +325,65 @@ pub fn exec_with_irq_masked<T>(f: impl FnOnce() -> T) -> T {
 
     ret
 }
+
+/// Call the handler of `descriptor`, and contain a panic in it.
+///
+/// Returns `false` if the handler panicked, now or earlier, and is faulted. The caller is expected
+/// to mask the IRQ then. An error returned by the handler counts as a panic.
+pub fn call_handler(descriptor: &IRQDescriptor) -> bool {
+    let addr = descriptor.handler_addr();
+
+    let mut r = &FAULTED_HANDLERS;
+    if r.lock(|faulted| faulted.contains(addr)) {
+        return false;
+    }
+
+    // Nested handlers resume at their own call, LIFO.
+    let mut point = RecoveryPoint::new();
+    let outer = RECOVERY_POINT.current().swap(&mut point, Ordering::Relaxed);
+    let returned = unsafe {
+        call_recoverable(&mut point, &mut || {
+            descriptor.handler.handle().expect("Error handling IRQ")
+        })
+    };
+    RECOVERY_POINT.current().store(outer, Ordering::Relaxed);
+
+    if returned {
+        return true;
+    }
+
+    if !r.lock(|faulted| faulted.insert(addr)) {
+        warn!(
+            "Too many faulted IRQ handlers, {} stays registered",
+            descriptor.name
+        );
+    }
+    fault::record_fault("IRQ handler", descriptor.name);
+
+    false
+}
+
+/// Whether `descriptor`'s handler panicked and is no longer called.
+pub fn is_faulted(descriptor: &IRQDescriptor) -> bool {
+    let mut r = &FAULTED_HANDLERS;
+    r.lock(|faulted| faulted.contains(descriptor.handler_addr()))
+}
+
+/// Whether the executing code runs below `call_handler()`, so that a panic can be recovered from.
+pub fn in_recoverable_handler() -> bool {
+    !RECOVERY_POINT.current().load(Ordering::Relaxed).is_null()
+}
+
+/// Resume at the innermost `call_handler()` of the executing core, which then reports the handler
+/// as faulted.
+///
+/// Only for the panic handler. Returns if `in_recoverable_handler()` is `false`.
+pub fn recover_from_handler_panic() {
+    let point = RECOVERY_POINT
+        .current()
+        .swap(ptr::null_mut(), Ordering::Relaxed);
+
+    if !point.is_null() {
+        unsafe { recover(point) }
+    }
+}
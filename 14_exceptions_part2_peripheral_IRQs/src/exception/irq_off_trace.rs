@@ -0,0 +1,124 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Measurement of IRQ-disabled windows.
+//!
+//! With the `irq_off_trace` feature, `local_irq_mask()`, `local_irq_mask_save()` and
+//! `local_irq_restore()` timestamp the transition from unmasked to masked together with their
+//! call site, and the transition back ends the window. The longest window on any core is kept and
+//! can be read with [`max_irq_off_duration()`].
+//!
+//! The call site is the caller of the masking function. `exec_with_irq_masked()` and the `lock()`
+//! of `IRQSafeNullLock` and `Spinlock` are `#[track_caller]`, so windows that they open report the
+//! code that took the lock. Masking by the hardware on exception entry is not measured.
+//!
+//! The hooks run with IRQs in any state and from within locks, so they only use per-core atomics
+//! and must not take a lock or print.
+
+use crate::{
+    bsp,
+    percpu::{CacheLinePadded, PerCpu},
+    time,
+};
+use core::{
+    panic::Location,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU64, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The IRQ-disabled window that is open on a core.
+struct Window {
+    /// Counter value at masking time. `0` if no window is open.
+    start: AtomicU64,
+    location: AtomicPtr<Location<'static>>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static WINDOWS: PerCpu<Window, { bsp::cpu::NUM_CORES }> = PerCpu::new([
+    CacheLinePadded::new(Window::new()),
+    CacheLinePadded::new(Window::new()),
+    CacheLinePadded::new(Window::new()),
+    CacheLinePadded::new(Window::new()),
+]);
+
+/// The longest window in counter ticks, and where it was opened.
+///
+/// Updated without a lock, so if two cores close a new longest window at the same time, the
+/// location may belong to the shorter one of the two.
+static MAX_TICKS: AtomicU64 = AtomicU64::new(0);
+static MAX_LOCATION: AtomicPtr<Location<'static>> = AtomicPtr::new(ptr::null_mut());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Window {
+    const fn new() -> Self {
+        Self {
+            start: AtomicU64::new(0),
+            location: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Open a window on the executing core. Called right after IRQs were masked.
+#[inline(always)]
+pub fn masked(location: &'static Location<'static>) {
+    let window = WINDOWS.current();
+
+    window
+        .location
+        .store(location as *const _ as *mut _, Ordering::Relaxed);
+    window.start.store(time::ticks(), Ordering::Relaxed);
+}
+
+/// Close the window of the executing core. Called right before IRQs are unmasked.
+///
+/// Windows that were not opened through `masked()`, e.g. the one from reset to the first unmask,
+/// are ignored.
+#[inline(always)]
+pub fn unmasked() {
+    let window = WINDOWS.current();
+
+    let start = window.start.swap(0, Ordering::Relaxed);
+    if start == 0 {
+        return;
+    }
+
+    let len = time::ticks().saturating_sub(start);
+    if MAX_TICKS.fetch_max(len, Ordering::Relaxed) < len {
+        MAX_LOCATION.store(window.location.load(Ordering::Relaxed), Ordering::Relaxed);
+    }
+}
+
+/// The longest IRQ-disabled window since boot or the last reset, and where it was opened.
+///
+/// `None` if no window was closed yet.
+pub fn max_irq_off_duration() -> Option<(Duration, &'static Location<'static>)> {
+    let location = MAX_LOCATION.load(Ordering::Relaxed);
+    if location.is_null() {
+        return None;
+    }
+
+    let ticks = MAX_TICKS.load(Ordering::Relaxed);
+    Some((time::ticks_to_duration(ticks), unsafe { &*location }))
+}
+
+/// Forget the longest window, e.g. to measure only what follows.
+pub fn reset_max_irq_off_duration() {
+    MAX_LOCATION.store(ptr::null_mut(), Ordering::Relaxed);
+    MAX_TICKS.store(0, Ordering::Relaxed);
+}
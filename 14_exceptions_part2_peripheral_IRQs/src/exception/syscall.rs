@@ -0,0 +1,98 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! System calls.
+//!
+//! Code running at EL0, see [`super::enter_el0()`], enters the kernel with `svc #<num>`. The
+//! immediate of the instruction selects the handler, `x0`-`x7` carry the arguments, and the
+//! handler's return value is placed in `x0`.
+//!
+//! There are no processes yet. Arguments that point to memory are not checked against the caller's
+//! address space.
+
+use crate::{cpu, info, print, synchronization, synchronization::InitStateLock};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The number of system call slots.
+pub const NUM_SYSCALLS: usize = 16;
+
+/// Returned for failed system calls and for numbers that have no handler.
+pub const SYSCALL_ERROR: u64 = u64::MAX;
+
+/// Well-known system call numbers.
+pub mod nr {
+    /// `write(ptr, len)`: Print `len` bytes of UTF-8 at `ptr`. Returns the number of bytes printed.
+    pub const WRITE: u16 = 0;
+
+    /// `exit(code)`: Terminate the calling program. Does not return.
+    pub const EXIT: u16 = 1;
+}
+
+/// A system call handler. Receives `x0`-`x7` of the caller and returns the value for `x0`.
+pub type SyscallHandler = fn(&[u64; 8]) -> u64;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static SYSCALLS: InitStateLock<[Option<SyscallHandler>; NUM_SYSCALLS]> =
+    InitStateLock::new([None; NUM_SYSCALLS]);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::ReadWriteEx;
+
+/// Register `handler` for system call `num`.
+pub fn register_syscall(num: u16, handler: SyscallHandler) -> Result<(), &'static str> {
+    let mut r = &SYSCALLS;
+    r.write(|syscalls| match syscalls.get_mut(num as usize) {
+        None => Err("System call number out of range"),
+        Some(Some(_)) => Err("System call already registered"),
+        Some(slot) => {
+            *slot = Some(handler);
+            Ok(())
+        }
+    })
+}
+
+/// Call the handler of system call `num`. Returns `SYSCALL_ERROR` if there is none.
+pub fn dispatch(num: u16, args: &[u64; 8]) -> u64 {
+    let mut r = &SYSCALLS;
+    let handler = r.read(|syscalls| syscalls.get(num as usize).copied().flatten());
+
+    match handler {
+        None => SYSCALL_ERROR,
+        Some(handler) => handler(args),
+    }
+}
+
+/// The kernel's `write` system call.
+pub fn write(args: &[u64; 8]) -> u64 {
+    let bytes = unsafe { core::slice::from_raw_parts(args[0] as *const u8, args[1] as usize) };
+
+    match core::str::from_utf8(bytes) {
+        Err(_) => SYSCALL_ERROR,
+        Ok(s) => {
+            print!("{}", s);
+            s.len() as u64
+        }
+    }
+}
+
+/// The kernel's `exit` system call. There is nothing to return to yet, so the core is parked.
+pub fn exit(args: &[u64; 8]) -> u64 {
+    info!("EL0 program exited with code {}", args[0]);
+
+    cpu::wait_forever()
+}
+
+/// Register the kernel's `write` and `exit` system calls.
+pub fn register_default_syscalls() -> Result<(), &'static str> {
+    register_syscall(nr::WRITE, write)?;
+    register_syscall(nr::EXIT, exit)
+}
@@ -0,0 +1,143 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Handing control to a loaded payload.
+//!
+//! A payload, e.g. a second stage kernel received over UART, is loaded into the BSP's payload area
+//! with [`load_payload()`] and started with [`exec_payload()`].
+//!
+//! This is less trivial than jumping to the entry point. Copying the image is a plain data write
+//! that ends up in the D-cache. Instruction fetches however go through the I-cache, which is not
+//! coherent with the D-cache on AArch64. Without maintenance, the core might fetch stale memory
+//! contents, or instructions that the I-cache holds from a previous payload. Therefore, before
+//! branching, the D-cache lines of the loaded region are cleaned to the Point of Unification and
+//! the I-cache is invalidated afterwards.
+//!
+//! If the sender of the payload provides its SHA-256 digest, [`exec_payload()`] runs an integrity
+//! check of the loaded bytes before branching, so that a corrupted transfer is not executed. This
+//! does not authenticate the sender.
+//!
+//! The payload area is never writable and executable at the same time. It is mapped read-write and
+//! execute-never while payloads are loaded, and [`exec_payload()`] remaps it read-only and
+//! executable right before branching.
+
+use crate::{
+    bsp, cpu, exception, loader, memory,
+    memory::mmu::{interface::MMU, AccessPermissions, AttributeFields, MemAttributes},
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Start address of the payload that was loaded last.
+static LOADED_START: AtomicUsize = AtomicUsize::new(0);
+
+/// Size of the payload that was loaded last.
+static LOADED_LEN: AtomicUsize = AtomicUsize::new(0);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Map the payload area for either loading or executing a payload.
+fn map_payload_area(executable: bool) -> Result<(), &'static str> {
+    let attributes = AttributeFields {
+        mem_attributes: MemAttributes::CacheableDRAM,
+        acc_perms: if executable {
+            AccessPermissions::ReadOnly
+        } else {
+            AccessPermissions::ReadWrite
+        },
+        execute_never: !executable,
+    };
+
+    // Neither the kernel's code nor its stack are in the payload area.
+    unsafe { memory::mmu::mmu().set_attributes(bsp::memory::payload_range(), attributes) }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Copy `image` to the start of the BSP's payload area and return the load address.
+pub fn load_payload(image: &[u8]) -> Result<usize, &'static str> {
+    load_payload_at(bsp::memory::payload_range().start, image.len(), |dest| {
+        dest.copy_from_slice(image);
+        Ok(())
+    })
+}
+
+/// Let `fill` write a payload of `len` bytes to `addr` and return the load address.
+///
+/// For payloads that arrive in pieces, e.g. over UART, and do not fit into a buffer at once. If
+/// `fill` fails, its error is returned, and the payload must not be executed.
+pub fn load_payload_at(
+    addr: usize,
+    len: usize,
+    fill: impl FnOnce(&mut [u8]) -> Result<(), &'static str>,
+) -> Result<usize, &'static str> {
+    let area = bsp::memory::payload_range();
+
+    if addr < area.start || addr > area.end || len > area.end - addr {
+        return Err("Payload does not fit into the payload area");
+    }
+
+    // A previous payload might have left the area executable.
+    map_payload_area(false)?;
+
+    LOADED_START.store(addr, Ordering::Relaxed);
+    LOADED_LEN.store(0, Ordering::Relaxed);
+    fill(unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, len) })?;
+    LOADED_LEN.store(len, Ordering::Relaxed);
+
+    Ok(addr)
+}
+
+/// Hand control to a loaded payload's entry point. Does not return.
+///
+/// If `expected_hash` is given, the payload that was loaded last must have this SHA-256 digest.
+/// IRQs are masked on the executing core, the payload area is made coherent for instruction fetches
+/// and remapped read-only and executable, and execution branches to `entry`.
+///
+/// # Panics
+///
+/// - If `entry` is outside of the payload area.
+/// - If the loaded payload does not match `expected_hash`.
+/// - If the payload area can not be remapped.
+///
+/// # Safety
+///
+/// - `entry` must point to valid code for the executing core.
+/// - The payload takes over the core. Nothing of the kernel's state is guaranteed to stay valid.
+pub unsafe fn exec_payload(entry: usize, expected_hash: Option<&[u8; loader::DIGEST_SIZE]>) -> ! {
+    let area = bsp::memory::payload_range();
+    assert!(
+        area.contains(&entry),
+        "Payload entry {:#x} outside of the payload area",
+        entry
+    );
+
+    if let Some(expected) = expected_hash {
+        let loaded = core::slice::from_raw_parts(
+            LOADED_START.load(Ordering::Relaxed) as *const u8,
+            LOADED_LEN.load(Ordering::Relaxed),
+        );
+        assert!(
+            loader::verify(loaded, expected),
+            "Payload does not match the expected hash"
+        );
+    }
+
+    exception::asynchronous::local_irq_mask();
+
+    cpu::cache::clean_dcache_range_to_pou(area);
+    if let Err(msg) = map_payload_area(true) {
+        panic!("Payload area: {}", msg);
+    }
+    cpu::cache::invalidate_icache();
+
+    cpu::branch_to(entry)
+}
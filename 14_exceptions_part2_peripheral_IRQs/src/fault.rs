@@ -0,0 +1,231 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Recoverable faults.
+//!
+//! Not every failure warrants a panic. A non-essential driver that fails to come up, or an optional
+//! mailbox tag that the firmware does not know, leaves the kernel in a degraded but usable state.
+//! Such failures are recorded with [`record_fault()`] and can be reviewed later with [`dump()`], or
+//! with the `faults` command of the panic shell.
+//!
+//! The log is bounded. On overflow, the oldest entry is dropped.
+
+use crate::{info, synchronization, synchronization::IRQSafeNullLock, time, warn};
+use core::{fmt, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const NUM_ENTRIES: usize = 16;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A recorded fault.
+#[derive(Copy, Clone)]
+pub struct Fault {
+    /// Uptime at which the fault was recorded.
+    pub timestamp: Duration,
+
+    /// The subsystem that reported the fault, e.g. "USB".
+    pub subsystem: &'static str,
+
+    /// What went wrong.
+    pub msg: &'static str,
+}
+
+/// A bounded log of faults.
+pub struct FaultLog {
+    entries: [Option<Fault>; NUM_ENTRIES],
+
+    /// Index of the oldest entry.
+    head: usize,
+    len: usize,
+
+    /// Number of entries that were dropped due to overflow.
+    dropped: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Visible to the crate so that `debug::KernelInfo` can point to it.
+pub(crate) static FAULT_LOG: IRQSafeNullLock<FaultLog> = IRQSafeNullLock::new(FaultLog::new());
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl FaultLog {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            entries: [None; NUM_ENTRIES],
+            head: 0,
+            len: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Append a fault, dropping the oldest one if the log is full.
+    pub fn push(&mut self, fault: Fault) {
+        let tail = (self.head + self.len) % NUM_ENTRIES;
+        self.entries[tail] = Some(fault);
+
+        if self.len < NUM_ENTRIES {
+            self.len += 1;
+        } else {
+            self.head = (self.head + 1) % NUM_ENTRIES;
+            self.dropped += 1;
+        }
+    }
+
+    /// Iterate over the faults, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &Fault> + '_ {
+        (0..self.len).filter_map(move |i| self.entries[(self.head + i) % NUM_ENTRIES].as_ref())
+    }
+
+    /// Number of faults in the log.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the log holds no faults.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of faults that were dropped due to overflow.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Format the log, handing each line of the report to `line`.
+    fn report(&self, mut line: impl FnMut(fmt::Arguments)) {
+        if self.is_empty() {
+            line(format_args!("No faults recorded"));
+            return;
+        }
+
+        line(format_args!("Recorded faults:"));
+        for fault in self.iter() {
+            line(format_args!(
+                "      [{:>3}.{:03}] {:<12} {}",
+                fault.timestamp.as_secs(),
+                fault.timestamp.subsec_millis(),
+                fault.subsystem,
+                fault.msg
+            ));
+        }
+
+        if self.dropped() > 0 {
+            line(format_args!(
+                "      ({} older faults dropped)",
+                self.dropped()
+            ));
+        }
+    }
+}
+
+/// Record a recoverable fault.
+///
+/// The kernel continues to run. The fault is printed as a warning and kept in the fault log.
+pub fn record_fault(subsystem: &'static str, msg: &'static str) {
+    use time::interface::TimeManager;
+
+    warn!("Fault in {}: {}", subsystem, msg);
+
+    let fault = Fault {
+        timestamp: time::time_manager().uptime(),
+        subsystem,
+        msg,
+    };
+
+    let mut r = &FAULT_LOG;
+    r.lock(|log| log.push(fault));
+}
+
+/// Print the fault log.
+pub fn dump() {
+    let mut r = &FAULT_LOG;
+    r.lock(|log| log.report(|line| info!("{}", line)));
+}
+
+/// Write the fault log to `out`, e.g. the console of the panic shell.
+pub fn write_to(out: &mut impl fmt::Write) -> fmt::Result {
+    let mut result = Ok(());
+
+    let mut r = &FAULT_LOG;
+    r.lock(|log| log.report(|line| result = result.and_then(|_| writeln!(out, "{}", line))));
+
+    result
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    fn fault(msg: &'static str) -> Fault {
+        Fault {
+            timestamp: Duration::from_secs(0),
+            subsystem: "test",
+            msg,
+        }
+    }
+
+    /// Faults must be listed in the order they were recorded.
+    #[kernel_test]
+    fn faults_are_kept_in_order() {
+        let mut log = FaultLog::new();
+
+        log.push(fault("first"));
+        log.push(fault("second"));
+        log.push(fault("third"));
+
+        let mut iter = log.iter().map(|f| f.msg);
+        assert_eq!(iter.next(), Some("first"));
+        assert_eq!(iter.next(), Some("second"));
+        assert_eq!(iter.next(), Some("third"));
+        assert_eq!(iter.next(), None);
+    }
+
+    /// On overflow, the oldest fault must be dropped.
+    #[kernel_test]
+    fn overflow_drops_oldest_fault() {
+        let mut log = FaultLog::new();
+
+        log.push(fault("oldest"));
+        for _ in 0..NUM_ENTRIES {
+            log.push(fault("newer"));
+        }
+
+        assert_eq!(log.len(), NUM_ENTRIES);
+        assert_eq!(log.dropped(), 1);
+        assert!(log.iter().all(|f| f.msg == "newer"));
+    }
+
+    /// The report must have a header line and one line per fault, or a single line if empty.
+    #[kernel_test]
+    fn report_has_one_line_per_fault() {
+        let mut log = FaultLog::new();
+        let mut lines = 0;
+        log.report(|_| lines += 1);
+        assert_eq!(lines, 1);
+
+        log.push(fault("first"));
+        log.push(fault("second"));
+        let mut lines = 0;
+        log.report(|_| lines += 1);
+        assert_eq!(lines, 1 + 2);
+    }
+}
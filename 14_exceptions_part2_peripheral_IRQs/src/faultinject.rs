@@ -0,0 +1,106 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Fault injection, for testing error paths.
+//!
+//! With the `faultinject` feature, a test can make the next calls of an operation fail with
+//! [`arm()`], so that the callers' error handling runs under QEMU, too. Each [`FaultPoint`] is
+//! checked in one place, which fails the way the real operation would:
+//!
+//! | Point         | Checked in                    | Failure                                  |
+//! |---------------|-------------------------------|------------------------------------------|
+//! | `MailboxSend` | `Mailbox::send()`             | `Err(())`, before the mailbox is touched |
+//! | `DriverInit`  | `driver::init_driver()`       | The driver's `init()` is not run         |
+//! | `Allocation`  | `memory::RecoveringAllocator` | A null pointer, before recovery          |
+//! | `Mapping`     | `MMU::set_attributes()`       | An error, with the tables untouched      |
+//!
+//! Without the feature, the module and all checks are compiled out, so kernel builds carry none of
+//! it.
+//!
+//! The checks run in any context, including IRQ handlers and the allocator, so they only use
+//! atomics.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const NUM_POINTS: usize = 4;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// An operation that can be made to fail.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FaultPoint {
+    MailboxSend,
+    DriverInit,
+    Allocation,
+    Mapping,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// How many more times each point fails.
+static REMAINING: [AtomicU32; NUM_POINTS] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl FaultPoint {
+    fn remaining(self) -> &'static AtomicU32 {
+        let index = match self {
+            Self::MailboxSend => 0,
+            Self::DriverInit => 1,
+            Self::Allocation => 2,
+            Self::Mapping => 3,
+        };
+
+        &REMAINING[index]
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Make the next `times` calls of the operation at `point` fail. `0` disarms it.
+///
+/// Replaces what is left of an earlier `arm()` of the same point.
+pub fn arm(point: FaultPoint, times: u32) {
+    point.remaining().store(times, Ordering::Relaxed);
+}
+
+/// Disarm all points.
+pub fn disarm_all() {
+    for remaining in REMAINING.iter() {
+        remaining.store(0, Ordering::Relaxed);
+    }
+}
+
+/// How many more times the operation at `point` fails.
+pub fn remaining(point: FaultPoint) -> u32 {
+    point.remaining().load(Ordering::Relaxed)
+}
+
+/// Whether the operation at `point` is to fail now. Uses up one of the armed failures if so.
+///
+/// Called by the operations themselves.
+pub fn should_fail(point: FaultPoint) -> bool {
+    point
+        .remaining()
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+        .is_ok()
+}
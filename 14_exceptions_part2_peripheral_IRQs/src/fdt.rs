@@ -0,0 +1,472 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Flattened Device Tree parsing.
+//!
+//! Only what the kernel needs is supported. The layout is described in chapter 5 of the Devicetree
+//! Specification, https://www.devicetree.org/specifications/. All values are big endian.
+
+use crate::memory::Region;
+use core::convert::TryFrom;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const FDT_MAGIC: u32 = 0xD00D_FEED;
+const HEADER_SIZE: usize = 40;
+
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_NOP: u32 = 0x4;
+const FDT_END: u32 = 0x9;
+
+/// Defaults for nodes that do not specify `#address-cells` and `#size-cells`.
+const DEFAULT_ADDRESS_CELLS: usize = 2;
+const DEFAULT_SIZE_CELLS: usize = 1;
+
+/// A structure block token with its payload.
+enum Token<'a> {
+    BeginNode(&'a str),
+    EndNode,
+    Prop(&'a str, &'a [u8]),
+}
+
+/// Iterator over the tokens of the structure block.
+struct Tokens<'a> {
+    fdt: &'a Fdt<'a>,
+    offset: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A validated device tree blob.
+pub struct Fdt<'a> {
+    blob: &'a [u8],
+    structs: &'a [u8],
+    strings: &'a [u8],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn be_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let b = bytes.get(offset..offset + 4)?;
+
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Return the NUL terminated string at `offset`.
+fn c_str(bytes: &[u8], offset: usize) -> Option<&str> {
+    let tail = bytes.get(offset..)?;
+    let len = tail.iter().position(|&b| b == 0)?;
+
+    core::str::from_utf8(&tail[..len]).ok()
+}
+
+/// Read a number of `cells` 32 bit cells. Values that do not fit a `usize` are rejected.
+fn read_cells(bytes: &[u8], cells: usize) -> Option<usize> {
+    let mut value: u64 = 0;
+
+    for i in 0..cells {
+        if value >> 32 != 0 {
+            return None;
+        }
+        value = (value << 32) | u64::from(be_u32(bytes, i * 4)?);
+    }
+
+    usize::try_from(value).ok()
+}
+
+const fn align4(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = Result<Token<'a>, &'static str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let structs = self.fdt.structs;
+
+        loop {
+            let token = match be_u32(structs, self.offset) {
+                None => return Some(Err("FDT: Structure block not terminated")),
+                Some(token) => token,
+            };
+            self.offset += 4;
+
+            match token {
+                FDT_NOP => continue,
+                FDT_END => return None,
+                FDT_END_NODE => return Some(Ok(Token::EndNode)),
+                FDT_BEGIN_NODE => {
+                    let name = match c_str(structs, self.offset) {
+                        None => return Some(Err("FDT: Malformed node name")),
+                        Some(name) => name,
+                    };
+                    self.offset = align4(self.offset + name.len() + 1);
+
+                    return Some(Ok(Token::BeginNode(name)));
+                }
+                FDT_PROP => {
+                    let (len, name_offset) = match (
+                        be_u32(structs, self.offset),
+                        be_u32(structs, self.offset + 4),
+                    ) {
+                        (Some(len), Some(name_offset)) => (len as usize, name_offset as usize),
+                        _ => return Some(Err("FDT: Malformed property")),
+                    };
+                    let data_start = self.offset + 8;
+
+                    let (name, data) = match (
+                        c_str(self.fdt.strings, name_offset),
+                        structs.get(data_start..data_start + len),
+                    ) {
+                        (Some(name), Some(data)) => (name, data),
+                        _ => return Some(Err("FDT: Malformed property")),
+                    };
+                    self.offset = align4(data_start + len);
+
+                    return Some(Ok(Token::Prop(name, data)));
+                }
+                _ => return Some(Err("FDT: Unknown token")),
+            }
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<'a> Fdt<'a> {
+    /// Validate the header of `blob` and create an instance.
+    pub fn new(blob: &'a [u8]) -> Result<Self, &'static str> {
+        let header = |index: usize| be_u32(blob, index * 4).ok_or("FDT: Truncated header");
+
+        if blob.len() < HEADER_SIZE || header(0)? != FDT_MAGIC {
+            return Err("FDT: Bad magic");
+        }
+
+        let total_size = header(1)? as usize;
+        let off_dt_struct = header(2)? as usize;
+        let off_dt_strings = header(3)? as usize;
+        let size_dt_strings = header(8)? as usize;
+        let size_dt_struct = header(9)? as usize;
+
+        let blob = blob.get(..total_size).ok_or("FDT: Truncated blob")?;
+        let structs = blob
+            .get(off_dt_struct..off_dt_struct + size_dt_struct)
+            .ok_or("FDT: Structure block out of bounds")?;
+        let strings = blob
+            .get(off_dt_strings..off_dt_strings + size_dt_strings)
+            .ok_or("FDT: Strings block out of bounds")?;
+
+        Ok(Self {
+            blob,
+            structs,
+            strings,
+        })
+    }
+
+    /// Create an instance from a blob in memory.
+    ///
+    /// # Safety
+    ///
+    /// - `addr` must point to readable memory that holds at least a header's worth of bytes, and
+    ///   stays unmodified for as long as the instance is used.
+    pub unsafe fn from_addr(addr: usize) -> Result<Fdt<'static>, &'static str> {
+        let header = core::slice::from_raw_parts(addr as *const u8, HEADER_SIZE);
+        if be_u32(header, 0) != Some(FDT_MAGIC) {
+            return Err("FDT: Bad magic");
+        }
+
+        let total_size = be_u32(header, 4).unwrap() as usize;
+
+        Fdt::new(core::slice::from_raw_parts(addr as *const u8, total_size))
+    }
+
+    /// The address range of the blob itself.
+    pub fn blob_region(&self) -> Region {
+        let range = self.blob.as_ptr_range();
+
+        Region::new(range.start as usize, range.end as usize)
+    }
+
+    fn tokens(&self) -> Tokens {
+        Tokens {
+            fdt: self,
+            offset: 0,
+        }
+    }
+
+    /// The value of `property` of the node at `path`, e.g. `/psci`, if both exist.
+    ///
+    /// Only nodes directly below the root are supported. A unit address in the tree's node name,
+    /// e.g. `@7e00b880`, need not be part of `path`.
+    pub fn property(&self, path: &str, property: &str) -> Result<Option<&[u8]>, &'static str> {
+        let wanted = path.trim_start_matches('/');
+        let mut depth = 0;
+        let mut in_node = false;
+
+        for token in self.tokens() {
+            match token? {
+                Token::BeginNode(name) => {
+                    depth += 1;
+
+                    if depth == 2 {
+                        in_node = name == wanted || name.split('@').next() == Some(wanted);
+                    }
+                }
+                Token::Prop(name, data) => {
+                    if in_node && depth == 2 && name == property {
+                        return Ok(Some(data));
+                    }
+                }
+                Token::EndNode => depth -= 1,
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Call `f` for each statically placed region below the `/reserved-memory` node.
+    ///
+    /// Child nodes with `status = "disabled"` and dynamically placed ones, which only have a `size`
+    /// property, are skipped.
+    pub fn for_each_reserved_region(
+        &self,
+        mut f: impl FnMut(Region) -> Result<(), &'static str>,
+    ) -> Result<(), &'static str> {
+        let mut depth = 0;
+        let mut in_reserved_memory = false;
+        let mut address_cells = DEFAULT_ADDRESS_CELLS;
+        let mut size_cells = DEFAULT_SIZE_CELLS;
+
+        // The `reg` of the current child node. Reported on its end, once `status` is known, too.
+        let mut reg: Option<&[u8]> = None;
+        let mut disabled = false;
+
+        for token in self.tokens() {
+            match token? {
+                Token::BeginNode(name) => {
+                    depth += 1;
+
+                    if depth == 2 && name == "reserved-memory" {
+                        in_reserved_memory = true;
+                        address_cells = DEFAULT_ADDRESS_CELLS;
+                        size_cells = DEFAULT_SIZE_CELLS;
+                    }
+
+                    reg = None;
+                    disabled = false;
+                }
+                Token::Prop(name, data) => match (in_reserved_memory, depth, name) {
+                    (true, 2, "#address-cells") => {
+                        address_cells = read_cells(data, 1).ok_or("FDT: Bad #address-cells")?;
+                    }
+                    (true, 2, "#size-cells") => {
+                        size_cells = read_cells(data, 1).ok_or("FDT: Bad #size-cells")?;
+                    }
+                    (true, 3, "reg") => reg = Some(data),
+                    (true, 3, "status") => disabled = data.starts_with(b"disabled"),
+                    _ => (),
+                },
+                Token::EndNode => {
+                    if in_reserved_memory && depth == 3 && !disabled {
+                        if let Some(reg) = reg.take() {
+                            let entry_size = (address_cells + size_cells) * 4;
+                            if entry_size == 0 || reg.len() % entry_size != 0 {
+                                return Err("FDT: Malformed reg property");
+                            }
+
+                            for entry in reg.chunks(entry_size) {
+                                let start = read_cells(entry, address_cells)
+                                    .ok_or("FDT: Reserved region out of range")?;
+                                let size = read_cells(&entry[address_cells * 4..], size_cells)
+                                    .ok_or("FDT: Reserved region out of range")?;
+                                let end = start
+                                    .checked_add(size)
+                                    .ok_or("FDT: Reserved region out of range")?;
+
+                                f(Region::new(start, end))?;
+                            }
+                        }
+                    }
+
+                    if in_reserved_memory && depth == 2 {
+                        in_reserved_memory = false;
+                    }
+
+                    depth -= 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{unreserved, RegionList};
+    use test_macros::kernel_test;
+
+    const BLOB_SIZE: usize = 512;
+    const STRINGS: &[u8] = b"#address-cells\0#size-cells\0reg\0status\0";
+    const STR_ADDRESS_CELLS: u32 = 0;
+    const STR_SIZE_CELLS: u32 = 15;
+    const STR_REG: u32 = 27;
+    const STR_STATUS: u32 = 31;
+
+    /// Minimal FDT writer.
+    struct Writer {
+        blob: [u8; BLOB_SIZE],
+        len: usize,
+    }
+
+    impl Writer {
+        fn u32(&mut self, value: u32) {
+            self.blob[self.len..self.len + 4].copy_from_slice(&value.to_be_bytes());
+            self.len += 4;
+        }
+
+        fn bytes(&mut self, bytes: &[u8]) {
+            self.blob[self.len..self.len + bytes.len()].copy_from_slice(bytes);
+            self.len = align4(self.len + bytes.len());
+        }
+
+        fn begin_node(&mut self, name: &[u8]) {
+            self.u32(FDT_BEGIN_NODE);
+            self.bytes(name);
+        }
+
+        fn prop(&mut self, name_offset: u32, data: &[u8]) {
+            self.u32(FDT_PROP);
+            self.u32(data.len() as u32);
+            self.u32(name_offset);
+            self.bytes(data);
+        }
+
+        fn prop_cells(&mut self, name_offset: u32, cells: &[u32]) {
+            self.u32(FDT_PROP);
+            self.u32(cells.len() as u32 * 4);
+            self.u32(name_offset);
+            for cell in cells {
+                self.u32(*cell);
+            }
+        }
+    }
+
+    /// Build a tree with two enabled and one disabled reserved region.
+    fn build_blob(w: &mut Writer) {
+        // An empty memory reservation block, i.e. only the terminating entry.
+        let off_mem_rsvmap = HEADER_SIZE;
+        w.len = off_mem_rsvmap + 16;
+
+        let off_dt_struct = w.len;
+        w.begin_node(b"\0");
+        w.prop_cells(STR_ADDRESS_CELLS, &[1]);
+        w.prop_cells(STR_SIZE_CELLS, &[1]);
+
+        w.begin_node(b"reserved-memory\0");
+        w.prop_cells(STR_ADDRESS_CELLS, &[2]);
+        w.prop_cells(STR_SIZE_CELLS, &[1]);
+
+        w.begin_node(b"vc-pool@300000\0");
+        w.prop_cells(STR_REG, &[0, 0x30_0000, 0x1_0000]);
+        w.u32(FDT_END_NODE);
+
+        w.begin_node(b"fw-log@400000\0");
+        w.prop_cells(STR_REG, &[0, 0x40_0000, 0x8000]);
+        w.u32(FDT_END_NODE);
+
+        w.begin_node(b"unused@500000\0");
+        w.prop_cells(STR_REG, &[0, 0x50_0000, 0x1000]);
+        w.prop(STR_STATUS, b"disabled\0");
+        w.u32(FDT_END_NODE);
+
+        w.u32(FDT_END_NODE);
+        w.u32(FDT_END_NODE);
+        w.u32(FDT_END);
+        let size_dt_struct = w.len - off_dt_struct;
+
+        let off_dt_strings = w.len;
+        w.bytes(STRINGS);
+
+        let total_size = w.len;
+        let header = [
+            FDT_MAGIC,
+            total_size as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            17,
+            16,
+            0,
+            STRINGS.len() as u32,
+            size_dt_struct as u32,
+        ];
+        w.len = 0;
+        for word in header.iter() {
+            w.u32(*word);
+        }
+        w.len = total_size;
+    }
+
+    /// The reserved regions of an FDT must be excluded from allocatable memory.
+    #[kernel_test]
+    fn reserved_regions_are_excluded_from_allocatable_memory() {
+        let mut w = Writer {
+            blob: [0; BLOB_SIZE],
+            len: 0,
+        };
+        build_blob(&mut w);
+
+        let fdt = Fdt::new(&w.blob[..w.len]).unwrap();
+        let mut regions: RegionList<4> = RegionList::new();
+        fdt.for_each_reserved_region(|region| regions.insert(region))
+            .unwrap();
+
+        // The disabled region is skipped.
+        let expected = [
+            Region::new(0x30_0000, 0x31_0000),
+            Region::new(0x40_0000, 0x40_8000),
+        ];
+        assert!(regions.as_slice() == &expected[..]);
+
+        let mut allocatable = unreserved(0x20_0000..0x60_0000, regions.as_slice());
+        assert_eq!(allocatable.next(), Some(0x20_0000..0x30_0000));
+        assert_eq!(allocatable.next(), Some(0x31_0000..0x40_0000));
+        assert_eq!(allocatable.next(), Some(0x40_8000..0x60_0000));
+        assert_eq!(allocatable.next(), None);
+    }
+
+    /// Properties must be found by the node's name, with or without its unit address.
+    #[kernel_test]
+    fn properties_are_found_by_node_path() {
+        let mut w = Writer {
+            blob: [0; BLOB_SIZE],
+            len: 0,
+        };
+        build_blob(&mut w);
+
+        let fdt = Fdt::new(&w.blob[..w.len]).unwrap();
+        assert_eq!(
+            fdt.property("/reserved-memory", "#size-cells"),
+            Ok(Some(&[0u8, 0, 0, 1][..]))
+        );
+        assert_eq!(fdt.property("/reserved-memory", "reg"), Ok(None));
+        assert_eq!(fdt.property("/psci", "method"), Ok(None));
+    }
+}
@@ -0,0 +1,209 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Framebuffer drawing.
+
+use crate::{
+    bsp, dma,
+    dma::interface::DmaEngine,
+    gfx::{Color, PixelFormat},
+};
+use core::ops::Range;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// A drawing surface with 32 bits per pixel, e.g. a framebuffer.
+///
+/// Rows are `pitch` bytes apart, which can be more than `width` pixels.
+pub struct Surface {
+    base_addr: usize,
+    width: usize,
+    height: usize,
+    pitch: usize,
+    format: PixelFormat,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Surface {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - `base_addr` must be 4 byte aligned and point to `height * pitch` bytes of memory that only
+    ///   this instance accesses.
+    /// - `format` must have 32 bits per pixel.
+    pub const unsafe fn new(
+        base_addr: usize,
+        width: usize,
+        height: usize,
+        pitch: usize,
+        format: PixelFormat,
+    ) -> Self {
+        Self {
+            base_addr,
+            width,
+            height,
+            pitch,
+            format,
+        }
+    }
+
+    /// The width in pixels.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// The height in pixels.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The layout of the pixels, which `put_pixel()` packs colors for.
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.format
+    }
+
+    fn pixel_ptr(&self, x: usize, y: usize) -> *mut u32 {
+        assert!(x < self.width && y < self.height);
+
+        (self.base_addr + y * self.pitch + x * 4) as *mut u32
+    }
+
+    /// Read a pixel.
+    pub fn pixel(&self, x: usize, y: usize) -> u32 {
+        unsafe { self.pixel_ptr(x, y).read_volatile() }
+    }
+
+    /// Write a raw pixel value, which must already be packed for `pixel_format()`.
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        unsafe { self.pixel_ptr(x, y).write_volatile(color) }
+    }
+
+    /// Write a pixel, packing `color` for `pixel_format()`.
+    pub fn put_pixel(&mut self, x: usize, y: usize, color: Color) {
+        self.set_pixel(x, y, color.to_raw(self.format));
+    }
+
+    /// Fill whole rows with `color`.
+    pub fn fill_rows(&mut self, rows: Range<usize>, color: u32) {
+        for y in rows {
+            for x in 0..self.width {
+                self.set_pixel(x, y, color);
+            }
+        }
+    }
+
+    /// Move the contents up by `lines` rows and fill the exposed rows at the bottom with
+    /// `bg_color`.
+    ///
+    /// The bulk move is done by the DMA engine if it is available, and by the CPU otherwise. If
+    /// the engine fails partway, the rows are half moved and the source partly overwritten, so
+    /// neither the old nor the new contents can be rebuilt. The whole surface is cleared to
+    /// `bg_color` then, which is at least a known state.
+    pub fn scroll_up(&mut self, lines: usize, bg_color: u32) {
+        if lines >= self.height {
+            self.fill_rows(0..self.height, bg_color);
+            return;
+        }
+
+        let dst = self.base_addr;
+        let src = self.base_addr + lines * self.pitch;
+        let len = (self.height - lines) * self.pitch;
+
+        match unsafe { bsp::dma::dma_engine().memmove(dst, src, len) } {
+            Ok(()) => (),
+            Err(dma::Error::Unavailable) => unsafe {
+                core::ptr::copy(src as *const u8, dst as *mut u8, len)
+            },
+            Err(dma::Error::Failed) => {
+                self.fill_rows(0..self.height, bg_color);
+                return;
+            }
+        }
+
+        self.fill_rows((self.height - lines)..self.height, bg_color);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::interface::DeviceDriver;
+    use test_macros::kernel_test;
+
+    const WIDTH: usize = 4;
+    const HEIGHT: usize = 5;
+    const PITCH_PIXELS: usize = 6;
+
+    const FORMAT: PixelFormat = PixelFormat {
+        order: crate::gfx::PixelOrder::Rgb,
+        bits_per_pixel: 32,
+    };
+
+    static mut PIXELS: [u32; PITCH_PIXELS * HEIGHT] = [0; PITCH_PIXELS * HEIGHT];
+
+    /// Scrolling must shift the rows up, with the DMA engine doing the overlapping move, and fill
+    /// the bottom.
+    #[kernel_test]
+    fn scroll_up_shifts_pixels_and_fills_bottom() {
+        bsp::DMA.init().unwrap();
+
+        let mut surface = unsafe {
+            Surface::new(
+                PIXELS.as_ptr() as usize,
+                WIDTH,
+                HEIGHT,
+                PITCH_PIXELS * 4,
+                FORMAT,
+            )
+        };
+
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                surface.set_pixel(x, y, (y * 16 + x) as u32);
+            }
+        }
+
+        surface.scroll_up(2, 0xFF);
+
+        for y in 0..(HEIGHT - 2) {
+            for x in 0..WIDTH {
+                assert_eq!(surface.pixel(x, y), ((y + 2) * 16 + x) as u32);
+            }
+        }
+
+        for y in (HEIGHT - 2)..HEIGHT {
+            for x in 0..WIDTH {
+                assert_eq!(surface.pixel(x, y), 0xFF);
+            }
+        }
+    }
+
+    /// `put_pixel()` must pack the color for the surface's pixel format.
+    #[kernel_test]
+    fn put_pixel_packs_for_pixel_format() {
+        let mut surface = unsafe {
+            Surface::new(
+                PIXELS.as_ptr() as usize,
+                WIDTH,
+                HEIGHT,
+                PITCH_PIXELS * 4,
+                FORMAT,
+            )
+        };
+        let color = Color::from_argb(0xFF12_3456);
+
+        surface.put_pixel(1, 1, color);
+        assert_eq!(surface.pixel(1, 1), color.to_raw(FORMAT));
+    }
+}
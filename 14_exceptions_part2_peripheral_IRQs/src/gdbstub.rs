@@ -0,0 +1,597 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A minimal GDB stub that speaks the remote serial protocol over the console.
+//!
+//! This allows source-level debugging without JTAG. After [`init()`], a `BRK #0`, e.g. from
+//! [`breakpoint()`] or a breakpoint that GDB set, and a completed single step hand control to the
+//! stub. It then serves GDB's requests until GDB continues or steps. Connect with
+//!
+//! ```console
+//! $ gdb-multiarch kernel -ex 'target remote /dev/ttyUSB0'
+//! ```
+//!
+//! # Supported packets
+//!
+//! - `?`, `g`, `G`, `p` and `P` for the stop reason and the registers of the exception context.
+//! - `m` and `M` for memory. Only normal memory that is mapped in the kernel's layout is accessed,
+//!   so neither unmapped addresses nor MMIO side effects can take the kernel down.
+//! - `Z0` and `z0` for software breakpoints. The original instruction is saved, and written back on
+//!   removal. Read-only code is remapped writable for the duration of the patch.
+//! - `c`, `s`, `D` and `k` to continue, single-step with `debug::single_step()`, detach and kill.
+//!   The last two remove all breakpoints, and continue.
+//!
+//! Any other packet gets the empty reply, which tells GDB that it is not supported.
+//!
+//! The stub polls the console with IRQs masked, as it runs in exception context. It does not know
+//! about other cores, which keep running.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/gdbstub.rs"]
+mod arch_gdbstub;
+pub use arch_gdbstub::{breakpoint, NUM_REGISTERS};
+
+use crate::{
+    bsp, console, cpu, debug, exception,
+    exception::ExceptionContext,
+    memory,
+    memory::mmu::{interface::MMU, AccessPermissions, AttributeFields},
+    synchronization::{interface::Mutex, IRQSafeNullLock},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The largest packet, without framing. Advertised to GDB with `qSupported`.
+const PACKET_SIZE: usize = 1024;
+
+/// How many software breakpoints can be set at a time.
+const MAX_BREAKPOINTS: usize = 16;
+
+/// How often a reply is sent again if GDB does not acknowledge it.
+const MAX_ATTEMPTS: usize = 3;
+
+/// `SIGTRAP`, which GDB expects for breakpoints and steps.
+const SIGTRAP: u8 = 5;
+
+/// A software breakpoint, and the instruction that it replaced.
+#[derive(Copy, Clone)]
+struct Breakpoint {
+    addr: usize,
+    saved: u32,
+}
+
+/// What to do when the stub returns.
+#[derive(Copy, Clone, PartialEq)]
+enum Resume {
+    Continue,
+    Step,
+}
+
+/// A packet that is being built.
+struct Reply {
+    buf: [u8; PACKET_SIZE],
+    len: usize,
+}
+
+/// The packet transport on the console.
+struct Connection<'a, C: ?Sized> {
+    console: &'a C,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Why the debuggee stopped.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static BREAKPOINTS: IRQSafeNullLock<[Option<Breakpoint>; MAX_BREAKPOINTS]> =
+    IRQSafeNullLock::new([None; MAX_BREAKPOINTS]);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+fn hex_digit(nibble: u8) -> u8 {
+    b"0123456789abcdef"[(nibble & 0xF) as usize]
+}
+
+fn from_hex_digit(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Parse a big endian hex number, as used for addresses and lengths.
+fn parse_hex(s: &[u8]) -> Option<u64> {
+    if s.is_empty() || s.len() > 16 {
+        return None;
+    }
+
+    s.iter()
+        .try_fold(0u64, |acc, c| Some((acc << 4) | from_hex_digit(*c)? as u64))
+}
+
+/// Parse `size` bytes of hex encoded target memory, i.e. a little endian value.
+fn parse_le_hex(s: &[u8], size: usize) -> Option<u64> {
+    if s.len() != 2 * size {
+        return None;
+    }
+
+    s.chunks(2).rev().try_fold(0u64, |acc, byte| {
+        Some((acc << 8) | (from_hex_digit(byte[0])? << 4 | from_hex_digit(byte[1])?) as u64)
+    })
+}
+
+/// Split `s` at the first `sep`.
+fn split(s: &[u8], sep: u8) -> Option<(&[u8], &[u8])> {
+    let pos = s.iter().position(|c| *c == sep)?;
+
+    Some((&s[..pos], &s[(pos + 1)..]))
+}
+
+/// Parse `addr,len`.
+fn parse_addr_len(s: &[u8]) -> Option<(usize, usize)> {
+    let (addr, len) = split(s, b',')?;
+
+    Some((parse_hex(addr)? as usize, parse_hex(len)? as usize))
+}
+
+impl Reply {
+    fn new() -> Self {
+        Self {
+            buf: [0; PACKET_SIZE],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, b: u8) {
+        if self.len < self.buf.len() {
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+    }
+
+    fn push_str(&mut self, s: &str) {
+        s.bytes().for_each(|b| self.push(b));
+    }
+
+    fn push_hex_byte(&mut self, b: u8) {
+        self.push(hex_digit(b >> 4));
+        self.push(hex_digit(b));
+    }
+
+    /// Push the low `size` bytes of `value` in target byte order.
+    fn push_le_hex(&mut self, value: u64, size: usize) {
+        value.to_le_bytes()[..size]
+            .iter()
+            .for_each(|b| self.push_hex_byte(*b));
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<'a, C> Connection<'a, C>
+where
+    C: console::interface::Write + console::interface::Read + ?Sized,
+{
+    fn read_byte(&self) -> u8 {
+        loop {
+            if let Some(b) = self.console.read_byte_nb() {
+                return b;
+            }
+
+            cpu::nop();
+        }
+    }
+
+    /// Receive a packet into `buf`, acknowledge it, and return its length.
+    ///
+    /// Packets with a bad checksum are rejected with `-`, and GDB sends them again.
+    fn recv_packet(&self, buf: &mut [u8]) -> usize {
+        loop {
+            while self.read_byte() != b'$' {}
+
+            let mut len = 0;
+            let mut sum = 0u8;
+            let mut overflow = false;
+            loop {
+                match self.read_byte() {
+                    b'#' => break,
+                    b => {
+                        sum = sum.wrapping_add(b);
+                        if len < buf.len() {
+                            buf[len] = b;
+                            len += 1;
+                        } else {
+                            overflow = true;
+                        }
+                    }
+                }
+            }
+
+            let checksum = from_hex_digit(self.read_byte())
+                .and_then(|hi| Some(hi << 4 | from_hex_digit(self.read_byte())?));
+
+            if !overflow && checksum == Some(sum) {
+                self.console.write_byte(b'+');
+                return len;
+            }
+
+            self.console.write_byte(b'-');
+        }
+    }
+
+    /// Send a packet, and wait for GDB to acknowledge it.
+    fn send_packet(&self, data: &[u8]) {
+        let sum = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+        for _ in 0..MAX_ATTEMPTS {
+            self.console.write_byte(b'$');
+            self.console.write_bytes(data);
+            self.console
+                .write_bytes(&[b'#', hex_digit(sum >> 4), hex_digit(sum)]);
+
+            if self.read_byte() == b'+' {
+                return;
+            }
+        }
+    }
+}
+
+/// Whether `len` bytes at `addr` may be accessed, and written if `write`.
+fn accessible(addr: usize, len: usize, write: bool) -> bool {
+    bsp::memory::mmu::virt_mem_layout()
+        .check_access(addr, len, write)
+        .is_ok()
+}
+
+/// Replace the instruction at `addr` with `instruction`, and return the replaced one.
+///
+/// # Safety
+///
+/// - `addr` must not be executed by another core meanwhile.
+unsafe fn patch_instruction(addr: usize, instruction: u32) -> Result<u32, &'static str> {
+    if addr % 4 != 0 || !accessible(addr, 4, false) {
+        return Err("Not a patchable instruction address");
+    }
+
+    let (_, attributes) = bsp::memory::mmu::virt_mem_layout().virt_addr_properties(addr)?;
+    let mmu = memory::mmu::mmu();
+    let page_start = addr & !(memory::mmu::GRANULE_SIZE - 1);
+    let page = page_start..(page_start + memory::mmu::GRANULE_SIZE);
+
+    // Code is read-only. The page stays executable, as the stub itself may be in it.
+    let remap = mmu.is_enabled() && matches!(attributes.acc_perms, AccessPermissions::ReadOnly);
+    if remap {
+        mmu.set_attributes(
+            page.clone(),
+            AttributeFields {
+                acc_perms: AccessPermissions::ReadWrite,
+                ..attributes
+            },
+        )?;
+    }
+
+    let ptr = addr as *mut u32;
+    let saved = ptr.read_volatile();
+    ptr.write_volatile(instruction);
+
+    if remap {
+        mmu.set_attributes(page, attributes)?;
+    }
+
+    cpu::cache::clean_dcache_range_to_pou(addr..(addr + 4));
+    cpu::cache::invalidate_icache();
+
+    Ok(saved)
+}
+
+fn insert_breakpoint(addr: usize) -> Result<(), &'static str> {
+    let mut r = &BREAKPOINTS;
+    r.lock(|breakpoints| {
+        if breakpoints.iter().flatten().any(|b| b.addr == addr) {
+            return Ok(());
+        }
+
+        let slot = breakpoints
+            .iter_mut()
+            .find(|b| b.is_none())
+            .ok_or("No breakpoint slot left")?;
+
+        let saved = unsafe { patch_instruction(addr, arch_gdbstub::BREAKPOINT_INSTRUCTION)? };
+        *slot = Some(Breakpoint { addr, saved });
+
+        Ok(())
+    })
+}
+
+fn remove_breakpoint(addr: usize) -> Result<(), &'static str> {
+    let mut r = &BREAKPOINTS;
+    r.lock(|breakpoints| {
+        let slot = breakpoints
+            .iter_mut()
+            .find(|b| b.map_or(false, |b| b.addr == addr))
+            .ok_or("No breakpoint at address")?;
+
+        unsafe { patch_instruction(addr, slot.unwrap().saved)? };
+        *slot = None;
+
+        Ok(())
+    })
+}
+
+fn remove_all_breakpoints() {
+    let mut r = &BREAKPOINTS;
+    let addrs = r.lock(|breakpoints| *breakpoints);
+
+    for b in addrs.iter().flatten() {
+        let _ = remove_breakpoint(b.addr);
+    }
+}
+
+fn is_breakpoint(addr: usize) -> bool {
+    let mut r = &BREAKPOINTS;
+    r.lock(|breakpoints| breakpoints.iter().flatten().any(|b| b.addr == addr))
+}
+
+fn read_registers(e: &ExceptionContext, reply: &mut Reply) {
+    for num in 0..NUM_REGISTERS {
+        let (value, size) = arch_gdbstub::register(e, num).unwrap();
+        reply.push_le_hex(value, size);
+    }
+}
+
+fn write_registers(e: &mut ExceptionContext, mut data: &[u8]) -> Result<(), &'static str> {
+    for num in 0..NUM_REGISTERS {
+        let (_, size) = arch_gdbstub::register(e, num).unwrap();
+        if data.len() < 2 * size {
+            return Err("Short register data");
+        }
+
+        let value = parse_le_hex(&data[..(2 * size)], size).ok_or("Malformed register data")?;
+        // GDB writes back all registers, including those that can not change.
+        let _ = arch_gdbstub::set_register(e, num, value);
+        data = &data[(2 * size)..];
+    }
+
+    Ok(())
+}
+
+fn read_memory(addr: usize, len: usize, reply: &mut Reply) -> Result<(), &'static str> {
+    if len > PACKET_SIZE / 2 || !accessible(addr, len, false) {
+        return Err("Memory not readable");
+    }
+
+    for i in 0..len {
+        reply.push_hex_byte(unsafe { ((addr + i) as *const u8).read_volatile() });
+    }
+
+    Ok(())
+}
+
+fn write_memory(addr: usize, len: usize, data: &[u8]) -> Result<(), &'static str> {
+    if data.len() != 2 * len || !accessible(addr, len, true) {
+        return Err("Memory not writable");
+    }
+
+    for (i, byte) in data.chunks(2).enumerate() {
+        let value = parse_le_hex(byte, 1).ok_or("Malformed memory data")? as u8;
+        unsafe { ((addr + i) as *mut u8).write_volatile(value) };
+    }
+
+    Ok(())
+}
+
+/// Handle one packet. Returns how to resume, if the packet resumes the debuggee.
+fn handle_packet(e: &mut ExceptionContext, packet: &[u8], reply: &mut Reply) -> Option<Resume> {
+    let ok_or_error = |reply: &mut Reply, result: Result<(), &'static str>| match result {
+        Ok(()) => reply.push_str("OK"),
+        Err(_) => reply.push_str("E01"),
+    };
+
+    let (cmd, args) = match packet.split_first() {
+        None => return None,
+        Some((cmd, args)) => (*cmd, args),
+    };
+
+    match cmd {
+        b'?' => {
+            reply.push(b'S');
+            reply.push_hex_byte(SIGTRAP);
+        }
+        b'g' => read_registers(e, reply),
+        b'G' => ok_or_error(reply, write_registers(e, args)),
+        b'p' => match parse_hex(args).and_then(|num| arch_gdbstub::register(e, num as usize)) {
+            Some((value, size)) => reply.push_le_hex(value, size),
+            None => reply.push_str("E01"),
+        },
+        b'P' => {
+            let result = split(args, b'=')
+                .and_then(|(num, value)| {
+                    let num = parse_hex(num)? as usize;
+                    let (_, size) = arch_gdbstub::register(e, num)?;
+                    Some((num, parse_le_hex(value, size)?))
+                })
+                .ok_or("Malformed register write")
+                .and_then(|(num, value)| arch_gdbstub::set_register(e, num, value));
+            ok_or_error(reply, result);
+        }
+        b'm' => {
+            match parse_addr_len(args)
+                .ok_or("Malformed memory read")
+                .and_then(|(addr, len)| read_memory(addr, len, reply))
+            {
+                Ok(()) => (),
+                Err(_) => {
+                    reply.len = 0;
+                    reply.push_str("E14");
+                }
+            }
+        }
+        b'M' => match split(args, b':').and_then(|(range, data)| {
+            let (addr, len) = parse_addr_len(range)?;
+            Some(write_memory(addr, len, data))
+        }) {
+            Some(Ok(())) => reply.push_str("OK"),
+            _ => reply.push_str("E14"),
+        },
+        b'Z' | b'z' => {
+            // Only software breakpoints, type `0`, are supported.
+            let breakpoint = Some(args)
+                .filter(|args| args.starts_with(b"0,"))
+                .and_then(|args| parse_addr_len(&args[2..]));
+
+            if let Some((addr, _kind)) = breakpoint {
+                let result = if cmd == b'Z' {
+                    insert_breakpoint(addr)
+                } else {
+                    remove_breakpoint(addr)
+                };
+                ok_or_error(reply, result);
+            }
+        }
+        b'c' | b's' => {
+            if let Some(addr) = parse_hex(args) {
+                e.set_elr(addr);
+            }
+
+            return Some(if cmd == b'c' {
+                Resume::Continue
+            } else {
+                Resume::Step
+            });
+        }
+        b'D' | b'k' => {
+            remove_all_breakpoints();
+            if cmd == b'D' {
+                reply.push_str("OK");
+            }
+
+            return Some(Resume::Continue);
+        }
+        b'H' => reply.push_str("OK"),
+        b'q' if args.starts_with(b"Supported") => reply.push_str("PacketSize=400"),
+        b'q' if args == b"Attached" => reply.push(b'1'),
+        _ => (),
+    }
+
+    None
+}
+
+/// Serve GDB until it resumes the debuggee.
+fn serve<C>(connection: &Connection<C>, e: &mut ExceptionContext) -> Resume
+where
+    C: console::interface::Write + console::interface::Read + ?Sized,
+{
+    let mut packet = [0u8; PACKET_SIZE];
+
+    // Tell GDB about the stop. If it is not attached yet, it asks with `?` later.
+    let mut reply = Reply::new();
+    reply.push(b'S');
+    reply.push_hex_byte(SIGTRAP);
+    connection.send_packet(reply.as_bytes());
+
+    loop {
+        let len = connection.recv_packet(&mut packet);
+
+        let mut reply = Reply::new();
+        let resume = handle_packet(e, &packet[..len], &mut reply);
+
+        // `k` expects no reply.
+        if resume.is_none() || reply.len > 0 {
+            connection.send_packet(reply.as_bytes());
+        }
+        if let Some(resume) = resume {
+            return resume;
+        }
+    }
+}
+
+/// The `SyncExceptionHandler` that hands breakpoints and steps to the stub.
+fn handle_exception(e: &mut ExceptionContext) -> bool {
+    if arch_gdbstub::stop_reason().is_none() {
+        return false;
+    }
+
+    debug::end_single_step(e);
+
+    // A compiled-in `BRK`, not one of GDB's. Continuing must not execute it again.
+    if arch_gdbstub::stop_reason() == Some(StopReason::Breakpoint)
+        && !is_breakpoint(e.elr() as usize)
+    {
+        e.skip_instruction();
+    }
+
+    let connection = Connection {
+        console: bsp::console::console(),
+    };
+    if serve(&connection, e) == Resume::Step {
+        debug::single_step(e);
+    }
+
+    true
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Hand debug exceptions to the stub.
+///
+/// # Safety
+///
+/// - Must be called during kernel init, after `exception::handling_init()`.
+/// - The console must not be used for anything else while GDB is attached.
+pub unsafe fn init() -> Result<(), &'static str> {
+    exception::register_sync_handler(handle_exception)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Register values must be encoded and parsed in target byte order.
+    #[kernel_test]
+    fn register_hex_is_little_endian() {
+        let mut reply = Reply::new();
+        reply.push_le_hex(0x1234_5678_9abc_def0, 8);
+
+        assert_eq!(reply.as_bytes(), b"f0debc9a78563412");
+        assert_eq!(
+            parse_le_hex(reply.as_bytes(), 8),
+            Some(0x1234_5678_9abc_def0)
+        );
+        assert_eq!(parse_le_hex(b"f0de", 4), None);
+    }
+
+    /// Addresses and lengths must be parsed as big endian hex.
+    #[kernel_test]
+    fn addr_len_is_parsed() {
+        assert_eq!(parse_addr_len(b"80000,4"), Some((0x80000, 4)));
+        assert_eq!(parse_addr_len(b"80000"), None);
+        assert_eq!(parse_addr_len(b"8000g,4"), None);
+    }
+}
@@ -0,0 +1,294 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Graphics.
+//!
+//! # Pixel formats
+//!
+//! The firmware decides whether the red or the blue component of a pixel comes first. Drawing code
+//! uses [`Color`], which [`Surface::put_pixel()`] packs for the surface's [`PixelFormat`], instead
+//! of raw `u32` values that only look right with one of the two orders.
+//!
+//! # Double buffering
+//!
+//! [`DoubleBuffer`] allocates a virtual framebuffer that is twice as high as the display and splits
+//! it into two halves. One half is displayed while the other one, the back buffer, is drawn to.
+//! [`DoubleBuffer::swap()`] then moves the displayed window to the back buffer, so that a frame is
+//! never shown half drawn.
+//!
+//! [`init()`] clamps the requested resolution to the attached display before allocating one. The
+//! firmware would otherwise accept sizes that the display cannot show.
+//!
+//! # Text
+//!
+//! [`FramebufferConsole`] renders lines of text with a built-in 8x8 pixel font. It is written to
+//! through [`interface::TextSink`], which is what the panic handler uses to show the end of the log
+//! on screen.
+
+mod console;
+mod font;
+
+use crate::bsp;
+
+pub use crate::framebuffer::Surface;
+pub use console::*;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Graphics interfaces.
+pub mod interface {
+    /// A line oriented text output.
+    pub trait TextSink {
+        /// The number of lines that fit at once.
+        fn rows(&self) -> usize;
+
+        /// Erase everything and start over at the top.
+        fn clear(&mut self);
+
+        /// Write a line, which must not contain a newline.
+        fn write_line(&mut self, line: &str);
+    }
+}
+
+/// The order of the color components in a pixel, starting at the least significant bits.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PixelOrder {
+    /// Blue in the lowest bits, e.g. `0xAARRGGBB` with 32 bits per pixel.
+    Bgr,
+
+    /// Red in the lowest bits, e.g. `0xAABBGGRR` with 32 bits per pixel.
+    Rgb,
+}
+
+/// The layout of a pixel in a framebuffer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PixelFormat {
+    pub order: PixelOrder,
+    pub bits_per_pixel: u32,
+}
+
+/// A color with 8 bits per component, independent of any pixel format.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Color {
+    pub a: u8,
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// Two framebuffers that take turns at being displayed.
+pub struct DoubleBuffer {
+    buffers: [Surface; 2],
+    height: usize,
+    front: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Color {
+    /// Create an instance from a `0xAARRGGBB` value.
+    pub const fn from_argb(argb: u32) -> Self {
+        Self {
+            a: (argb >> 24) as u8,
+            r: (argb >> 16) as u8,
+            g: (argb >> 8) as u8,
+            b: argb as u8,
+        }
+    }
+
+    /// Pack the color into a raw pixel value for `format`.
+    ///
+    /// With 16 bits per pixel, the components are reduced to 5, 6 and 5 bits and alpha is dropped.
+    pub fn to_raw(&self, format: PixelFormat) -> u32 {
+        let (low, high) = match format.order {
+            PixelOrder::Bgr => (self.b as u32, self.r as u32),
+            PixelOrder::Rgb => (self.r as u32, self.b as u32),
+        };
+        let g = self.g as u32;
+
+        match format.bits_per_pixel {
+            16 => ((high >> 3) << 11) | ((g >> 2) << 5) | (low >> 3),
+            24 => (high << 16) | (g << 8) | low,
+            _ => ((self.a as u32) << 24) | (high << 16) | (g << 8) | low,
+        }
+    }
+}
+
+impl DoubleBuffer {
+    /// Allocate the framebuffer from the firmware and display the first buffer.
+    ///
+    /// Must only be called once, as the firmware hands out a single framebuffer.
+    pub fn new(width: u32, height: u32) -> Result<Self, &'static str> {
+        let info = bsp::framebuffer::allocate(width, height, 2 * height)
+            .map_err(|_| "Framebuffer allocation failed")?;
+
+        if info.virtual_height < 2 * info.height {
+            return Err("Firmware refused a virtual framebuffer for two buffers");
+        }
+
+        let fb_range = bsp::memory::framebuffer_range();
+        if info.base_addr < fb_range.start || info.base_addr + info.size > fb_range.end {
+            return Err("Framebuffer outside of the mapped VideoCore memory");
+        }
+
+        let back_addr = info.base_addr + info.height * info.pitch;
+        let buffers = unsafe {
+            [
+                Surface::new(
+                    info.base_addr,
+                    info.width,
+                    info.height,
+                    info.pitch,
+                    info.pixel_format,
+                ),
+                Surface::new(
+                    back_addr,
+                    info.width,
+                    info.height,
+                    info.pitch,
+                    info.pixel_format,
+                ),
+            ]
+        };
+
+        let mut db = Self {
+            buffers,
+            height: info.height,
+            front: 1,
+        };
+        db.swap()?;
+
+        Ok(db)
+    }
+
+    /// The buffer that is currently displayed.
+    pub fn front(&self) -> &Surface {
+        &self.buffers[self.front]
+    }
+
+    /// The buffer to draw the next frame into.
+    pub fn back_mut(&mut self) -> &mut Surface {
+        &mut self.buffers[1 - self.front]
+    }
+
+    /// The index of the displayed buffer, `0` or `1`.
+    pub fn front_index(&self) -> usize {
+        self.front
+    }
+
+    /// The row of the virtual framebuffer at which the displayed buffer starts.
+    pub fn front_offset(&self) -> usize {
+        self.front * self.height
+    }
+
+    /// Display the back buffer, which turns the previous front buffer into the new back buffer.
+    ///
+    /// If the firmware supports it, this waits for the next vertical sync, so that the old front
+    /// buffer is no longer scanned out once this returns. Otherwise, drawing right after the swap
+    /// may tear.
+    pub fn swap(&mut self) -> Result<(), &'static str> {
+        let back = 1 - self.front;
+        let y = (back * self.height) as u32;
+
+        let applied = bsp::framebuffer::set_virtual_offset(0, y)
+            .map_err(|_| "Setting the virtual offset failed")?;
+        if applied != (0, y) {
+            return Err("Firmware did not apply the virtual offset");
+        }
+        self.front = back;
+
+        // Not all firmware versions expose vsync. Without it, there is nothing to wait for.
+        let _ = bsp::framebuffer::wait_for_vsync();
+
+        Ok(())
+    }
+}
+
+/// Clamp each dimension of `requested` to `display`, if a display is attached.
+///
+/// Without a display, the request is kept, as there is nothing to clamp to.
+pub fn clamp_resolution(requested: (u32, u32), display: Option<(u32, u32)>) -> (u32, u32) {
+    match display {
+        None => requested,
+        Some((width, height)) => (
+            core::cmp::min(requested.0, width),
+            core::cmp::min(requested.1, height),
+        ),
+    }
+}
+
+/// Allocate a double buffer of `width` x `height` pixels, clamped to what the display supports.
+///
+/// The returned buffers report the size that is actually in effect. Must only be called once, like
+/// `DoubleBuffer::new()`.
+pub fn init(width: u32, height: u32) -> Result<DoubleBuffer, &'static str> {
+    let display =
+        bsp::framebuffer::display_size().map_err(|_| "Querying the display size failed")?;
+    let (width, height) = clamp_resolution((width, height), display);
+
+    DoubleBuffer::new(width, height)
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    const RGB: PixelFormat = PixelFormat {
+        order: PixelOrder::Rgb,
+        bits_per_pixel: 32,
+    };
+
+    const BGR: PixelFormat = PixelFormat {
+        order: PixelOrder::Bgr,
+        bits_per_pixel: 32,
+    };
+
+    /// The same color must swap its red and blue components between the two pixel orders.
+    #[kernel_test]
+    fn color_packs_differently_for_rgb_and_bgr() {
+        let color = Color::from_argb(0x80FF_8000);
+
+        assert_eq!(color.to_raw(BGR), 0x80FF_8000);
+        assert_eq!(color.to_raw(RGB), 0x8000_80FF);
+        assert_ne!(color.to_raw(RGB), color.to_raw(BGR));
+    }
+
+    /// With 16 bits per pixel, colors must be packed as 5-6-5 without alpha.
+    #[kernel_test]
+    fn color_packs_565_for_16_bits_per_pixel() {
+        let color = Color::from_argb(0xFFFF_0000);
+
+        let bgr16 = PixelFormat {
+            bits_per_pixel: 16,
+            ..BGR
+        };
+        let rgb16 = PixelFormat {
+            bits_per_pixel: 16,
+            ..RGB
+        };
+
+        assert_eq!(color.to_raw(bgr16), 0xF800);
+        assert_eq!(color.to_raw(rgb16), 0x001F);
+    }
+
+    /// Over-large requests must be clamped per dimension, and kept as is without a display.
+    #[kernel_test]
+    fn resolution_is_clamped_to_display() {
+        let display = Some((1920, 1080));
+
+        assert_eq!(clamp_resolution((4096, 4096), display), (1920, 1080));
+        assert_eq!(clamp_resolution((640, 4096), display), (640, 1080));
+        assert_eq!(clamp_resolution((640, 480), display), (640, 480));
+        assert_eq!(clamp_resolution((4096, 4096), None), (4096, 4096));
+    }
+}
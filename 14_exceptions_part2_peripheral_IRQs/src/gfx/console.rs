@@ -0,0 +1,160 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A text console on a framebuffer.
+
+use super::{
+    font::{self, GLYPH_HEIGHT, GLYPH_WIDTH},
+    interface, Color, Surface,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Renders lines of text onto a [`Surface`], scrolling up once the bottom is reached.
+///
+/// Characters that do not fit into a row are cut off.
+pub struct FramebufferConsole {
+    surface: Surface,
+    fg: Color,
+    bg: Color,
+
+    /// The text row that the next line goes to.
+    row: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl FramebufferConsole {
+    /// Create an instance. Starts at the top, without clearing the surface.
+    pub const fn new(surface: Surface, fg: Color, bg: Color) -> Self {
+        Self {
+            surface,
+            fg,
+            bg,
+            row: 0,
+        }
+    }
+
+    /// The number of characters that fit into a row.
+    pub fn columns(&self) -> usize {
+        self.surface.width() / GLYPH_WIDTH
+    }
+
+    fn draw_glyph(&mut self, column: usize, row: usize, c: char) {
+        let glyph = font::glyph(c);
+        let x0 = column * GLYPH_WIDTH;
+        let y0 = row * GLYPH_HEIGHT;
+
+        for (dy, bits) in glyph.iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                let color = if bits & (1 << dx) != 0 {
+                    self.fg
+                } else {
+                    self.bg
+                };
+                self.surface.put_pixel(x0 + dx, y0 + dy, color);
+            }
+        }
+    }
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl interface::TextSink for FramebufferConsole {
+    fn rows(&self) -> usize {
+        self.surface.height() / GLYPH_HEIGHT
+    }
+
+    fn clear(&mut self) {
+        let bg = self.bg.to_raw(self.surface.pixel_format());
+        let height = self.surface.height();
+
+        self.surface.fill_rows(0..height, bg);
+        self.row = 0;
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.rows() == 0 {
+            return;
+        }
+
+        if self.row == self.rows() {
+            let bg = self.bg.to_raw(self.surface.pixel_format());
+            self.surface.scroll_up(GLYPH_HEIGHT, bg);
+            self.row -= 1;
+        }
+
+        let (row, columns) = (self.row, self.columns());
+        for (column, c) in line.chars().take(columns).enumerate() {
+            self.draw_glyph(column, row, c);
+        }
+
+        self.row += 1;
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gfx::{interface::TextSink, PixelFormat, PixelOrder};
+    use test_macros::kernel_test;
+
+    const WIDTH: usize = 2 * GLYPH_WIDTH;
+    const HEIGHT: usize = 2 * GLYPH_HEIGHT;
+
+    const FORMAT: PixelFormat = PixelFormat {
+        order: PixelOrder::Bgr,
+        bits_per_pixel: 32,
+    };
+
+    const FG: Color = Color::from_argb(0xFFFF_FFFF);
+    const BG: Color = Color::from_argb(0xFF00_0000);
+
+    static mut PIXELS: [u32; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
+
+    /// A written line must show up as the font's glyphs, and overflowing lines scroll the text up.
+    #[kernel_test]
+    fn write_line_draws_glyphs_and_scrolls() {
+        let surface =
+            unsafe { Surface::new(PIXELS.as_ptr() as usize, WIDTH, HEIGHT, WIDTH * 4, FORMAT) };
+        let mut console = FramebufferConsole::new(surface, FG, BG);
+
+        let is_glyph = |console: &FramebufferConsole, column: usize, row: usize, c: char| {
+            font::glyph(c).iter().enumerate().all(|(dy, bits)| {
+                (0..GLYPH_WIDTH).all(|dx| {
+                    let expected = if bits & (1 << dx) != 0 { FG } else { BG };
+                    console
+                        .surface
+                        .pixel(column * GLYPH_WIDTH + dx, row * GLYPH_HEIGHT + dy)
+                        == expected.to_raw(FORMAT)
+                })
+            })
+        };
+
+        console.clear();
+        assert_eq!(console.rows(), 2);
+        assert_eq!(console.columns(), 2);
+
+        // The third character does not fit and must be cut off.
+        console.write_line("AB!");
+        assert!(is_glyph(&console, 0, 0, 'A'));
+        assert!(is_glyph(&console, 1, 0, 'B'));
+
+        console.write_line("C");
+        console.write_line("D");
+        assert!(is_glyph(&console, 0, 0, 'C'));
+        assert!(is_glyph(&console, 0, 1, 'D'));
+        assert!(is_glyph(&console, 1, 1, ' '));
+    }
+}
@@ -144,17 +144,44 @@ mod panic_wait;
 mod runtime_init;
 mod synchronization;
 
+pub mod bench;
+pub mod bootloader;
 pub mod bsp;
+pub mod collections;
 pub mod console;
 pub mod cpu;
+pub mod debug;
+pub mod dma;
 pub mod driver;
 pub mod exception;
+pub mod exec;
+pub mod fault;
+#[cfg(feature = "faultinject")]
+pub mod faultinject;
+pub mod fdt;
+pub mod framebuffer;
+pub mod gdbstub;
+pub mod gfx;
+pub mod loader;
+pub mod log;
 pub mod memory;
+pub mod panic;
+pub mod percpu;
+pub mod pmu;
 pub mod print;
+pub mod profile;
+pub mod sched;
+pub mod serial;
+#[cfg(feature = "smp_test")]
+pub mod smp_test;
 pub mod state;
+pub mod thermal;
 pub mod time;
 pub mod usb;
 
+pub use exec::exec_payload;
+pub use fault::record_fault;
+
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
@@ -0,0 +1,276 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! The in-memory kernel log.
+//!
+//! Everything printed with [`print!`](crate::print!) and its siblings is also kept in a bounded
+//! ring of bytes, so that the most recent output can be shown again, e.g. on the framebuffer after
+//! a panic. On overflow, the oldest bytes are dropped.
+//!
+//! # Levels
+//!
+//! [`info!`](crate::info!) and [`debug!`](crate::debug!) print only if their [`Level`] is enabled,
+//! see [`set_max_level()`]. Warnings are always printed. A filtered message does not make it into
+//! the ring either. The `loglevel=` command line parameter sets the level during kernel init.
+
+use crate::{synchronization, synchronization::IRQSafeNullLock};
+use core::{
+    fmt, str,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const LOG_SIZE: usize = 4096;
+
+/// Longest line, in bytes, that [`LogRing::tail_lines()`] hands out. Longer lines are truncated.
+const LINE_SIZE: usize = 160;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How much is printed. Each level includes the ones before it.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub enum Level {
+    Warn,
+    Info,
+    Debug,
+}
+
+/// A bounded ring of log output.
+pub struct LogRing {
+    buf: [u8; LOG_SIZE],
+
+    /// Index of the oldest byte.
+    head: usize,
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static LOG_RING: IRQSafeNullLock<LogRing> = IRQSafeNullLock::new(LogRing::new());
+
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// The longest valid UTF-8 prefix of `bytes`. Truncation or overflow may have cut a character.
+pub(crate) fn valid_prefix(bytes: &[u8]) -> &str {
+    match str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(e) => str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or(""),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl Level {
+    /// Parse a level's name, e.g. the value of `loglevel=debug`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "warn" => Some(Self::Warn),
+            "info" => Some(Self::Info),
+            "debug" => Some(Self::Debug),
+            _ => None,
+        }
+    }
+}
+
+impl LogRing {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; LOG_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn byte(&self, i: usize) -> u8 {
+        self.buf[(self.head + i) % LOG_SIZE]
+    }
+
+    /// Append `s`, dropping the oldest bytes if the ring is full.
+    pub fn push_str(&mut self, s: &str) {
+        for b in s.bytes() {
+            let tail = (self.head + self.len) % LOG_SIZE;
+            self.buf[tail] = b;
+
+            if self.len < LOG_SIZE {
+                self.len += 1;
+            } else {
+                self.head = (self.head + 1) % LOG_SIZE;
+            }
+        }
+    }
+
+    /// Number of bytes in the ring.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether nothing was logged yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Call `f` with each of the last `n` lines, oldest first and without their newlines.
+    ///
+    /// After an overflow, the oldest line may be missing its beginning.
+    pub fn tail_lines(&self, n: usize, mut f: impl FnMut(&str)) {
+        // A trailing newline ends the last line, it does not start an empty one.
+        let end = match self.len {
+            len if len > 0 && self.byte(len - 1) == b'\n' => len - 1,
+            len => len,
+        };
+        if n == 0 || end == 0 {
+            return;
+        }
+
+        // Walk back to the newline in front of the n-th line from the end.
+        let mut start = end;
+        let mut lines = 0;
+        while start > 0 {
+            if self.byte(start - 1) == b'\n' {
+                lines += 1;
+                if lines == n {
+                    break;
+                }
+            }
+            start -= 1;
+        }
+
+        let mut line = [0; LINE_SIZE];
+        let mut line_len = 0;
+        for i in start..=end {
+            let b = if i < end { self.byte(i) } else { b'\n' };
+
+            if b == b'\n' {
+                f(valid_prefix(&line[..line_len]));
+                line_len = 0;
+            } else if line_len < LINE_SIZE {
+                line[line_len] = b;
+                line_len += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Write for LogRing {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+/// Append formatted output to the kernel log.
+pub fn record(args: fmt::Arguments) {
+    use fmt::Write;
+
+    let mut r = &LOG_RING;
+    r.lock(|ring| {
+        let _ = ring.write_fmt(args);
+    });
+}
+
+/// Print messages up to `level` from now on. `Level::Info` is the default.
+pub fn set_max_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// The most verbose level that is printed.
+pub fn max_level() -> Level {
+    match MAX_LEVEL.load(Ordering::Relaxed) {
+        0 => Level::Warn,
+        1 => Level::Info,
+        _ => Level::Debug,
+    }
+}
+
+/// Whether messages of `level` are printed.
+pub fn enabled(level: Level) -> bool {
+    level <= max_level()
+}
+
+/// Call `f` with each of the last `n` lines of the kernel log, oldest first.
+pub fn tail_lines(n: usize, f: impl FnMut(&str)) {
+    let mut r = &LOG_RING;
+    r.lock(|ring| ring.tail_lines(n, f));
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// Only the requested number of lines must be handed out, oldest first.
+    #[kernel_test]
+    fn tail_lines_returns_last_lines_in_order() {
+        let mut ring = LogRing::new();
+        ring.push_str("first\nsecond\nthird\n");
+
+        let mut lines = 0;
+        ring.tail_lines(2, |line| {
+            assert_eq!(line, if lines == 0 { "second" } else { "third" });
+            lines += 1;
+        });
+        assert_eq!(lines, 2);
+
+        // Asking for more lines than there are must return all of them.
+        let mut lines = 0;
+        ring.tail_lines(10, |_| lines += 1);
+        assert_eq!(lines, 3);
+    }
+
+    /// On overflow, the oldest bytes must be dropped and the newest output kept.
+    #[kernel_test]
+    fn overflow_keeps_newest_output() {
+        let mut ring = LogRing::new();
+
+        for _ in 0..(2 * LOG_SIZE / 8) {
+            ring.push_str("old old\n");
+        }
+        ring.push_str("newest\n");
+
+        assert_eq!(ring.len(), LOG_SIZE);
+
+        let mut lines = 0;
+        ring.tail_lines(1, |line| {
+            assert_eq!(line, "newest");
+            lines += 1;
+        });
+        assert_eq!(lines, 1);
+    }
+
+    /// A level must enable itself and the less verbose ones only.
+    #[kernel_test]
+    fn max_level_filters_more_verbose_levels() {
+        assert_eq!(Level::from_name("debug"), Some(Level::Debug));
+        assert_eq!(Level::from_name("verbose"), None);
+
+        set_max_level(Level::Warn);
+        assert!(enabled(Level::Warn));
+        assert!(!enabled(Level::Info));
+
+        set_max_level(Level::Info);
+        assert_eq!(max_level(), Level::Info);
+        assert!(enabled(Level::Info));
+        assert!(!enabled(Level::Debug));
+    }
+}
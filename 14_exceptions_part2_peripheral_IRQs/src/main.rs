@@ -15,22 +15,28 @@
 
 extern crate alloc;
 
-use core::borrow::BorrowMut;
 use libkernel::{
-    bsp,
-    bsp::device_driver::{
-        Mailbox, Message, PropertyTag, PropertyTagPowerState, PropertyTagTemperature,
-    },
-    cpu, driver, exception, info, memory, state, time, warn,
+    bootloader, bsp, cpu, driver, exception, fault, info, log, memory, pmu, profile, sched, state,
+    thermal, time, warn,
 };
 use linked_list_allocator::LockedHeap;
+use memory::RecoveringAllocator;
+
+/// Stack of the task that runs the drivers' late init.
+static mut LATE_INIT_STACK: [u8; 16 * 1024] = [0; 16 * 1024];
 
 #[global_allocator]
-static GLOBAL_ALLOCATOR: LockedHeap = LockedHeap::empty();
+static GLOBAL_ALLOCATOR: RecoveringAllocator<LockedHeap> =
+    RecoveringAllocator::new(LockedHeap::empty());
 
+/// Reached only if the handler from `memory::set_alloc_error_handler()`, if any, did not recover.
 #[alloc_error_handler]
-fn foo(_: core::alloc::Layout) -> ! {
-    panic!("TITSUP")
+fn foo(layout: core::alloc::Layout) -> ! {
+    panic!(
+        "TITSUP: Allocation of {} bytes with alignment {} failed",
+        layout.size(),
+        layout.align()
+    )
 }
 
 /// Early init code.
@@ -50,20 +56,76 @@ unsafe fn kernel_init() -> ! {
 
     exception::handling_init();
 
+    // Optional and IMPLEMENTATION DEFINED system registers are probed with plain `mrs`, e.g. in
+    // `cpu::num_cores()`. Should a core not implement one, the probe reads zero instead of
+    // faulting.
+    if let Err(string) = exception::register_sync_handler(exception::emulate_sysreg_read_as_zero) {
+        panic!("Exception handling: {}", string);
+    }
+
+    // Run exception handlers on their own stack, so that an overflow of the kernel stack does not
+    // take the panic path down with it.
+    cpu::use_sp_el0(true);
+
+    pmu::init();
+
     if let Err(string) = memory::mmu::mmu().init() {
         panic!("MMU: {}", string);
     }
 
-    GLOBAL_ALLOCATOR.lock().init(0x0020_0000, 4 * 1024 * 1024);
+    // Keep the allocator off the memory that the firmware reserved, and find out how the secondary
+    // cores are started.
+    match bsp::boot_args()
+        .dtb_addr()
+        .map(|addr| libkernel::fdt::Fdt::from_addr(addr))
+    {
+        None => (),
+        Some(Err(msg)) => fault::record_fault("FDT", msg),
+        Some(Ok(fdt)) => {
+            if let Err(msg) = bsp::memory::init_reserved_regions(&fdt) {
+                fault::record_fault("FDT", msg);
+            }
+            if let Err(msg) = cpu::psci::init(&fdt) {
+                fault::record_fault("PSCI", msg);
+            }
+        }
+    }
+
+    let heap = bsp::memory::allocatable_heap_range();
+    if let Err(msg) = memory::validate_heap(&heap) {
+        panic!("Heap: {}", msg);
+    }
+    GLOBAL_ALLOCATOR
+        .lock()
+        .init(heap.start, heap.end - heap.start);
 
     for i in bsp::driver::driver_manager().all_device_drivers().iter() {
-        if i.init().is_err() {
+        if driver::init_driver(*i).is_err() {
             panic!("Error loading driver: {}", i.compatible())
         }
     }
     bsp::driver::driver_manager().post_device_driver_init();
     // println! is usable from here on.
 
+    // Let the command line configure the kernel.
+    if let Err(msg) = bsp::cmdline::init() {
+        warn!("{}", msg);
+    }
+    bsp::cmdline::get("loglevel", |name| match log::Level::from_name(name) {
+        Some(level) => log::set_max_level(level),
+        None => warn!("Unknown loglevel: {}", name),
+    });
+    // The firmware's default line has a `console=` for Linux' framebuffer console, too.
+    match bsp::cmdline::get_last_accepted("console", bsp::console::parse_backend) {
+        Some(backend) => bsp::console::select_backend(backend),
+        None => {
+            bsp::cmdline::get("console", |name| warn!("Unknown console: {}", name));
+        }
+    }
+
+    time::check_frequency();
+    time::select_counter();
+
     // Let device drivers register and enable their handlers with the interrupt controller.
     for i in bsp::driver::driver_manager().all_device_drivers() {
         if let Err(msg) = i.register_and_enable_irq_handler() {
@@ -71,6 +133,25 @@ unsafe fn kernel_init() -> ! {
         }
     }
 
+    if let Err(msg) = time::init() {
+        warn!("Error registering sleep wakeup: {}", msg);
+    }
+
+    if let Err(msg) = profile::init() {
+        warn!("Error registering profiler: {}", msg);
+    }
+
+    driver::validate_mmio_regions(
+        bsp::driver::driver_manager().all_device_drivers(),
+        bsp::memory::heap_range(),
+    );
+
+    // A host tool that wants to upload a new image sends the boot key. With IRQs still masked, the
+    // console's RX IRQ handler does not consume it.
+    if bootloader::boot_key_pressed(bsp::console::console()) {
+        bootloader::run(bsp::console::console())
+    }
+
     // Unmask interrupts on the boot CPU core.
     exception::asynchronous::local_irq_unmask();
 
@@ -81,12 +162,21 @@ unsafe fn kernel_init() -> ! {
     kernel_main()
 }
 
+/// Entry of the late init task.
+fn late_init_task() {
+    use driver::interface::DriverManager;
+
+    bsp::driver::driver_manager().run_late_init();
+}
+
 /// The main function running after the early init.
 unsafe fn kernel_main() -> ! {
     use driver::interface::DriverManager;
     use exception::asynchronous::interface::IRQManager;
 
     info!("Booting on: {}", bsp::board_name());
+    info!("Cores: {}", cpu::num_cores());
+    cpu::id::print_feature_summary();
 
     info!("MMU online. Special regions:");
     bsp::memory::mmu::virt_mem_layout().print_layout();
@@ -101,6 +191,7 @@ unsafe fn kernel_main() -> ! {
         "Architectural timer resolution: {} ns",
         time::time_manager().resolution().as_nanos()
     );
+    info!("Architectural timer counter: {:?}", time::counter_kind());
 
     info!("Drivers loaded:");
     for (i, driver) in bsp::driver::driver_manager()
@@ -114,22 +205,20 @@ unsafe fn kernel_main() -> ! {
     info!("Registered IRQ handlers:");
     bsp::exception::asynchronous::irq_manager().print_handler();
 
-    let tmb = &mut PropertyTagTemperature {
-        temperature_id: PropertyTagTemperature::TEMPERATURE_ID,
-        value: 0,
-    };
-    let mut tag = PropertyTag::new(0x00030006, tmb);
-    let mut temp = Message::new(tag.borrow_mut());
-
-    match bsp::MAILBOX.send(Mailbox::BCM_MAILBOX_PROP_CHANNEL, &mut temp) {
-        Ok(tres) => {
-            info!("Temp is {:.2} C", tres.value / 1000);
-        }
-        _ => {}
+    match thermal::start_sampling() {
+        Ok(()) => info!("Temp is {}", thermal::history().last().unwrap().1),
+        Err(msg) => warn!("Thermal: {}", msg),
     }
 
     info!("USB CORE {}", bsp::DWHCI);
 
+    fault::dump();
+
+    // Slow driver setup finishes in the background, while kernel_main() idles.
+    if let Err(msg) = sched::spawn(late_init_task, &mut LATE_INIT_STACK) {
+        warn!("Error spawning late init: {}", msg);
+    }
+
     info!("Echoing input now");
-    cpu::wait_forever();
+    sched::idle();
 }
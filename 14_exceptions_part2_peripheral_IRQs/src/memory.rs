@@ -6,12 +6,273 @@
 
 pub mod mmu;
 
+mod heap;
+pub use heap::*;
+
 use core::ops::Range;
 
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Heaps smaller than this are assumed to be a misconfiguration of the memory map.
+pub const MIN_HEAP_SIZE: usize = 64 * 1024;
+
+/// Iterator over an address range in granule sized chunks.
+///
+/// Yields `(addr, len)` tuples. All chunks but the first start at a granule aligned address, and
+/// all chunks but the last end at one. An unaligned start or end is handled by clipping the first
+/// or last chunk, so the chunks exactly cover the range.
+#[derive(Clone)]
+pub struct PageRange {
+    next: usize,
+    end: usize,
+    granule: usize,
+}
+
+/// A physical address region `start..end`.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Region {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// Exclusive ownership of a physical memory region, e.g. a buffer that is shared with a DMA engine
+/// or the VideoCore.
+///
+/// The region is expected to be identity mapped.
+pub struct PhysRegion {
+    region: Region,
+}
+
+/// A sorted list of regions with fixed capacity.
+///
+/// Overlapping and adjacent regions are coalesced on insertion, so the regions in the list never
+/// touch.
+pub struct RegionList<const N: usize> {
+    regions: [Region; N],
+    len: usize,
+}
+
+/// Iterator over the parts of an address range that are not covered by a [`RegionList`].
+pub struct Unreserved<'a> {
+    next: usize,
+    end: usize,
+    reserved: core::slice::Iter<'a, Region>,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
 
+impl Region {
+    /// Create an instance.
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The region as a range.
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+}
+
+impl PhysRegion {
+    /// Create an instance.
+    ///
+    /// # Safety
+    ///
+    /// - `region` must be valid, identity mapped DRAM that nothing else uses for as long as the
+    ///   instance lives.
+    pub const unsafe fn new(region: Region) -> Self {
+        Self { region }
+    }
+
+    /// The owned region.
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    /// The size of the region in bytes.
+    pub fn len(&self) -> usize {
+        self.region.end - self.region.start
+    }
+
+    /// Return whether the region is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Zero the region and clean it from the D-cache, so that devices see the zeros, too.
+    ///
+    /// Without the clean, a device may read stale memory contents while the zeros still sit in
+    /// the cache.
+    pub fn zero(&mut self) {
+        unsafe { core::ptr::write_bytes(self.region.start as *mut u8, 0, self.len()) };
+
+        crate::cpu::cache::clean_dcache_range_to_poc(self.region.range());
+    }
+
+    /// The region as a slice, if all of it is mapped non-cacheable.
+    ///
+    /// CPU reads through such a mapping see what devices wrote, without cache maintenance.
+    pub fn as_uncached_slice(&self) -> Option<&[u8]> {
+        let layout = crate::bsp::memory::mmu::virt_mem_layout();
+
+        let uncached = PageRange::new(self.region.start, self.region.end).all(|(addr, _)| {
+            matches!(
+                layout.virt_addr_properties(addr),
+                Ok((
+                    _,
+                    mmu::AttributeFields {
+                        mem_attributes: mmu::MemAttributes::NonCacheableDRAM,
+                        ..
+                    }
+                ))
+            )
+        });
+
+        if !uncached {
+            return None;
+        }
+
+        Some(unsafe { core::slice::from_raw_parts(self.region.start as *const u8, self.len()) })
+    }
+}
+
+impl<const N: usize> RegionList<{ N }> {
+    /// Create an empty instance.
+    pub const fn new() -> Self {
+        Self {
+            regions: [Region::new(0, 0); N],
+            len: 0,
+        }
+    }
+
+    /// Insert a region, coalescing it with the ones it overlaps or touches. Empty regions are
+    /// ignored.
+    pub fn insert(&mut self, region: Region) -> Result<(), &'static str> {
+        if region.start >= region.end {
+            return Ok(());
+        }
+
+        let mut merged = region;
+        let mut result = [Region::new(0, 0); N];
+        let mut len = 0;
+        let mut placed = false;
+
+        let mut push = |r: Region| {
+            if len == N {
+                return Err("Region list full");
+            }
+            result[len] = r;
+            len += 1;
+            Ok(())
+        };
+
+        for r in self.as_slice() {
+            if r.end < merged.start {
+                push(*r)?;
+            } else if r.start > merged.end {
+                if !placed {
+                    push(merged)?;
+                    placed = true;
+                }
+                push(*r)?;
+            } else {
+                merged.start = core::cmp::min(merged.start, r.start);
+                merged.end = core::cmp::max(merged.end, r.end);
+            }
+        }
+
+        if !placed {
+            push(merged)?;
+        }
+
+        self.regions = result;
+        self.len = len;
+
+        Ok(())
+    }
+
+    /// The regions, sorted by address.
+    pub fn as_slice(&self) -> &[Region] {
+        &self.regions[..self.len]
+    }
+}
+
+/// Return the parts of `range` that none of the sorted, non-overlapping `reserved` regions cover.
+pub fn unreserved(range: Range<usize>, reserved: &[Region]) -> Unreserved {
+    Unreserved {
+        next: range.start,
+        end: range.end,
+        reserved: reserved.iter(),
+    }
+}
+
+impl<'a> Iterator for Unreserved<'a> {
+    type Item = Range<usize>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.end {
+            let gap_start = self.next;
+
+            match self.reserved.next() {
+                Some(r) if r.end <= gap_start => continue,
+                Some(r) if r.start < self.end => {
+                    self.next = core::cmp::max(gap_start, r.end);
+
+                    if r.start > gap_start {
+                        return Some(gap_start..r.start);
+                    }
+                }
+                _ => {
+                    self.next = self.end;
+                    return Some(gap_start..self.end);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+impl PageRange {
+    /// Create an instance for `start..end` with the MMU's translation granule as page size.
+    pub fn new(start: usize, end: usize) -> Self {
+        Self::with_granule(start, end, mmu::GRANULE_SIZE)
+    }
+
+    /// Create an instance for `start..end` with a custom granule, e.g. the cache line size.
+    ///
+    /// `granule` must be a power of two.
+    pub fn with_granule(start: usize, end: usize, granule: usize) -> Self {
+        assert!(granule.is_power_of_two());
+
+        Self {
+            next: start,
+            end,
+            granule,
+        }
+    }
+}
+
+impl Iterator for PageRange {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+
+        let addr = self.next;
+        let chunk_end = core::cmp::min((addr & !(self.granule - 1)) + self.granule, self.end);
+        self.next = chunk_end;
+
+        Some((addr, chunk_end - addr))
+    }
+}
+
 /// Zero out a memory region.
 ///
 /// # Safety
@@ -30,6 +291,50 @@ where
     }
 }
 
+/// Check that `heap` is large enough to be handed to the allocator.
+///
+/// An empty or tiny heap makes the first allocations fail with no indication of why, so the
+/// computed base and size are reported on the early console before returning an error. The caller
+/// must not proceed into code that allocates in that case.
+pub fn validate_heap(heap: &Range<usize>) -> Result<(), &'static str> {
+    let size = heap.end.saturating_sub(heap.start);
+
+    let msg = if size == 0 {
+        "Heap is empty"
+    } else if size < MIN_HEAP_SIZE {
+        "Heap is suspiciously small"
+    } else {
+        return Ok(());
+    };
+
+    crate::early_println!(
+        "[W] {}: base {:#010x}, size {:#x}, expected at least {:#x}. Check the heap region of the \
+         memory map and the firmware reserved regions.",
+        msg,
+        heap.start,
+        size,
+        MIN_HEAP_SIZE
+    );
+
+    Err(msg)
+}
+
+/// Panic if any two of the given named address ranges intersect.
+///
+/// The panic message names both offending ranges. Empty ranges never intersect.
+pub fn assert_no_overlaps(regions: &[(&str, Range<usize>)]) {
+    for (i, (name_a, a)) in regions.iter().enumerate() {
+        for (name_b, b) in regions.iter().skip(i + 1) {
+            if a.start < a.end && b.start < b.end && a.start < b.end && b.start < a.end {
+                panic!(
+                    "Memory regions overlap: {} {:#x}..{:#x} and {} {:#x}..{:#x}",
+                    name_a, a.start, a.end, name_b, b.start, b.end
+                );
+            }
+        }
+    }
+}
+
 //--------------------------------------------------------------------------------------------------
 // Testing
 //--------------------------------------------------------------------------------------------------
@@ -39,6 +344,20 @@ mod tests {
     use super::*;
     use test_macros::kernel_test;
 
+    /// Only heaps of at least `MIN_HEAP_SIZE` bytes must be accepted.
+    #[kernel_test]
+    fn validate_heap_rejects_empty_and_small_heaps() {
+        let base = 0x20_0000;
+
+        assert_eq!(validate_heap(&(base..base)), Err("Heap is empty"));
+        assert_eq!(validate_heap(&(base..base - 1)), Err("Heap is empty"));
+        assert_eq!(
+            validate_heap(&(base..base + MIN_HEAP_SIZE - 1)),
+            Err("Heap is suspiciously small")
+        );
+        assert_eq!(validate_heap(&(base..base + MIN_HEAP_SIZE)), Ok(()));
+    }
+
     /// Check `zero_volatile()`.
     #[kernel_test]
     fn zero_volatile_works() {
@@ -49,4 +368,67 @@ mod tests {
 
         assert_eq!(x, [0, 0, 0]);
     }
+
+    const PAGE: usize = mmu::GRANULE_SIZE;
+
+    /// An aligned range yields full pages.
+    #[kernel_test]
+    fn page_range_aligned() {
+        let mut pages = PageRange::new(4 * PAGE, 6 * PAGE);
+
+        assert_eq!(pages.next(), Some((4 * PAGE, PAGE)));
+        assert_eq!(pages.next(), Some((5 * PAGE, PAGE)));
+        assert_eq!(pages.next(), None);
+    }
+
+    /// Unaligned ends clip the first and last chunk.
+    #[kernel_test]
+    fn page_range_unaligned_at_both_ends() {
+        let mut pages = PageRange::new(PAGE + 0x100, 3 * PAGE + 0x10);
+
+        assert_eq!(pages.next(), Some((PAGE + 0x100, PAGE - 0x100)));
+        assert_eq!(pages.next(), Some((2 * PAGE, PAGE)));
+        assert_eq!(pages.next(), Some((3 * PAGE, 0x10)));
+        assert_eq!(pages.next(), None);
+    }
+
+    /// A range inside a single page yields exactly that range, an empty range nothing.
+    #[kernel_test]
+    fn page_range_smaller_than_one_page() {
+        let mut pages = PageRange::new(PAGE + 0x40, PAGE + 0x80);
+
+        assert_eq!(pages.next(), Some((PAGE + 0x40, 0x40)));
+        assert_eq!(pages.next(), None);
+
+        assert_eq!(PageRange::new(PAGE, PAGE).next(), None);
+    }
+
+    /// Overlapping and adjacent regions must be coalesced, disjoint ones kept sorted.
+    #[kernel_test]
+    fn region_list_coalesces() {
+        let mut list: RegionList<4> = RegionList::new();
+
+        list.insert(Region::new(0x5000, 0x6000)).unwrap();
+        list.insert(Region::new(0x1000, 0x2000)).unwrap();
+        list.insert(Region::new(0x2000, 0x3000)).unwrap();
+        list.insert(Region::new(0x5800, 0x7000)).unwrap();
+        list.insert(Region::new(0x9000, 0x9000)).unwrap();
+
+        let expected = [Region::new(0x1000, 0x3000), Region::new(0x5000, 0x7000)];
+        assert!(list.as_slice() == &expected[..]);
+
+        // Bridging both regions leaves a single one.
+        list.insert(Region::new(0x2800, 0x5000)).unwrap();
+        assert!(list.as_slice() == &[Region::new(0x1000, 0x7000)][..]);
+    }
+
+    /// Adjacent and empty ranges must not be reported as overlapping.
+    #[kernel_test]
+    fn adjacent_regions_do_not_overlap() {
+        assert_no_overlaps(&[
+            ("A", 0x1000..0x2000),
+            ("B", 0x2000..0x3000),
+            ("Empty", 0x1800..0x1800),
+        ]);
+    }
 }
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Recovery from heap exhaustion.
+//!
+//! By default, a failed allocation ends in the binary's `#[alloc_error_handler]`, which halts with
+//! a diagnostic. Wrapping the global allocator in [`RecoveringAllocator`] gives a callback that was
+//! set with [`set_alloc_error_handler()`] the chance to make room first, e.g. by dropping caches or
+//! by growing the heap into memory that was discovered at runtime. If it returns
+//! [`AllocRecovery::Retry`], the allocation is retried once.
+//!
+//! # Re-entrancy
+//!
+//! The callback runs in the middle of an allocation, in whatever context the allocation happened,
+//! including IRQ handlers. The allocator's lock is not held, so the callback can grow the heap.
+//! It must not allocate itself, though: A nested failure is not handed to the callback again but
+//! aborts right away.
+
+use crate::{synchronization, synchronization::InitStateLock};
+use core::{
+    alloc::{GlobalAlloc, Layout},
+    ops,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// What a failed allocation should lead to.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AllocRecovery {
+    /// Room was made. Retry the allocation once.
+    Retry,
+
+    /// Give up, and leave it to the `#[alloc_error_handler]`.
+    Abort,
+}
+
+/// A global allocator that consults the alloc error handler before giving up.
+///
+/// Derefs to the wrapped allocator, so that e.g. its init functions stay reachable.
+pub struct RecoveringAllocator<A> {
+    inner: A,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Writable only during kernel init. RO afterwards.
+static ALLOC_ERROR_HANDLER: InitStateLock<Option<fn(Layout) -> AllocRecovery>> =
+    InitStateLock::new(None);
+
+/// Set while the alloc error handler runs.
+static IN_RECOVERY: AtomicBool = AtomicBool::new(false);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::ReadWriteEx;
+
+/// Ask the alloc error handler what to do about the failed allocation of `layout`.
+fn recovery(layout: Layout) -> AllocRecovery {
+    let mut r = &ALLOC_ERROR_HANDLER;
+    let handler = match r.read(|handler| *handler) {
+        None => return AllocRecovery::Abort,
+        Some(handler) => handler,
+    };
+
+    // A failure of an allocation that the handler made itself.
+    if IN_RECOVERY.load(Ordering::Relaxed) {
+        return AllocRecovery::Abort;
+    }
+
+    IN_RECOVERY.store(true, Ordering::Relaxed);
+    let recovery = handler(layout);
+    IN_RECOVERY.store(false, Ordering::Relaxed);
+
+    recovery
+}
+
+/// Call `alloc`, and once more if it failed and the alloc error handler made room.
+fn with_retry(layout: Layout, mut alloc: impl FnMut() -> *mut u8) -> *mut u8 {
+    #[cfg(feature = "faultinject")]
+    let ptr = if crate::faultinject::should_fail(crate::faultinject::FaultPoint::Allocation) {
+        core::ptr::null_mut()
+    } else {
+        alloc()
+    };
+    #[cfg(not(feature = "faultinject"))]
+    let ptr = alloc();
+    if !ptr.is_null() {
+        return ptr;
+    }
+
+    match recovery(layout) {
+        AllocRecovery::Retry => alloc(),
+        AllocRecovery::Abort => ptr,
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Set the callback that failed allocations are handed to.
+///
+/// Must be called during kernel init. See the module documentation for what the callback
+/// may do.
+pub fn set_alloc_error_handler(f: fn(Layout) -> AllocRecovery) {
+    let mut r = &ALLOC_ERROR_HANDLER;
+    r.write(|handler| *handler = Some(f));
+}
+
+impl<A> RecoveringAllocator<A> {
+    /// Create an instance.
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+}
+
+impl<A> ops::Deref for RecoveringAllocator<A> {
+    type Target = A;
+
+    fn deref(&self) -> &Self::Target {
+        &self.inner
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for RecoveringAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        with_retry(layout, || self.inner.alloc(layout))
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        with_retry(layout, || self.inner.alloc_zeroed(layout))
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+
+        with_retry(new_layout, || self.inner.realloc(ptr, layout, new_size))
+    }
+}
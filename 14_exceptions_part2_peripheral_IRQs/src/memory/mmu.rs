@@ -13,12 +13,27 @@
 //!
 //! The `MMU` driver of the `arch` code uses `bsp::memory::mmu::virt_mem_layout()` to compile and
 //! install respective translation tables.
+//!
+//! # Table walk attributes
+//!
+//! When the MMU walks the translation tables, it accesses them with the cacheability and
+//! shareability from [`TableWalkAttributes`], not with the attributes that the tables' own memory
+//! is mapped with. The two must agree. Walks that bypass the caches while the kernel writes the
+//! tables through them see stale descriptors, and walks with a narrower shareability than the
+//! mapping miss descriptor updates from other cores.
+//!
+//! The defaults suit tables in cacheable DRAM. Use [`set_table_walk_attributes()`] before
+//! `MMU::init()` to change them. `init()` refuses attributes that do not match the table memory.
 
 #[cfg(target_arch = "aarch64")]
 #[path = "../_arch/aarch64/memory/mmu.rs"]
 mod arch_mmu;
 pub use arch_mmu::*;
 
+mod shootdown;
+pub use shootdown::*;
+
+use crate::{synchronization, synchronization::InitStateLock};
 use core::{fmt, ops::RangeInclusive};
 
 //--------------------------------------------------------------------------------------------------
@@ -37,9 +52,108 @@ pub mod interface {
         ///
         /// - Changes the HW's global state.
         unsafe fn init(&self) -> Result<(), &'static str>;
+
+        /// Turn the MMU off, leaving the executing core on flat physical addressing.
+        ///
+        /// The data caches are cleaned and invalidated, so that memory holds everything that was
+        /// written through them. The TLBs and the instruction cache are invalidated, so that a
+        /// later `init()` starts from a clean slate.
+        ///
+        /// # Safety
+        ///
+        /// - The executing code, its stack and all data that is accessed afterwards must be
+        ///   identity mapped. Otherwise, their addresses change at the switch. The kernel's tables
+        ///   map everything identity, tables installed with `activate_table()` might not.
+        /// - Afterwards, data accesses are Device-nGnRnE. Exclusive accesses, e.g. atomics, and
+        ///   unaligned accesses must be avoided, just like before `init()`.
+        /// - IRQs must be masked on the executing core, and stay masked afterwards, because IRQ
+        ///   handlers take locks. Checked with an assertion.
+        unsafe fn disable(&self);
+
+        /// Whether the MMU is on.
+        fn is_enabled(&self) -> bool;
+
+        /// The attributes that the MMU currently walks the translation tables with, read back
+        /// from the hardware.
+        fn table_walk_attributes(&self) -> super::TableWalkAttributes;
+
+        /// Walk the translation tables and check them for self-consistency.
+        ///
+        /// A debugging aid for code that changes the tables at runtime.
+        fn validate(&self) -> Result<(), super::Inconsistencies>;
+
+        /// Install `table` as the active translation tables of the executing core and return the
+        /// previously active ones for `restore_table()`.
+        ///
+        /// Fails if the table is not aligned to the translation granule.
+        ///
+        /// # Safety
+        ///
+        /// - `table` must map the currently executing code, its stack and all data that is accessed
+        ///   until the previous tables are restored.
+        /// - `table` must not be modified or dropped while it is active.
+        unsafe fn activate_table(
+            &self,
+            table: &super::TranslationTable,
+        ) -> Result<super::TableBase, &'static str>;
+
+        /// Reinstall tables that were returned by `activate_table()`.
+        ///
+        /// # Safety
+        ///
+        /// - Same as for `activate_table()`.
+        unsafe fn restore_table(&self, base: super::TableBase);
+
+        /// Change the attributes of `range` in the kernel's tables, keeping the output addresses.
+        ///
+        /// Fails if `range` is not aligned to the translation granule, or not covered by the
+        /// tables, and then changes nothing. Otherwise, the change is visible to all cores once
+        /// this returns. Should the `tlb_shootdown()` of the change fail, the stale translations
+        /// are invalidated by a broadcast instead.
+        ///
+        /// # Safety
+        ///
+        /// - Nothing that is in use may lose the permissions it is accessed with, e.g. the
+        ///   executing code or its stack.
+        unsafe fn set_attributes(
+            &self,
+            range: core::ops::Range<usize>,
+            attributes: super::AttributeFields,
+        ) -> Result<(), &'static str>;
     }
 }
 
+/// Maximum number of findings that are recorded by `MMU::validate()`.
+pub const MAX_INCONSISTENCIES: usize = 8;
+
+/// An inconsistency in the translation tables.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, PartialEq)]
+pub enum Inconsistency {
+    /// A table descriptor does not point to an aligned next level table of the translation tables.
+    InvalidTableAddress { virt_addr: usize, table_addr: usize },
+
+    /// A next level table is referenced by more than one table descriptor, so the VAs it
+    /// translates are mapped twice.
+    DuplicateMapping {
+        virt_addr: usize,
+        other_virt_addr: usize,
+    },
+
+    /// A block or page output address lacks the alignment of the block or page size.
+    MisalignedOutput {
+        virt_addr: usize,
+        output_addr: usize,
+    },
+}
+
+/// The findings of `MMU::validate()`. Bounded to `MAX_INCONSISTENCIES` entries, the total count of
+/// findings is kept nonetheless.
+pub struct Inconsistencies {
+    findings: [Option<Inconsistency>; MAX_INCONSISTENCIES],
+    total: usize,
+}
+
 /// Architecture agnostic translation types.
 #[allow(missing_docs)]
 #[derive(Copy, Clone)]
@@ -55,6 +169,9 @@ pub enum MemAttributes {
     NonCacheableDRAM,
     CacheableDRAM,
     Device,
+
+    /// Device memory that allows gathering of writes. Suitable for framebuffers.
+    WriteCombining,
 }
 
 /// Architecture agnostic access permissions.
@@ -63,6 +180,9 @@ pub enum MemAttributes {
 pub enum AccessPermissions {
     ReadOnly,
     ReadWrite,
+
+    /// Read-write for the kernel and unprivileged code. Executable for unprivileged code only.
+    ReadWriteUser,
 }
 
 /// Collection of memory attributes.
@@ -83,6 +203,38 @@ pub struct RangeDescriptor {
     pub attribute_fields: AttributeFields,
 }
 
+/// Cacheability of the translation table walks, for either the inner or the outer cache domain.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WalkCacheability {
+    NonCacheable,
+    WriteBackWriteAllocate,
+    WriteThrough,
+    WriteBackNoWriteAllocate,
+}
+
+/// Shareability of the translation table walks.
+#[allow(missing_docs)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum WalkShareability {
+    NonShareable,
+    OuterShareable,
+    InnerShareable,
+}
+
+/// The memory attributes that the MMU accesses the translation tables with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TableWalkAttributes {
+    /// Cacheability in the inner cache domain, i.e. the cores' own caches.
+    pub inner: WalkCacheability,
+
+    /// Cacheability in the outer cache domain, i.e. caches outside of the cluster.
+    pub outer: WalkCacheability,
+
+    /// Which other observers the walks are coherent with.
+    pub shareability: WalkShareability,
+}
+
 /// Type for expressing the kernel's virtual memory layout.
 pub struct KernelVirtualLayout<const NUM_SPECIAL_RANGES: usize> {
     /// The last (inclusive) address of the address space.
@@ -92,9 +244,33 @@ pub struct KernelVirtualLayout<const NUM_SPECIAL_RANGES: usize> {
     inner: [RangeDescriptor; NUM_SPECIAL_RANGES],
 }
 
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Writable only during kernel init. RO afterwards.
+static TABLE_WALK_ATTRIBUTES: InitStateLock<TableWalkAttributes> =
+    InitStateLock::new(TableWalkAttributes::DEFAULT);
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
+use synchronization::interface::ReadWriteEx;
+
+impl TableWalkAttributes {
+    /// Inner shareable, write-back cacheable walks, for tables in cacheable DRAM.
+    pub const DEFAULT: Self = Self {
+        inner: WalkCacheability::WriteBackWriteAllocate,
+        outer: WalkCacheability::WriteBackWriteAllocate,
+        shareability: WalkShareability::InnerShareable,
+    };
+}
+
+impl Default for TableWalkAttributes {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
 
 impl Default for AttributeFields {
     fn default() -> AttributeFields {
@@ -106,6 +282,92 @@ impl Default for AttributeFields {
     }
 }
 
+impl fmt::Display for Inconsistency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Inconsistency::InvalidTableAddress {
+                virt_addr,
+                table_addr,
+            } => write!(
+                f,
+                "{:#010x}: Invalid next level table address {:#x}",
+                virt_addr, table_addr
+            ),
+            Inconsistency::DuplicateMapping {
+                virt_addr,
+                other_virt_addr,
+            } => write!(
+                f,
+                "{:#010x}: Next level table also mapped at {:#010x}",
+                virt_addr, other_virt_addr
+            ),
+            Inconsistency::MisalignedOutput {
+                virt_addr,
+                output_addr,
+            } => write!(
+                f,
+                "{:#010x}: Misaligned output address {:#x}",
+                virt_addr, output_addr
+            ),
+        }
+    }
+}
+
+impl Inconsistencies {
+    /// Create an instance without findings.
+    pub const fn new() -> Self {
+        Self {
+            findings: [None; MAX_INCONSISTENCIES],
+            total: 0,
+        }
+    }
+
+    /// Record a finding. Only the first `MAX_INCONSISTENCIES` findings are stored.
+    pub fn push(&mut self, finding: Inconsistency) {
+        if let Some(slot) = self.findings.get_mut(self.total) {
+            *slot = Some(finding);
+        }
+
+        self.total += 1;
+    }
+
+    /// The total number of findings, including the ones that were not stored.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// Iterate over the stored findings.
+    pub fn iter(&self) -> impl Iterator<Item = &Inconsistency> {
+        self.findings.iter().flatten()
+    }
+
+    /// `Ok` if there are no findings.
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.total == 0 {
+            Ok(())
+        } else {
+            Err(self)
+        }
+    }
+}
+
+/// Scale a size in bytes to the largest unit that keeps it non-zero.
+fn size_with_unit(size: usize) -> (usize, &'static str) {
+    // log2(1024).
+    const KIB_RSHIFT: u32 = 10;
+
+    // log2(1024 * 1024).
+    const MIB_RSHIFT: u32 = 20;
+
+    if (size >> MIB_RSHIFT) > 0 {
+        (size >> MIB_RSHIFT, "MiB")
+    } else if (size >> KIB_RSHIFT) > 0 {
+        (size >> KIB_RSHIFT, "KiB")
+    } else {
+        (size, "Byte")
+    }
+}
+
 /// Human-readable output of a RangeDescriptor.
 impl fmt::Display for RangeDescriptor {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -113,31 +375,19 @@ impl fmt::Display for RangeDescriptor {
         // Rust to copy the value.
         let start = *(self.virtual_range)().start();
         let end = *(self.virtual_range)().end();
-        let size = end - start + 1;
-
-        // log2(1024).
-        const KIB_RSHIFT: u32 = 10;
-
-        // log2(1024 * 1024).
-        const MIB_RSHIFT: u32 = 20;
-
-        let (size, unit) = if (size >> MIB_RSHIFT) > 0 {
-            (size >> MIB_RSHIFT, "MiB")
-        } else if (size >> KIB_RSHIFT) > 0 {
-            (size >> KIB_RSHIFT, "KiB")
-        } else {
-            (size, "Byte")
-        };
+        let (size, unit) = size_with_unit(end - start + 1);
 
         let attr = match self.attribute_fields.mem_attributes {
             MemAttributes::NonCacheableDRAM => "NC",
             MemAttributes::CacheableDRAM => "C",
             MemAttributes::Device => "Dev",
+            MemAttributes::WriteCombining => "WC",
         };
 
         let acc_p = match self.attribute_fields.acc_perms {
             AccessPermissions::ReadOnly => "RO",
             AccessPermissions::ReadWrite => "RW",
+            AccessPermissions::ReadWriteUser => "RWU",
         };
 
         let xn = if self.attribute_fields.execute_never {
@@ -148,7 +398,7 @@ impl fmt::Display for RangeDescriptor {
 
         write!(
             f,
-            "      {:#010x} - {:#010x} | {: >3} {} | {: <3} {} {: <3} | {}",
+            "      {:#010x} - {:#010x} | {: >3} {} | {: <3} {: <3} {: <3} | {}",
             start, end, size, unit, attr, acc_p, xn, self.name
         )
     }
@@ -189,13 +439,58 @@ impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualLayout<{ NUM_SPECIAL_RANGES }
         Ok((virt_addr, AttributeFields::default()))
     }
 
-    /// Print the memory layout.
+    /// Check that the kernel may access `len` bytes at `virt_addr` directly, e.g. on behalf of a
+    /// debugger, and write them if `write`.
+    ///
+    /// The range must be mapped, and must not be device memory, where a read can have side
+    /// effects.
+    pub fn check_access(
+        &self,
+        virt_addr: usize,
+        len: usize,
+        write: bool,
+    ) -> Result<(), &'static str> {
+        let end = virt_addr.checked_add(len).ok_or("Address out of range")?;
+
+        // Attributes are uniform within a granule.
+        let first = virt_addr & !(GRANULE_SIZE - 1);
+        for page in (first..end).step_by(GRANULE_SIZE) {
+            let (_, attributes) = self.virt_addr_properties(core::cmp::max(page, virt_addr))?;
+
+            if let MemAttributes::Device = attributes.mem_attributes {
+                return Err("Device memory");
+            }
+            if write && matches!(attributes.acc_perms, AccessPermissions::ReadOnly) {
+                return Err("Read-only memory");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Print the memory layout, followed by the MMIO regions that were registered at runtime.
     pub fn print_layout(&self) {
         use crate::info;
 
         for i in self.inner.iter() {
             info!("{}", i);
         }
+
+        crate::bsp::memory::with_mmio_regions(|mmio| {
+            for i in mmio {
+                let (size, unit) = size_with_unit(i.region.end - i.region.start);
+
+                info!(
+                    "      {:#010x} - {:#010x} | {: >3} {} | {: <11} | {}",
+                    i.region.start,
+                    i.region.end - 1,
+                    size,
+                    unit,
+                    "MMIO",
+                    i.name
+                );
+            }
+        });
     }
 
     #[cfg(test)]
@@ -203,3 +498,17 @@ impl<const NUM_SPECIAL_RANGES: usize> KernelVirtualLayout<{ NUM_SPECIAL_RANGES }
         &self.inner
     }
 }
+
+/// Set the attributes that `MMU::init()` configures the translation table walks with.
+///
+/// Has no effect on an MMU that is already on.
+pub fn set_table_walk_attributes(attrs: TableWalkAttributes) {
+    let mut r = &TABLE_WALK_ATTRIBUTES;
+    r.write(|a| *a = attrs);
+}
+
+/// The attributes that `MMU::init()` configures the translation table walks with.
+pub fn table_walk_attributes() -> TableWalkAttributes {
+    let mut r = &TABLE_WALK_ATTRIBUTES;
+    r.read(|a| *a)
+}
@@ -0,0 +1,151 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! TLB shootdown.
+//!
+//! # Correctness
+//!
+//! Each core caches translations in its own TLB, and keeps using them after the descriptors in
+//! memory changed, until it invalidates them. A changed mapping can therefore only be relied upon,
+//! e.g. a page that lost its write permission be handed out, once every core that might have
+//! walked the old descriptors dropped them. [`tlb_shootdown()`] takes care of the other cores that
+//! serve IPIs, see `cpu::smp::ipi_cores()`. `MMU::set_attributes()` calls it after updating the
+//! descriptors and invalidating the executing core's TLB, and falls back to a broadcast
+//! invalidation if it fails.
+//!
+//! IPIs are RPi 3 only, see `cpu::smp`. On the RPi 4, there are no other cores to take care of.
+//!
+//! # Ack protocol
+//!
+//! 1. The initiator claims the single shootdown slot. While another core's shootdown holds it, the
+//!    initiator acknowledges that one itself if it is one of its targets, so that two initiators
+//!    never wait for each other.
+//! 2. It publishes the range, then sets one pending ack bit per target core, and sends each target
+//!    an `IpiKind::TlbFlush`.
+//! 3. A target that finds its pending ack bit set invalidates the range, then clears its bit.
+//!    Without its bit set, e.g. for a `TlbFlush` that was sent directly, it invalidates its whole
+//!    TLB.
+//! 4. The initiator waits until all bits are clear, or until [`SHOOTDOWN_TIMEOUT`] passed, and
+//!    gives up the slot.
+
+use crate::{
+    bsp, cpu,
+    cpu::smp::IpiKind,
+    memory::mmu,
+    percpu::{CacheLinePadded, PerCpu},
+    time,
+};
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How long the initiator waits for the acks of all target cores.
+pub const SHOOTDOWN_TIMEOUT: Duration = Duration::from_millis(100);
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Whether a shootdown holds the slot.
+static SHOOTDOWN_BUSY: AtomicBool = AtomicBool::new(false);
+
+/// The range of the shootdown in the slot.
+static SHOOTDOWN_START: AtomicUsize = AtomicUsize::new(0);
+static SHOOTDOWN_END: AtomicUsize = AtomicUsize::new(0);
+
+/// One bit per target core that did not acknowledge yet.
+static PENDING_ACKS: AtomicU32 = AtomicU32::new(0);
+
+/// How many shootdowns each core acknowledged.
+static SHOOTDOWNS_ACKED: PerCpu<AtomicUsize, { bsp::cpu::NUM_CORES }> = PerCpu::new([
+    CacheLinePadded::new(AtomicUsize::new(0)),
+    CacheLinePadded::new(AtomicUsize::new(0)),
+    CacheLinePadded::new(AtomicUsize::new(0)),
+    CacheLinePadded::new(AtomicUsize::new(0)),
+]);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Send a `TlbFlush` to every core in `targets`.
+fn send_flushes(targets: u32) -> Result<(), &'static str> {
+    for core in 0..bsp::cpu::NUM_CORES {
+        if targets & (1 << core) != 0 {
+            cpu::smp::send_ipi(core as u8, IpiKind::TlbFlush)?;
+        }
+    }
+
+    Ok(())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Invalidate `range` in the TLBs of all other cores that serve IPIs, and wait until they did.
+///
+/// Returns right away if there are no such cores.
+pub fn tlb_shootdown(range: Range<usize>) -> Result<(), &'static str> {
+    let me = 1 << cpu::smp::core_id::<usize>();
+    let targets = cpu::smp::ipi_cores() & !me;
+    if targets == 0 {
+        return Ok(());
+    }
+
+    while SHOOTDOWN_BUSY
+        .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        if PENDING_ACKS.load(Ordering::Acquire) & me != 0 {
+            handle_tlb_flush();
+        }
+        cpu::nop();
+    }
+
+    SHOOTDOWN_START.store(range.start, Ordering::Relaxed);
+    SHOOTDOWN_END.store(range.end, Ordering::Relaxed);
+    PENDING_ACKS.store(targets, Ordering::Release);
+
+    let result = send_flushes(targets).and_then(|_| {
+        time::with_timeout(SHOOTDOWN_TIMEOUT, || {
+            Some(()).filter(|_| PENDING_ACKS.load(Ordering::Acquire) == 0)
+        })
+        .map_err(|_| "TLB shootdown timed out")
+    });
+
+    PENDING_ACKS.store(0, Ordering::Relaxed);
+    SHOOTDOWN_BUSY.store(false, Ordering::Release);
+
+    result
+}
+
+/// Handle a `TlbFlush` IPI on the executing core, see the ack protocol.
+pub fn handle_tlb_flush() {
+    let core: usize = cpu::smp::core_id();
+    let me = 1 << core;
+
+    if PENDING_ACKS.load(Ordering::Acquire) & me == 0 {
+        mmu::invalidate_local_tlb();
+        return;
+    }
+
+    let start = SHOOTDOWN_START.load(Ordering::Relaxed);
+    let end = SHOOTDOWN_END.load(Ordering::Relaxed);
+    mmu::invalidate_local_tlb_range(start..end);
+
+    SHOOTDOWNS_ACKED.current().fetch_add(1, Ordering::Relaxed);
+    PENDING_ACKS.fetch_and(!me, Ordering::Release);
+}
+
+/// How many shootdowns `core` acknowledged since boot.
+pub fn shootdowns_acked(core: usize) -> usize {
+    SHOOTDOWNS_ACKED.get(core).load(Ordering::Relaxed)
+}
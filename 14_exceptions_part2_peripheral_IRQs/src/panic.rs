@@ -0,0 +1,380 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Configurable panic behavior.
+//!
+//! After the panic handler printed its diagnostics, it acts according to [`behavior()`]:
+//!
+//! - [`PanicBehavior::Halt`]: Park the core. The default.
+//! - [`PanicBehavior::Reboot`]: Reset the board after a delay, e.g. for unattended systems.
+//! - [`PanicBehavior::DebugShell`]: Drop into a minimal, read-only shell on the UART, to inspect
+//!   the wreckage.
+//!
+//! If a framebuffer console was handed over with [`set_framebuffer_console()`], the panic handler
+//! also draws the end of the kernel log and the panic message on it, before acting. Should drawing
+//! panic in turn, e.g. because the framebuffer is what broke, the console is dropped and the panic
+//! continues on the UART only.
+//!
+//! Everything on the panic path works without the heap, as it may be what broke. The configuration
+//! is stored with plain atomic loads and stores, which do not depend on the MMU being on.
+//!
+//! The framebuffer console and the kernel log are reached through their `IRQSafeNullLock`s. These
+//! only mask IRQs on the executing core and never wait, so the panic path can not deadlock on them,
+//! even if the panic hit while one of them was held. They do not exclude other cores, though. If
+//! another core writes the log at the same time, the drawn log may be garbled.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/panic.rs"]
+mod arch_panic;
+pub use arch_panic::*;
+
+use crate::{
+    bsp, gfx::interface::TextSink, log, synchronization, synchronization::IRQSafeNullLock, time,
+    time::interface::TimeManager,
+};
+use core::{
+    fmt,
+    fmt::Write,
+    panic::PanicInfo,
+    str,
+    sync::atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const KIND_HALT: u8 = 0;
+const KIND_REBOOT: u8 = 1;
+const KIND_DEBUG_SHELL: u8 = 2;
+
+/// Longest command line, in bytes, that the debug shell accepts.
+const LINE_SIZE: usize = 64;
+
+const PROMPT: &str = "debug> ";
+
+/// Longest panic message, in bytes, that is drawn on the framebuffer console.
+const MESSAGE_SIZE: usize = 256;
+
+/// A panic message, truncated to fit.
+struct MessageBuffer {
+    buf: [u8; MESSAGE_SIZE],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// What the panic handler does after printing its diagnostics.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PanicBehavior {
+    /// Wait forever.
+    Halt,
+
+    /// Reset the board after the given delay.
+    Reboot(Duration),
+
+    /// Run the debug shell.
+    DebugShell,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static KIND: AtomicU8 = AtomicU8::new(KIND_HALT);
+static REBOOT_DELAY_NS: AtomicU64 = AtomicU64::new(0);
+
+static FRAMEBUFFER_CONSOLE: IRQSafeNullLock<Option<&'static mut dyn TextSink>> =
+    IRQSafeNullLock::new(None);
+
+/// Set while the panic handler draws on the framebuffer console.
+static FRAMEBUFFER_DRAWING: AtomicBool = AtomicBool::new(false);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl MessageBuffer {
+    const fn new() -> Self {
+        Self {
+            buf: [0; MESSAGE_SIZE],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        log::valid_prefix(&self.buf[..self.len])
+    }
+}
+
+impl fmt::Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(MESSAGE_SIZE - self.len);
+        self.buf[self.len..(self.len + n)].copy_from_slice(&s.as_bytes()[..n]);
+        self.len += n;
+
+        Ok(())
+    }
+}
+
+/// Parse a hexadecimal number, with or without a `0x` prefix.
+fn parse_hex(s: &str) -> Option<usize> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+
+    usize::from_str_radix(digits, 16).ok()
+}
+
+/// Read a line into `buf`, echoing it back. Returns the line without the newline.
+fn read_line<'a>(out: &mut impl Write, buf: &'a mut [u8; LINE_SIZE]) -> &'a str {
+    let mut len = 0;
+
+    loop {
+        let c = unsafe { bsp::console::panic_read_char() };
+
+        match c {
+            '\n' => {
+                let _ = out.write_char('\n');
+                break;
+            }
+            // Backspace and DEL.
+            '\x08' | '\x7F' => {
+                if len > 0 {
+                    len -= 1;
+                    let _ = out.write_str("\x08 \x08");
+                }
+            }
+            c if c.is_ascii() && !c.is_ascii_control() && len < LINE_SIZE => {
+                buf[len] = c as u8;
+                len += 1;
+                let _ = out.write_char(c);
+            }
+            _ => (),
+        }
+    }
+
+    // Only printable ASCII was stored.
+    str::from_utf8(&buf[..len]).unwrap_or("")
+}
+
+/// Print the 64 bit word at `addr`.
+fn peek(out: &mut impl Write, arg: Option<&str>) -> fmt::Result {
+    let addr = match arg.and_then(parse_hex) {
+        None => return writeln!(out, "Usage: peek <hex address>"),
+        Some(addr) => addr,
+    };
+
+    if addr % 8 != 0 {
+        return writeln!(out, "Address must be 8 byte aligned");
+    }
+
+    // Reading an unmapped address would fault, and end the shell with a nested panic.
+    if let Err(msg) = bsp::memory::mmu::virt_mem_layout().check_access(addr, 8, false) {
+        return writeln!(out, "{:#018x}: {}", addr, msg);
+    }
+
+    let value = unsafe { core::ptr::read_volatile(addr as *const u64) };
+    writeln!(out, "{:#018x}: {:#018x}", addr, value)
+}
+
+/// Print the return addresses of the frames above the panic handler.
+fn backtrace(out: &mut impl Write, regs: &Registers) -> fmt::Result {
+    let mut i = 0;
+    let mut result = Ok(());
+
+    unsafe {
+        walk_frames(regs.fp, |lr| {
+            result = result.and_then(|_| writeln!(out, "{:>3}: {:#018x}", i, lr));
+            i += 1;
+        })
+    };
+
+    result?;
+    if i == 0 {
+        writeln!(out, "No frame records found")?;
+    }
+
+    Ok(())
+}
+
+fn execute(out: &mut impl Write, line: &str, regs: &Registers) -> fmt::Result {
+    let mut words = line.split_whitespace();
+
+    match words.next() {
+        None => Ok(()),
+        Some("help") => writeln!(
+            out,
+            "Commands:\n\
+             \x20 peek <addr>  Print the 64 bit word at a hex address\n\
+             \x20 regs         Print the registers captured at the panic\n\
+             \x20 backtrace    Print the return addresses of the panicking call chain\n\
+             \x20 faults       Print the recorded recoverable faults\n\
+             \x20 reboot       Reset the board"
+        ),
+        Some("peek") => peek(out, words.next()),
+        Some("regs") => writeln!(out, "{}", regs),
+        Some("backtrace") => backtrace(out, regs),
+        Some("faults") => crate::fault::write_to(out),
+        Some("reboot") => crate::panic_wait::_panic_reset(),
+        Some(cmd) => writeln!(out, "Unknown command: {}. Try 'help'", cmd),
+    }
+}
+
+fn debug_shell(regs: &Registers) -> ! {
+    let mut out = unsafe { bsp::console::panic_console_out() };
+    let mut buf = [0; LINE_SIZE];
+
+    let _ = writeln!(
+        out,
+        "Entering the debug shell. Type 'help' for a list of commands"
+    );
+    loop {
+        let _ = out.write_str(PROMPT);
+
+        let line = read_line(&mut out, &mut buf);
+        let _ = execute(&mut out, line, regs);
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Set what the panic handler does after printing its diagnostics.
+pub fn set_behavior(behavior: PanicBehavior) {
+    let kind = match behavior {
+        PanicBehavior::Halt => KIND_HALT,
+        PanicBehavior::Reboot(delay) => {
+            REBOOT_DELAY_NS.store(delay.as_nanos() as u64, Ordering::Relaxed);
+            KIND_REBOOT
+        }
+        PanicBehavior::DebugShell => KIND_DEBUG_SHELL,
+    };
+
+    KIND.store(kind, Ordering::Release);
+}
+
+/// The configured panic behavior.
+pub fn behavior() -> PanicBehavior {
+    match KIND.load(Ordering::Acquire) {
+        KIND_REBOOT => PanicBehavior::Reboot(Duration::from_nanos(
+            REBOOT_DELAY_NS.load(Ordering::Relaxed),
+        )),
+        KIND_DEBUG_SHELL => PanicBehavior::DebugShell,
+        _ => PanicBehavior::Halt,
+    }
+}
+
+/// Hand over a console that the panic handler draws the end of the kernel log on.
+///
+/// The console is used by the panic handler only, so nothing else must draw on its framebuffer.
+pub fn set_framebuffer_console(console: &'static mut dyn TextSink) {
+    let mut r = &FRAMEBUFFER_CONSOLE;
+    r.lock(|c| *c = Some(console));
+}
+
+/// Draw the end of the kernel log and the panic message on the framebuffer console, if there is
+/// one. Called by the panic handler, after it printed the message to the UART.
+pub(crate) fn draw_on_framebuffer(info: &PanicInfo) {
+    let mut msg = MessageBuffer::new();
+    let _ = match info.message() {
+        Some(args) => write!(msg, "Kernel panic: {}", args),
+        None => write!(msg, "Kernel panic!"),
+    };
+
+    let mut r = &FRAMEBUFFER_CONSOLE;
+    r.lock(|console| {
+        let console = match console {
+            None => return,
+            Some(console) => console,
+        };
+
+        FRAMEBUFFER_DRAWING.store(true, Ordering::Relaxed);
+
+        console.clear();
+
+        // Leave room for the panic message at the bottom.
+        let msg_lines = msg.as_str().lines().count();
+        log::tail_lines(console.rows().saturating_sub(msg_lines), |line| {
+            console.write_line(line)
+        });
+        for line in msg.as_str().lines() {
+            console.write_line(line);
+        }
+
+        FRAMEBUFFER_DRAWING.store(false, Ordering::Relaxed);
+    });
+}
+
+/// If a panic happened while drawing on the framebuffer console, drop the console and return
+/// `true`.
+///
+/// Called by the panic handler on a nested panic, to decide whether it can carry on with the UART.
+pub(crate) fn abandon_framebuffer() -> bool {
+    if !FRAMEBUFFER_DRAWING.load(Ordering::Relaxed) {
+        return false;
+    }
+    FRAMEBUFFER_DRAWING.store(false, Ordering::Relaxed);
+
+    let mut r = &FRAMEBUFFER_CONSOLE;
+    r.lock(|c| *c = None);
+
+    true
+}
+
+/// Act on the configured behavior. Called by the panic handler, after it printed the message.
+pub(crate) fn finish(regs: &Registers) -> ! {
+    match behavior() {
+        PanicBehavior::Halt => crate::panic_wait::_panic_exit(),
+        PanicBehavior::Reboot(delay) => {
+            let mut out = unsafe { bsp::console::panic_console_out() };
+            let _ = writeln!(out, "Rebooting in {} ms", delay.as_millis());
+
+            time::time_manager().spin_for(delay);
+            crate::panic_wait::_panic_reset()
+        }
+        PanicBehavior::DebugShell => debug_shell(regs),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// The behavior must read back as it was set, including the reboot delay.
+    #[kernel_test]
+    fn behavior_round_trips() {
+        set_behavior(PanicBehavior::Reboot(Duration::from_millis(1500)));
+        assert_eq!(
+            behavior(),
+            PanicBehavior::Reboot(Duration::from_millis(1500))
+        );
+
+        set_behavior(PanicBehavior::DebugShell);
+        assert_eq!(behavior(), PanicBehavior::DebugShell);
+
+        // Leave the default behind for the tests that follow.
+        set_behavior(PanicBehavior::Halt);
+        assert_eq!(behavior(), PanicBehavior::Halt);
+    }
+
+    /// Hex numbers must parse with and without a prefix.
+    #[kernel_test]
+    fn parse_hex_accepts_prefixes() {
+        assert_eq!(parse_hex("0x80800"), Some(0x80800));
+        assert_eq!(parse_hex("80800"), Some(0x80800));
+        assert_eq!(parse_hex("0xZZ"), None);
+    }
+}
@@ -2,10 +2,24 @@
 //
 // Copyright (c) 2018-2020 Andre Richter <andre.o.richter@gmail.com>
 
-//! A panic handler that infinitely waits.
+//! A panic handler that prints the panic message, and then acts as configured in [`crate::panic`].
+//!
+//! The message goes to the UART first, and then, with the end of the kernel log, to the framebuffer
+//! console if one was set.
 
-use crate::{bsp, cpu};
-use core::{fmt, panic::PanicInfo};
+use crate::{bsp, cpu, exception, panic};
+use core::{
+    fmt,
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+/// Set on entry to the panic handler, to catch panics of the panic path itself.
+static PANICKING: AtomicBool = AtomicBool::new(false);
 
 //--------------------------------------------------------------------------------------------------
 // Private Code
@@ -30,10 +44,20 @@ fn _panic_print(args: fmt::Arguments) {
 #[cfg(not(test))]
 #[linkage = "weak"]
 #[no_mangle]
-fn _panic_exit() -> ! {
+pub(crate) fn _panic_exit() -> ! {
     cpu::wait_forever()
 }
 
+/// The point of reset for `PanicBehavior::Reboot` and the debug shell's `reboot` command.
+///
+/// Linked weakly, so that integration tests can overload it to check that a reset was requested.
+/// QEMU does not emulate the board's watchdog.
+#[linkage = "weak"]
+#[no_mangle]
+pub(crate) fn _panic_reset() -> ! {
+    bsp::reset()
+}
+
 /// Prints with a newline - only use from the panic handler.
 ///
 /// Carbon copy from https://doc.rust-lang.org/src/std/macros.rs.html
@@ -46,13 +70,43 @@ macro_rules! panic_println {
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    let regs = panic::Registers::capture();
+
+    // A panic while handling a panic, e.g. a fault in the debug shell, must not loop.
+    let nested = PANICKING.load(Ordering::Relaxed);
+
+    // A panicking IRQ handler only takes itself down, see `exception::asynchronous`.
+    if !nested && exception::asynchronous::in_recoverable_handler() {
+        if let Some(args) = info.message() {
+            panic_println!("\nIRQ handler panic: {}", args);
+        } else {
+            panic_println!("\nIRQ handler panic!");
+        }
+
+        exception::asynchronous::recover_from_handler_panic();
+    }
+
+    PANICKING.store(true, Ordering::Relaxed);
+
     if let Some(args) = info.message() {
         panic_println!("\nKernel panic: {}", args);
     } else {
         panic_println!("\nKernel panic!");
     }
 
-    _panic_exit()
+    if nested {
+        // The framebuffer, not the original panic, is what failed. The UART still works.
+        if panic::abandon_framebuffer() {
+            panic_println!("Panic while drawing on the framebuffer, continuing on the UART only");
+            panic::finish(&regs)
+        }
+
+        _panic_exit()
+    }
+
+    panic::draw_on_framebuffer(info);
+
+    panic::finish(&regs)
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -62,6 +116,6 @@ fn panic(info: &PanicInfo) -> ! {
 /// The point of exit when the library is compiled for testing.
 #[cfg(test)]
 #[no_mangle]
-fn _panic_exit() -> ! {
+pub(crate) fn _panic_exit() -> ! {
     cpu::qemu_exit_failure()
 }
@@ -0,0 +1,212 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Per-core data.
+//!
+//! Data that is logically owned by a core, like statistics, is stored in a [`PerCpu`] container
+//! which holds one slot per core. Each slot occupies its own cache line, so that a core updating
+//! its slot does not invalidate the cache lines of other cores' slots (false sharing).
+
+use crate::{bsp, cpu, info};
+use core::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The cache line size of the Cortex-A53 and Cortex-A72.
+pub const CACHE_LINE_SIZE: usize = 64;
+
+/// A value that is aligned to, and padded to a multiple of, the cache line size.
+#[repr(C, align(64))]
+pub struct CacheLinePadded<T>(T);
+
+/// A container that stores one `T` per core.
+///
+/// # Initialization
+///
+/// A `PerCpu` is meant to live in a `static`, which is shared by all cores. All slots must
+/// therefore be initialized at compile time, or by the boot core before the other cores are
+/// started. Afterwards, access is through shared references only, so `T` needs interior mutability
+/// (e.g. atomics) if it is to be updated.
+pub struct PerCpu<T, const CORES: usize> {
+    slots: [CacheLinePadded<T>; CORES],
+}
+
+/// Statistics of a single core.
+///
+/// The counters are atomics, so that other cores can read them while the owning core updates
+/// them.
+pub struct Stats {
+    irqs_serviced: AtomicUsize,
+    context_switches: AtomicUsize,
+    idle_ns: AtomicU64,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static STATS: PerCpu<Stats, { bsp::cpu::NUM_CORES }> = PerCpu::new([
+    CacheLinePadded::new(Stats::new()),
+    CacheLinePadded::new(Stats::new()),
+    CacheLinePadded::new(Stats::new()),
+    CacheLinePadded::new(Stats::new()),
+]);
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<T> CacheLinePadded<T> {
+    /// Create an instance.
+    pub const fn new(value: T) -> Self {
+        Self(value)
+    }
+}
+
+impl<T, const CORES: usize> PerCpu<T, CORES> {
+    /// Create an instance.
+    pub const fn new(slots: [CacheLinePadded<T>; CORES]) -> Self {
+        Self { slots }
+    }
+
+    /// Return the slot of the executing core.
+    pub fn current(&self) -> &T {
+        self.get(cpu::smp::core_id())
+    }
+
+    /// Return the slot of the given core.
+    ///
+    /// Panics if `core` is not smaller than `CORES`.
+    pub fn get(&self, core: usize) -> &T {
+        &self.slots[core].0
+    }
+
+    /// Iterate over the slots of all cores, in order of core id.
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.slots.iter().map(|slot| &slot.0)
+    }
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            irqs_serviced: AtomicUsize::new(0),
+            context_switches: AtomicUsize::new(0),
+            idle_ns: AtomicU64::new(0),
+        }
+    }
+
+    /// Account one serviced IRQ.
+    pub fn inc_irqs_serviced(&self) {
+        self.irqs_serviced.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account one context switch.
+    pub fn inc_context_switches(&self) {
+        self.context_switches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Account time spent idling.
+    pub fn add_idle_time(&self, duration: Duration) {
+        self.idle_ns
+            .fetch_add(duration.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Number of serviced IRQs.
+    pub fn irqs_serviced(&self) -> usize {
+        self.irqs_serviced.load(Ordering::Relaxed)
+    }
+
+    /// Number of context switches.
+    pub fn context_switches(&self) -> usize {
+        self.context_switches.load(Ordering::Relaxed)
+    }
+
+    /// Total time spent idling.
+    pub fn idle_time(&self) -> Duration {
+        Duration::from_nanos(self.idle_ns.load(Ordering::Relaxed))
+    }
+}
+
+/// Return the statistics of the executing core.
+pub fn stats() -> &'static Stats {
+    STATS.current()
+}
+
+/// Return the statistics of the given core.
+pub fn stats_of(core: usize) -> &'static Stats {
+    STATS.get(core)
+}
+
+/// Print the statistics of all cores.
+pub fn print_all() {
+    info!("      Core | IRQs serviced | Context switches | Idle time");
+    info!("      -----+---------------+------------------+-------------");
+    for (core, stats) in STATS.iter().enumerate() {
+        info!(
+            "      {:>4} | {:>13} | {:>16} | {:>8} ms",
+            core,
+            stats.irqs_serviced(),
+            stats.context_switches(),
+            stats.idle_time().as_millis()
+        );
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sched;
+    use core::{mem, sync::atomic::AtomicU32};
+    use test_macros::kernel_test;
+
+    static mut STACK: [u8; 8192] = [0; 8192];
+
+    fn returning_task() {}
+
+    /// Context switches must be counted for the executing core. IRQs and idle time are covered by
+    /// the `percpu_stats` integration test, which sets up IRQ handling.
+    #[kernel_test]
+    fn context_switches_are_counted() {
+        let stats = stats();
+        let switches = stats.context_switches();
+
+        // Switching to the task and back from it after it returned.
+        sched::spawn(returning_task, unsafe { &mut STACK }).unwrap();
+        sched::yield_now();
+        assert_eq!(stats.context_switches(), switches + 2);
+
+        // Other cores are not running, so their counters must not move.
+        assert_eq!(stats_of(1).context_switches(), 0);
+    }
+
+    /// `current()` must return the executing core's slot, and slots must not share cache lines.
+    #[kernel_test]
+    fn per_cpu_slots_are_core_indexed_and_cache_line_aligned() {
+        static PER_CPU: PerCpu<AtomicU32, 2> = PerCpu::new([
+            CacheLinePadded::new(AtomicU32::new(0)),
+            CacheLinePadded::new(AtomicU32::new(1)),
+        ]);
+
+        assert_eq!(cpu::smp::core_id::<usize>(), 0);
+        assert!(core::ptr::eq(PER_CPU.current(), PER_CPU.get(0)));
+
+        for slot in PER_CPU.iter() {
+            assert_eq!(slot as *const _ as usize % CACHE_LINE_SIZE, 0);
+        }
+        assert_eq!(
+            mem::size_of::<CacheLinePadded<AtomicU32>>(),
+            CACHE_LINE_SIZE
+        );
+    }
+}
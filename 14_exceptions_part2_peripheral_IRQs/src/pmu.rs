@@ -0,0 +1,36 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Performance monitors.
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/pmu.rs"]
+mod arch_pmu;
+pub use arch_pmu::*;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// The number of `event`s that happened between two `read()`s, taking counter wrap into account.
+pub fn delta(event: Event, start: u64, end: u64) -> u64 {
+    end.wrapping_sub(start) & event.counter_mask()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// A 32 bit event counter that wrapped between the reads must still give the right delta.
+    #[kernel_test]
+    fn delta_handles_event_counter_wrap() {
+        assert_eq!(delta(Event::L1DCacheRefill, 0xFFFF_FFF0, 0x10), 0x20);
+        assert_eq!(delta(Event::Cycles, 100, 150), 50);
+    }
+}
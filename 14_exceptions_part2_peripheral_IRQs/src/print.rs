@@ -4,7 +4,7 @@
 
 //! Printing facilities.
 
-use crate::{bsp, console};
+use crate::{bsp, console, log};
 use core::fmt;
 
 //--------------------------------------------------------------------------------------------------
@@ -15,7 +15,20 @@ use core::fmt;
 pub fn _print(args: fmt::Arguments) {
     use console::interface::Write;
 
-    bsp::console::console().write_fmt(args).unwrap();
+    log::record(args);
+    if bsp::console::backend() != bsp::console::Backend::Null {
+        bsp::console::console().write_fmt(args).unwrap();
+    }
+}
+
+#[doc(hidden)]
+pub fn _early_print(args: fmt::Arguments) {
+    use fmt::Write;
+
+    log::record(args);
+
+    // The console driver might not be initialized yet. The panic UART brings the UART up by itself.
+    let _ = unsafe { bsp::console::panic_console_out().write_fmt(args) };
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -41,37 +54,90 @@ macro_rules! println {
     })
 }
 
+/// Prints with a newline, before the console driver is initialized.
+///
+/// Use only for reporting during early kernel init, e.g. a misconfiguration that prevents the
+/// drivers from being loaded.
+#[macro_export]
+macro_rules! early_println {
+    ($($arg:tt)*) => ({
+        $crate::print::_early_print(format_args_nl!($($arg)*));
+    })
+}
+
 /// Prints an info, with a newline.
 #[macro_export]
 macro_rules! info {
     ($string:expr) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
-
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000
-        ));
+        if $crate::log::enabled($crate::log::Level::Info) {
+            #[allow(unused_imports)]
+            use crate::time::interface::TimeManager;
+
+            let timestamp = $crate::time::time_manager().uptime();
+            let timestamp_subsec_us = timestamp.subsec_micros();
+
+            $crate::print::_print(format_args_nl!(
+                concat!("[  {:>3}.{:03}{:03}] ", $string),
+                timestamp.as_secs(),
+                timestamp_subsec_us / 1_000,
+                timestamp_subsec_us % 1_000
+            ));
+        }
     });
     ($format_string:expr, $($arg:tt)*) => ({
-        #[allow(unused_imports)]
-        use crate::time::interface::TimeManager;
-
-        let timestamp = $crate::time::time_manager().uptime();
-        let timestamp_subsec_us = timestamp.subsec_micros();
+        if $crate::log::enabled($crate::log::Level::Info) {
+            #[allow(unused_imports)]
+            use crate::time::interface::TimeManager;
+
+            let timestamp = $crate::time::time_manager().uptime();
+            let timestamp_subsec_us = timestamp.subsec_micros();
+
+            $crate::print::_print(format_args_nl!(
+                concat!("[  {:>3}.{:03}{:03}] ", $format_string),
+                timestamp.as_secs(),
+                timestamp_subsec_us / 1_000,
+                timestamp_subsec_us % 1_000,
+                $($arg)*
+            ));
+        }
+    })
+}
 
-        $crate::print::_print(format_args_nl!(
-            concat!("[  {:>3}.{:03}{:03}] ", $format_string),
-            timestamp.as_secs(),
-            timestamp_subsec_us / 1_000,
-            timestamp_subsec_us % 1_000,
-            $($arg)*
-        ));
+/// Prints a debug message, with a newline.
+#[macro_export]
+macro_rules! debug {
+    ($string:expr) => ({
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            #[allow(unused_imports)]
+            use crate::time::interface::TimeManager;
+
+            let timestamp = $crate::time::time_manager().uptime();
+            let timestamp_subsec_us = timestamp.subsec_micros();
+
+            $crate::print::_print(format_args_nl!(
+                concat!("[D {:>3}.{:03}{:03}] ", $string),
+                timestamp.as_secs(),
+                timestamp_subsec_us / 1_000,
+                timestamp_subsec_us % 1_000
+            ));
+        }
+    });
+    ($format_string:expr, $($arg:tt)*) => ({
+        if $crate::log::enabled($crate::log::Level::Debug) {
+            #[allow(unused_imports)]
+            use crate::time::interface::TimeManager;
+
+            let timestamp = $crate::time::time_manager().uptime();
+            let timestamp_subsec_us = timestamp.subsec_micros();
+
+            $crate::print::_print(format_args_nl!(
+                concat!("[D {:>3}.{:03}{:03}] ", $format_string),
+                timestamp.as_secs(),
+                timestamp_subsec_us / 1_000,
+                timestamp_subsec_us % 1_000,
+                $($arg)*
+            ));
+        }
     })
 }
 
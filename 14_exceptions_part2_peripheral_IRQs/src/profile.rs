@@ -0,0 +1,268 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Sampling profiler.
+//!
+//! A periodic IRQ of the virtual timer samples the PC that it interrupted into a histogram. The
+//! kernel's code is split into [`NUM_BUCKETS`] equally sized address buckets, so memory use is
+//! fixed. The sampling rate is capped at [`MAX_RATE_HZ`], which bounds the overhead to a short IRQ
+//! handler run per sample.
+//!
+//! The handler must be registered with [`init()`] during kernel init. Afterwards, profiling can be
+//! [`start()`]ed and [`stop()`]ped at any time, and the hottest buckets printed with [`report()`].
+
+use crate::{bsp, exception, info, synchronization, synchronization::IRQSafeNullLock, time, warn};
+use core::{ops::Range, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of address buckets in the histogram.
+pub const NUM_BUCKETS: usize = 256;
+
+/// The highest supported sampling rate.
+pub const MAX_RATE_HZ: u32 = 10_000;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of buckets printed by `report()`.
+const NUM_REPORTED: usize = 8;
+
+struct Histogram {
+    /// The sampling period. Zero if profiling is stopped.
+    period: Duration,
+
+    /// Start of the sampled address range.
+    base: usize,
+
+    /// Address range covered by a single bucket.
+    bucket_size: usize,
+
+    buckets: [u32; NUM_BUCKETS],
+
+    /// Samples that were taken outside of the sampled address range.
+    outside: u32,
+}
+
+/// The virtual timer's IRQ handler.
+struct ProfileTimer;
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static HISTOGRAM: IRQSafeNullLock<Histogram> = IRQSafeNullLock::new(Histogram::new());
+
+static PROFILE_TIMER: ProfileTimer = ProfileTimer;
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            period: Duration::from_secs(0),
+            base: 0,
+            bucket_size: 1,
+            buckets: [0; NUM_BUCKETS],
+            outside: 0,
+        }
+    }
+
+    /// Clear all samples and cover `range` with the buckets.
+    fn reset(&mut self, range: Range<usize>) {
+        let len = range.end - range.start;
+
+        self.base = range.start;
+        self.bucket_size = core::cmp::max(1, (len + NUM_BUCKETS - 1) / NUM_BUCKETS);
+        self.buckets = [0; NUM_BUCKETS];
+        self.outside = 0;
+    }
+
+    fn record(&mut self, pc: usize) {
+        let index = pc.wrapping_sub(self.base) / self.bucket_size;
+
+        match self.buckets.get_mut(index) {
+            Some(bucket) => *bucket = bucket.saturating_add(1),
+            None => self.outside = self.outside.saturating_add(1),
+        }
+    }
+
+    fn bucket_range(&self, index: usize) -> Range<usize> {
+        let start = self.base + index * self.bucket_size;
+
+        start..(start + self.bucket_size)
+    }
+
+    fn num_samples(&self) -> u32 {
+        self.buckets.iter().sum::<u32>() + self.outside
+    }
+
+    /// The samples of all buckets that overlap `range`.
+    fn samples_in(&self, range: &Range<usize>) -> u32 {
+        (0..NUM_BUCKETS)
+            .filter(|&i| {
+                let bucket = self.bucket_range(i);
+                bucket.start < range.end && range.start < bucket.end
+            })
+            .map(|i| self.buckets[i])
+            .sum()
+    }
+
+    /// The index of the bucket with the most samples, ignoring the buckets in `exclude`.
+    fn hottest(&self, exclude: &[usize]) -> Option<usize> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(i, &count)| count > 0 && !exclude.contains(i))
+            .max_by_key(|(_, &count)| count)
+            .map(|(i, _)| i)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+/// Register and enable the profiling timer IRQ.
+///
+/// Must be called during kernel init, after `time::select_counter()`. Fails if the sleeps took the
+/// virtual timer, see `time::arm_virtual_timer()`.
+pub fn init() -> Result<(), &'static str> {
+    use bsp::exception::asynchronous::{irq_manager, virtual_timer_irq};
+    use exception::asynchronous::{interface::IRQManager, IRQDescriptor};
+
+    if time::counter_kind() == time::CounterKind::Virtual {
+        return Err("The virtual timer is taken by the sleeps");
+    }
+
+    let descriptor = IRQDescriptor {
+        name: "Profiler",
+        handler: &PROFILE_TIMER,
+    };
+
+    irq_manager().register_handler(virtual_timer_irq(), descriptor)?;
+    irq_manager().enable(virtual_timer_irq());
+
+    Ok(())
+}
+
+/// Clear the histogram and start sampling the kernel's code at `rate_hz`.
+pub fn start(rate_hz: u32) -> Result<(), &'static str> {
+    if rate_hz == 0 || rate_hz > MAX_RATE_HZ {
+        return Err("Unsupported profiling rate");
+    }
+
+    let period = Duration::from_nanos(1_000_000_000 / u64::from(rate_hz));
+
+    let mut r = &HISTOGRAM;
+    r.lock(|hist| {
+        hist.reset(bsp::memory::ro_range());
+        hist.period = period;
+    });
+
+    if let Err(msg) = time::arm_virtual_timer(period) {
+        r.lock(|hist| hist.period = Duration::from_secs(0));
+        return Err(msg);
+    }
+
+    Ok(())
+}
+
+/// Stop sampling. The histogram is kept for `report()`.
+pub fn stop() {
+    time::disarm_virtual_timer();
+
+    let mut r = &HISTOGRAM;
+    r.lock(|hist| hist.period = Duration::from_secs(0));
+}
+
+/// The total number of samples taken since the last `start()`.
+pub fn num_samples() -> u32 {
+    let mut r = &HISTOGRAM;
+    r.lock(|hist| hist.num_samples())
+}
+
+/// The address range and sample count of the bucket with the most samples.
+pub fn hottest_bucket() -> Option<(Range<usize>, u32)> {
+    let mut r = &HISTOGRAM;
+    r.lock(|hist| {
+        hist.hottest(&[])
+            .map(|i| (hist.bucket_range(i), hist.buckets[i]))
+    })
+}
+
+/// The number of samples in the buckets that overlap `range`.
+///
+/// Code that straddles a bucket boundary is counted in full, unlike with `hottest_bucket()`.
+pub fn samples_in(range: Range<usize>) -> u32 {
+    let mut r = &HISTOGRAM;
+    r.lock(|hist| hist.samples_in(&range))
+}
+
+/// Print the hottest buckets.
+pub fn report() {
+    let mut r = &HISTOGRAM;
+    r.lock(|hist| {
+        let total = hist.num_samples();
+        if total == 0 {
+            warn!("Profiler: No samples");
+            return;
+        }
+
+        info!(
+            "Profiler: {} samples, {} outside of kernel code",
+            total, hist.outside
+        );
+
+        let mut reported = [usize::max_value(); NUM_REPORTED];
+        for i in 0..NUM_REPORTED {
+            let index = match hist.hottest(&reported[..i]) {
+                None => break,
+                Some(index) => index,
+            };
+            reported[i] = index;
+
+            let range = hist.bucket_range(index);
+            let count = hist.buckets[index];
+            info!(
+                "      {:#010x}..{:#010x}: {:>6} samples ({:>3} %)",
+                range.start,
+                range.end,
+                count,
+                (u64::from(count) * 100) / u64::from(total)
+            );
+        }
+    });
+}
+
+//------------------------------------------------------------------------------
+// OS Interface Code
+//------------------------------------------------------------------------------
+
+impl exception::asynchronous::interface::IRQHandler for ProfileTimer {
+    fn handle(&self) -> Result<(), &'static str> {
+        let mut r = &HISTOGRAM;
+        r.lock(|hist| {
+            if hist.period == Duration::from_secs(0) {
+                time::disarm_virtual_timer();
+                return;
+            }
+
+            if let Some(pc) = exception::interrupted_pc() {
+                hist.record(pc as usize);
+            }
+
+            // Re-arming also de-asserts the IRQ. It can not fail, as `start()` armed the timer.
+            let _ = time::arm_virtual_timer(hist.period);
+        });
+
+        Ok(())
+    }
+}
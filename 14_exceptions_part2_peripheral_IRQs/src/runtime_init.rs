@@ -37,7 +37,11 @@ unsafe fn bss_range() -> Range<*mut usize> {
 /// - Must only be called pre `kernel_init()`.
 #[inline(always)]
 unsafe fn zero_bss() {
-    memory::zero_volatile(bss_range());
+    let bss = bss_range();
+
+    for (addr, len) in memory::PageRange::new(bss.start as usize, bss.end as usize) {
+        memory::zero_volatile((addr as *mut usize)..((addr + len) as *mut usize));
+    }
 }
 
 //--------------------------------------------------------------------------------------------------
@@ -0,0 +1,334 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Cooperative round-robin scheduling.
+//!
+//! Tasks run until they call [`yield_now()`], [`block()`] or return from their entry function.
+//! Slot 0 of the task table belongs to the idle task, which is whatever code called into the
+//! scheduler first, usually `kernel_main()`. It only runs when no other task is runnable.
+//!
+//! The scheduler does not allocate. Task stacks are provided by the caller of [`spawn()`].
+
+#[cfg(target_arch = "aarch64")]
+#[path = "_arch/aarch64/sched.rs"]
+mod arch_sched;
+pub use arch_sched::*;
+
+use crate::{cpu, percpu, synchronization, synchronization::IRQSafeNullLock, time};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Number of task slots, including the idle task.
+const NUM_TASKS: usize = 8;
+
+const IDLE_TASK: usize = 0;
+
+#[derive(Copy, Clone, PartialEq)]
+enum TaskState {
+    Free,
+    Runnable,
+    Blocked,
+}
+
+#[derive(Copy, Clone)]
+struct Task {
+    state: TaskState,
+    context: Context,
+}
+
+struct CompletionInner {
+    done: bool,
+    waiter: Option<TaskId>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Identifies a task.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TaskId(usize);
+
+/// A fixed-size task table with round-robin selection.
+pub struct Scheduler<const N: usize> {
+    tasks: [Task; N],
+    current: usize,
+}
+
+/// A one-shot event that tasks can wait for.
+pub struct Completion {
+    inner: IRQSafeNullLock<CompletionInner>,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static SCHEDULER: IRQSafeNullLock<Scheduler<NUM_TASKS>> = IRQSafeNullLock::new(Scheduler::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+impl Task {
+    const FREE: Self = Self {
+        state: TaskState::Free,
+        context: Context::new(),
+    };
+}
+
+impl<const N: usize> Scheduler<N> {
+    /// The idle task's slot is never allocated and its state is ignored, so all slots start free.
+    const fn new() -> Self {
+        Self {
+            tasks: [Task::FREE; N],
+            current: IDLE_TASK,
+        }
+    }
+
+    fn spawn(&mut self, entry: fn(), stack: &'static mut [u8]) -> Result<TaskId, &'static str> {
+        let slot = match (1..N).find(|&i| self.tasks[i].state == TaskState::Free) {
+            None => return Err("No free task slot"),
+            Some(i) => i,
+        };
+
+        self.tasks[slot] = Task {
+            state: TaskState::Runnable,
+            context: Context::new_task(entry, stack),
+        };
+
+        Ok(TaskId(slot))
+    }
+
+    /// The next runnable task after the current one, or the idle task if there is none.
+    fn pick_next(&self) -> usize {
+        (1..=N)
+            .map(|i| (self.current + i) % N)
+            .find(|&i| i != IDLE_TASK && self.tasks[i].state == TaskState::Runnable)
+            .unwrap_or(IDLE_TASK)
+    }
+
+    /// Select the next task and return the contexts to switch between, if a switch is needed.
+    fn prepare_switch(&mut self) -> Option<(*mut Context, *const Context)> {
+        let next = self.pick_next();
+        if next == self.current {
+            return None;
+        }
+
+        let from = &mut self.tasks[self.current].context as *mut Context;
+        let to = &self.tasks[next].context as *const Context;
+        self.current = next;
+
+        Some((from, to))
+    }
+
+    fn set_state(&mut self, task: usize, state: TaskState) {
+        // The idle task must always be able to run.
+        if task != IDLE_TASK {
+            self.tasks[task].state = state;
+        }
+    }
+
+    fn num_runnable(&self) -> usize {
+        self.tasks
+            .iter()
+            .skip(1)
+            .filter(|t| t.state == TaskState::Runnable)
+            .count()
+    }
+}
+
+/// Mark the current task as blocked, without switching away from it yet, and return it.
+fn mark_current_blocked() -> TaskId {
+    let mut r = &SCHEDULER;
+    r.lock(|sched| {
+        let current = sched.current;
+        sched.set_state(current, TaskState::Blocked);
+
+        TaskId(current)
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Create a new runnable task that executes `entry` on `stack`.
+///
+/// The task is retired when `entry` returns.
+pub fn spawn(entry: fn(), stack: &'static mut [u8]) -> Result<TaskId, &'static str> {
+    let mut r = &SCHEDULER;
+    r.lock(|sched| sched.spawn(entry, stack))
+}
+
+/// The currently running task.
+pub fn current() -> TaskId {
+    let mut r = &SCHEDULER;
+    r.lock(|sched| TaskId(sched.current))
+}
+
+/// Give up the CPU in favor of the next runnable task.
+///
+/// Returns immediately if no other task is runnable.
+pub fn yield_now() {
+    let mut r = &SCHEDULER;
+    let switch = r.lock(|sched| sched.prepare_switch());
+
+    if let Some((from, to)) = switch {
+        percpu::stats().inc_context_switches();
+        unsafe { Context::switch(from, to) }
+    }
+}
+
+/// Stop scheduling the current task until it is passed to [`unblock()`].
+///
+/// Calling this from the idle task is equivalent to [`yield_now()`].
+pub fn block() {
+    mark_current_blocked();
+
+    yield_now()
+}
+
+/// Make a blocked task runnable again.
+pub fn unblock(task: TaskId) {
+    let mut r = &SCHEDULER;
+    r.lock(|sched| {
+        if sched.tasks[task.0].state == TaskState::Blocked {
+            sched.set_state(task.0, TaskState::Runnable)
+        }
+    })
+}
+
+/// Retire the current task.
+pub fn exit() -> ! {
+    let mut r = &SCHEDULER;
+    r.lock(|sched| {
+        let current = sched.current;
+        sched.set_state(current, TaskState::Free)
+    });
+
+    yield_now();
+
+    // Only the idle task can get here, and it must never exit.
+    cpu::wait_forever()
+}
+
+/// The idle loop. Runs all other tasks and waits for interrupts when none is runnable.
+///
+/// Waiting is tickless, see `time::idle_tickless()`.
+pub fn idle() -> ! {
+    loop {
+        yield_now();
+
+        let mut r = &SCHEDULER;
+        if r.lock(|sched| sched.num_runnable()) == 0 {
+            time::idle_tickless();
+        }
+    }
+}
+
+impl Completion {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            inner: IRQSafeNullLock::new(CompletionInner {
+                done: false,
+                waiter: None,
+            }),
+        }
+    }
+
+    /// Block the current task until `complete()` is called. Returns immediately if that happened
+    /// already.
+    ///
+    /// The task is marked blocked in the same critical section that publishes it as the waiter. A
+    /// `complete()` that finds the waiter therefore always finds it blocked, and its wakeup is not
+    /// lost.
+    pub fn wait(&self) {
+        loop {
+            let mut r = &self.inner;
+            let must_wait = r.lock(|inner| {
+                if inner.done {
+                    return false;
+                }
+
+                inner.waiter = Some(mark_current_blocked());
+                true
+            });
+
+            if !must_wait {
+                return;
+            }
+
+            yield_now();
+        }
+    }
+
+    /// Signal the event and wake up the waiting task, if any.
+    pub fn complete(&self) {
+        let mut r = &self.inner;
+        let waiter = r.lock(|inner| {
+            inner.done = true;
+            inner.waiter.take()
+        });
+
+        if let Some(task) = waiter {
+            unblock(task);
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    const NUM_ROUNDS: usize = 3;
+
+    static mut STACKS: [[u8; 8192]; 3] = [[0; 8192]; 3];
+    static RUN_LOG: IRQSafeNullLock<([usize; 9], usize)> = IRQSafeNullLock::new(([0; 9], 0));
+
+    fn yielding_task() {
+        for _ in 0..NUM_ROUNDS {
+            let mut r = &RUN_LOG;
+            r.lock(|(log, len)| {
+                log[*len] = current().0;
+                *len += 1;
+            });
+
+            yield_now();
+        }
+    }
+
+    /// Three tasks that yield after every step must be run in turns.
+    #[kernel_test]
+    fn tasks_are_rotated_fairly() {
+        let tasks: [TaskId; 3] = unsafe {
+            [
+                spawn(yielding_task, &mut STACKS[0]).unwrap(),
+                spawn(yielding_task, &mut STACKS[1]).unwrap(),
+                spawn(yielding_task, &mut STACKS[2]).unwrap(),
+            ]
+        };
+
+        // Returns once all tasks have finished.
+        yield_now();
+
+        let mut r = &RUN_LOG;
+        r.lock(|(log, len)| {
+            assert_eq!(*len, NUM_ROUNDS * tasks.len());
+
+            for (i, &id) in log.iter().enumerate() {
+                assert_eq!(TaskId(id), tasks[i % tasks.len()]);
+            }
+        });
+    }
+}
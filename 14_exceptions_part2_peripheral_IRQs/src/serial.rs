@@ -0,0 +1,509 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Framed packets over the console's serial line.
+//!
+//! # Frame format
+//!
+//! A frame carries a kind, the payload length and the payload, followed by a CRC-32 over all of
+//! them:
+//!
+//! | Bytes | Content                                            |
+//! |-------|----------------------------------------------------|
+//! | 1     | Kind: `1` for data, `2` for a retransmit request   |
+//! | 2     | Payload length, little endian                      |
+//! | n     | Payload                                            |
+//! | 4     | CRC-32 (IEEE 802.3) of the above, little endian    |
+//!
+//! This is COBS encoded, so that it contains no zero bytes, and terminated by a zero byte. A
+//! zero byte is also sent before each frame. It ends any partial frame that the receiver may still
+//! be holding, e.g. after the sender was reset mid-frame, so that the receiver is back in sync with
+//! the next frame.
+//!
+//! # Errors
+//!
+//! A frame that is cut off runs into the per-byte timeout of [`Framer::recv_frame()`]. A frame that
+//! got corrupted on the way fails the CRC. In both cases, the frame is dropped.
+//! [`Framer::recv_frame_retrying()`] answers corrupted frames with a retransmit request, which the
+//! sender sees as [`FrameError::RetransmitRequested`] when it waits for a reply.
+//!
+//! # Caveats
+//!
+//! Bytes go through `write_byte()` and `read_byte_nb()` of the console, so they are passed on
+//! without any conversion of line endings. While the console's RX IRQ is enabled, its handler
+//! consumes the received bytes first. Use a `Framer` while IRQs are masked, or before the RX IRQ
+//! is enabled.
+
+use crate::{console, time};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const KIND_DATA: u8 = 1;
+const KIND_RETRANSMIT: u8 = 2;
+
+/// Kind and payload length.
+const HEADER_SIZE: usize = 3;
+
+/// CRC-32.
+const TRAILER_SIZE: usize = 4;
+
+const MAX_RAW_SIZE: usize = HEADER_SIZE + MAX_PAYLOAD_SIZE + TRAILER_SIZE;
+
+/// COBS adds one byte per 254 bytes, plus one.
+const MAX_ENCODED_SIZE: usize = MAX_RAW_SIZE + MAX_RAW_SIZE / 254 + 2;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The largest payload that fits in a frame.
+pub const MAX_PAYLOAD_SIZE: usize = 1024;
+
+/// Errors of sending or receiving a frame.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FrameError {
+    /// No byte arrived within the timeout. A partially received frame is dropped.
+    Timeout,
+
+    /// The payload is larger than `MAX_PAYLOAD_SIZE` or than the receive buffer.
+    TooLong,
+
+    /// The frame is not valid COBS, is too short, or its header does not match its length.
+    Malformed,
+
+    /// The frame's CRC does not match its content.
+    Crc,
+
+    /// The other end asked for the last frame to be sent again.
+    RetransmitRequested,
+}
+
+/// A packet channel over a console.
+pub struct Framer<'a, C: ?Sized> {
+    console: &'a C,
+    timeout: Duration,
+    buf: [u8; MAX_ENCODED_SIZE],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// CRC-32 with the IEEE 802.3 polynomial, as used by e.g. zlib.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+
+    for b in bytes {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+/// COBS encode `data` into `out`.
+fn cobs_encode(data: &[u8], mut out: impl FnMut(u8)) {
+    for mut segment in data.split(|b| *b == 0) {
+        // A code of 0xFF stands for 254 bytes without a zero after them.
+        while segment.len() >= 254 {
+            out(0xFF);
+            segment[..254].iter().for_each(|b| out(*b));
+            segment = &segment[254..];
+        }
+
+        out(segment.len() as u8 + 1);
+        segment.iter().for_each(|b| out(*b));
+    }
+}
+
+/// COBS decode `data` in place, returning the decoded length.
+///
+/// Decoding never makes the data longer, so the output can overwrite the input as it goes.
+fn cobs_decode(data: &mut [u8]) -> Option<usize> {
+    let mut read = 0;
+    let mut write = 0;
+
+    while read < data.len() {
+        let code = data[read] as usize;
+        if code == 0 {
+            return None;
+        }
+        read += 1;
+
+        let end = read + code - 1;
+        if end > data.len() {
+            return None;
+        }
+        data.copy_within(read..end, write);
+        write += code - 1;
+        read = end;
+
+        if code != 0xFF && read < data.len() {
+            data[write] = 0;
+            write += 1;
+        }
+    }
+
+    Some(write)
+}
+
+impl<'a, C> Framer<'a, C>
+where
+    C: console::interface::Write + console::interface::Read + ?Sized,
+{
+    /// Encode and send a frame of `kind`.
+    fn send(&mut self, kind: u8, payload: &[u8]) -> Result<(), FrameError> {
+        if payload.len() > MAX_PAYLOAD_SIZE {
+            return Err(FrameError::TooLong);
+        }
+
+        let body_len = HEADER_SIZE + payload.len();
+        self.buf[0] = kind;
+        self.buf[1..HEADER_SIZE].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        self.buf[HEADER_SIZE..body_len].copy_from_slice(payload);
+        let crc = crc32(&self.buf[..body_len]);
+        self.buf[body_len..(body_len + TRAILER_SIZE)].copy_from_slice(&crc.to_le_bytes());
+
+        let console = self.console;
+        console.write_byte(0);
+        cobs_encode(&self.buf[..(body_len + TRAILER_SIZE)], |b| {
+            console.write_byte(b)
+        });
+        console.write_byte(0);
+
+        Ok(())
+    }
+
+    /// Receive the bytes up to the next delimiter, skipping empty frames, and decode them.
+    ///
+    /// Returns the length of the decoded frame, which is left at the start of `self.buf`.
+    fn recv_raw(&mut self) -> Result<usize, FrameError> {
+        let console = self.console;
+        let mut len = 0;
+        let mut overflow = false;
+
+        loop {
+            let b = time::with_timeout(self.timeout, || console.read_byte_nb())
+                .map_err(|_| FrameError::Timeout)?;
+
+            match b {
+                0 if len == 0 && !overflow => continue,
+                0 => break,
+                // Keep reading up to the delimiter, so that the next frame starts in sync.
+                _ if len == self.buf.len() => overflow = true,
+                _ => {
+                    self.buf[len] = b;
+                    len += 1;
+                }
+            }
+        }
+
+        if overflow {
+            return Err(FrameError::TooLong);
+        }
+
+        cobs_decode(&mut self.buf[..len]).ok_or(FrameError::Malformed)
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl<'a, C> Framer<'a, C>
+where
+    C: console::interface::Write + console::interface::Read + ?Sized,
+{
+    /// Create an instance that waits at most `timeout` for each byte when receiving.
+    pub fn new(console: &'a C, timeout: Duration) -> Self {
+        Self {
+            console,
+            timeout,
+            buf: [0; MAX_ENCODED_SIZE],
+        }
+    }
+
+    /// Send `payload` as a frame.
+    pub fn send_frame(&mut self, payload: &[u8]) -> Result<(), FrameError> {
+        self.send(KIND_DATA, payload)
+    }
+
+    /// Ask the other end to send its last frame again.
+    pub fn request_retransmit(&mut self) -> Result<(), FrameError> {
+        self.send(KIND_RETRANSMIT, &[])
+    }
+
+    /// Receive a frame into `buf` and return the length of its payload.
+    pub fn recv_frame(&mut self, buf: &mut [u8]) -> Result<usize, FrameError> {
+        let raw_len = self.recv_raw()?;
+        if raw_len < HEADER_SIZE + TRAILER_SIZE {
+            return Err(FrameError::Malformed);
+        }
+
+        let (body, trailer) = self.buf[..raw_len].split_at(raw_len - TRAILER_SIZE);
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        if crc32(body) != crc {
+            return Err(FrameError::Crc);
+        }
+
+        let payload = &body[HEADER_SIZE..];
+        if u16::from_le_bytes([body[1], body[2]]) as usize != payload.len() {
+            return Err(FrameError::Malformed);
+        }
+
+        match body[0] {
+            KIND_DATA => (),
+            KIND_RETRANSMIT => return Err(FrameError::RetransmitRequested),
+            _ => return Err(FrameError::Malformed),
+        }
+
+        if payload.len() > buf.len() {
+            return Err(FrameError::TooLong);
+        }
+        buf[..payload.len()].copy_from_slice(payload);
+
+        Ok(payload.len())
+    }
+
+    /// Like `recv_frame()`, but request a retransmit for corrupted frames, up to `attempts` times
+    /// in total.
+    ///
+    /// Errors other than corruption, e.g. a timeout, are returned right away.
+    pub fn recv_frame_retrying(
+        &mut self,
+        buf: &mut [u8],
+        attempts: usize,
+    ) -> Result<usize, FrameError> {
+        let mut result = Err(FrameError::Timeout);
+
+        for _ in 0..attempts {
+            result = self.recv_frame(buf);
+
+            match result {
+                Err(FrameError::Crc) | Err(FrameError::Malformed) => self.request_retransmit()?,
+                _ => return result,
+            }
+        }
+
+        result
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{cell::RefCell, fmt};
+    use test_macros::kernel_test;
+
+    const TIMEOUT: Duration = Duration::from_millis(10);
+
+    /// Bytes in flight in one direction. Unit tests have no heap, so this does not use a `Vec`.
+    struct Queue {
+        bytes: [u8; 2 * MAX_ENCODED_SIZE],
+        start: usize,
+        end: usize,
+    }
+
+    impl Default for Queue {
+        fn default() -> Self {
+            Self {
+                bytes: [0; 2 * MAX_ENCODED_SIZE],
+                start: 0,
+                end: 0,
+            }
+        }
+    }
+
+    impl Queue {
+        fn push(&mut self, b: u8) {
+            self.bytes[self.end] = b;
+            self.end += 1;
+        }
+
+        fn pop(&mut self) -> Option<u8> {
+            if self.start == self.end {
+                return None;
+            }
+            self.start += 1;
+
+            Some(self.bytes[self.start - 1])
+        }
+
+        fn as_mut_slice(&mut self) -> &mut [u8] {
+            &mut self.bytes[self.start..self.end]
+        }
+    }
+
+    /// One end of a serial line, with separate queues for both directions.
+    #[derive(Default)]
+    struct End {
+        tx: RefCell<Queue>,
+        rx: RefCell<Queue>,
+    }
+
+    impl End {
+        /// Deliver everything that `self` sent to `other`.
+        fn transfer_to(&self, other: &End) {
+            let mut tx = self.tx.borrow_mut();
+            let mut rx = other.rx.borrow_mut();
+            while let Some(b) = tx.pop() {
+                rx.push(b);
+            }
+        }
+
+        /// Flip a bit of the first `target` byte that was sent and is still in flight.
+        fn corrupt(&self, target: u8) {
+            let mut tx = self.tx.borrow_mut();
+            let b = tx
+                .as_mut_slice()
+                .iter_mut()
+                .find(|b| **b == target)
+                .unwrap();
+            *b ^= 0x20;
+        }
+    }
+
+    impl console::interface::Write for End {
+        fn write_char(&self, c: char) {
+            self.tx.borrow_mut().push(c as u8);
+        }
+
+        fn write_fmt(&self, _args: fmt::Arguments) -> fmt::Result {
+            Ok(())
+        }
+
+        fn flush(&self) {}
+    }
+
+    impl console::interface::Read for End {
+        fn read_byte_nb(&self) -> Option<u8> {
+            self.rx.borrow_mut().pop()
+        }
+
+        fn clear(&self) {
+            *self.rx.borrow_mut() = Queue::default();
+        }
+    }
+
+    /// Encoding and decoding must round-trip, and the encoding must be free of zeros, also for
+    /// runs around the 254 byte block size.
+    #[kernel_test]
+    fn cobs_roundtrips() {
+        let mut long = [0u8; 600];
+        for (i, b) in long.iter_mut().enumerate() {
+            *b = (i % 255) as u8 + 1;
+        }
+        let inputs: [&[u8]; 6] = [&[], &[0], &[0, 0], &[1, 0, 2], &long[..254], &long];
+
+        for input in inputs.iter() {
+            let mut encoded = [0u8; 610];
+            let mut len = 0;
+            cobs_encode(input, |b| {
+                encoded[len] = b;
+                len += 1;
+            });
+            assert!(!encoded[..len].contains(&0));
+
+            let len = cobs_decode(&mut encoded[..len]).unwrap();
+            assert_eq!(&encoded[..len], *input);
+        }
+    }
+
+    /// Frames must arrive unchanged, including zeros, empty and maximum size payloads.
+    #[kernel_test]
+    fn frames_roundtrip() {
+        let host = End::default();
+        let target = End::default();
+        let mut sender = Framer::new(&host, TIMEOUT);
+        let mut receiver = Framer::new(&target, TIMEOUT);
+
+        let mut max = [0u8; MAX_PAYLOAD_SIZE];
+        for (i, b) in max.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let payloads: [&[u8]; 4] = [b"hello, world", &[0, 0, 0], &[], &max];
+
+        let mut buf = [0u8; MAX_PAYLOAD_SIZE];
+        for payload in payloads.iter() {
+            sender.send_frame(payload).unwrap();
+            host.transfer_to(&target);
+
+            let len = receiver.recv_frame(&mut buf).unwrap();
+            assert_eq!(&buf[..len], *payload);
+        }
+
+        assert_eq!(
+            sender.send_frame(&[0; MAX_PAYLOAD_SIZE + 1]),
+            Err(FrameError::TooLong)
+        );
+    }
+
+    /// A flipped bit in the payload must be caught by the CRC, and the retransmit request reach the
+    /// sender.
+    #[kernel_test]
+    fn corruption_is_detected_by_crc() {
+        let host = End::default();
+        let target = End::default();
+        let mut sender = Framer::new(&host, TIMEOUT);
+        let mut receiver = Framer::new(&target, TIMEOUT);
+        let mut buf = [0u8; 64];
+
+        sender.send_frame(b"hello, world").unwrap();
+        host.corrupt(b'w');
+        sender.send_frame(b"hello, world").unwrap();
+        host.transfer_to(&target);
+
+        assert_eq!(receiver.recv_frame(&mut buf), Err(FrameError::Crc));
+        assert_eq!(receiver.recv_frame(&mut buf), Ok(12));
+
+        // The same again, retrying this time.
+        sender.send_frame(b"hello, world").unwrap();
+        host.corrupt(b'w');
+        sender.send_frame(b"hello, world").unwrap();
+        host.transfer_to(&target);
+
+        assert_eq!(receiver.recv_frame_retrying(&mut buf, 2), Ok(12));
+        assert_eq!(&buf[..12], b"hello, world");
+
+        target.transfer_to(&host);
+        assert_eq!(
+            sender.recv_frame(&mut buf),
+            Err(FrameError::RetransmitRequested)
+        );
+    }
+
+    /// A frame that is cut off must time out, and the next complete frame still be received.
+    #[kernel_test]
+    fn partial_frame_times_out() {
+        let host = End::default();
+        let target = End::default();
+        let mut sender = Framer::new(&host, TIMEOUT);
+        let mut receiver = Framer::new(&target, TIMEOUT);
+        let mut buf = [0u8; 64];
+
+        sender.send_frame(b"hello, world").unwrap();
+        {
+            let mut tx = host.tx.borrow_mut();
+            tx.end = tx.start + (tx.end - tx.start) / 2;
+        }
+        host.transfer_to(&target);
+
+        assert_eq!(receiver.recv_frame(&mut buf), Err(FrameError::Timeout));
+
+        sender.send_frame(b"again").unwrap();
+        host.transfer_to(&target);
+        assert_eq!(receiver.recv_frame(&mut buf), Ok(5));
+        assert_eq!(&buf[..5], b"again");
+    }
+}
@@ -0,0 +1,146 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A test of `Spinlock` and the SMP boot, in which all cores increment a shared counter.
+//!
+//! Every core that exists adds [`ITERATIONS`] to the counter, one locked increment at a time. An
+//! update that got lost, because two cores were in the critical section together, shows as a total
+//! below cores × `ITERATIONS`.
+//!
+//! The secondary cores run with their caches off, so [`run()`] may only be called while the boot
+//! core has its MMU off, too. See `cpu::smp`.
+//!
+//! # QEMU only
+//!
+//! With the MMU and caches off, exclusive accesses only work on QEMU, so this tests `Spinlock`
+//! under QEMU only. The outcome says nothing about real hardware.
+
+use crate::{
+    bsp, cpu, info,
+    synchronization::{interface::Mutex, Spinlock},
+    time,
+    time::interface::TimeManager,
+};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How long the secondary cores get to finish after the boot core did.
+const FINISH_TIMEOUT: Duration = Duration::from_secs(1);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// How often each core increments the counter.
+pub const ITERATIONS: u64 = 100_000;
+
+/// The outcome of `run()`.
+pub struct Report {
+    /// The number of cores that took part, the boot core included.
+    pub cores: usize,
+
+    /// The final value of the counter.
+    pub total: u64,
+
+    /// How long each core took for its increments, `None` for the cores that did not finish.
+    pub elapsed: [Option<Duration>; bsp::cpu::NUM_CORES],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static COUNTER: Spinlock<u64> = Spinlock::new(0);
+
+static ELAPSED: Spinlock<[Option<Duration>; bsp::cpu::NUM_CORES]> =
+    Spinlock::new([None; bsp::cpu::NUM_CORES]);
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+
+/// Increment the counter `ITERATIONS` times, and record how long it took.
+fn hammer(core: usize) {
+    let start = time::time_manager().uptime();
+
+    for _ in 0..ITERATIONS {
+        let mut r = &COUNTER;
+        r.lock(|counter| *counter += 1);
+    }
+
+    let elapsed = time::time_manager().uptime() - start;
+    let mut r = &ELAPSED;
+    r.lock(|times| times[core] = Some(elapsed));
+}
+
+fn all_finished(cores: usize) -> bool {
+    let mut r = &ELAPSED;
+    r.lock(|times| {
+        (0..cores)
+            .filter(|core| cpu::smp::is_core_started(*core))
+            .all(|core| times[core].is_some())
+    })
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Report {
+    /// The total that the counter must reach.
+    pub fn expected_total(&self) -> u64 {
+        self.cores as u64 * ITERATIONS
+    }
+
+    /// Print the total and each core's completion time.
+    pub fn print(&self) {
+        info!(
+            "SMP counter: {} of {} on {} cores",
+            self.total,
+            self.expected_total(),
+            self.cores
+        );
+
+        for (core, elapsed) in self.elapsed.iter().enumerate() {
+            if let Some(elapsed) = elapsed {
+                info!("      Core {}: {:?}", core, elapsed);
+            }
+        }
+    }
+}
+
+/// Start the secondary cores, let all cores hammer the counter, and report the outcome once all of
+/// them finished.
+///
+/// # Safety
+///
+/// - See `cpu::smp::start_secondary_cores_with()`.
+/// - The boot core's MMU must be off.
+pub unsafe fn run() -> Result<Report, &'static str> {
+    let secondaries = cpu::smp::start_secondary_cores_with(hammer);
+    hammer(cpu::smp::core_id());
+
+    let num_cores = cpu::num_cores() as usize;
+    if time::with_timeout(FINISH_TIMEOUT, || {
+        Some(()).filter(|_| all_finished(num_cores))
+    })
+    .is_err()
+    {
+        return Err("Secondary cores did not finish");
+    }
+
+    let mut r = &COUNTER;
+    let total = r.lock(|counter| *counter);
+    let mut r = &ELAPSED;
+    let elapsed = r.lock(|times| *times);
+
+    Ok(Report {
+        cores: secondaries + 1,
+        total,
+        elapsed,
+    })
+}
@@ -4,7 +4,10 @@
 
 //! Synchronization primitives.
 
-use core::cell::UnsafeCell;
+use core::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
 
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
@@ -82,6 +85,22 @@ pub struct InitStateLock<T: ?Sized> {
     data: UnsafeCell<T>,
 }
 
+/// A lock that protects its data against concurrent access from other cores, too.
+///
+/// Like `IRQSafeNullLock`, it masks IRQs on the executing core while held. Waiting cores spin, so
+/// it must only be held for short critical sections, and never be taken twice by the same core.
+///
+/// # QEMU only
+///
+/// The lock relies on exclusive accesses. On real hardware, these need the MMU and D-cache to be
+/// enabled, but the secondary cores run with both off (see `cpu::smp`). Only QEMU, which does not
+/// care, gives the cross-core guarantee. Until the secondaries are brought up with the MMU on, the
+/// lock is for the `smp_test` kernel under QEMU only.
+pub struct Spinlock<T: ?Sized> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
 //--------------------------------------------------------------------------------------------------
 // Public Code
 //--------------------------------------------------------------------------------------------------
@@ -107,6 +126,18 @@ impl<T> InitStateLock<T> {
     }
 }
 
+unsafe impl<T: ?Sized + Send> Sync for Spinlock<T> {}
+
+impl<T> Spinlock<T> {
+    /// Wraps `data` into a new, unlocked `Spinlock`.
+    pub const fn new(data: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+}
+
 //------------------------------------------------------------------------------
 // OS Interface Code
 //------------------------------------------------------------------------------
@@ -115,6 +146,7 @@ use crate::{exception, state};
 impl<T> interface::Mutex for &IRQSafeNullLock<T> {
     type Data = T;
 
+    #[track_caller]
     fn lock<R>(&mut self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
         // In a real lock, there would be code encapsulating this line that ensures that this
         // mutable reference will ever only be given out once at a time.
@@ -125,6 +157,31 @@ impl<T> interface::Mutex for &IRQSafeNullLock<T> {
     }
 }
 
+impl<T> interface::Mutex for &Spinlock<T> {
+    type Data = T;
+
+    #[track_caller]
+    fn lock<R>(&mut self, f: impl FnOnce(&mut Self::Data) -> R) -> R {
+        exception::asynchronous::exec_with_irq_masked(|| {
+            while self
+                .locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                // Wait without hammering the line with exclusive accesses.
+                while self.locked.load(Ordering::Relaxed) {
+                    core::sync::atomic::spin_loop_hint();
+                }
+            }
+
+            let result = f(unsafe { &mut *self.data.get() });
+            self.locked.store(false, Ordering::Release);
+
+            result
+        })
+    }
+}
+
 impl<T> interface::ReadWriteEx for &InitStateLock<T> {
     type Data = T;
 
@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Thermal monitoring.
+//!
+//! After [`start_sampling()`], the board's temperature is sampled every [`SAMPLE_PERIOD`] and
+//! appended to a bounded history, which keeps the last [`HISTORY_SIZE`] readings. The history shows
+//! the thermal trend, e.g. to tell whether the SoC was throttled under load, rather than a single
+//! snapshot.
+//!
+//! A reading is a blocking mailbox call, so it must not be taken in IRQ context. Sampling is driven
+//! by a periodic timer of the kernel's [`TimerWheel`](time::TimerWheel) instead, whose callbacks
+//! run in task context from the idle loop, see [`time::idle_tickless()`].
+
+use crate::{bsp, fault, info, synchronization, synchronization::IRQSafeNullLock, time};
+use core::{fmt, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// The number of readings that the history keeps.
+pub const HISTORY_SIZE: usize = 128;
+
+/// The interval between two readings.
+pub const SAMPLE_PERIOD: Duration = Duration::from_secs(1);
+
+/// A temperature, in thousandths of a degree Celsius.
+#[derive(Copy, Clone, Debug, PartialEq, PartialOrd)]
+pub struct Temperature(u32);
+
+/// A ring of timestamped readings. On overflow, the oldest reading is dropped.
+#[derive(Copy, Clone)]
+pub struct History {
+    readings: [(Duration, Temperature); HISTORY_SIZE],
+
+    /// Index of the oldest reading.
+    head: usize,
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static HISTORY: IRQSafeNullLock<History> = IRQSafeNullLock::new(History::new());
+
+//--------------------------------------------------------------------------------------------------
+// Private Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+/// Take a reading. Runs in task context, as a callback of the timer wheel.
+fn sample() {
+    match bsp::temperature() {
+        Ok(temperature) => record(temperature),
+        Err(()) => fault::record_fault("Thermal", "Temperature query failed"),
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl Temperature {
+    /// Create an instance from thousandths of a degree Celsius, as reported by the firmware.
+    pub const fn from_millicelsius(millicelsius: u32) -> Self {
+        Self(millicelsius)
+    }
+
+    /// The temperature in thousandths of a degree Celsius.
+    pub const fn millicelsius(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:03} C", self.0 / 1000, self.0 % 1000)
+    }
+}
+
+impl History {
+    /// Create an empty instance.
+    pub const fn new() -> Self {
+        Self {
+            readings: [(Duration::from_secs(0), Temperature(0)); HISTORY_SIZE],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    /// Append a reading, dropping the oldest one if the history is full.
+    pub fn push(&mut self, timestamp: Duration, temperature: Temperature) {
+        let tail = (self.head + self.len) % HISTORY_SIZE;
+        self.readings[tail] = (timestamp, temperature);
+
+        if self.len < HISTORY_SIZE {
+            self.len += 1;
+        } else {
+            self.head = (self.head + 1) % HISTORY_SIZE;
+        }
+    }
+
+    /// Number of readings in the history.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no reading was taken yet.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over the readings, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (Duration, Temperature)> + '_ {
+        (0..self.len).map(move |i| self.readings[(self.head + i) % HISTORY_SIZE])
+    }
+}
+
+/// Append a reading with the current uptime to the history.
+pub fn record(temperature: Temperature) {
+    use time::interface::TimeManager;
+
+    let timestamp = time::time_manager().uptime();
+
+    let mut r = &HISTORY;
+    r.lock(|history| history.push(timestamp, temperature));
+}
+
+/// The recorded readings, oldest first.
+///
+/// Iterates over a snapshot, so that readings taken in the meantime do not disturb the iteration.
+pub fn history() -> impl Iterator<Item = (Duration, Temperature)> {
+    let mut r = &HISTORY;
+    let snapshot = r.lock(|history| *history);
+
+    (0..snapshot.len).map(move |i| snapshot.readings[(snapshot.head + i) % HISTORY_SIZE])
+}
+
+/// Print the recorded readings, oldest first.
+pub fn print_history() {
+    info!("Temperature history:");
+    for (timestamp, temperature) in history() {
+        info!(
+            "      {:>5}.{:03}s: {}",
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            temperature
+        );
+    }
+}
+
+/// Take a first reading now and start periodic sampling.
+///
+/// The following readings are taken whenever the idle loop runs the expired timers of the timer
+/// wheel, so a core that never idles delays them.
+pub fn start_sampling() -> Result<(), &'static str> {
+    let temperature = bsp::temperature().map_err(|_| "Temperature query failed")?;
+    record(temperature);
+
+    let mut r = time::timer_wheel();
+    r.lock(|wheel| wheel.schedule_in("thermal", SAMPLE_PERIOD, Some(SAMPLE_PERIOD), sample))
+        .map(|_| ())
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// On overflow, only the most recent readings must remain, oldest first.
+    #[kernel_test]
+    fn history_keeps_recent_readings_in_order() {
+        const NUM_READINGS: usize = HISTORY_SIZE + 10;
+
+        let mut history = History::new();
+        for i in 0..NUM_READINGS {
+            history.push(
+                Duration::from_secs(i as u64),
+                Temperature::from_millicelsius(40_000 + i as u32),
+            );
+        }
+
+        assert_eq!(history.len(), HISTORY_SIZE);
+
+        let first = NUM_READINGS - HISTORY_SIZE;
+        for (i, (timestamp, temperature)) in history.iter().enumerate() {
+            assert_eq!(timestamp, Duration::from_secs((first + i) as u64));
+            assert_eq!(
+                temperature,
+                Temperature::from_millicelsius(40_000 + (first + i) as u32)
+            );
+        }
+    }
+}
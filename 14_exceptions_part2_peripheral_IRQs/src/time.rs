@@ -3,12 +3,27 @@
 // Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
 
 //! Timer primitives.
+//!
+//! # Tickless idle
+//!
+//! There is no fixed-rate timer tick, and hence no `jiffies` counter that a tick would increment.
+//! Time is read from the counter with `uptime()`, and software timers are driven by their
+//! deadlines instead: [`idle_tickless()`] arms the timer for the earliest deadline of the
+//! [`TimerWheel`], waits for interrupts, and runs the timers that expired once the core is woken.
+//! An idle core is therefore only woken when a timer is due or a device needs attention.
 
 #[cfg(target_arch = "aarch64")]
 #[path = "_arch/aarch64/time.rs"]
 mod arch_time;
 pub use arch_time::*;
 
+mod timer_wheel;
+pub use timer_wheel::*;
+
+use crate::{cpu, synchronization::interface::Mutex};
+use core::time::Duration;
+use interface::TimeManager;
+
 //--------------------------------------------------------------------------------------------------
 // Public Definitions
 //--------------------------------------------------------------------------------------------------
@@ -31,5 +46,118 @@ pub mod interface {
 
         /// Spin for a given duration.
         fn spin_for(&self, duration: Duration);
+
+        /// Sleep the core for at least the given duration.
+        ///
+        /// Unlike `spin_for()`, the core waits for interrupts in between. Wakeups by unrelated
+        /// interrupts do not cut the sleep short.
+        fn sleep(&self, duration: Duration);
+    }
+}
+
+/// The deadline of [`with_timeout()`] passed before the poll function yielded a value.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Timeout;
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+/// Call `poll` until it yields `Some`, or until `timeout` has passed.
+///
+/// `poll` must not block. It is expected to check for a condition, e.g. with a non-blocking
+/// primitive like `read_char_nb()`, and return right away. A `poll` that blocks can overrun the
+/// deadline by however long it blocks.
+///
+/// `poll` is called at least once, even if `timeout` is zero.
+pub fn with_timeout<T>(
+    timeout: Duration,
+    mut poll: impl FnMut() -> Option<T>,
+) -> Result<T, Timeout> {
+    let deadline = time_manager().uptime() + timeout;
+
+    loop {
+        if let Some(value) = poll() {
+            return Ok(value);
+        }
+
+        if time_manager().uptime() >= deadline {
+            return Err(Timeout);
+        }
+
+        cpu::nop();
+    }
+}
+
+/// Wait for interrupts until the next timer of the wheel is due, then run all expired timers.
+///
+/// Without registered timers, only other interrupts wake the core. Returns the number of timers
+/// that expired, which is zero if an unrelated interrupt ended the wait early.
+pub fn idle_tickless() -> usize {
+    use crate::exception::asynchronous::{local_irq_mask_save, local_irq_restore};
+
+    // Masked from reading the deadline until the wait, so that a timer that an IRQ handler
+    // schedules meanwhile is not missed. A pending IRQ still ends the wait.
+    let saved = unsafe { local_irq_mask_save() };
+
+    let mut r = timer_wheel();
+    match r.lock(|wheel| wheel.next_deadline()) {
+        None => cpu::idle(),
+        Some(deadline) => cpu::idle_until(deadline),
+    }
+
+    unsafe { local_irq_restore(saved) };
+
+    // Run outside of the lock, so that callbacks can schedule timers.
+    let now = time_manager().uptime();
+    r.lock(|wheel| wheel.expire(now)).run()
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    /// A poll function that succeeds must have its value returned, without waiting for the
+    /// deadline.
+    #[kernel_test]
+    fn with_timeout_returns_value_of_successful_poll() {
+        let mut calls = 0;
+        let start = time_manager().uptime();
+
+        let result = with_timeout(Duration::from_secs(10), || {
+            calls += 1;
+            if calls == 3 {
+                Some(calls * 7)
+            } else {
+                None
+            }
+        });
+
+        assert_eq!(result, Ok(21));
+        assert_eq!(calls, 3);
+        assert!(time_manager().uptime() - start < Duration::from_secs(1));
+    }
+
+    /// A poll function that never succeeds must time out once the deadline passed.
+    #[kernel_test]
+    fn with_timeout_times_out() {
+        const TIMEOUT: Duration = Duration::from_millis(20);
+
+        let mut calls = 0;
+        let start = time_manager().uptime();
+
+        let result: Result<(), Timeout> = with_timeout(TIMEOUT, || {
+            calls += 1;
+            None
+        });
+
+        assert_eq!(result, Err(Timeout));
+        assert!(calls > 0);
+        assert!(time_manager().uptime() - start >= TIMEOUT);
     }
 }
@@ -0,0 +1,351 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Software timers.
+//!
+//! The wheel has a fixed number of slots, because it must be usable before the heap is set up. A
+//! timer's handle is the index of the slot it occupies, together with the slot's generation. The
+//! generation changes with every timer that takes the slot, so a stale handle, e.g. of a one-shot
+//! timer that expired already, does not cancel the timer that reuses its slot.
+//!
+//! [`TimerWheel::expire()`] does not call the callbacks of the expired timers itself. It returns
+//! them as [`ExpiredTimers`], to be run once the wheel's lock is released, so that a callback can
+//! schedule timers on the wheel.
+
+use crate::{info, synchronization, synchronization::IRQSafeNullLock, time};
+use core::{fmt, time::Duration};
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+const NUM_TIMERS: usize = 16;
+
+#[derive(Copy, Clone)]
+struct Timer {
+    generation: u32,
+    name: &'static str,
+    deadline: Duration,
+    period: Option<Duration>,
+    callback: TimerCallback,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Function that is called when a timer expires.
+pub type TimerCallback = fn();
+
+/// Identifies a registered timer.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimerHandle {
+    slot: usize,
+    generation: u32,
+}
+
+/// A snapshot of a registered timer, as shown by `TimerWheel::dump()`.
+#[derive(Copy, Clone)]
+pub struct TimerInfo {
+    /// The timer's handle.
+    pub handle: TimerHandle,
+
+    /// The name the timer was registered with.
+    pub name: &'static str,
+
+    /// Time left until the deadline. Zero if the deadline has passed already.
+    pub remaining: Duration,
+
+    /// The period for periodic timers.
+    pub period: Option<Duration>,
+}
+
+/// A fixed-capacity collection of software timers.
+pub struct TimerWheel {
+    timers: [Option<Timer>; NUM_TIMERS],
+    next_generation: u32,
+}
+
+/// The callbacks of the timers that `TimerWheel::expire()` found expired.
+#[must_use]
+pub struct ExpiredTimers {
+    callbacks: [Option<TimerCallback>; NUM_TIMERS],
+    len: usize,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Global instances
+//--------------------------------------------------------------------------------------------------
+
+static TIMER_WHEEL: IRQSafeNullLock<TimerWheel> = IRQSafeNullLock::new(TimerWheel::new());
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+use synchronization::interface::Mutex;
+
+/// Return a reference to the kernel's timer wheel.
+pub fn timer_wheel() -> &'static IRQSafeNullLock<TimerWheel> {
+    &TIMER_WHEEL
+}
+
+impl TimerWheel {
+    /// Create an instance.
+    pub const fn new() -> Self {
+        Self {
+            timers: [None; NUM_TIMERS],
+            next_generation: 0,
+        }
+    }
+
+    /// Register a timer that expires at the absolute uptime `deadline`.
+    ///
+    /// If `period` is given, the timer is re-armed with `deadline + period` every time it expires.
+    pub fn schedule_at(
+        &mut self,
+        name: &'static str,
+        deadline: Duration,
+        period: Option<Duration>,
+        callback: TimerCallback,
+    ) -> Result<TimerHandle, &'static str> {
+        let slot = match self.timers.iter().position(|t| t.is_none()) {
+            None => return Err("No free timer slot"),
+            Some(i) => i,
+        };
+
+        let generation = self.next_generation;
+        self.next_generation = self.next_generation.wrapping_add(1);
+
+        self.timers[slot] = Some(Timer {
+            generation,
+            name,
+            deadline,
+            period,
+            callback,
+        });
+
+        Ok(TimerHandle { slot, generation })
+    }
+
+    /// Register a timer that expires `delay` from now.
+    pub fn schedule_in(
+        &mut self,
+        name: &'static str,
+        delay: Duration,
+        period: Option<Duration>,
+        callback: TimerCallback,
+    ) -> Result<TimerHandle, &'static str> {
+        use time::interface::TimeManager;
+
+        let deadline = time::time_manager().uptime() + delay;
+        self.schedule_at(name, deadline, period, callback)
+    }
+
+    /// Remove a timer. Cancelling an already expired one-shot timer is a no-op, even if its slot
+    /// was reused.
+    pub fn cancel(&mut self, handle: TimerHandle) {
+        let slot = &mut self.timers[handle.slot];
+
+        if let Some(timer) = slot {
+            if timer.generation == handle.generation {
+                *slot = None;
+            }
+        }
+    }
+
+    /// The earliest deadline of all registered timers.
+    pub fn next_deadline(&self) -> Option<Duration> {
+        self.timers.iter().flatten().map(|t| t.deadline).min()
+    }
+
+    /// Collect the callbacks of all timers whose deadline is not later than `now`.
+    ///
+    /// One-shot timers are removed, periodic ones are re-armed. The callbacks are meant to be run
+    /// with `ExpiredTimers::run()` after the wheel's lock was released.
+    pub fn expire(&mut self, now: Duration) -> ExpiredTimers {
+        let mut expired = ExpiredTimers {
+            callbacks: [None; NUM_TIMERS],
+            len: 0,
+        };
+
+        for slot in self.timers.iter_mut() {
+            let timer = match slot {
+                Some(t) if t.deadline <= now => t,
+                _ => continue,
+            };
+
+            expired.callbacks[expired.len] = Some(timer.callback);
+            expired.len += 1;
+
+            if let Some(period) = timer.period {
+                timer.deadline += period;
+            } else {
+                *slot = None;
+            }
+        }
+
+        expired
+    }
+
+    /// Iterate over all registered timers, with remaining times relative to `now`.
+    pub fn timers(&self, now: Duration) -> impl Iterator<Item = TimerInfo> + '_ {
+        self.timers.iter().enumerate().filter_map(move |(i, slot)| {
+            slot.map(|t| TimerInfo {
+                handle: TimerHandle {
+                    slot: i,
+                    generation: t.generation,
+                },
+                name: t.name,
+                remaining: t.deadline.checked_sub(now).unwrap_or_default(),
+                period: t.period,
+            })
+        })
+    }
+
+    /// Print all registered timers and their remaining times.
+    pub fn dump(&self) {
+        use time::interface::TimeManager;
+
+        let now = time::time_manager().uptime();
+
+        info!("Registered timers:");
+        for timer in self.timers(now) {
+            info!("      {}", timer);
+        }
+    }
+}
+
+impl ExpiredTimers {
+    /// The number of expired timers.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no timer expired.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Call the callbacks. Returns how many were called.
+    pub fn run(self) -> usize {
+        for callback in self.callbacks[..self.len].iter().flatten() {
+            callback();
+        }
+
+        self.len
+    }
+}
+
+impl fmt::Display for TimerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "#{:<2} {:<20} in {:>6} ms",
+            self.handle.slot,
+            self.name,
+            self.remaining.as_millis()
+        )?;
+
+        match self.period {
+            Some(p) => write!(f, ", every {} ms", p.as_millis()),
+            None => write!(f, ", one-shot"),
+        }
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use test_macros::kernel_test;
+
+    fn nop() {}
+
+    /// The dump must list every registered timer with its remaining time and period.
+    #[kernel_test]
+    fn dump_lists_registered_timers() {
+        let mut wheel = TimerWheel::new();
+        let now = Duration::from_secs(10);
+
+        let a = wheel
+            .schedule_at("heartbeat", now + Duration::from_millis(250), None, nop)
+            .unwrap();
+        let b = wheel
+            .schedule_at(
+                "poll",
+                now + Duration::from_millis(40),
+                Some(Duration::from_millis(50)),
+                nop,
+            )
+            .unwrap();
+        let c = wheel
+            .schedule_at("overdue", now - Duration::from_millis(5), None, nop)
+            .unwrap();
+
+        let mut seen = 0;
+        for (i, timer) in wheel.timers(now).enumerate() {
+            match i {
+                0 => {
+                    assert_eq!(timer.handle, a);
+                    assert_eq!(timer.name, "heartbeat");
+                    assert_eq!(timer.remaining.as_millis(), 250);
+                    assert!(timer.period.is_none());
+                }
+                1 => {
+                    assert_eq!(timer.handle, b);
+                    assert_eq!(timer.name, "poll");
+                    assert_eq!(timer.remaining.as_millis(), 40);
+                    assert_eq!(timer.period, Some(Duration::from_millis(50)));
+                }
+                _ => {
+                    assert_eq!(timer.handle, c);
+                    assert_eq!(timer.remaining.as_millis(), 0);
+                }
+            }
+            seen += 1;
+        }
+        assert_eq!(seen, 3);
+
+        wheel.cancel(a);
+        assert_eq!(wheel.timers(now).count(), 2);
+    }
+
+    /// Expiring re-arms periodic timers and removes one-shot timers.
+    #[kernel_test]
+    fn expire_rearms_periodic_timers() {
+        let mut wheel = TimerWheel::new();
+        let now = Duration::from_secs(1);
+
+        wheel.schedule_at("once", now, None, nop).unwrap();
+        wheel
+            .schedule_at("periodic", now, Some(Duration::from_millis(10)), nop)
+            .unwrap();
+
+        assert_eq!(wheel.expire(now).run(), 2);
+        assert_eq!(wheel.timers(now).count(), 1);
+        assert_eq!(wheel.next_deadline(), Some(now + Duration::from_millis(10)));
+    }
+
+    /// A stale handle must not cancel the timer that reuses its slot.
+    #[kernel_test]
+    fn stale_handle_does_not_cancel_reused_slot() {
+        let mut wheel = TimerWheel::new();
+        let now = Duration::from_secs(1);
+
+        let stale = wheel.schedule_at("once", now, None, nop).unwrap();
+        assert_eq!(wheel.expire(now).len(), 1);
+
+        let reused = wheel.schedule_at("later", now * 2, None, nop).unwrap();
+        assert_eq!(reused.slot, stale.slot);
+
+        wheel.cancel(stale);
+        assert_eq!(wheel.timers(now).count(), 1);
+
+        wheel.cancel(reused);
+        assert_eq!(wheel.timers(now).count(), 0);
+    }
+}
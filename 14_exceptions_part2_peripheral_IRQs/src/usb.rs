@@ -1 +1,82 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Universal Serial Bus.
+
 pub mod device;
+pub mod hub;
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// USB interfaces.
+pub mod interface {
+    use super::SetupPacket;
+
+    /// Control transfers to a single device, e.g. through the default control pipe of an
+    /// enumerated device on the host controller.
+    pub trait ControlTransfer {
+        /// Execute a control transfer.
+        ///
+        /// For device-to-host requests, `data` receives the response. For host-to-device requests,
+        /// it holds the data stage's payload. Returns the number of bytes transferred in the data
+        /// stage.
+        fn control_transfer(
+            &self,
+            setup: &SetupPacket,
+            data: &mut [u8],
+        ) -> Result<usize, &'static str>;
+    }
+}
+
+/// The setup stage of a control transfer, as defined in chapter 9.3 of the USB 2.0 specification.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct SetupPacket {
+    /// Direction, type and recipient of the request.
+    pub request_type: u8,
+
+    /// The specific request.
+    pub request: u8,
+
+    /// Request dependent.
+    pub value: u16,
+
+    /// Request dependent, typically an interface, endpoint or port.
+    pub index: u16,
+
+    /// Number of bytes in the data stage.
+    pub length: u16,
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl SetupPacket {
+    /// Device-to-host direction bit of `request_type`.
+    pub const DIR_IN: u8 = 0x80;
+
+    /// Class request type.
+    pub const TYPE_CLASS: u8 = 0x20;
+
+    /// Device recipient.
+    pub const RECIPIENT_DEVICE: u8 = 0x00;
+
+    /// Other recipient. Used by hubs to address ports.
+    pub const RECIPIENT_OTHER: u8 = 0x03;
+
+    /// Standard GET_STATUS request.
+    pub const GET_STATUS: u8 = 0;
+
+    /// Standard CLEAR_FEATURE request.
+    pub const CLEAR_FEATURE: u8 = 1;
+
+    /// Standard SET_FEATURE request.
+    pub const SET_FEATURE: u8 = 3;
+
+    /// Standard GET_DESCRIPTOR request.
+    pub const GET_DESCRIPTOR: u8 = 6;
+}
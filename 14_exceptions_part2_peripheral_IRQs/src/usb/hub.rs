@@ -0,0 +1,392 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! USB hub driver.
+//!
+//! The onboard USB ports and Ethernet of the RPi 3 are behind a hub (LAN9514), so any device needs
+//! hub support to be reached. The driver follows chapter 11 of the USB 2.0 specification: Read the
+//! hub descriptor, power all downstream ports, then poll the ports for connects and reset connected
+//! ports, which enables them.
+
+use super::{interface::ControlTransfer, SetupPacket};
+use crate::{time, time::interface::TimeManager};
+use core::time::Duration;
+
+//--------------------------------------------------------------------------------------------------
+// Private Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Maximum number of supported downstream ports.
+const MAX_PORTS: usize = 8;
+
+const DESCRIPTOR_TYPE_HUB: u8 = 0x29;
+
+// Hub class feature selectors, chapter 11.24.2 of the USB 2.0 specification.
+const PORT_RESET: u16 = 4;
+const PORT_POWER: u16 = 8;
+const C_PORT_CONNECTION: u16 = 16;
+const C_PORT_RESET: u16 = 20;
+
+/// Time a port reset may take before it is considered failed.
+const PORT_RESET_TIMEOUT_MS: u32 = 500;
+
+/// Reset recovery time (TRSTRCY), keeps the device address free for the freshly reset device.
+const PORT_RESET_RECOVERY: Duration = Duration::from_millis(10);
+
+//--------------------------------------------------------------------------------------------------
+// Public Definitions
+//--------------------------------------------------------------------------------------------------
+
+/// Status of a downstream port, as returned by the hub's GET_STATUS port request.
+#[derive(Copy, Clone, Default)]
+pub struct PortStatus {
+    /// The port number, starting at 1.
+    pub port: u8,
+
+    /// wPortStatus.
+    pub status: u16,
+
+    /// wPortChange.
+    pub change: u16,
+}
+
+/// A USB hub, reached via control transfers.
+pub struct UsbHub<'a, C: ControlTransfer> {
+    ctrl: &'a C,
+    num_ports: usize,
+
+    /// Time from powering a port until power is good.
+    power_on_to_power_good: Duration,
+    ports: [PortStatus; MAX_PORTS],
+}
+
+//--------------------------------------------------------------------------------------------------
+// Public Code
+//--------------------------------------------------------------------------------------------------
+
+impl PortStatus {
+    const CONNECTION: u16 = 1 << 0;
+    const ENABLE: u16 = 1 << 1;
+    const RESET: u16 = 1 << 4;
+    const POWER: u16 = 1 << 8;
+    const LOW_SPEED: u16 = 1 << 9;
+    const HIGH_SPEED: u16 = 1 << 10;
+
+    const C_CONNECTION: u16 = 1 << 0;
+    const C_RESET: u16 = 1 << 4;
+
+    /// A device is attached to the port.
+    pub fn is_connected(&self) -> bool {
+        self.status & Self::CONNECTION != 0
+    }
+
+    /// The port is enabled, i.e. it was reset successfully.
+    pub fn is_enabled(&self) -> bool {
+        self.status & Self::ENABLE != 0
+    }
+
+    /// The port is powered.
+    pub fn is_powered(&self) -> bool {
+        self.status & Self::POWER != 0
+    }
+
+    /// The attached device is a low speed device.
+    pub fn is_low_speed(&self) -> bool {
+        self.status & Self::LOW_SPEED != 0
+    }
+
+    /// The attached device is a high speed device.
+    pub fn is_high_speed(&self) -> bool {
+        self.status & Self::HIGH_SPEED != 0
+    }
+}
+
+impl<'a, C: ControlTransfer> UsbHub<'a, C> {
+    /// Create an instance for the hub reachable through `ctrl`.
+    pub const fn new(ctrl: &'a C) -> Self {
+        Self {
+            ctrl,
+            num_ports: 0,
+            power_on_to_power_good: Duration::from_millis(0),
+            ports: [PortStatus {
+                port: 0,
+                status: 0,
+                change: 0,
+            }; MAX_PORTS],
+        }
+    }
+
+    /// Read the hub descriptor and power all downstream ports.
+    pub fn init(&mut self) -> Result<(), &'static str> {
+        let mut desc = [0u8; 16];
+        let setup = SetupPacket {
+            request_type: SetupPacket::DIR_IN
+                | SetupPacket::TYPE_CLASS
+                | SetupPacket::RECIPIENT_DEVICE,
+            request: SetupPacket::GET_DESCRIPTOR,
+            value: u16::from(DESCRIPTOR_TYPE_HUB) << 8,
+            index: 0,
+            length: desc.len() as u16,
+        };
+
+        let len = self.ctrl.control_transfer(&setup, &mut desc)?;
+        if len < 7 || desc[1] != DESCRIPTOR_TYPE_HUB {
+            return Err("Invalid hub descriptor");
+        }
+
+        let num_ports = desc[2] as usize;
+        if num_ports > MAX_PORTS {
+            return Err("Hub has too many ports");
+        }
+
+        self.num_ports = num_ports;
+        // bPwrOn2PwrGood is in units of 2 ms.
+        self.power_on_to_power_good = Duration::from_millis(u64::from(desc[5]) * 2);
+
+        for port in 1..=num_ports {
+            self.ports[port - 1].port = port as u8;
+            self.set_port_feature(port, PORT_POWER)?;
+        }
+
+        time::time_manager().spin_for(self.power_on_to_power_good);
+
+        for port in 1..=num_ports {
+            self.ports[port - 1] = self.port_status(port)?;
+        }
+
+        Ok(())
+    }
+
+    /// Poll all ports for connection changes and reset newly connected ports.
+    ///
+    /// Returns the number of ports that were newly enabled.
+    pub fn poll(&mut self) -> Result<usize, &'static str> {
+        let mut enabled = 0;
+
+        for port in 1..=self.num_ports {
+            let status = self.port_status(port)?;
+
+            if status.change & PortStatus::C_CONNECTION != 0 {
+                self.clear_port_feature(port, C_PORT_CONNECTION)?;
+
+                if status.is_connected() {
+                    self.reset_port(port)?;
+                    enabled += 1;
+                }
+            }
+
+            self.ports[port - 1] = self.port_status(port)?;
+        }
+
+        Ok(enabled)
+    }
+
+    /// The number of downstream ports.
+    pub fn num_ports(&self) -> usize {
+        self.num_ports
+    }
+
+    /// The status of all downstream ports, as of the last `init()` or `poll()`.
+    pub fn ports(&self) -> impl Iterator<Item = PortStatus> + '_ {
+        self.ports[..self.num_ports].iter().copied()
+    }
+
+    fn port_request(
+        &self,
+        request_type: u8,
+        request: u8,
+        feature: u16,
+        port: usize,
+    ) -> SetupPacket {
+        SetupPacket {
+            request_type: request_type | SetupPacket::TYPE_CLASS | SetupPacket::RECIPIENT_OTHER,
+            request,
+            value: feature,
+            index: port as u16,
+            length: 0,
+        }
+    }
+
+    fn set_port_feature(&self, port: usize, feature: u16) -> Result<(), &'static str> {
+        let setup = self.port_request(0, SetupPacket::SET_FEATURE, feature, port);
+        self.ctrl.control_transfer(&setup, &mut []).map(|_| ())
+    }
+
+    fn clear_port_feature(&self, port: usize, feature: u16) -> Result<(), &'static str> {
+        let setup = self.port_request(0, SetupPacket::CLEAR_FEATURE, feature, port);
+        self.ctrl.control_transfer(&setup, &mut []).map(|_| ())
+    }
+
+    fn port_status(&self, port: usize) -> Result<PortStatus, &'static str> {
+        let mut buf = [0u8; 4];
+        let mut setup = self.port_request(SetupPacket::DIR_IN, SetupPacket::GET_STATUS, 0, port);
+        setup.length = buf.len() as u16;
+
+        if self.ctrl.control_transfer(&setup, &mut buf)? != buf.len() {
+            return Err("Short port status");
+        }
+
+        Ok(PortStatus {
+            port: port as u8,
+            status: u16::from_le_bytes([buf[0], buf[1]]),
+            change: u16::from_le_bytes([buf[2], buf[3]]),
+        })
+    }
+
+    /// Reset a port and wait for the reset to complete, which enables the port.
+    fn reset_port(&self, port: usize) -> Result<(), &'static str> {
+        self.set_port_feature(port, PORT_RESET)?;
+
+        let mut wait_ms = PORT_RESET_TIMEOUT_MS;
+        loop {
+            let status = self.port_status(port)?;
+            if status.change & PortStatus::C_RESET != 0 && status.status & PortStatus::RESET == 0 {
+                break;
+            }
+
+            wait_ms -= 1;
+            if wait_ms == 0 {
+                return Err("Time out waiting for port reset");
+            }
+
+            time::time_manager().spin_for(Duration::from_millis(1));
+        }
+
+        self.clear_port_feature(port, C_PORT_RESET)?;
+        time::time_manager().spin_for(PORT_RESET_RECOVERY);
+
+        Ok(())
+    }
+}
+
+//--------------------------------------------------------------------------------------------------
+// Testing
+//--------------------------------------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+    use test_macros::kernel_test;
+
+    const MODEL_PORTS: usize = 4;
+
+    /// A modeled hub with a device attached to port 3.
+    struct ModelHub {
+        status: [Cell<u16>; MODEL_PORTS],
+        change: [Cell<u16>; MODEL_PORTS],
+        attached: usize,
+    }
+
+    impl ModelHub {
+        fn new() -> Self {
+            Self {
+                status: Default::default(),
+                change: Default::default(),
+                attached: 3,
+            }
+        }
+
+        fn port(&self, setup: &SetupPacket) -> Result<usize, &'static str> {
+            match setup.index as usize {
+                p @ 1..=MODEL_PORTS => Ok(p - 1),
+                _ => Err("Invalid port"),
+            }
+        }
+
+        fn set_bits(cell: &Cell<u16>, bits: u16) {
+            cell.set(cell.get() | bits);
+        }
+
+        fn clear_bits(cell: &Cell<u16>, bits: u16) {
+            cell.set(cell.get() & !bits);
+        }
+    }
+
+    impl ControlTransfer for ModelHub {
+        fn control_transfer(
+            &self,
+            setup: &SetupPacket,
+            data: &mut [u8],
+        ) -> Result<usize, &'static str> {
+            match setup.request {
+                SetupPacket::GET_DESCRIPTOR => {
+                    let desc = [
+                        9,
+                        DESCRIPTOR_TYPE_HUB,
+                        MODEL_PORTS as u8,
+                        0,
+                        0,
+                        1,
+                        0,
+                        0,
+                        0xFF,
+                    ];
+                    let len = core::cmp::min(desc.len(), data.len());
+                    data[..len].copy_from_slice(&desc[..len]);
+                    Ok(len)
+                }
+                SetupPacket::SET_FEATURE => {
+                    let p = self.port(setup)?;
+                    match setup.value {
+                        PORT_POWER => {
+                            Self::set_bits(&self.status[p], PortStatus::POWER);
+                            // Powering the port makes an attached device visible.
+                            if p + 1 == self.attached {
+                                Self::set_bits(&self.status[p], PortStatus::CONNECTION);
+                                Self::set_bits(&self.change[p], PortStatus::C_CONNECTION);
+                            }
+                        }
+                        // The modeled reset completes instantly and enables the port.
+                        PORT_RESET => {
+                            Self::set_bits(&self.status[p], PortStatus::ENABLE);
+                            Self::set_bits(&self.change[p], PortStatus::C_RESET);
+                        }
+                        _ => return Err("Unsupported feature"),
+                    }
+                    Ok(0)
+                }
+                SetupPacket::CLEAR_FEATURE => {
+                    let p = self.port(setup)?;
+                    match setup.value {
+                        C_PORT_CONNECTION => {
+                            Self::clear_bits(&self.change[p], PortStatus::C_CONNECTION)
+                        }
+                        C_PORT_RESET => Self::clear_bits(&self.change[p], PortStatus::C_RESET),
+                        _ => return Err("Unsupported feature"),
+                    }
+                    Ok(0)
+                }
+                SetupPacket::GET_STATUS => {
+                    let p = self.port(setup)?;
+                    data[..2].copy_from_slice(&self.status[p].get().to_le_bytes());
+                    data[2..4].copy_from_slice(&self.change[p].get().to_le_bytes());
+                    Ok(4)
+                }
+                _ => Err("Unsupported request"),
+            }
+        }
+    }
+
+    /// All ports must be powered, and the attached device must be detected and its port enabled.
+    #[kernel_test]
+    fn hub_powers_ports_and_detects_connection() {
+        let model = ModelHub::new();
+        let mut hub = UsbHub::new(&model);
+
+        assert_eq!(hub.init(), Ok(()));
+        assert_eq!(hub.num_ports(), MODEL_PORTS);
+        assert!(hub.ports().all(|p| p.is_powered()));
+
+        assert_eq!(hub.poll(), Ok(1));
+
+        for p in hub.ports() {
+            let attached = p.port as usize == model.attached;
+
+            assert_eq!(p.is_connected(), attached);
+            assert_eq!(p.is_enabled(), attached);
+            assert_eq!(p.change, 0);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Console throughput tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bench, bsp, cpu};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The configured divisors must result in the intended 230400 baud, give or take 1 %.
+#[kernel_test]
+fn baud_rate_matches_configuration() {
+    let baud = bsp::console::baud_rate();
+
+    assert!(baud > 228_096 && baud < 232_704);
+}
+
+/// The measured TX throughput must not fall below 90 % of what the baud rate allows.
+///
+/// QEMU does not model the baud rate and transmits as fast as the host allows, so only the lower
+/// bound is checked. On real HW, the result is capped by the baud rate anyways.
+#[kernel_test]
+fn console_tx_throughput_is_within_tolerance() {
+    let throughput = bench::console_tx_throughput(1024);
+
+    assert!(throughput.percent_of_max() >= 90);
+}
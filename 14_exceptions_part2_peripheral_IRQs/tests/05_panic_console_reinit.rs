@@ -0,0 +1,40 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! The panic handler must be able to print even if the console was misconfigured before.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+use libkernel::{bsp, cpu, println};
+
+/// Overwrites libkernel's `panic_wait::_panic_exit()`.
+///
+/// At this point, the panic handler has printed its message, so the console must have been
+/// restored to its default configuration.
+#[no_mangle]
+fn _panic_exit() -> ! {
+    if unsafe { bsp::console::qemu_console_config_is_default() } {
+        cpu::qemu_exit_success()
+    }
+
+    cpu::qemu_exit_failure()
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    println!("Testing panic output after console corruption");
+    println!("-------------------------------------------------------------------\n");
+
+    bsp::console::qemu_corrupt_console_config();
+    if bsp::console::qemu_console_config_is_default() {
+        // Corrupting did not work, so the test would prove nothing.
+        cpu::qemu_exit_failure()
+    }
+
+    panic!("Console was corrupted")
+}
@@ -0,0 +1,28 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+
+TIMEOUT_SECS = 3
+
+# Verify that the panic message names both overlapping regions.
+class OverlapPanicMessage
+    def name
+        'Overlap panic names both regions'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expected = 'Memory regions overlap: Device A 0x1000..0x2000 and Device C 0x1800..0x2800'
+        raise('Overlap was not reported') if qemu_out.expect(expected, TIMEOUT_SECS).nil?
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [OverlapPanicMessage.new]
+end
@@ -0,0 +1,33 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Overlapping memory regions must be caught with a panic that names both regions.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+/// Overwrites libkernel's `panic_wait::_panic_exit()` with the QEMU-exit version.
+///
+/// Reaching this code is a success, because the overlap check is supposed to panic. The console
+/// test in `06_memory_region_overlap.rb` checks that both regions are named.
+mod panic_exit_success;
+
+use libkernel::{bsp, cpu, memory, println};
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    println!("Testing detection of overlapping memory regions");
+
+    memory::assert_no_overlaps(&[
+        ("Device A", 0x1000..0x2000),
+        ("Device B", 0x3000..0x4000),
+        ("Device C", 0x1800..0x2800),
+    ]);
+
+    // If execution reaches here, the overlap was not detected.
+    cpu::qemu_exit_failure()
+}
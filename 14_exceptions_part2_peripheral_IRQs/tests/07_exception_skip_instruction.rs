@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Synchronous exception handlers must be able to resume after the faulting instruction.
+
+#![feature(asm)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use libkernel::{bsp, cpu, exception, exception::ExceptionContext};
+use test_macros::kernel_test;
+
+static NUM_BRK_HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+/// Handles `brk #0x42` by skipping it.
+fn skip_brk(e: &mut ExceptionContext) -> bool {
+    const BRK_0X42: u32 = 0xD420_0000 | (0x42 << 5);
+
+    if unsafe { e.instruction() } != BRK_0X42 {
+        return false;
+    }
+
+    NUM_BRK_HANDLED.fetch_add(1, Ordering::Relaxed);
+    e.skip_instruction();
+
+    true
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+    exception::register_sync_handler(skip_brk).unwrap();
+    exception::register_sync_handler(exception::emulate_sysreg_read_as_zero).unwrap();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A handled `brk` must be skipped, execution must resume right after it.
+#[kernel_test]
+fn handled_brk_is_skipped() {
+    let mut x: u64 = 1;
+
+    unsafe {
+        asm!(
+            "brk #0x42",
+            "add {x}, {x}, #1",
+            x = inout(reg) x,
+        );
+    }
+
+    assert_eq!(NUM_BRK_HANDLED.load(Ordering::Relaxed), 1);
+    assert_eq!(x, 2);
+}
+
+/// Reading a system register that the core does not implement must read as zero, and execution
+/// must resume right after the `mrs`.
+#[kernel_test]
+fn unimplemented_sysreg_reads_as_zero() {
+    let mut x: u64 = 0x42;
+
+    // An IMPLEMENTATION DEFINED encoding that neither QEMU nor the RPis' cores implement.
+    unsafe {
+        asm!(
+            "mrs {x}, S3_7_C15_C15_7",
+            "add {x}, {x}, #1",
+            x = inout(reg) x,
+        );
+    }
+
+    assert_eq!(x, 1);
+}
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A loaded payload must be executable after handing control to it.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use libkernel::{bsp, cpu, exception, exec, memory, println};
+
+const SENTINEL: u32 = 0x5E47_1E11;
+
+/// Written by the payload.
+static PAYLOAD_SENTINEL: AtomicU32 = AtomicU32::new(0);
+
+/// A position independent payload that stores a sentinel and branches back into the kernel.
+///
+/// ```text
+///   0: ldr x0, 24       // Address to store to
+///   4: ldr w1, 32       // Value to store
+///   8: str w1, [x0]
+///  12: ldr x2, 40       // Address to continue at
+///  16: br  x2
+///  20: nop
+///  24: .quad <address of PAYLOAD_SENTINEL>
+///  32: .word <SENTINEL>, 0
+///  40: .quad <address of payload_returned()>
+/// ```
+fn payload() -> [u32; 12] {
+    let sentinel_addr = &PAYLOAD_SENTINEL as *const _ as u64;
+    let return_addr = payload_returned as *const () as u64;
+
+    [
+        0x5800_00C0,
+        0x1800_00E1,
+        0xB900_0001,
+        0x5800_00E2,
+        0xD61F_0040,
+        0xD503_201F,
+        sentinel_addr as u32,
+        (sentinel_addr >> 32) as u32,
+        SENTINEL,
+        0,
+        return_addr as u32,
+        (return_addr >> 32) as u32,
+    ]
+}
+
+/// Where the payload continues after storing the sentinel.
+extern "C" fn payload_returned() -> ! {
+    if PAYLOAD_SENTINEL.load(Ordering::Relaxed) == SENTINEL {
+        println!("Payload wrote the sentinel");
+        cpu::qemu_exit_success()
+    }
+
+    cpu::qemu_exit_failure()
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    println!("Testing execution of a loaded payload");
+
+    exception::handling_init();
+
+    // The MMU enables the caches, which is what makes the cache maintenance necessary.
+    if let Err(string) = memory::mmu::mmu().init() {
+        println!("MMU: {}", string);
+        cpu::qemu_exit_failure()
+    }
+
+    let image = payload();
+    let image_bytes =
+        core::slice::from_raw_parts(image.as_ptr() as *const u8, core::mem::size_of_val(&image));
+
+    let entry = match exec::load_payload(image_bytes) {
+        Ok(addr) => addr,
+        Err(string) => {
+            println!("Load: {}", string);
+            cpu::qemu_exit_failure()
+        }
+    };
+
+    // Verifying the image must not change it.
+    let hash = libkernel::loader::sha256(image_bytes);
+    exec::exec_payload(entry, Some(&hash))
+}
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Sampling profiler tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{bsp, cpu, exception, profile, time, time::interface::TimeManager};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    // On the RPi 3, the interrupt controllers need no driver init.
+    profile::init().expect("Registering the profiler");
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Upper bound for the size of `busy_loop()`'s machine code.
+const BUSY_LOOP_MAX_SIZE: usize = 256;
+
+/// Burn cycles for `duration`, almost exclusively inside of this function.
+#[inline(never)]
+fn busy_loop(duration: Duration) {
+    let deadline = time::time_manager().uptime() + duration;
+
+    while time::time_manager().uptime() < deadline {
+        cpu::spin_for_cycles(1000);
+    }
+}
+
+/// A tight loop under profiling must dominate the histogram.
+///
+/// Only lower bounds are checked, so that late or missed samples of a loaded host do not fail the
+/// test. `busy_loop()` may straddle a bucket boundary, so its samples are counted over all the
+/// buckets it overlaps.
+#[kernel_test]
+fn busy_loop_dominates_histogram() {
+    const RATE_HZ: u32 = 1000;
+    const DURATION: Duration = Duration::from_millis(200);
+
+    assert!(profile::start(RATE_HZ).is_ok());
+    busy_loop(DURATION);
+    profile::stop();
+
+    // At least a quarter of the nominal number of samples.
+    let nominal = RATE_HZ * DURATION.as_millis() as u32 / 1000;
+    let total = profile::num_samples();
+    assert!(total >= nominal / 4);
+
+    let busy_loop_start = busy_loop as *const () as usize;
+    let busy_loop_end = busy_loop_start + BUSY_LOOP_MAX_SIZE;
+    assert!(profile::samples_in(busy_loop_start..busy_loop_end) * 2 >= total);
+    assert!(profile::hottest_bucket().is_some());
+
+    profile::report();
+}
+
+/// Rates above the supported maximum must be rejected.
+#[kernel_test]
+fn excessive_rate_is_rejected() {
+    assert!(profile::start(profile::MAX_RATE_HZ + 1).is_err());
+    assert!(profile::start(0).is_err());
+}
+
+/// While the sleeps own the virtual timer, the profiler must not arm it.
+#[kernel_test]
+fn start_is_rejected_with_virtual_counter() {
+    time::set_counter_kind(time::CounterKind::Virtual);
+    let result = profile::start(100);
+    time::set_counter_kind(time::CounterKind::Physical);
+
+    assert!(result.is_err());
+    assert!(time::arm_virtual_timer(Duration::from_millis(1)).is_ok());
+    time::disarm_virtual_timer();
+}
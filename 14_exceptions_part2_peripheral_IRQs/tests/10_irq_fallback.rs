@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ fallback handler tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, exception, exception::asynchronous::interface::IRQManager, time};
+use test_macros::kernel_test;
+
+/// Set by the fallback, after it stored the IRQ number in `FALLBACK_IRQ`.
+static FALLBACK_CALLED: AtomicBool = AtomicBool::new(false);
+
+static mut FALLBACK_IRQ: Option<bsp::device_driver::IRQNumber> = None;
+
+fn fallback(irq: bsp::device_driver::IRQNumber) {
+    // The timer IRQ is level triggered, so silence it before returning.
+    time::disarm_virtual_timer();
+
+    unsafe { FALLBACK_IRQ = Some(irq) };
+    FALLBACK_CALLED.store(true, Ordering::Release);
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    // On the RPi 3, the interrupt controllers need no driver init. No handler is registered for the
+    // virtual timer, so its IRQ can only reach the fallback.
+    let irqm = bsp::exception::asynchronous::irq_manager();
+    irqm.set_fallback(fallback);
+    irqm.enable(bsp::exception::asynchronous::virtual_timer_irq());
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// An IRQ without a registered handler must be passed to the fallback, together with its number.
+#[kernel_test]
+fn fallback_receives_unregistered_irq() {
+    time::arm_virtual_timer(Duration::from_millis(1)).unwrap();
+
+    for _ in 0..1000 {
+        if FALLBACK_CALLED.load(Ordering::Acquire) {
+            break;
+        }
+
+        cpu::spin_for_cycles(10_000);
+    }
+
+    assert!(FALLBACK_CALLED.load(Ordering::Acquire));
+    assert!(unsafe { FALLBACK_IRQ } == Some(bsp::exception::asynchronous::virtual_timer_irq()));
+}
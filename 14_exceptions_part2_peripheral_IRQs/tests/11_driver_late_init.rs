@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Driver late init tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use libkernel::{bsp, cpu, driver, driver::interface::DriverManager, exception, state};
+use test_macros::kernel_test;
+
+/// A driver that records when its init functions run.
+struct SlowDriver {
+    init_done: AtomicBool,
+    late_init_calls: AtomicUsize,
+    late_init_in_main: AtomicBool,
+}
+
+impl driver::interface::DeviceDriver for SlowDriver {
+    fn compatible(&self) -> &str {
+        "Slow Driver"
+    }
+
+    fn init(&self) -> Result<(), ()> {
+        self.init_done.store(true, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    fn late_init(&self) -> Result<(), &'static str> {
+        let in_main = state::state_manager().state() == state::State::SingleCoreMain;
+
+        self.late_init_in_main.store(in_main, Ordering::Relaxed);
+        self.late_init_calls.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+struct TestDriverManager;
+
+impl driver::interface::DriverManager for TestDriverManager {
+    fn all_device_drivers(&self) -> &[&'static (dyn driver::interface::DeviceDriver + Sync)] {
+        &DRIVERS[..]
+    }
+
+    fn post_device_driver_init(&self) {}
+}
+
+static SLOW_DRIVER: SlowDriver = SlowDriver {
+    init_done: AtomicBool::new(false),
+    late_init_calls: AtomicUsize::new(0),
+    late_init_in_main: AtomicBool::new(false),
+};
+
+static DRIVERS: [&'static (dyn driver::interface::DeviceDriver + Sync); 1] = [&SLOW_DRIVER];
+
+static DRIVER_MANAGER: TestDriverManager = TestDriverManager;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    for i in DRIVER_MANAGER.all_device_drivers() {
+        i.init().unwrap();
+    }
+    DRIVER_MANAGER.post_device_driver_init();
+
+    exception::asynchronous::local_irq_unmask();
+    state::state_manager().transition_to_single_core_main();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Late init must not run as part of driver init, only when the main phase asks for it.
+#[kernel_test]
+fn late_init_runs_only_in_main_phase() {
+    assert!(SLOW_DRIVER.init_done.load(Ordering::Relaxed));
+    assert_eq!(SLOW_DRIVER.late_init_calls.load(Ordering::Relaxed), 0);
+
+    DRIVER_MANAGER.run_late_init();
+
+    assert_eq!(SLOW_DRIVER.late_init_calls.load(Ordering::Relaxed), 1);
+    assert!(SLOW_DRIVER.late_init_in_main.load(Ordering::Relaxed));
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Exception handlers must run on the dedicated exception stack after `cpu::use_sp_el0(true)`.
+
+#![feature(asm)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use libkernel::{bsp, cpu, exception, exception::ExceptionContext};
+use test_macros::kernel_test;
+
+/// The stack pointer that the last `brk #0x43` was handled on.
+static HANDLER_SP: AtomicUsize = AtomicUsize::new(0);
+
+/// Return the current stack pointer.
+#[inline(always)]
+fn stack_pointer() -> usize {
+    let sp: usize;
+    unsafe { asm!("mov {}, sp", out(reg) sp, options(nomem, nostack, preserves_flags)) };
+
+    sp
+}
+
+/// Handles `brk #0x43` by recording the stack pointer and skipping it.
+fn record_sp(e: &mut ExceptionContext) -> bool {
+    const BRK_0X43: u32 = 0xD420_0000 | (0x43 << 5);
+
+    if unsafe { e.instruction() } != BRK_0X43 {
+        return false;
+    }
+
+    HANDLER_SP.store(stack_pointer(), Ordering::Relaxed);
+    e.skip_instruction();
+
+    true
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+    exception::register_sync_handler(record_sp).unwrap();
+
+    cpu::use_sp_el0(true);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Normal code keeps its stack, while the handler runs on the exception stack.
+#[kernel_test]
+fn exception_handler_runs_on_exception_stack() {
+    let exception_stack = cpu::exception_stack_range();
+
+    assert!(cpu::is_using_sp_el0());
+    assert!(!exception_stack.contains(&stack_pointer()));
+
+    unsafe { asm!("brk #0x43") };
+
+    assert!(exception_stack.contains(&HANDLER_SP.load(Ordering::Relaxed)));
+    assert!(!exception_stack.contains(&stack_pointer()));
+}
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! The PMU must tell cache friendly from cache hostile code.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bench, bsp, cpu, exception, memory, pmu};
+use test_macros::kernel_test;
+
+/// Eight times the size of the Cortex-A53's and Cortex-A72's L1 data cache.
+const BUF_SIZE: usize = 256 * 1024;
+
+/// The cache line size of the Cortex-A53 and Cortex-A72.
+const CACHE_LINE_SIZE: usize = 64;
+
+static mut BUF: [u8; BUF_SIZE] = [0; BUF_SIZE];
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    // Caching needs the MMU.
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    pmu::init();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Both loops must count cycles and instructions. Touching a new cache line on every access must
+/// miss more than touching the same one, if the core counts refills.
+#[kernel_test]
+fn thrashing_loop_misses_more_than_tight_loop() {
+    let num_lines = BUF_SIZE / CACHE_LINE_SIZE;
+
+    let tight = bench::measure_pmu(4, || {
+        for _ in 0..num_lines {
+            unsafe { core::ptr::read_volatile(&BUF[0]) };
+        }
+    });
+
+    let thrashing = bench::measure_pmu(4, || {
+        for line in 0..num_lines {
+            unsafe { core::ptr::read_volatile(&BUF[line * CACHE_LINE_SIZE]) };
+        }
+    });
+
+    bench::print_pmu_measurement("Tight", &tight);
+    bench::print_pmu_measurement("Thrashing", &thrashing);
+
+    // Every iteration loads at least once.
+    for m in [&tight, &thrashing].iter() {
+        assert!(m.cycles > 0);
+        assert!(m.instructions >= (m.iterations * num_lines) as u64);
+    }
+
+    // Emulators often do not model caches. Skip only the refill comparison then.
+    if !pmu::is_supported(pmu::Event::L1DCacheRefill) {
+        return;
+    }
+
+    assert!(thrashing.l1d_misses > tight.l1d_misses);
+    assert!(thrashing.misses_per_iteration() > tight.misses_per_iteration());
+}
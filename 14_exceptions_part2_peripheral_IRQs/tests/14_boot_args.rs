@@ -0,0 +1,39 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! The registers that the boot loader hands over must survive until the kernel reads them.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The registers must hold exactly what QEMU passes.
+///
+/// The test runner starts QEMU with `-kernel` and without `-dtb`. QEMU then does not treat the
+/// image as a Linux kernel and enters it with x0-x3 cleared. The saved registers start out as
+/// `u64::MAX`, so all zeros also shows that they were captured at kernel entry and not clobbered
+/// afterwards.
+#[kernel_test]
+fn boot_args_match_qemu_handover() {
+    let args = bsp::boot_args();
+
+    assert_eq!((args.x0, args.x1, args.x2, args.x3), (0, 0, 0, 0));
+    assert_eq!(args.dtb_addr(), None);
+}
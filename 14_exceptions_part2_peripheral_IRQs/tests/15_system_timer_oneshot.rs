@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! System timer one-shot tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, driver::interface::DeviceDriver, exception, println};
+use test_macros::kernel_test;
+
+/// The system timer's counter when the one-shot fired. Zero until then.
+static FIRED_AT: AtomicU64 = AtomicU64::new(0);
+
+fn record_fire() {
+    FIRED_AT.store(bsp::SYSTEM_TIMER.ticks(), Ordering::Release);
+}
+
+fn never_called() {
+    panic!("One-shot fired too early");
+}
+
+/// Return whether the system timer counts, which emulators do not always model.
+fn system_timer_is_running() -> bool {
+    let start = bsp::SYSTEM_TIMER.ticks();
+    cpu::spin_for_cycles(100_000);
+
+    bsp::SYSTEM_TIMER.ticks() != start
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    // On the RPi 3, the interrupt controllers need no driver init.
+    bsp::SYSTEM_TIMER.register_and_enable_irq_handler().unwrap();
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A one-shot must fire once, not earlier than requested, and not much later.
+#[kernel_test]
+fn one_shot_fires_after_delay() {
+    if !system_timer_is_running() {
+        println!("System timer not running, skipping");
+        return;
+    }
+
+    let delay = Duration::from_millis(5);
+    let start = bsp::SYSTEM_TIMER.ticks();
+    bsp::SYSTEM_TIMER.after(delay, record_fire).unwrap();
+
+    for _ in 0..1000 {
+        if FIRED_AT.load(Ordering::Acquire) != 0 {
+            break;
+        }
+
+        cpu::spin_for_cycles(10_000);
+    }
+
+    let fired_at = FIRED_AT.load(Ordering::Acquire);
+    assert_ne!(fired_at, 0);
+
+    let elapsed_us = fired_at - start;
+    assert!(elapsed_us >= delay.as_micros() as u64);
+    assert!(elapsed_us < delay.as_micros() as u64 + 50_000);
+
+    // The channel is free again.
+    assert_eq!(bsp::SYSTEM_TIMER.num_free_channels(), 2);
+}
+
+/// Only channels 1 and 3 can be handed out, a third one-shot must be refused.
+#[kernel_test]
+fn third_one_shot_is_refused() {
+    let hour = Duration::from_secs(3600);
+
+    bsp::SYSTEM_TIMER.after(hour, never_called).unwrap();
+    bsp::SYSTEM_TIMER.after(hour, never_called).unwrap();
+
+    assert_eq!(bsp::SYSTEM_TIMER.num_free_channels(), 0);
+    assert!(bsp::SYSTEM_TIMER.after(hour, never_called).is_err());
+}
@@ -0,0 +1,41 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+
+TIMEOUT_SECS = 3
+
+# Verify that reading without input times out.
+class ReadTimeoutExpires
+    def name
+        'Read without input times out'
+    end
+
+    def run(qemu_out, _qemu_in)
+        raise('Read did not time out') if qemu_out.expect('TIMEOUT_OK', TIMEOUT_SECS).nil?
+    end
+end
+
+# Verify that input arriving before the timeout is returned. Depends on test 1 being run first.
+class ReadBeforeTimeout
+    def name
+        'Read returns input that arrives in time'
+    end
+
+    def run(qemu_out, qemu_in)
+        raise('Kernel did not ask for input') if qemu_out.expect('SEND', TIMEOUT_SECS).nil?
+
+        qemu_in.write_nonblock('X')
+        raise('Input was not returned') if qemu_out.expect('RX_OK', TIMEOUT_SECS).nil?
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [ReadTimeoutExpires.new, ReadBeforeTimeout.new]
+end
@@ -0,0 +1,44 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Console read timeout tests.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{bsp, console, print, time, time::interface::TimeManager};
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use bsp::console::{console, qemu_bring_up_console};
+    use console::interface::*;
+
+    qemu_bring_up_console();
+
+    // Without input, the timeout must expire, and not much earlier or later than requested.
+    let timeout = Duration::from_millis(200);
+    let start = time::time_manager().uptime();
+    let c = console().read_char_timeout(timeout);
+    let elapsed = time::time_manager().uptime() - start;
+
+    assert_eq!(c, None);
+    assert!(elapsed >= timeout);
+    assert!(elapsed < timeout * 2);
+    print!("TIMEOUT_OK\n");
+
+    // Input that arrives before the timeout must be returned.
+    print!("SEND\n");
+    assert_eq!(
+        console().read_char_timeout(Duration::from_secs(3)),
+        Some('X')
+    );
+    print!("RX_OK\n");
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    loop {}
+}
@@ -0,0 +1,86 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Activating a second set of translation tables must change translations, and restoring the
+//! previous ones must undo it.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception, memory};
+use test_macros::kernel_test;
+
+/// One translation granule worth of data.
+#[repr(align(65536))]
+struct Page([u64; memory::mmu::GRANULE_SIZE / 8]);
+
+const ASID: u8 = 1;
+
+static mut PAGE_A: Page = Page([0; memory::mmu::GRANULE_SIZE / 8]);
+static mut PAGE_B: Page = Page([0; memory::mmu::GRANULE_SIZE / 8]);
+
+static mut SECOND_TABLE: memory::mmu::TranslationTable = memory::mmu::TranslationTable::new(ASID);
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The second tables redirect `PAGE_A` to `PAGE_B`, until the kernel's tables are restored.
+#[kernel_test]
+fn activate_table_changes_translation() {
+    use memory::mmu::interface::MMU;
+
+    unsafe {
+        let page_a = &PAGE_A as *const _ as usize;
+        let page_b = &PAGE_B as *const _ as usize;
+
+        PAGE_A.0[0] = 0xAAAA;
+        PAGE_B.0[0] = 0xBBBB;
+
+        SECOND_TABLE
+            .populate(|virt_addr| {
+                let (output_addr, attribute_fields) =
+                    bsp::memory::mmu::virt_mem_layout().virt_addr_properties(virt_addr)?;
+
+                if virt_addr == page_a {
+                    Ok((page_b, attribute_fields))
+                } else {
+                    Ok((output_addr, attribute_fields))
+                }
+            })
+            .unwrap();
+
+        let read_a = || core::ptr::read_volatile(&PAGE_A.0[0]);
+        assert_eq!(read_a(), 0xAAAA);
+
+        let prev = memory::mmu::mmu().activate_table(&SECOND_TABLE).unwrap();
+        assert_eq!(read_a(), 0xBBBB);
+
+        let active = memory::mmu::mmu().activate_table(&SECOND_TABLE).unwrap();
+        assert_eq!(active.asid(), ASID);
+        assert_eq!(active.base_addr() % memory::mmu::GRANULE_SIZE, 0);
+
+        memory::mmu::mmu().restore_table(prev);
+        assert_eq!(read_a(), 0xAAAA);
+        assert_eq!(prev.asid(), 0);
+    }
+}
@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Tables with different ASIDs must keep their own translations, and repopulating a table must
+//! drop the stale TLB entries of its ASID.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception, memory};
+use memory::mmu::{AsidAllocator, TranslationTable, GRANULE_SIZE};
+use test_macros::kernel_test;
+
+/// One translation granule worth of data.
+#[repr(align(65536))]
+struct Page([u64; GRANULE_SIZE / 8]);
+
+static mut WINDOW: Page = Page([0; GRANULE_SIZE / 8]);
+static mut PAGE_A: Page = Page([0; GRANULE_SIZE / 8]);
+static mut PAGE_B: Page = Page([0; GRANULE_SIZE / 8]);
+static mut PAGE_C: Page = Page([0; GRANULE_SIZE / 8]);
+
+static mut TABLE_A: TranslationTable = TranslationTable::new(0);
+static mut TABLE_B: TranslationTable = TranslationTable::new(0);
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Map `WINDOW` to `page` in `table`, everything else like the kernel does.
+unsafe fn map_window(table: &mut TranslationTable, page: &Page) {
+    let window = &WINDOW as *const _ as usize;
+    let page = page as *const _ as usize;
+
+    table
+        .populate(|virt_addr| {
+            let (output_addr, attribute_fields) =
+                bsp::memory::mmu::virt_mem_layout().virt_addr_properties(virt_addr)?;
+
+            if virt_addr == window {
+                Ok((page, attribute_fields))
+            } else {
+                Ok((output_addr, attribute_fields))
+            }
+        })
+        .unwrap();
+}
+
+/// Each table sees its own page through the window, also after remapping an inactive table.
+#[kernel_test]
+fn switching_asids_keeps_translations_apart() {
+    use memory::mmu::interface::MMU;
+
+    let mut allocator = AsidAllocator::new();
+    let asid_a = allocator.allocate();
+    let asid_b = allocator.allocate();
+    assert_ne!(asid_a, asid_b);
+
+    unsafe {
+        TABLE_A.set_asid(asid_a.value());
+        TABLE_B.set_asid(asid_b.value());
+
+        PAGE_A.0[0] = 0xAAAA;
+        PAGE_B.0[0] = 0xBBBB;
+        PAGE_C.0[0] = 0xCCCC;
+
+        map_window(&mut TABLE_A, &PAGE_A);
+        map_window(&mut TABLE_B, &PAGE_B);
+
+        let read_window = || core::ptr::read_volatile(&WINDOW.0[0]);
+
+        let prev = memory::mmu::mmu().activate_table(&TABLE_A).unwrap();
+        assert_eq!(read_window(), 0xAAAA);
+
+        memory::mmu::mmu().activate_table(&TABLE_B).unwrap();
+        assert_eq!(read_window(), 0xBBBB);
+
+        // Remap A while B is active. Switching back must not hit A's stale TLB entry.
+        map_window(&mut TABLE_A, &PAGE_C);
+        memory::mmu::mmu().activate_table(&TABLE_A).unwrap();
+        assert_eq!(read_window(), 0xCCCC);
+
+        memory::mmu::mmu().activate_table(&TABLE_B).unwrap();
+        assert_eq!(read_window(), 0xBBBB);
+
+        memory::mmu::mmu().restore_table(prev);
+        assert_eq!(read_window(), 0);
+    }
+}
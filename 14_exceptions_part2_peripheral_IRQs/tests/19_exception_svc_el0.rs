@@ -0,0 +1,76 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! System calls from an EL0 program must reach their handlers with the caller's arguments, and
+//! return values must make it back to the caller.
+
+#![feature(asm)]
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception, exception::syscall, println};
+
+const EL0_STACK_SIZE: usize = 16 * 1024;
+
+#[repr(align(16))]
+struct Stack([u8; EL0_STACK_SIZE]);
+
+static mut EL0_STACK: Stack = Stack([0; EL0_STACK_SIZE]);
+
+const MSG: &str = "Hello from EL0\n";
+
+/// The EL0 program. Writes a message, then exits with the result of the write and some markers.
+extern "C" fn el0_main() -> ! {
+    let written: u64;
+
+    unsafe {
+        asm!(
+            "svc #0",
+            inlateout("x0") MSG.as_ptr() => written,
+            in("x1") MSG.len(),
+        );
+
+        asm!(
+            "svc #1",
+            in("x0") written,
+            in("x1") 0x11,
+            in("x2") 0x22,
+            in("x3") 0x33,
+            in("x4") 0x44,
+            in("x5") 0x55,
+            in("x6") 0x66,
+            in("x7") 0x77,
+            options(noreturn)
+        );
+    }
+}
+
+/// Replaces the kernel's `exit`, which would park the core.
+fn test_exit(args: &[u64; 8]) -> u64 {
+    if args != &[MSG.len() as u64, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77] {
+        panic!("Unexpected exit arguments: {:x?}", args);
+    }
+
+    println!("[ok]");
+    cpu::qemu_exit_success()
+}
+
+/// The MMU stays off, so that EL0 can execute the test's code in the kernel image.
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    println!("Testing system calls from EL0");
+    println!("-------------------------------------------------------------------\n");
+
+    exception::handling_init();
+    exception::register_syscall(syscall::nr::WRITE, syscall::write).unwrap();
+    exception::register_syscall(syscall::nr::EXIT, test_exit).unwrap();
+
+    let stack_top = &EL0_STACK as *const _ as usize + EL0_STACK_SIZE;
+    exception::enter_el0(el0_main as *const () as usize, stack_top)
+}
@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A program in the user area must run at EL0 with the MMU on, and reach the kernel through system
+//! calls.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use libkernel::{bsp, cpu, exception, exception::syscall, memory, println};
+
+const MSG: &[u8] = b"Hello from EL0\n";
+
+/// The EL0 program, hand assembled:
+///
+/// ```text
+///     adr x0, msg
+///     mov x1, #15
+///     svc #0          // write(msg, 15)
+///     svc #1          // exit(<return value of write>)
+/// msg:
+///     .ascii "Hello from EL0\n"
+/// ```
+const PROGRAM: [u32; 4] = [0x1000_0080, 0xD280_01E1, 0xD400_0001, 0xD400_0021];
+
+static WRITE_OBSERVED: AtomicBool = AtomicBool::new(false);
+
+/// Check the message, then let the kernel's `write` print it.
+fn test_write(args: &[u64; 8]) -> u64 {
+    let bytes = unsafe { core::slice::from_raw_parts(args[0] as *const u8, args[1] as usize) };
+    if bytes != MSG {
+        panic!("Unexpected write from EL0");
+    }
+
+    WRITE_OBSERVED.store(true, Ordering::Relaxed);
+    syscall::write(args)
+}
+
+/// Replaces the kernel's `exit`, which would park the core.
+fn test_exit(args: &[u64; 8]) -> u64 {
+    if !WRITE_OBSERVED.load(Ordering::Relaxed) {
+        panic!("EL0 program exited without writing");
+    }
+
+    if args[0] != MSG.len() as u64 {
+        panic!("Unexpected exit code {}", args[0]);
+    }
+
+    println!("[ok]");
+    cpu::qemu_exit_success()
+}
+
+/// Copy the program to the start of the user area and make it visible to instruction fetches.
+unsafe fn load_program() -> usize {
+    let area = bsp::memory::user_range();
+    let entry = area.start as *mut u32;
+
+    for (i, instruction) in PROGRAM.iter().enumerate() {
+        core::ptr::write_volatile(entry.add(i), *instruction);
+    }
+
+    let msg = entry.add(PROGRAM.len()) as *mut u8;
+    core::ptr::copy_nonoverlapping(MSG.as_ptr(), msg, MSG.len());
+
+    cpu::cache::clean_dcache_range_to_pou(area.clone());
+    cpu::cache::invalidate_icache();
+
+    area.start
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    println!("Testing a program at EL0");
+    println!("-------------------------------------------------------------------\n");
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    exception::register_syscall(syscall::nr::WRITE, test_write).unwrap();
+    exception::register_syscall(syscall::nr::EXIT, test_exit).unwrap();
+
+    let entry = load_program();
+    exception::enter_el0(entry, bsp::memory::user_range().end)
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A `WFE` at EL0 must trap to the kernel, which yields to the next task instead of sleeping.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use libkernel::{bsp, cpu, exception, memory, println, sched};
+
+/// The EL0 program, hand assembled:
+///
+/// ```text
+///     wfe
+///     svc #1          // exit
+/// ```
+const PROGRAM: [u32; 2] = [0xD503_205F, 0xD400_0021];
+
+static mut TASK_STACK: [u8; 16 * 1024] = [0; 16 * 1024];
+
+static TASK_RAN: AtomicBool = AtomicBool::new(false);
+
+/// Can only run if the trapped `WFE` yielded the core.
+fn kernel_task() {
+    TASK_RAN.store(true, Ordering::Relaxed);
+}
+
+fn test_exit(_args: &[u64; 8]) -> u64 {
+    if !TASK_RAN.load(Ordering::Relaxed) {
+        panic!("Kernel task did not run while EL0 executed WFE");
+    }
+
+    println!("[ok]");
+    cpu::qemu_exit_success()
+}
+
+/// Copy the program to the start of the user area and make it visible to instruction fetches.
+unsafe fn load_program() -> usize {
+    let area = bsp::memory::user_range();
+    let entry = area.start as *mut u32;
+
+    for (i, instruction) in PROGRAM.iter().enumerate() {
+        core::ptr::write_volatile(entry.add(i), *instruction);
+    }
+
+    cpu::cache::clean_dcache_range_to_pou(area.clone());
+    cpu::cache::invalidate_icache();
+
+    area.start
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    println!("Testing trapped WFE from EL0");
+    println!("-------------------------------------------------------------------\n");
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    exception::register_syscall(exception::syscall::nr::EXIT, test_exit).unwrap();
+    exception::trap_el0_wfx(true);
+
+    sched::spawn(kernel_task, &mut TASK_STACK).unwrap();
+
+    let entry = load_program();
+    exception::enter_el0(entry, bsp::memory::user_range().end)
+}
@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Suspending and resuming the drivers must restore the UART's configuration bit-for-bit.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, driver, exception};
+use test_macros::kernel_test;
+
+/// Offsets of IBRD, FBRD, LCRH, CR, IFLS and IMSC.
+const CONFIG_REG_OFFSETS: [usize; 6] = [0x24, 0x28, 0x2C, 0x30, 0x34, 0x38];
+
+const CR_OFFSET: usize = 0x30;
+
+/// Read a UART register directly, bypassing the driver.
+fn uart_reg(offset: usize) -> u32 {
+    unsafe { core::ptr::read_volatile((bsp::memory::uart0_base() + offset) as *const u32) }
+}
+
+fn uart_config() -> [u32; 6] {
+    let mut config = [0; 6];
+    for (reg, &offset) in config.iter_mut().zip(CONFIG_REG_OFFSETS.iter()) {
+        *reg = uart_reg(offset);
+    }
+
+    config
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use driver::interface::DriverManager;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    // Other drivers' devices are not all emulated by QEMU, so only bring up the UART.
+    let uart = bsp::driver::driver_manager()
+        .all_device_drivers()
+        .iter()
+        .find(|d| d.compatible() == "BCM PL011 UART")
+        .unwrap();
+    uart.init().unwrap();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The UART must be disabled while suspended, and come back with its exact configuration.
+#[kernel_test]
+fn uart_config_is_restored_after_resume() {
+    use driver::interface::DriverManager;
+
+    let before = uart_config();
+    assert_ne!(before[3], 0);
+
+    // Nothing must be printed from here until resume.
+    bsp::driver::driver_manager().suspend_all();
+    let suspended_cr = uart_reg(CR_OFFSET);
+    bsp::driver::driver_manager().resume_all();
+
+    assert_eq!(suspended_cr, 0);
+    assert_eq!(uart_config(), before);
+}
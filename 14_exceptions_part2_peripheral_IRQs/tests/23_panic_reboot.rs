@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! With `PanicBehavior::Reboot`, the panic handler must reset the board after the delay.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{
+    bsp, cpu, panic, panic::PanicBehavior, println, time, time::interface::TimeManager,
+};
+
+const DELAY: Duration = Duration::from_millis(300);
+
+static mut PANIC_TIME: Duration = Duration::from_secs(0);
+
+/// Overwrites libkernel's `panic_wait::_panic_reset()`.
+///
+/// QEMU does not emulate the watchdog, so reaching this function stands in for the reset.
+#[no_mangle]
+fn _panic_reset() -> ! {
+    let elapsed = time::time_manager().uptime() - unsafe { PANIC_TIME };
+
+    if elapsed >= DELAY {
+        cpu::qemu_exit_success()
+    }
+
+    cpu::qemu_exit_failure()
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    println!("Testing reboot on panic");
+    println!("-------------------------------------------------------------------\n");
+
+    panic::set_behavior(PanicBehavior::Reboot(DELAY));
+    assert_eq!(panic::behavior(), PanicBehavior::Reboot(DELAY));
+
+    PANIC_TIME = time::time_manager().uptime();
+    panic!("Reboot expected")
+}
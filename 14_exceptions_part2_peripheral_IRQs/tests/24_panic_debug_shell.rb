@@ -0,0 +1,54 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+
+TIMEOUT_SECS = 3
+
+# Verify that the panic handler drops into the shell.
+class ShellPrompt
+    def name
+        'Panic enters the debug shell'
+    end
+
+    def run(qemu_out, _qemu_in)
+        raise('No panic message') if qemu_out.expect('Debug shell expected', TIMEOUT_SECS).nil?
+        raise('No shell prompt') if qemu_out.expect('debug> ', TIMEOUT_SECS).nil?
+    end
+end
+
+# Verify the register dump. Depends on test 1 being run first.
+class RegsCommand
+    def name
+        'Command "regs" prints the registers'
+    end
+
+    def run(qemu_out, qemu_in)
+        qemu_in.write_nonblock("regs\r")
+        raise('No ELR_EL1 in the dump') if qemu_out.expect('ELR_EL1:', TIMEOUT_SECS).nil?
+        raise('No ESR_EL1 in the dump') if qemu_out.expect('ESR_EL1:', TIMEOUT_SECS).nil?
+        raise('No prompt after the dump') if qemu_out.expect('debug> ', TIMEOUT_SECS).nil?
+    end
+end
+
+# Verify that unknown commands are rejected. Depends on test 1 being run first.
+class UnknownCommand
+    def name
+        'Unknown commands are rejected'
+    end
+
+    def run(qemu_out, qemu_in)
+        qemu_in.write_nonblock("poke\r")
+        raise('Command was not rejected') if qemu_out.expect('Unknown command: poke', TIMEOUT_SECS).nil?
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [ShellPrompt.new, RegsCommand.new, UnknownCommand.new]
+end
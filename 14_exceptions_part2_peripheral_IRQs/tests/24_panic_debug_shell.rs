@@ -0,0 +1,23 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! With `PanicBehavior::DebugShell`, the panic handler must run the debug shell.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, panic, panic::PanicBehavior};
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    panic::set_behavior(PanicBehavior::DebugShell);
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    panic!("Debug shell expected")
+}
@@ -0,0 +1,55 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Pending IRQ query tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{
+    bsp, cpu, exception, exception::asynchronous::interface::IRQManager, time,
+    time::interface::TimeManager,
+};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    // On the RPi 3, the interrupt controllers need no driver init. IRQs stay masked at the CPU, so
+    // a raised IRQ stays pending instead of being serviced.
+    bsp::exception::asynchronous::irq_manager()
+        .enable(bsp::exception::asynchronous::virtual_timer_irq());
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A raised, unserviced IRQ must show as pending, and disappear once its source is silenced.
+#[kernel_test]
+fn raised_irq_shows_as_pending() {
+    let irqm = bsp::exception::asynchronous::irq_manager();
+    let timer_irq = bsp::exception::asynchronous::virtual_timer_irq();
+
+    assert!(!irqm.pending().contains(timer_irq));
+
+    time::arm_virtual_timer(Duration::from_millis(1)).unwrap();
+    time::time_manager().spin_for(Duration::from_millis(10));
+
+    assert!(irqm.pending().contains(timer_irq));
+    irqm.print_pending();
+
+    // The timer IRQ is level triggered.
+    time::disarm_virtual_timer();
+    assert!(!irqm.pending().contains(timer_irq));
+}
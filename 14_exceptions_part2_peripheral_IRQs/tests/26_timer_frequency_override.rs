@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Generic timer frequency override tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{bsp, cpu, time, time::interface::TimeManager};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// QEMU programs a plausible frequency, so the sanity check must not override it.
+#[kernel_test]
+fn plausible_frequency_is_kept() {
+    time::check_frequency();
+
+    assert_eq!(time::frequency_override(), None);
+}
+
+/// With an override, durations must be computed from it instead of CNTFRQ_EL0.
+#[kernel_test]
+fn uptime_uses_override() {
+    time::set_frequency_override(100_000_000);
+    assert_eq!(time::frequency_override(), Some(100_000_000));
+    assert_eq!(time::time_manager().resolution(), Duration::from_nanos(10));
+    let slow = time::time_manager().uptime();
+
+    // Twice the frequency halves the duration that the same counter value converts to.
+    time::set_frequency_override(200_000_000);
+    assert_eq!(time::time_manager().resolution(), Duration::from_nanos(5));
+    let fast = time::time_manager().uptime();
+
+    assert!(fast <= slow / 2 + Duration::from_millis(1));
+    assert!(fast * 2 >= slow);
+
+    time::set_frequency_override(0);
+    assert_eq!(time::frequency_override(), None);
+}
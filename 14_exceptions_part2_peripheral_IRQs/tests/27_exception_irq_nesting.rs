@@ -0,0 +1,49 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ nesting depth tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Simulated nested entries and exits must be tracked, and runaway nesting be reported.
+#[kernel_test]
+fn nesting_depth_tracks_entry_and_exit() {
+    assert_eq!(exception::irq_nesting_depth(), 0);
+
+    for depth in 1..=exception::MAX_IRQ_NESTING_DEPTH {
+        assert_eq!(exception::irq_enter(), depth);
+    }
+    assert_eq!(exception::irq_nesting_overruns(), 0);
+
+    // One past the maximum.
+    assert_eq!(exception::irq_enter(), exception::MAX_IRQ_NESTING_DEPTH + 1);
+    assert_eq!(exception::irq_nesting_overruns(), 1);
+
+    for depth in (0..=exception::MAX_IRQ_NESTING_DEPTH).rev() {
+        exception::irq_exit();
+        assert_eq!(exception::irq_nesting_depth(), depth);
+    }
+
+    // An unbalanced exit must not wrap around.
+    exception::irq_exit();
+    assert_eq!(exception::irq_nesting_depth(), 0);
+}
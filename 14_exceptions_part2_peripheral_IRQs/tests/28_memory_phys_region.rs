@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Physical region zeroing tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception, memory, memory::Region};
+use test_macros::kernel_test;
+
+/// The cache line size of the Cortex-A53 and Cortex-A72.
+const LINE_SIZE: usize = 64;
+
+/// Kernel data, which is mapped cacheable.
+static mut CACHED_BUFFER: [u8; 4096] = [0xAA; 4096];
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A region in the non-cacheable DMA heap must read back zero through its uncached slice.
+#[kernel_test]
+fn uncached_region_reads_back_zero() {
+    // Deliberately not cache line aligned. Nothing else uses the heap in this test.
+    let start = bsp::memory::heap_range().start + 3;
+    let region = Region::new(start, start + 3000);
+    let mut phys = unsafe { memory::PhysRegion::new(region) };
+
+    unsafe { core::ptr::write_bytes(start as *mut u8, 0xAA, phys.len()) };
+    phys.zero();
+
+    let slice = phys.as_uncached_slice().unwrap();
+    assert_eq!(slice.len(), 3000);
+    assert!(slice.iter().all(|byte| *byte == 0));
+
+    // The clean must cover exactly the lines that the region touches: From the line holding the
+    // first byte up to and including the line holding the last one.
+    let first_line = start / LINE_SIZE * LINE_SIZE;
+    let end_line = (start + 3000 + LINE_SIZE - 1) / LINE_SIZE * LINE_SIZE;
+    let cleaned = cpu::cache::clean_dcache_range_to_poc(region.range());
+    assert_eq!(cleaned, first_line..end_line);
+}
+
+/// A cacheable region has no uncached slice, but must be zeroed just the same.
+#[kernel_test]
+fn cached_region_has_no_uncached_slice() {
+    let buffer = unsafe { &CACHED_BUFFER };
+    let start = buffer.as_ptr() as usize;
+    let mut phys = unsafe { memory::PhysRegion::new(Region::new(start, start + buffer.len())) };
+
+    assert!(phys.as_uncached_slice().is_none());
+    phys.zero();
+
+    assert!(buffer
+        .iter()
+        .all(|byte| unsafe { core::ptr::read_volatile(byte) } == 0));
+}
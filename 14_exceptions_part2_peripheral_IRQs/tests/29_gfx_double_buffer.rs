@@ -0,0 +1,73 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Double buffering tests.
+
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception, gfx::DoubleBuffer, memory};
+use linked_list_allocator::LockedHeap;
+use test_macros::kernel_test;
+
+const WIDTH: u32 = 64;
+const HEIGHT: u32 = 48;
+
+/// Mailbox messages are marshalled on the heap.
+#[global_allocator]
+static GLOBAL_ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+#[alloc_error_handler]
+fn alloc_error(_: core::alloc::Layout) -> ! {
+    panic!("Out of heap memory")
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    let heap = bsp::memory::heap_range();
+    GLOBAL_ALLOCATOR
+        .lock()
+        .init(heap.start, heap.end - heap.start);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Swapping must display the buffer that was drawn to, as reported by the firmware.
+#[kernel_test]
+fn swap_moves_displayed_offset_to_back_buffer() {
+    let mut db = DoubleBuffer::new(WIDTH, HEIGHT).unwrap();
+    assert_eq!(db.front_index(), 0);
+    assert_eq!(bsp::framebuffer::virtual_offset(), Ok((0, 0)));
+
+    db.back_mut().fill_rows(0..(HEIGHT as usize), 0x00FF_00FF);
+    db.swap().unwrap();
+
+    assert_eq!(db.front_index(), 1);
+    assert_eq!(db.front_offset(), HEIGHT as usize);
+    assert_eq!(bsp::framebuffer::virtual_offset(), Ok((0, HEIGHT)));
+    assert_eq!(db.front().pixel(0, 0), 0x00FF_00FF);
+
+    // And back again.
+    db.swap().unwrap();
+    assert_eq!(db.front_index(), 0);
+    assert_eq!(bsp::framebuffer::virtual_offset(), Ok((0, 0)));
+}
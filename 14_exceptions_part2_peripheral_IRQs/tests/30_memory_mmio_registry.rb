@@ -0,0 +1,41 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+
+TIMEOUT_SECS = 3
+
+# Verify that the registered region is printed with its range, size and name.
+class RegisteredRegionPrinted
+    def name
+        'Registered MMIO region in layout'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expected = '0x3f300000 - 0x3f300fff |   4 KiB | MMIO        | Test device'
+        raise('Registered region not printed') if qemu_out.expect(expected, TIMEOUT_SECS).nil?
+    end
+end
+
+# Verify that the refused regions did not make it into the layout.
+class RefusedRegionsNotPrinted
+    def name
+        'Refused MMIO regions not in layout'
+    end
+
+    def run(qemu_out, _qemu_in)
+        output = qemu_out.expect('End of memory layout', TIMEOUT_SECS)
+        raise('Layout not finished') if output.nil?
+        raise('Refused region printed') if output.first.include?('Overlapping device')
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [RegisteredRegionPrinted.new, RefusedRegionsNotPrinted.new]
+end
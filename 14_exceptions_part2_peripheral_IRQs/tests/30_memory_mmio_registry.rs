@@ -0,0 +1,33 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! MMIO regions registered at runtime must show up in the printed memory layout.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, println};
+
+const BASE: usize = 0x3F30_0000;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    bsp::memory::register_mmio("Test device", BASE, 0x1000).unwrap();
+
+    // Overlapping and empty regions are refused and stay out of the layout.
+    assert!(bsp::memory::register_mmio("Overlapping device", BASE + 0x800, 0x1000).is_err());
+    assert!(bsp::memory::register_mmio("Empty device", BASE + 0x2000, 0).is_err());
+    assert_eq!(bsp::memory::with_mmio_regions(|mmio| mmio.len()), 1);
+
+    println!("Memory layout:");
+    bsp::memory::mmu::virt_mem_layout().print_layout();
+    println!("End of memory layout");
+
+    cpu::qemu_exit_success()
+}
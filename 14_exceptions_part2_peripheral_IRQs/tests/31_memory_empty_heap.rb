@@ -0,0 +1,28 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+
+TIMEOUT_SECS = 3
+
+# Verify that the warning names the computed base and size of the heap.
+class EmptyHeapWarning
+    def name
+        'Empty heap warning'
+    end
+
+    def run(qemu_out, _qemu_in)
+        expected = '[W] Heap is empty: base 0x00200000, size 0x0'
+        raise('Empty heap was not reported') if qemu_out.expect(expected, TIMEOUT_SECS).nil?
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [EmptyHeapWarning.new]
+end
@@ -0,0 +1,26 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! An empty heap must be refused with a warning that names its base and size.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use libkernel::{cpu, memory};
+
+/// Where a misconfigured memory map could place the heap.
+const HEAP_BASE: usize = 0x0020_0000;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    // Deliberately no console bring up. The warning must make it out before any driver init.
+    if memory::validate_heap(&(HEAP_BASE..HEAP_BASE)).is_ok() {
+        cpu::qemu_exit_failure()
+    }
+
+    cpu::qemu_exit_success()
+}
@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! MMU disable tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception, memory};
+use test_macros::kernel_test;
+
+/// Kernel data, which is mapped cacheable while the MMU is on.
+static mut BUFFER: [u64; 8] = [0; 8];
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Data written through the cache must still be there at its physical address once the MMU is off.
+#[kernel_test]
+fn physical_memory_accessible_after_disable() {
+    use memory::mmu::interface::MMU;
+
+    assert!(memory::mmu::mmu().is_enabled());
+
+    let addr = unsafe { BUFFER.as_mut_ptr() };
+    for i in 0..8 {
+        unsafe { addr.add(i).write_volatile(0xdead_beef_0000_0000 | i as u64) };
+    }
+
+    unsafe { memory::mmu::mmu().disable() };
+    assert!(!memory::mmu::mmu().is_enabled());
+
+    // The kernel is identity mapped, so the virtual address is the physical one.
+    for i in 0..8 {
+        assert_eq!(
+            unsafe { addr.add(i).read_volatile() },
+            0xdead_beef_0000_0000 | i as u64
+        );
+    }
+
+    // Writes land in memory directly from now on.
+    unsafe { addr.write_volatile(0x1234) };
+    assert_eq!(unsafe { addr.read_volatile() }, 0x1234);
+}
@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Sleep deadline tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use libkernel::{
+    bsp, cpu, exception,
+    exception::asynchronous::{
+        interface::{IRQHandler, IRQManager},
+        IRQDescriptor,
+    },
+    time,
+    time::interface::TimeManager,
+};
+use test_macros::kernel_test;
+
+/// An interrupt source that has nothing to do with the sleep.
+struct UnrelatedIrq;
+
+static UNRELATED_IRQ: UnrelatedIrq = UnrelatedIrq;
+static UNRELATED_IRQ_SEEN: AtomicBool = AtomicBool::new(false);
+
+impl IRQHandler for UnrelatedIrq {
+    fn handle(&self) -> Result<(), &'static str> {
+        time::disarm_virtual_timer();
+        UNRELATED_IRQ_SEEN.store(true, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    time::init().unwrap();
+
+    let irqm = bsp::exception::asynchronous::irq_manager();
+    let descriptor = IRQDescriptor {
+        name: "Unrelated",
+        handler: &UNRELATED_IRQ,
+    };
+    irqm.register_handler(
+        bsp::exception::asynchronous::virtual_timer_irq(),
+        descriptor,
+    )
+    .unwrap();
+    irqm.enable(bsp::exception::asynchronous::virtual_timer_irq());
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// An unrelated IRQ in the middle of a sleep must not cut it short.
+#[kernel_test]
+fn sleep_lasts_full_duration_despite_unrelated_irq() {
+    const SLEEP: Duration = Duration::from_millis(50);
+
+    time::arm_virtual_timer(Duration::from_millis(5)).unwrap();
+
+    let start = time::time_manager().uptime();
+    unsafe { exception::asynchronous::local_irq_unmask() };
+    time::time_manager().sleep(SLEEP);
+    unsafe { exception::asynchronous::local_irq_mask() };
+    let elapsed = time::time_manager().uptime() - start;
+
+    assert!(UNRELATED_IRQ_SEEN.load(Ordering::Relaxed));
+    assert!(elapsed >= SLEEP);
+    assert!(elapsed < Duration::from_secs(1));
+}
+
+/// A deadline in the past must return right away.
+#[kernel_test]
+fn sleep_until_past_deadline_returns() {
+    let start = time::ticks();
+
+    time::sleep_until(start);
+    time::sleep_until(0);
+
+    assert!(time::ticks() >= start);
+}
@@ -0,0 +1,91 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Allocation failure recovery tests.
+
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+extern crate alloc;
+
+mod panic_exit_failure;
+
+use alloc::vec::Vec;
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use libkernel::{
+    bsp, cpu, exception, memory,
+    memory::{AllocRecovery, RecoveringAllocator},
+};
+use linked_list_allocator::LockedHeap;
+use test_macros::kernel_test;
+
+/// The heap starts out too small for the test's allocation, and grows by this much on failure.
+const INITIAL_HEAP_SIZE: usize = 4 * 1024;
+const HEAP_GROWTH: usize = 64 * 1024;
+
+#[global_allocator]
+static GLOBAL_ALLOCATOR: RecoveringAllocator<LockedHeap> =
+    RecoveringAllocator::new(LockedHeap::empty());
+
+static NUM_RECOVERIES: AtomicUsize = AtomicUsize::new(0);
+
+#[alloc_error_handler]
+fn alloc_error(_: Layout) -> ! {
+    panic!("Allocation failed despite recovery")
+}
+
+/// Grow the heap into the memory right behind it.
+fn grow_heap(_: Layout) -> AllocRecovery {
+    NUM_RECOVERIES.store(
+        NUM_RECOVERIES.load(Ordering::Relaxed) + 1,
+        Ordering::Relaxed,
+    );
+    unsafe { GLOBAL_ALLOCATOR.lock().extend(HEAP_GROWTH) };
+
+    AllocRecovery::Retry
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    let heap = bsp::memory::heap_range();
+    assert!(heap.end - heap.start >= INITIAL_HEAP_SIZE + HEAP_GROWTH);
+    GLOBAL_ALLOCATOR.lock().init(heap.start, INITIAL_HEAP_SIZE);
+
+    memory::set_alloc_error_handler(grow_heap);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// An allocation that does not fit must succeed on the retry after the handler grew the heap.
+#[kernel_test]
+fn retry_after_growing_heap_succeeds() {
+    let buffer: Vec<u8> = Vec::with_capacity(16 * 1024);
+
+    assert!(buffer.capacity() >= 16 * 1024);
+    assert_eq!(NUM_RECOVERIES.load(Ordering::Relaxed), 1);
+
+    // Fits into the grown heap without another recovery.
+    let small: Vec<u8> = Vec::with_capacity(1024);
+    assert!(small.capacity() >= 1024);
+    assert_eq!(NUM_RECOVERIES.load(Ordering::Relaxed), 1);
+}
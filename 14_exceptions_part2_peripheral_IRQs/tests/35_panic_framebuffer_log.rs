@@ -0,0 +1,111 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A panic must draw the end of the kernel log and the panic message on the framebuffer console.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+use core::str;
+use libkernel::{bsp, cpu, gfx::interface::TextSink, panic, println};
+
+const ROWS: usize = 8;
+const LINE_SIZE: usize = 64;
+
+/// Records the lines instead of drawing them.
+struct MockSink {
+    lines: [[u8; LINE_SIZE]; ROWS],
+    lens: [usize; ROWS],
+    num_lines: usize,
+    cleared: bool,
+}
+
+impl MockSink {
+    const fn new() -> Self {
+        Self {
+            lines: [[0; LINE_SIZE]; ROWS],
+            lens: [0; ROWS],
+            num_lines: 0,
+            cleared: false,
+        }
+    }
+
+    fn line(&self, i: usize) -> &str {
+        str::from_utf8(&self.lines[i][..self.lens[i]]).unwrap_or("")
+    }
+}
+
+impl TextSink for MockSink {
+    fn rows(&self) -> usize {
+        ROWS
+    }
+
+    fn clear(&mut self) {
+        self.num_lines = 0;
+        self.cleared = true;
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.num_lines < ROWS {
+            let n = line.len().min(LINE_SIZE);
+            self.lines[self.num_lines][..n].copy_from_slice(&line.as_bytes()[..n]);
+            self.lens[self.num_lines] = n;
+        }
+        self.num_lines += 1;
+    }
+}
+
+static mut SINK: MockSink = MockSink::new();
+
+/// The last log lines that fit, with the last row left for the panic message.
+const EXPECTED: [&str; ROWS] = [
+    "Log line 3",
+    "Log line 4",
+    "Log line 5",
+    "Log line 6",
+    "Log line 7",
+    "Log line 8",
+    "Log line 9",
+    "Kernel panic: Framebuffer expected",
+];
+
+/// Overwrites libkernel's `panic_wait::_panic_exit()`, to check what was drawn.
+#[no_mangle]
+fn _panic_exit() -> ! {
+    let sink = unsafe { &SINK };
+
+    if !sink.cleared || sink.num_lines != ROWS {
+        cpu::qemu_exit_failure()
+    }
+
+    for (i, expected) in EXPECTED.iter().enumerate() {
+        if sink.line(i) != *expected {
+            println!(
+                "Line {}: expected '{}', got '{}'",
+                i,
+                expected,
+                sink.line(i)
+            );
+            cpu::qemu_exit_failure()
+        }
+    }
+
+    cpu::qemu_exit_success()
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    println!("Testing the framebuffer log on panic");
+    println!("-------------------------------------------------------------------\n");
+
+    for i in 0..10 {
+        println!("Log line {}", i);
+    }
+
+    panic::set_framebuffer_console(&mut SINK);
+    panic!("Framebuffer expected")
+}
@@ -0,0 +1,45 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Translation table walk attribute tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception, memory};
+use memory::mmu::{interface::MMU, MemAttributes, TableWalkAttributes};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The TCR walk fields must hold the configured attributes, which match the cacheable DRAM that the
+/// kernel's tables live in.
+#[kernel_test]
+fn tcr_fields_match_table_memory() {
+    let configured = memory::mmu::table_walk_attributes();
+    assert_eq!(configured, TableWalkAttributes::default());
+
+    assert_eq!(memory::mmu::mmu().table_walk_attributes(), configured);
+    assert!(
+        memory::mmu::check_table_walk_attributes(configured, MemAttributes::CacheableDRAM).is_ok()
+    );
+}
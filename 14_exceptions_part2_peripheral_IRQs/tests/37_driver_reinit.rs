@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Driver reinit tests.
+
+#![feature(custom_test_frameworks)]
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, console, cpu, driver, println, state};
+use test_macros::kernel_test;
+
+const UART: &str = "BCM PL011 UART";
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Reinit of the UART must leave a freshly initialized console behind, which still prints.
+#[kernel_test]
+fn uart_reinit_gives_fresh_working_console() {
+    use console::interface::Statistics;
+    use driver::interface::DriverManager;
+
+    let driver_manager = bsp::driver::driver_manager();
+    let uart = driver_manager
+        .all_device_drivers()
+        .iter()
+        .find(|d| d.compatible() == UART)
+        .unwrap();
+
+    // Reinit expects the handler from boot to be there.
+    uart.register_and_enable_irq_handler().unwrap();
+    assert!(bsp::console::console().chars_written() > 0);
+
+    assert_eq!(driver_manager.reinit(UART), Ok(()));
+    assert_eq!(bsp::console::console().chars_written(), 0);
+
+    println!();
+    assert!(bsp::console::console().chars_written() > 0);
+
+    // The handler must have been registered again, so a second reinit works just the same.
+    assert_eq!(driver_manager.reinit(UART), Ok(()));
+}
+
+/// Reinit of a driver that does not exist must fail.
+#[kernel_test]
+fn reinit_of_unknown_driver_fails() {
+    use driver::interface::DriverManager;
+
+    assert!(bsp::driver::driver_manager()
+        .reinit("No such driver")
+        .is_err());
+}
+
+/// After kernel init, reinit must work just the same, with the handler from boot kept registered.
+///
+/// Runs last, because it ends the kernel init phase.
+#[kernel_test]
+fn uart_reinit_works_after_kernel_init() {
+    use console::interface::Statistics;
+    use driver::interface::DriverManager;
+
+    state::state_manager().transition_to_single_core_main();
+
+    let driver_manager = bsp::driver::driver_manager();
+    assert_eq!(driver_manager.reinit(UART), Ok(()));
+    assert_eq!(bsp::console::console().chars_written(), 0);
+
+    println!();
+    assert!(bsp::console::console().chars_written() > 0);
+
+    assert_eq!(driver_manager.reinit(UART), Ok(()));
+}
@@ -0,0 +1,135 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+require 'digest'
+require 'expect'
+require 'io/console'
+require 'timeout'
+require 'zlib'
+
+TIMEOUT_SECS = 3
+
+FRAME_DATA = 1
+FRAME_RETRANSMIT = 2
+
+# The host side of the serial framing layer, see `src/serial.rs`.
+module Framing
+    def self.cobs_encode(bytes)
+        out = []
+        bytes.pack('C*').split("\x00".b, -1).each do |segment|
+            segment = segment.bytes
+            while segment.length >= 254
+                out << 0xFF
+                out.concat(segment.shift(254))
+            end
+            out << segment.length + 1
+            out.concat(segment)
+        end
+        out
+    end
+
+    def self.cobs_decode(bytes)
+        out = []
+        i = 0
+        while i < bytes.length
+            code = bytes[i]
+            out.concat(bytes[(i + 1)...(i + code)])
+            i += code
+            out << 0 if code != 0xFF && i < bytes.length
+        end
+        out
+    end
+
+    def self.encode(kind, payload)
+        body = [kind, payload.length].pack('Cv').bytes + payload
+        raw = body + [Zlib.crc32(body.pack('C*'))].pack('V').bytes
+        [0] + cobs_encode(raw) + [0]
+    end
+
+    def self.send(qemu_in, kind, payload)
+        qemu_in.write(encode(kind, payload).pack('C*'))
+    end
+
+    # Receive a frame and return its kind and payload. Skips any text output before it.
+    def self.recv(qemu_out)
+        Timeout.timeout(TIMEOUT_SECS) do
+            nil until qemu_out.getbyte.zero?
+
+            data = []
+            loop do
+                byte = qemu_out.getbyte
+                next if byte.zero? && data.empty?
+                break if byte.zero?
+
+                data << byte
+            end
+
+            raw = cobs_decode(data)
+            body = raw[0...-4]
+            raise('Frame CRC mismatch') if Zlib.crc32(body.pack('C*')) != raw[-4..].pack('C*').unpack1('V')
+
+            [body[0], body[3..]]
+        end
+    end
+end
+
+# The image of `08_exec_payload`, for the given addresses.
+def payload(sentinel_addr, sentinel, return_addr)
+    [0x5800_00C0, 0x1800_00E1, 0xB900_0001, 0x5800_00E2, 0xD61F_0040, 0xD503_201F].pack('V*') +
+        [sentinel_addr, sentinel, 0, return_addr].pack('Q<VVQ<')
+end
+
+def expect_hex(qemu_out, name)
+    match = qemu_out.expect(/#{name} 0x(\h+)/, TIMEOUT_SECS)
+    raise("No #{name}") if match.nil?
+
+    match[1].to_i(16)
+end
+
+# Verify that the upload recovers from a corrupted chunk, and that the image gets executed.
+class UploadAndExecute
+    def name
+        'Uploaded image is executed'
+    end
+
+    def run(qemu_out, qemu_in)
+        # Binary frames must pass the terminal unchanged.
+        qemu_in.raw!
+
+        load_addr = expect_hex(qemu_out, 'LOAD_ADDR')
+        image = payload(expect_hex(qemu_out, 'SENTINEL_ADDR'), expect_hex(qemu_out, 'SENTINEL'),
+                        expect_hex(qemu_out, 'RETURN_ADDR'))
+        raise('Loader did not start') if qemu_out.expect('Waiting for image', TIMEOUT_SECS).nil?
+
+        header = ['H'.ord, load_addr, image.length].pack('CQ<V').bytes + Digest::SHA256.digest(image).bytes
+        Framing.send(qemu_in, FRAME_DATA, header)
+        kind, ack = Framing.recv(qemu_out)
+        raise('Header not acknowledged') if kind != FRAME_DATA || ack != ['A'.ord, 0, 0, 0, 0]
+
+        chunk = ['D'.ord, 0].pack('CV').bytes + image.bytes
+
+        # Flip a bit in the data, which the CRC must catch.
+        corrupted = Framing.encode(FRAME_DATA, chunk)
+        corrupted[-6] ^= 0x01
+        corrupted[-6] ^= 0x03 if corrupted[-6].zero?
+        qemu_in.write(corrupted.pack('C*'))
+        kind, = Framing.recv(qemu_out)
+        raise('No retransmit request for the corrupted chunk') if kind != FRAME_RETRANSMIT
+
+        Framing.send(qemu_in, FRAME_DATA, chunk)
+        kind, verified = Framing.recv(qemu_out)
+        raise('Image not verified') if kind != FRAME_DATA || verified != ['V'.ord]
+
+        raise('Image did not run') if qemu_out.expect('Payload wrote the sentinel', TIMEOUT_SECS).nil?
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [UploadAndExecute.new]
+end
@@ -0,0 +1,56 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! An image that the host uploads over the RAM loader must be executed.
+//!
+//! The host builds the image itself, from the addresses that are printed here. See
+//! `08_exec_payload` for what it does.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use core::sync::atomic::{AtomicU32, Ordering};
+use libkernel::{bootloader, bsp, cpu, exception, memory, println};
+
+const SENTINEL: u32 = 0x5E47_1E11;
+
+/// Written by the uploaded image.
+static PAYLOAD_SENTINEL: AtomicU32 = AtomicU32::new(0);
+
+/// Where the image continues after storing the sentinel.
+extern "C" fn payload_returned() -> ! {
+    if PAYLOAD_SENTINEL.load(Ordering::Relaxed) == SENTINEL {
+        println!("Payload wrote the sentinel");
+        cpu::qemu_exit_success()
+    }
+
+    cpu::qemu_exit_failure()
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        println!("MMU: {}", string);
+        cpu::qemu_exit_failure()
+    }
+
+    println!("LOAD_ADDR {:#x}", bsp::memory::payload_range().start);
+    println!(
+        "SENTINEL_ADDR {:#x}",
+        &PAYLOAD_SENTINEL as *const _ as usize
+    );
+    println!("SENTINEL {:#x}", SENTINEL);
+    println!("RETURN_ADDR {:#x}", payload_returned as *const () as usize);
+
+    bootloader::run(bsp::console::console())
+}
@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A panicking IRQ handler must not take down the kernel.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use libkernel::{
+    bsp, cpu, exception,
+    exception::asynchronous::{interface::IRQManager, IRQDescriptor},
+    time,
+};
+use test_macros::kernel_test;
+
+/// A handler that panics on every call.
+struct PanickingHandler {
+    calls: AtomicUsize,
+}
+
+static PANICKING_HANDLER: PanickingHandler = PanickingHandler {
+    calls: AtomicUsize::new(0),
+};
+
+const DESCRIPTOR: IRQDescriptor = IRQDescriptor {
+    name: "Panicking handler",
+    handler: &PANICKING_HANDLER,
+};
+
+impl exception::asynchronous::interface::IRQHandler for PanickingHandler {
+    fn handle(&self) -> Result<(), &'static str> {
+        self.calls.fetch_add(1, Ordering::Relaxed);
+
+        // Leaves the level triggered timer IRQ asserted.
+        panic!("Buggy driver")
+    }
+}
+
+/// Spin until `PANICKING_HANDLER` was called, or give up.
+fn wait_for_call() -> bool {
+    for _ in 0..1000 {
+        if PANICKING_HANDLER.calls.load(Ordering::Relaxed) > 0 {
+            return true;
+        }
+
+        cpu::spin_for_cycles(10_000);
+    }
+
+    false
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    // On the RPi 3, the interrupt controllers need no driver init.
+    let irqm = bsp::exception::asynchronous::irq_manager();
+    let irq = bsp::exception::asynchronous::virtual_timer_irq();
+    irqm.register_handler(irq, DESCRIPTOR).unwrap();
+    irqm.enable(irq);
+    exception::asynchronous::local_irq_unmask();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The panic must be contained, and the handler's IRQ be masked and marked faulted.
+#[kernel_test]
+fn panicking_handler_is_masked() {
+    let irqm = bsp::exception::asynchronous::irq_manager();
+    let irq = bsp::exception::asynchronous::virtual_timer_irq();
+
+    time::arm_virtual_timer(Duration::from_millis(1)).unwrap();
+    assert!(wait_for_call());
+
+    // Execution got here, so the kernel survived.
+    assert!(!irqm.is_enabled(irq));
+    assert!(exception::asynchronous::is_faulted(&DESCRIPTOR));
+    assert!(!exception::asynchronous::in_recoverable_handler());
+    assert_eq!(PANICKING_HANDLER.calls.load(Ordering::Relaxed), 1);
+}
+
+/// A faulted handler must not be called again, even if its IRQ is unmasked.
+#[kernel_test]
+fn faulted_handler_is_not_called_again() {
+    let irqm = bsp::exception::asynchronous::irq_manager();
+    let irq = bsp::exception::asynchronous::virtual_timer_irq();
+
+    irqm.enable(irq);
+    cpu::spin_for_cycles(1_000_000);
+
+    assert!(!irqm.is_enabled(irq));
+    assert_eq!(PANICKING_HANDLER.calls.load(Ordering::Relaxed), 1);
+
+    time::disarm_virtual_timer();
+}
@@ -0,0 +1,54 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! The core count must match the machine.
+//!
+//! QEMU's `raspi3` machine always models the four cores of the BCM2837, and rejects `-smp` with any
+//! other count. The test therefore checks against the four cores of the modelled SoC. Starting
+//! only the cores that exist, for lower core counts, is covered by the unit test of the filter in
+//! `_arch/aarch64/cpu/smp.rs`.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu};
+use test_macros::kernel_test;
+
+/// The number of cores of the modelled SoC.
+const MODELLED_CORES: u8 = 4;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// `num_cores()` must report the cores of the machine.
+#[kernel_test]
+fn num_cores_matches_machine() {
+    assert_eq!(cpu::num_cores(), MODELLED_CORES);
+    assert!(cpu::num_cores() as usize <= bsp::cpu::NUM_CORES);
+}
+
+/// Every secondary core that exists must start, and no other.
+#[kernel_test]
+fn only_existing_secondaries_start() {
+    let num_cores = cpu::num_cores() as usize;
+
+    let started = unsafe { cpu::smp::start_secondary_cores() };
+    assert_eq!(started, num_cores - 1);
+
+    for core in 0..num_cores {
+        assert!(cpu::smp::is_core_started(core));
+    }
+    assert!(!cpu::smp::is_core_started(bsp::cpu::NUM_CORES));
+}
@@ -0,0 +1,42 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! All cores hammering a `Spinlock` protected counter must not lose a single update.
+//!
+//! QEMU's `raspi3` machine always models four cores, which matches `-smp 4`.
+//!
+//! QEMU only: the cores run with their MMU and caches off, where exclusive accesses only work on
+//! QEMU. See `smp_test`.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, smp_test};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The counter total must be exactly cores × iterations.
+#[kernel_test]
+fn counter_total_is_exact() {
+    // The MMU is off in the test kernel.
+    let report = unsafe { smp_test::run() }.unwrap();
+    report.print();
+
+    assert_eq!(report.cores, 4);
+    assert_eq!(report.total, report.expected_total());
+    assert!(report.elapsed[..report.cores].iter().all(|e| e.is_some()));
+}
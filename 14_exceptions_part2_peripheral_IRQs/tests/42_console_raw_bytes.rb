@@ -0,0 +1,52 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+require 'io/console'
+require 'timeout'
+
+TIMEOUT_SECS = 3
+
+# Must match `PATTERN` in the kernel.
+PATTERN = [0x0A, 0x0D, 0x00, 0xFF, 0x0D, 0x0A, 0x7F, 'A'.ord].freeze
+
+# Verify that written bytes arrive unchanged.
+class RawWritePassesThrough
+    def name
+        'Raw write passes bytes unchanged'
+    end
+
+    def run(qemu_out, qemu_in)
+        # The terminal must not translate line endings either.
+        qemu_in.raw!
+
+        raise('No raw output') if qemu_out.expect('RAW:', TIMEOUT_SECS).nil?
+
+        received = Timeout.timeout(TIMEOUT_SECS) { PATTERN.map { qemu_out.getbyte } }
+        raise("Bytes were changed: #{received}") if received != PATTERN
+    end
+end
+
+# Verify that received bytes arrive unchanged. Depends on test 1 being run first.
+class RawReadPassesThrough
+    def name
+        'Raw read passes bytes unchanged'
+    end
+
+    def run(qemu_out, qemu_in)
+        raise('Kernel did not ask for input') if qemu_out.expect('SEND', TIMEOUT_SECS).nil?
+
+        qemu_in.write(PATTERN.pack('C*'))
+        raise('Input was changed') if qemu_out.expect('RX_OK', TIMEOUT_SECS).nil?
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [RawWritePassesThrough.new, RawReadPassesThrough.new]
+end
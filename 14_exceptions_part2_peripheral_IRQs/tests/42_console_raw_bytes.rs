@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Raw console I/O must pass line endings and other control bytes unchanged.
+
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{bsp, console, print, time};
+
+/// Bytes that the char-oriented API would convert or that a terminal would interpret.
+const PATTERN: [u8; 8] = [0x0A, 0x0D, 0x00, 0xFF, 0x0D, 0x0A, 0x7F, b'A'];
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use bsp::console::{console, qemu_bring_up_console};
+    use console::interface::*;
+
+    qemu_bring_up_console();
+
+    // Written bytes must reach the host as they are.
+    print!("RAW:");
+    console().write_bytes(&PATTERN);
+    print!("\n");
+
+    // Received bytes must reach the kernel as they are.
+    print!("SEND\n");
+    let mut buf = [0u8; PATTERN.len()];
+    let mut len = 0;
+    let received = time::with_timeout(Duration::from_secs(3), || {
+        len += console().read_bytes(&mut buf[len..]);
+
+        Some(()).filter(|_| len == buf.len())
+    });
+
+    assert!(received.is_ok());
+    assert_eq!(buf, PATTERN);
+    print!("RX_OK\n");
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    loop {}
+}
@@ -0,0 +1,80 @@
+# frozen_string_literal: true
+
+# SPDX-License-Identifier: MIT OR Apache-2.0
+#
+# Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+require 'expect'
+require 'io/console'
+require 'timeout'
+
+TIMEOUT_SECS = 3
+
+# The host side of the remote serial protocol, as GDB speaks it.
+module Rsp
+    def self.checksum(data)
+        format('%02x', data.bytes.sum % 256)
+    end
+
+    def self.send(qemu_in, qemu_out, data)
+        qemu_in.write("$#{data}##{checksum(data)}")
+        raise("Packet #{data} not acknowledged") if Timeout.timeout(TIMEOUT_SECS) { qemu_out.getc } != '+'
+    end
+
+    # Receive a packet, check and acknowledge it, and return its data.
+    def self.recv(qemu_in, qemu_out)
+        Timeout.timeout(TIMEOUT_SECS) do
+            nil until qemu_out.getc == '$'
+
+            data = +''
+            while (c = qemu_out.getc) != '#'
+                data << c
+            end
+            raise("Bad checksum for #{data}") if qemu_out.read(2) != checksum(data)
+
+            qemu_in.write('+')
+            data
+        end
+    end
+end
+
+# Verify that the stub reports the breakpoint, and returns the register that the kernel set.
+class ReadRegister
+    def name
+        'Register is read at the breakpoint'
+    end
+
+    def run(qemu_out, qemu_in)
+        # Packets must pass the terminal unchanged.
+        qemu_in.raw!
+
+        raise('Stub not initialized') if qemu_out.expect('GDB_READY', TIMEOUT_SECS).nil?
+        raise('No stop reply') if Rsp.recv(qemu_in, qemu_out) != 'S05'
+
+        Rsp.send(qemu_in, qemu_out, '?')
+        raise('Wrong stop reason') if Rsp.recv(qemu_in, qemu_out) != 'S05'
+
+        Rsp.send(qemu_in, qemu_out, 'p0')
+        x0 = Rsp.recv(qemu_in, qemu_out)
+        raise("x0 is #{x0}") if x0 != 'f0debc9a78563412'
+    end
+end
+
+# Verify that the kernel runs on after continuing. Depends on test 1 being run first.
+class Continue
+    def name
+        'Kernel continues after the breakpoint'
+    end
+
+    def run(qemu_out, qemu_in)
+        Rsp.send(qemu_in, qemu_out, 'c')
+        raise('Kernel did not continue') if qemu_out.expect('CONTINUED', TIMEOUT_SECS).nil?
+    end
+end
+
+##--------------------------------------------------------------------------------------------------
+## Test registration
+##--------------------------------------------------------------------------------------------------
+def subtest_collection
+    [ReadRegister.new, Continue.new]
+end
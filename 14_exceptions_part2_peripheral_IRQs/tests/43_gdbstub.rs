@@ -0,0 +1,30 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A host debugger must be able to read a register at a breakpoint and continue.
+
+#![feature(asm)]
+#![feature(format_args_nl)]
+#![no_main]
+#![no_std]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, exception, gdbstub, print};
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+    gdbstub::init().unwrap();
+    print!("GDB_READY\n");
+
+    // Stop with a known value in x0, for the host to read.
+    asm!("brk #0", in("x0") 0x1234_5678_9abc_def0u64, options(nomem, nostack));
+    print!("CONTINUED\n");
+
+    // The QEMU process running this test will be closed by the I/O test harness.
+    loop {}
+}
@@ -0,0 +1,80 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! A watchpoint must trap a write to the watched address.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::{
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use libkernel::{
+    bsp, cpu, debug, exception,
+    exception::{ExceptionContext, Syndrome},
+};
+use test_macros::kernel_test;
+
+/// The variable that gets corrupted.
+static mut WATCHED: u64 = 0;
+
+/// The watchpoint's slot, for the handler to clear it.
+static SLOT: AtomicUsize = AtomicUsize::new(usize::MAX);
+
+static NUM_WRITES_TRAPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Count trapped writes, and clear the watchpoint so that the write can complete.
+fn on_watchpoint(_e: &mut ExceptionContext) -> bool {
+    match Syndrome::current() {
+        Syndrome::Watchpoint { is_write: true, .. } => (),
+        _ => return false,
+    }
+
+    NUM_WRITES_TRAPPED.fetch_add(1, Ordering::Relaxed);
+    debug::clear_watchpoint(SLOT.load(Ordering::Relaxed));
+
+    true
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+    exception::register_sync_handler(on_watchpoint).unwrap();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The Cortex-A53 that QEMU models has six breakpoints and four watchpoints.
+#[kernel_test]
+fn debug_register_counts_match_cortex_a53() {
+    assert_eq!(debug::num_hw_breakpoints(), 6);
+    assert_eq!(debug::num_watchpoints(), 4);
+}
+
+/// A read must not trap a store watchpoint, and a write must trap exactly once.
+#[kernel_test]
+fn watchpoint_fires_on_write() {
+    let addr = unsafe { &WATCHED as *const _ as usize };
+    let slot = debug::set_watchpoint(addr, debug::WatchAccess::Store, 8).unwrap();
+    SLOT.store(slot, Ordering::Relaxed);
+
+    assert_eq!(unsafe { ptr::read_volatile(&WATCHED) }, 0);
+    assert_eq!(NUM_WRITES_TRAPPED.load(Ordering::Relaxed), 0);
+
+    unsafe { ptr::write_volatile(&mut WATCHED, 0xdead) };
+
+    // The handler cleared the watchpoint, after which the write went through.
+    assert_eq!(NUM_WRITES_TRAPPED.load(Ordering::Relaxed), 1);
+    assert_eq!(unsafe { ptr::read_volatile(&WATCHED) }, 0xdead);
+}
@@ -0,0 +1,88 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Single-stepping must execute exactly one instruction per step.
+
+#![feature(asm)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use libkernel::{
+    bsp, cpu, debug, exception,
+    exception::{ExceptionContext, Syndrome},
+};
+use test_macros::kernel_test;
+
+const NUM_STEPS: usize = 3;
+
+/// The ELR of each software step exception.
+static STEP_PCS: [AtomicU64; NUM_STEPS] = [AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0)];
+
+static NUM_STEPS_TAKEN: AtomicUsize = AtomicUsize::new(0);
+
+/// `brk #0x44` starts stepping, and each step records the PC and steps again until `NUM_STEPS`.
+fn stepper(e: &mut ExceptionContext) -> bool {
+    match Syndrome::current() {
+        Syndrome::Brk { comment: 0x44 } => {
+            e.skip_instruction();
+            debug::single_step(e);
+        }
+        Syndrome::SoftwareStep { .. } => {
+            let step = NUM_STEPS_TAKEN.fetch_add(1, Ordering::Relaxed);
+            STEP_PCS[step].store(e.elr(), Ordering::Relaxed);
+
+            if step + 1 < NUM_STEPS {
+                debug::single_step(e);
+            } else {
+                debug::end_single_step(e);
+            }
+        }
+        _ => return false,
+    }
+
+    true
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+    exception::register_sync_handler(stepper).unwrap();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Each step must advance the PC by exactly one instruction.
+#[kernel_test]
+fn each_step_executes_one_instruction() {
+    let brk_addr: u64;
+
+    unsafe {
+        asm!(
+            "adr {}, 1f",
+            "1: brk #0x44",
+            "nop",
+            "nop",
+            "nop",
+            "nop",
+            out(reg) brk_addr,
+        );
+    }
+
+    assert_eq!(NUM_STEPS_TAKEN.load(Ordering::Relaxed), NUM_STEPS);
+
+    // Stepping starts at the first `nop`, and each step stops before the next instruction.
+    for (i, pc) in STEP_PCS.iter().enumerate() {
+        assert_eq!(pc.load(Ordering::Relaxed), brk_addr + 4 * (i as u64 + 2));
+    }
+}
@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Resolution clamping tests.
+
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception, gfx, memory};
+use linked_list_allocator::LockedHeap;
+use test_macros::kernel_test;
+
+/// Mailbox messages are marshalled on the heap.
+#[global_allocator]
+static GLOBAL_ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+#[alloc_error_handler]
+fn alloc_error(_: core::alloc::Layout) -> ! {
+    panic!("Out of heap memory")
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    let heap = bsp::memory::heap_range();
+    GLOBAL_ALLOCATOR
+        .lock()
+        .init(heap.start, heap.end - heap.start);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A request larger than the display must be clamped to the display's native mode.
+#[kernel_test]
+fn init_clamps_over_large_request() {
+    let display = bsp::framebuffer::display_size().unwrap().unwrap();
+
+    let db = gfx::init(display.0 + 1000, display.1 + 1000).unwrap();
+    assert_eq!(db.front().width(), display.0 as usize);
+    assert_eq!(db.front().height(), display.1 as usize);
+}
@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Tickless idle tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use libkernel::{
+    bsp, cpu, exception, synchronization::interface::Mutex, time, time::interface::TimeManager,
+};
+use test_macros::kernel_test;
+
+static FIRED: AtomicBool = AtomicBool::new(false);
+
+fn fire() {
+    FIRED.store(true, Ordering::Relaxed);
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    time::init().unwrap();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Idle until the timer fired `delay` after now. Returns the number of wakeups and the time that
+/// passed.
+fn idle_until_fired(delay: Duration) -> (usize, Duration) {
+    FIRED.store(false, Ordering::Relaxed);
+
+    let mut r = time::timer_wheel();
+    r.lock(|wheel| wheel.schedule_in("tickless", delay, None, fire))
+        .unwrap();
+
+    let start = time::time_manager().uptime();
+    let mut wakeups = 0;
+    unsafe { exception::asynchronous::local_irq_unmask() };
+    while !FIRED.load(Ordering::Relaxed) {
+        time::idle_tickless();
+        wakeups += 1;
+    }
+    unsafe { exception::asynchronous::local_irq_mask() };
+
+    (wakeups, time::time_manager().uptime() - start)
+}
+
+/// An idle core must sleep through to the next timer's deadline in a single wait, instead of
+/// waking up at a fixed rate until it is due.
+#[kernel_test]
+fn idle_waits_until_next_timer() {
+    const DELAY: Duration = Duration::from_millis(50);
+
+    let (wakeups, elapsed) = idle_until_fired(DELAY);
+
+    assert_eq!(wakeups, 1);
+    assert!(elapsed >= DELAY);
+    assert!(elapsed < Duration::from_secs(1));
+
+    let mut r = time::timer_wheel();
+    assert_eq!(r.lock(|wheel| wheel.next_deadline()), None);
+}
+
+/// A deadline that is not a whole number of counter ticks must be rounded up. Otherwise, the core
+/// wakes up just before the timer is due, and has to wait a second time.
+#[kernel_test]
+fn idle_wakes_once_for_uneven_deadline() {
+    const DELAY: Duration = Duration::from_millis(50);
+
+    // One Hz below the counter's frequency, so that the delay is no whole number of ticks.
+    let resolution = time::time_manager().resolution().as_nanos() as u64;
+    time::set_frequency_override(1_000_000_000 / resolution - 1);
+
+    let (wakeups, elapsed) = idle_until_fired(DELAY);
+
+    time::set_frequency_override(0);
+
+    assert_eq!(wakeups, 1);
+    assert!(elapsed >= DELAY);
+}
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! GPU memory tests.
+//!
+//! QEMU does not implement the memory tags, so there the test only checks that an unsupported
+//! allocation leaves nothing allocated. The full round trip runs on hardware.
+
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{
+    bsp,
+    bsp::{device_driver::PropertyTagAllocateMemory, gpu_memory},
+    cpu, exception, memory,
+};
+use linked_list_allocator::LockedHeap;
+use test_macros::kernel_test;
+
+/// Mailbox messages are marshalled on the heap.
+#[global_allocator]
+static GLOBAL_ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+#[alloc_error_handler]
+fn alloc_error(_: core::alloc::Layout) -> ! {
+    panic!("Out of heap memory")
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    let heap = bsp::memory::heap_range();
+    GLOBAL_ALLOCATOR
+        .lock()
+        .init(heap.start, heap.end - heap.start);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A buffer must lock to a non-null bus address, and be unlocked and released without a leak.
+#[kernel_test]
+fn allocate_lock_unlock_release() {
+    use gpu_memory::{Error, GpuBuffer};
+
+    let mut buffer = match GpuBuffer::allocate(4096, 4096, PropertyTagAllocateMemory::FLAG_DIRECT) {
+        Err(Error::Unsupported) => {
+            assert_eq!(gpu_memory::num_allocated(), 0);
+            return;
+        }
+        result => result.unwrap(),
+    };
+    assert_ne!(buffer.handle(), 0);
+    assert_eq!(gpu_memory::num_allocated(), 1);
+
+    let bus_addr = buffer.lock().unwrap();
+    assert_ne!(bus_addr, 0);
+    assert_eq!(buffer.lock(), Ok(bus_addr));
+
+    buffer.unlock().unwrap();
+    assert_eq!(buffer.bus_addr(), None);
+
+    buffer.release().unwrap();
+    assert_eq!(gpu_memory::num_allocated(), 0);
+}
@@ -0,0 +1,85 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! IRQ-disabled window measurement tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{bsp, cpu, exception, synchronization, time, time::interface::TimeManager};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A deliberately long critical section must be reported as the longest one, with its call site.
+#[kernel_test]
+fn long_critical_section_is_reported_as_max() {
+    use exception::asynchronous::{local_irq_mask, local_irq_unmask};
+
+    const CRITICAL: Duration = Duration::from_millis(20);
+
+    exception::reset_max_irq_off_duration();
+
+    unsafe {
+        local_irq_unmask();
+        local_irq_mask();
+        let line = line!() - 1;
+        time::time_manager().spin_for(CRITICAL);
+        local_irq_unmask();
+        local_irq_mask();
+
+        let (duration, location) = exception::max_irq_off_duration().unwrap();
+        assert!(duration >= CRITICAL);
+        assert!(duration < Duration::from_secs(1));
+        assert!(location.file().ends_with("49_exception_irq_off_trace.rs"));
+        assert_eq!(location.line(), line);
+    }
+
+    // A shorter section afterwards must not replace it.
+    unsafe {
+        local_irq_unmask();
+        local_irq_mask();
+    }
+    let (duration, _) = exception::max_irq_off_duration().unwrap();
+    assert!(duration >= CRITICAL);
+}
+
+/// A window opened by a lock must report the code that took the lock, not the lock's
+/// implementation.
+#[kernel_test]
+fn lock_window_reports_lock_caller() {
+    use exception::asynchronous::{local_irq_mask, local_irq_unmask};
+    use synchronization::{interface::Mutex, IRQSafeNullLock};
+
+    const CRITICAL: Duration = Duration::from_millis(20);
+    static LOCK: IRQSafeNullLock<()> = IRQSafeNullLock::new(());
+
+    exception::reset_max_irq_off_duration();
+
+    unsafe { local_irq_unmask() };
+    let mut r = &LOCK;
+    r.lock(|_| time::time_manager().spin_for(CRITICAL));
+    let line = line!() - 1;
+    unsafe { local_irq_mask() };
+
+    let (duration, location) = exception::max_irq_off_duration().unwrap();
+    assert!(duration >= CRITICAL);
+    assert!(location.file().ends_with("49_exception_irq_off_trace.rs"));
+    assert_eq!(location.line(), line);
+}
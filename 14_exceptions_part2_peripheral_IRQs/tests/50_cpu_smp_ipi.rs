@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Inter-processor interrupt tests.
+//!
+//! The secondary cores run with their caches off, so the boot core keeps its MMU off, too.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::{
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, cpu::smp::IpiKind, time};
+use test_macros::kernel_test;
+
+const TARGET_CORE: usize = 1;
+
+/// How often the target core observed a `TlbFlush` IPI.
+static TLB_FLUSHES_SEEN: AtomicUsize = AtomicUsize::new(0);
+
+fn observe(ipi: IpiKind) {
+    if ipi == IpiKind::TlbFlush && cpu::smp::core_id::<usize>() == TARGET_CORE {
+        TLB_FLUSHES_SEEN.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn serve_ipis(core: usize) {
+    if core == TARGET_CORE {
+        cpu::smp::ipi_loop()
+    }
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    cpu::smp::set_ipi_handler(observe);
+    assert_eq!(cpu::smp::start_secondary_cores_with(serve_ipis), 3);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A `TlbFlush` IPI from the boot core must be observed by the target core's handler.
+#[kernel_test]
+fn tlb_flush_ipi_reaches_target_core() {
+    cpu::smp::send_ipi(TARGET_CORE as u8, IpiKind::TlbFlush).unwrap();
+
+    let seen = time::with_timeout(Duration::from_millis(100), || {
+        Some(()).filter(|_| TLB_FLUSHES_SEEN.load(Ordering::Relaxed) == 1)
+    });
+    assert!(seen.is_ok());
+
+    // And once more, now that the target core waits in its loop.
+    cpu::smp::send_ipi(TARGET_CORE as u8, IpiKind::TlbFlush).unwrap();
+
+    let seen = time::with_timeout(Duration::from_millis(100), || {
+        Some(()).filter(|_| TLB_FLUSHES_SEEN.load(Ordering::Relaxed) == 2)
+    });
+    assert!(seen.is_ok());
+}
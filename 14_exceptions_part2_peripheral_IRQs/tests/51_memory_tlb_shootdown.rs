@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! TLB shootdown tests.
+//!
+//! The secondary cores run with their caches off, so the boot core keeps its MMU off, too. Changing
+//! the inactive tables still goes through the whole shootdown.
+//!
+//! With their MMU off, the secondaries hold no translations that could go stale. The test therefore
+//! only checks that every target acknowledged the shootdown, not that a stale translation was
+//! dropped.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{bsp, cpu, memory, memory::mmu::interface::MMU, time};
+use test_macros::kernel_test;
+
+/// Cores 1 to 3.
+const SECONDARIES: u32 = 0b1110;
+
+fn serve_ipis(_core: usize) {
+    cpu::smp::ipi_loop()
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    assert_eq!(cpu::smp::start_secondary_cores_with(serve_ipis), 3);
+
+    // The secondaries announce themselves once they are ready for IPIs.
+    time::with_timeout(Duration::from_millis(100), || {
+        Some(()).filter(|_| cpu::smp::ipi_cores() == SECONDARIES)
+    })
+    .unwrap();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A change of the tables on the boot core must be acknowledged as flushed by every other core.
+#[kernel_test]
+fn mapping_change_is_flushed_on_other_cores() {
+    let range = 0x0100_0000..0x0102_0000;
+
+    unsafe {
+        memory::mmu::mmu()
+            .set_attributes(range, memory::mmu::AttributeFields::default())
+            .unwrap();
+    }
+
+    for core in 1..bsp::cpu::NUM_CORES {
+        assert_eq!(memory::mmu::shootdowns_acked(core), 1);
+    }
+    assert_eq!(memory::mmu::shootdowns_acked(0), 0);
+}
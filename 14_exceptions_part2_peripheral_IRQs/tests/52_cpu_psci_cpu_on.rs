@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! PSCI secondary core start test.
+//!
+//! On a machine with PSCI in its device tree, the secondary cores must be started with `CPU_ON`.
+//!
+//! The test only runs on QEMU's `raspi3`, which has no PSCI and passes no device tree. It therefore
+//! only covers the fallback to the spin table, and that `CPU_ON` fails with `NotSupported`. The
+//! PSCI path is only exercised on a board whose firmware provides PSCI. QEMU's `virt` machine
+//! would, but it does not have the RPi's memory map, so the kernel can not run there.
+
+#![feature(global_asm)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use libkernel::{bsp, cpu, cpu::psci, fdt::Fdt};
+use test_macros::kernel_test;
+
+// An entry for `CPU_ON` that only sets `FLAG_ENTRY_REACHED` and parks the core. It needs no stack.
+global_asm!(
+    "
+.section .text
+
+.global flag_entry
+flag_entry:
+    adrp   x1,  FLAG_ENTRY_REACHED
+    add    x1,  x1,  :lo12:FLAG_ENTRY_REACHED
+    mov    w2,  #1
+    str    w2,  [x1]
+1:  wfe
+    b      1b
+"
+);
+
+extern "C" {
+    fn flag_entry();
+}
+
+/// How many secondary cores arrived.
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Set by `flag_entry`.
+#[no_mangle]
+static FLAG_ENTRY_REACHED: AtomicU32 = AtomicU32::new(0);
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    if let Some(addr) = bsp::boot_args().dtb_addr() {
+        psci::init(&Fdt::from_addr(addr).unwrap()).unwrap();
+    }
+
+    STARTED.store(cpu::smp::start_secondary_cores(), Ordering::Relaxed);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The secondary cores must arrive, and with PSCI, the firmware must know them as started.
+#[kernel_test]
+fn secondary_cores_start_via_cpu_on() {
+    assert_eq!(STARTED.load(Ordering::Relaxed), 3);
+
+    for core in 1..bsp::cpu::NUM_CORES {
+        assert!(cpu::smp::is_core_started(core));
+
+        // Only `CPU_ON` leaves a core that the firmware counts as on. Should the firmware disagree,
+        // the core only sets a flag.
+        let result = unsafe { psci::cpu_on(core as u64, flag_entry as usize, 0) };
+        match psci::conduit() {
+            None => assert_eq!(result, Err(psci::Error::NotSupported)),
+            Some(_) => assert_eq!(result, Err(psci::Error::AlreadyOn)),
+        }
+    }
+
+    assert_eq!(FLAG_ENTRY_REACHED.load(Ordering::Relaxed), 0);
+}
@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! PSCI core offlining test.
+//!
+//! On a machine with PSCI in its device tree, a secondary core that calls `CPU_OFF` must drop out
+//! of the online cores. QEMU's `raspi3` has no PSCI, so there the core must get `NotSupported` back
+//! and stay online.
+//!
+//! The test only runs on QEMU's `raspi3`, so it only covers the `NotSupported` path. The PSCI path
+//! is only exercised on a board whose firmware provides PSCI. See `52_cpu_psci_cpu_on`.
+//!
+//! The secondary cores run with their caches off, so the boot core keeps its MMU off, too.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use libkernel::{bsp, cpu, cpu::psci, fdt::Fdt, time};
+use test_macros::kernel_test;
+
+const TARGET_CORE: usize = 1;
+
+/// Set by the boot core to ask the target core to go offline.
+static GO_OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// Set by the target core if `CPU_OFF` returned, i.e. failed.
+static CPU_OFF_RETURNED: AtomicBool = AtomicBool::new(false);
+
+/// Set with `CPU_OFF_RETURNED` if the failure was `NotSupported`.
+static CPU_OFF_NOT_SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+fn wait_to_go_offline(core: usize) {
+    if core != TARGET_CORE {
+        return;
+    }
+
+    while !GO_OFFLINE.load(Ordering::Acquire) {
+        cpu::nop();
+    }
+
+    let error = unsafe { psci::cpu_off() };
+    CPU_OFF_NOT_SUPPORTED.store(error == psci::Error::NotSupported, Ordering::Relaxed);
+    CPU_OFF_RETURNED.store(true, Ordering::Release);
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    if let Some(addr) = bsp::boot_args().dtb_addr() {
+        psci::init(&Fdt::from_addr(addr).unwrap()).unwrap();
+    }
+
+    assert_eq!(cpu::smp::start_secondary_cores_with(wait_to_go_offline), 3);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Offlining a secondary core must decrease the number of online cores by one.
+#[kernel_test]
+fn cpu_off_takes_a_core_offline() {
+    assert_eq!(cpu::num_online_cores(), 4);
+
+    GO_OFFLINE.store(true, Ordering::Release);
+
+    if psci::conduit().is_none() {
+        time::with_timeout(Duration::from_millis(100), || {
+            Some(()).filter(|_| CPU_OFF_RETURNED.load(Ordering::Acquire))
+        })
+        .unwrap();
+        assert!(CPU_OFF_NOT_SUPPORTED.load(Ordering::Relaxed));
+        assert_eq!(cpu::num_online_cores(), 4);
+
+        return;
+    }
+
+    time::with_timeout(Duration::from_millis(100), || {
+        Some(()).filter(|_| cpu::num_online_cores() == 3)
+    })
+    .unwrap();
+    assert!(!cpu::smp::is_core_started(TARGET_CORE));
+    assert!(!CPU_OFF_RETURNED.load(Ordering::Acquire));
+}
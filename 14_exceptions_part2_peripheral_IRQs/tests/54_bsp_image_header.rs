@@ -0,0 +1,39 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Image header tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The image must start with a header that describes where it goes and how big it is.
+#[kernel_test]
+fn image_header_describes_the_image() {
+    let image = bsp::memory::kernel_image_range();
+    let header = bsp::image_header().unwrap();
+
+    assert_eq!(image.start, 0x80000);
+    assert_eq!(header.text_offset, 0x80000);
+    assert_eq!(header.image_size, (image.end - image.start) as u64);
+
+    // Little endian, with a base at the start of DRAM.
+    assert_eq!(header.flags & 0b1001, 0);
+}
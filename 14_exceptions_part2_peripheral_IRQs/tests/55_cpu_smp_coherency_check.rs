@@ -0,0 +1,58 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! SMP coherency check tests.
+//!
+//! QEMU keeps its cores coherent without emulating `CPUECTLR_EL1`, which reads as zero there and is
+//! reported as unknown.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{
+    bsp, cpu,
+    cpu::smp::{CoherencyError, CoherencySetup},
+    exception, memory,
+};
+use test_macros::kernel_test;
+
+/// `SCTLR_EL1.C`.
+const SCTLR_EL1_C: u64 = 1 << 2;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// With the MMU and caches on, the check must pass.
+#[kernel_test]
+fn check_passes_after_mmu_init() {
+    assert_eq!(CoherencySetup::capture().check(), Ok(()));
+}
+
+/// A cleared D-cache bit must be reported as such.
+#[kernel_test]
+fn check_fails_without_dcache() {
+    let mut setup = CoherencySetup::capture();
+    setup.sctlr_el1 &= !SCTLR_EL1_C;
+
+    assert_eq!(setup.check(), Err(CoherencyError::DCacheOff));
+}
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Timer counter selection tests.
+//!
+//! The boot code clears `CNTVOFF_EL2` before it drops to EL1, so both counters read the same and
+//! the virtual one can be tested against the physical one.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{
+    bsp, cpu, time,
+    time::{interface::TimeManager, CounterKind},
+};
+use test_macros::kernel_test;
+
+/// How far the durations of the two counters may drift apart, for the reads in between.
+const TOLERANCE: Duration = Duration::from_millis(1);
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// Whether `a` and `b` are at most `TOLERANCE` apart.
+fn close(a: Duration, b: Duration) -> bool {
+    let diff = if a > b { a - b } else { b - a };
+
+    diff <= TOLERANCE
+}
+
+/// Without an offset, the physical counter must be picked at EL1.
+#[kernel_test]
+fn zero_offset_selects_physical_counter() {
+    assert_eq!(time::select_counter(), CounterKind::Physical);
+    assert_eq!(time::counter_kind(), CounterKind::Physical);
+}
+
+/// With the virtual counter selected, time must be monotonic and agree with the physical counter.
+#[kernel_test]
+fn virtual_counter_durations_are_monotonic_and_offset_correct() {
+    const SPIN: Duration = Duration::from_millis(10);
+
+    time::set_counter_kind(CounterKind::Virtual);
+
+    let mut last = time::time_manager().uptime();
+    for _ in 0..1000 {
+        let now = time::time_manager().uptime();
+        assert!(now >= last);
+        last = now;
+    }
+
+    let uptime = time::time_manager().uptime();
+    let virt = time::ticks_to_duration(time::counter(CounterKind::Virtual));
+    assert!(close(uptime, virt));
+
+    let start_virt = time::ticks();
+    let start_phys = time::counter(CounterKind::Physical);
+    time::time_manager().spin_for(SPIN);
+    let virt = time::ticks_to_duration(time::ticks() - start_virt);
+    let phys = time::ticks_to_duration(time::counter(CounterKind::Physical) - start_phys);
+
+    time::set_counter_kind(CounterKind::Physical);
+
+    assert!(virt >= SPIN);
+    assert!(close(virt, phys));
+}
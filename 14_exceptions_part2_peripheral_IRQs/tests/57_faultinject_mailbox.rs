@@ -0,0 +1,77 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Fault injection tests, with the mailbox as the failing operation.
+
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{
+    bsp, cpu, exception,
+    faultinject::{self, FaultPoint},
+    memory, thermal,
+};
+use linked_list_allocator::LockedHeap;
+use test_macros::kernel_test;
+
+/// Mailbox messages are marshalled on the heap.
+#[global_allocator]
+static GLOBAL_ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+#[alloc_error_handler]
+fn alloc_error(_: core::alloc::Layout) -> ! {
+    panic!("Out of heap memory")
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    let heap = bsp::memory::heap_range();
+    GLOBAL_ALLOCATOR
+        .lock()
+        .init(heap.start, heap.end - heap.start);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A failed mailbox send must surface as an error of the caller, without a panic.
+#[kernel_test]
+fn mailbox_fault_is_handled_by_caller() {
+    faultinject::arm(FaultPoint::MailboxSend, 1);
+
+    assert_eq!(thermal::start_sampling(), Err("Temperature query failed"));
+    assert_eq!(faultinject::remaining(FaultPoint::MailboxSend), 0);
+}
+
+/// An armed point must fail exactly as often as it was armed for.
+#[kernel_test]
+fn armed_faults_are_used_up() {
+    faultinject::arm(FaultPoint::MailboxSend, 2);
+
+    assert!(bsp::read_customer_otp(0, &mut [0; 1]).is_err());
+    assert!(bsp::temperature().is_err());
+    assert_eq!(faultinject::remaining(FaultPoint::MailboxSend), 0);
+    assert!(!faultinject::should_fail(FaultPoint::MailboxSend));
+
+    faultinject::arm(FaultPoint::Mapping, 3);
+    faultinject::disarm_all();
+    assert_eq!(faultinject::remaining(FaultPoint::Mapping), 0);
+}
@@ -0,0 +1,97 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Round-trip the notify-xHCI-reset tag through a mock firmware.
+//!
+//! Only the RPi 4 sends the tag to its firmware, but the mock needs no board, so this runs under
+//! the RPi 3 QEMU run, too.
+
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{
+    bsp::{
+        self,
+        device_driver::{Message, PropertyTag, PropertyTagNotifyXhciReset, PropertyTags},
+    },
+    cpu, exception, memory,
+};
+use linked_list_allocator::LockedHeap;
+use test_macros::kernel_test;
+
+/// Mailbox messages are marshalled on the heap.
+#[global_allocator]
+static GLOBAL_ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+#[alloc_error_handler]
+fn alloc_error(_: core::alloc::Layout) -> ! {
+    panic!("Out of heap memory")
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    let heap = bsp::memory::heap_range();
+    GLOBAL_ALLOCATOR
+        .lock()
+        .init(heap.start, heap.end - heap.start);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// The firmware must see the tag ID and the VL805's PCI address, and the answer must be accepted.
+#[kernel_test]
+fn notify_xhci_reset_round_trips() {
+    let notify_tag =
+        &mut PropertyTagNotifyXhciReset::new(PropertyTagNotifyXhciReset::VL805_PCI_DEV_ADDR);
+    let tag = PropertyTag::new(PropertyTags::NOTIFY_XHCI_RESET, notify_tag);
+    let mut msg = Message::new(&tag);
+
+    let mut seen = [0; 6];
+    let reply = msg.send_to_mock(|buffer| {
+        seen.copy_from_slice(&buffer[..6]);
+
+        // Answer like the firmware: Request succeeded, tag responded with the 4 byte address.
+        buffer[1] = 0x8000_0000;
+        buffer[4] = (1 << 31) | 4;
+    });
+
+    assert_eq!(reply.map(|tag| tag.pci_dev_addr), Ok(0x0010_0000));
+    assert_eq!(seen[1], 0);
+    assert_eq!(seen[2], 0x00030058);
+    assert_eq!(seen[3], 4);
+    assert_eq!(seen[4], 4);
+    assert_eq!(seen[5], 0x0010_0000);
+    assert_eq!(msg.response_slice(), &[0x0010_0000]);
+}
+
+/// A request that the firmware did not accept must be reported as an error.
+#[kernel_test]
+fn notify_xhci_reset_error_is_reported() {
+    let notify_tag =
+        &mut PropertyTagNotifyXhciReset::new(PropertyTagNotifyXhciReset::VL805_PCI_DEV_ADDR);
+    let tag = PropertyTag::new(PropertyTags::NOTIFY_XHCI_RESET, notify_tag);
+    let mut msg = Message::new(&tag);
+
+    let reply = msg.send_to_mock(|buffer| buffer[1] = 0x8000_0001);
+
+    assert!(reply.is_err());
+}
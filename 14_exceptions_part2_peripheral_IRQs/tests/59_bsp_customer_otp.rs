@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Read the customer OTP rows through a mock firmware, and through QEMU's.
+
+#![feature(alloc_error_handler)]
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use libkernel::{bsp, cpu, exception, memory};
+use linked_list_allocator::LockedHeap;
+use test_macros::kernel_test;
+
+/// Mailbox messages are marshalled on the heap.
+#[global_allocator]
+static GLOBAL_ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+#[alloc_error_handler]
+fn alloc_error(_: core::alloc::Layout) -> ! {
+    panic!("Out of heap memory")
+}
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    use memory::mmu::interface::MMU;
+
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    if let Err(string) = memory::mmu::mmu().init() {
+        panic!("MMU: {}", string);
+    }
+
+    let heap = bsp::memory::heap_range();
+    GLOBAL_ALLOCATOR
+        .lock()
+        .init(heap.start, heap.end - heap.start);
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// A mock firmware that answers with `start` and `count` and rows that encode their index.
+fn answer(buffer: &mut [u32], start: u32, count: u32) {
+    buffer[1] = 0x8000_0000;
+    buffer[4] = (1 << 31) | (8 + 4 * count);
+    buffer[5] = start;
+    buffer[6] = count;
+    for row in 0..count {
+        buffer[7 + row as usize] = 0x0100 + start + row;
+    }
+}
+
+/// The firmware must see the requested range, and the rows of its answer be returned.
+#[kernel_test]
+fn customer_otp_round_trips() {
+    let mut seen = [0; 2];
+    let mut rows = [0; 3];
+    let result = bsp::read_customer_otp_via(2, &mut rows, |msg| {
+        msg.send_to_mock(|buffer| {
+            seen.copy_from_slice(&buffer[5..7]);
+            answer(buffer, 2, 3);
+        })
+        .map(|_| ())
+    });
+
+    assert_eq!(result, Ok(()));
+    assert_eq!(seen, [2, 3]);
+    assert_eq!(rows, [0x0102, 0x0103, 0x0104]);
+}
+
+/// A range beyond the customer region must be rejected before anything is sent.
+#[kernel_test]
+fn customer_otp_out_of_range_is_rejected() {
+    let mut sent = false;
+    let result = bsp::read_customer_otp_via(6, &mut [0; 3], |_| {
+        sent = true;
+        Ok(())
+    });
+
+    assert!(result.is_err());
+    assert!(!sent);
+}
+
+/// An answer for another range than the requested one must be rejected.
+#[kernel_test]
+fn customer_otp_mismatching_echo_is_rejected() {
+    let mut rows = [0; 3];
+    let result = bsp::read_customer_otp_via(2, &mut rows, |msg| {
+        msg.send_to_mock(|buffer| answer(buffer, 1, 3)).map(|_| ())
+    });
+
+    assert!(result.is_err());
+    assert_eq!(rows, [0; 3]);
+}
+
+/// QEMU does not implement the tag, which must be reported as an error.
+#[kernel_test]
+fn customer_otp_is_unsupported_under_qemu() {
+    assert!(bsp::read_customer_otp(0, &mut [0; 1]).is_err());
+}
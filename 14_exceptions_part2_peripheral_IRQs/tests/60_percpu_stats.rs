@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: MIT OR Apache-2.0
+//
+// Copyright (c) 2020 Andre Richter <andre.o.richter@gmail.com>
+
+//! Per-core statistics tests.
+
+#![feature(custom_test_frameworks)]
+#![no_main]
+#![no_std]
+#![reexport_test_harness_main = "test_main"]
+#![test_runner(libkernel::test_runner)]
+
+mod panic_exit_failure;
+
+use core::time::Duration;
+use libkernel::{bsp, cpu, exception, percpu, time, time::interface::TimeManager};
+use test_macros::kernel_test;
+
+#[no_mangle]
+unsafe fn kernel_init() -> ! {
+    bsp::console::qemu_bring_up_console();
+
+    exception::handling_init();
+
+    time::init().unwrap();
+
+    test_main();
+
+    cpu::qemu_exit_success()
+}
+
+/// An IRQ that is taken must be counted for the core that serviced it.
+#[kernel_test]
+fn serviced_irq_is_counted() {
+    let stats = percpu::stats();
+    let irqs = stats.irqs_serviced();
+
+    // The sleep wakeup IRQ is taken as soon as the wait restores the unmasked state.
+    let deadline = time::time_manager().uptime() + Duration::from_millis(5);
+    unsafe { exception::asynchronous::local_irq_unmask() };
+    cpu::idle_until(deadline);
+    unsafe { exception::asynchronous::local_irq_mask() };
+
+    assert!(stats.irqs_serviced() > irqs);
+
+    // Other cores are not running, so their counters must not move.
+    assert_eq!(percpu::stats_of(1).irqs_serviced(), 0);
+}
+
+/// Time spent idle must be added to the core's idle time.
+#[kernel_test]
+fn idle_time_is_counted() {
+    const IDLE: Duration = Duration::from_millis(50);
+
+    let stats = percpu::stats();
+    let idle = stats.idle_time();
+
+    let start = time::time_manager().uptime();
+    let deadline = start + IDLE;
+    // A spurious wakeup ends a wait early, so wait until the deadline really passed.
+    while time::time_manager().uptime() < deadline {
+        cpu::idle_until(deadline);
+    }
+    let elapsed = time::time_manager().uptime() - start;
+
+    // The short stretches between the waits are not idle time.
+    let grown = stats.idle_time() - idle;
+    assert!(grown >= IDLE - Duration::from_millis(5));
+    assert!(grown <= elapsed);
+}